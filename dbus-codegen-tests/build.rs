@@ -147,6 +147,7 @@ fn generate_code(xml: &str, opts: &GenOpts, outfile: &str) {
 fn main() {
     let ffidisp = GenOpts {
         connectiontype: ConnectionType::Ffidisp,
+        propstorage: true,
         ..Default::default()
     };
     generate_code(POLICYKIT_XML, &ffidisp, "policykit.rs");
@@ -154,6 +155,7 @@ fn main() {
     let blocking_client = GenOpts {
         connectiontype: ConnectionType::Blocking,
         methodtype: None,
+        constants: true,
         ..Default::default()
     };
     generate_code(POLICYKIT_XML, &blocking_client, "policykit_blocking.rs");
@@ -165,6 +167,20 @@ fn main() {
     };
     generate_code(POLICYKIT_XML, &nonblock_client, "policykit_nonblock.rs");
 
+    let mock_client = GenOpts {
+        methodtype: None,
+        mock: true,
+        ..Default::default()
+    };
+    generate_code(POLICYKIT_XML, &mock_client, "policykit_mock.rs");
+
+    let async_client = GenOpts {
+        methodtype: None,
+        asyncclient: true,
+        ..Default::default()
+    };
+    generate_code(POLICYKIT_XML, &async_client, "policykit_async.rs");
+
     let mut g = GenOpts {
         methodtype: Some("MTFnMut".into()),
         serveraccess: ServerAccess::AsRefClosure,
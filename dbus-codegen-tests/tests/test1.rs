@@ -62,3 +62,56 @@ fn test_main() {
 
     for _ in t.run(&c, c.iter(100)) { if quit.load(Ordering::SeqCst) { break; } }
 }
+
+#[test]
+fn test_constants() {
+    use policykit_blocking::{org_freedesktop_dbus_peer, org_freedesktop_policy_kit1_authority as authority};
+    assert_eq!(authority::INTERFACE, "org.freedesktop.PolicyKit1.Authority");
+    assert_eq!(authority::METHOD_CHECK_AUTHORIZATION, "CheckAuthorization");
+    assert_eq!(authority::PROPERTY_BACKEND_NAME, "BackendName");
+    assert_eq!(authority::SIGNAL_CHANGED, "Changed");
+    assert_eq!(authority::match_rule().match_str(), "type='signal',interface='org.freedesktop.PolicyKit1.Authority'");
+
+    let c = dbus::blocking::Connection::new_session().unwrap();
+    let p = c.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", std::time::Duration::from_secs(5));
+    let (id,): (String,) = p.method_call(org_freedesktop_dbus_peer::INTERFACE, org_freedesktop_dbus_peer::METHOD_GET_MACHINE_ID, ()).unwrap();
+    assert!(!id.is_empty());
+}
+
+#[test]
+fn test_prop_storage() {
+    use dbus::message::SignalArgs;
+    use dbus::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+    use std::sync::{Arc, Mutex};
+
+    let props = Arc::new(Mutex::new(policykit::OrgFreedesktopPolicyKit1AuthorityProps {
+        backend_name: "js".into(), backend_version: "0.105".into(), backend_features: 1,
+    }));
+    let f = dbus::tree::Factory::new_fn::<()>();
+    let i = policykit::org_freedesktop_policy_kit1_authority_props_server(&f, (), props.clone());
+    let t = f.tree(()).add(f.object_path("/props", ()).introspectable().add(i));
+    let c = dbus::ffidisp::Connection::new_session().unwrap();
+    t.set_registered(&c, true).unwrap();
+    let cname = c.unique_name();
+    let path = dbus::Path::from("/props");
+    let m = props.lock().unwrap().set_backend_name("polkitd".into(), &path);
+    let pc = PropertiesPropertiesChanged::from_message(&m).unwrap();
+    assert_eq!(pc.interface_name, "org.freedesktop.PolicyKit1.Authority");
+    assert_eq!(pc.changed_properties["BackendName"].0.as_str(), Some("polkitd"));
+    assert_eq!(&*m.path().unwrap(), "/props");
+
+    let quit = Arc::new(AtomicBool::new(false));
+    let quit2 = quit.clone();
+    let client = std::thread::spawn(move || {
+        use policykit_blocking::OrgFreedesktopPolicyKit1Authority;
+        let c2 = dbus::blocking::Connection::new_session().unwrap();
+        let p = c2.with_proxy(cname, "/props", std::time::Duration::from_millis(1000));
+        let r = (p.backend_name(), p.backend_features());
+        quit2.store(true, Ordering::SeqCst);
+        r
+    });
+    for _ in t.run(&c, c.iter(100)) { if quit.load(Ordering::SeqCst) { break; } }
+    let (name, features) = client.join().unwrap();
+    assert_eq!(name.unwrap(), "polkitd");
+    assert_eq!(features.unwrap(), 1);
+}
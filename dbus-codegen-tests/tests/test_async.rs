@@ -0,0 +1,37 @@
+extern crate dbus;
+
+#[allow(dead_code)]
+#[deny(trivial_casts)]
+mod policykit_async;
+
+use policykit_async::{OrgFreedesktopDBusPeer, OrgFreedesktopDBusPeerAsync};
+use dbus::nonblock::Process;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::Duration;
+
+fn raw() -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+
+#[test]
+fn test_async() {
+    let c = dbus::blocking::Connection::new_session().unwrap();
+    let p = c.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(5));
+    let id = OrgFreedesktopDBusPeer::get_machine_id(&p).unwrap();
+
+    let ch = dbus::channel::Channel::get_private(dbus::channel::BusType::Session).unwrap();
+    let c = dbus::nonblock::LocalConnection::from(ch);
+    let p = dbus::nonblock::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", &c);
+    let mut reply = OrgFreedesktopDBusPeerAsync::get_machine_id(&p);
+    let waker = unsafe { Waker::from_raw(raw()) };
+    for _ in 0..50 {
+        if let Poll::Ready(r) = Pin::new(&mut reply).poll(&mut Context::from_waker(&waker)) {
+            assert_eq!(r.unwrap(), id);
+            return;
+        }
+        c.as_ref().read_write(Some(Duration::from_millis(100))).unwrap();
+        c.process_all();
+    }
+    panic!("No reply to GetMachineId");
+}
@@ -0,0 +1,36 @@
+extern crate dbus;
+
+#[allow(dead_code)]
+#[deny(trivial_casts)]
+mod policykit_mock;
+
+use policykit_mock::{OrgFreedesktopPolicyKit1Authority, OrgFreedesktopPolicyKit1AuthorityMock};
+use std::collections::HashMap;
+
+// Code under test, which only knows about the trait.
+fn backend<A: OrgFreedesktopPolicyKit1Authority>(a: &A) -> Result<String, dbus::Error> {
+    Ok(format!("{} {}", a.backend_name()?, a.backend_version()?))
+}
+
+#[test]
+fn test_mock() {
+    let m = OrgFreedesktopPolicyKit1AuthorityMock::default()
+        .on_backend_name(|| Ok("js".into()))
+        .on_backend_version(|| Ok("0.105".into()))
+        .on_check_authorization(|subject, action_id, _details, flags, _cancellation_id| {
+            assert_eq!(subject.0, "unix-process");
+            assert_eq!(flags, 1);
+            Ok((action_id == "org.example.allowed", false, HashMap::new()))
+        });
+    assert_eq!(backend(&m).unwrap(), "js 0.105");
+
+    let subject = || ("unix-process", HashMap::new());
+    let r = m.check_authorization(subject(), "org.example.allowed", HashMap::new(), 1, "").unwrap();
+    assert!(r.0);
+    let r = m.check_authorization(subject(), "org.example.denied", HashMap::new(), 1, "").unwrap();
+    assert!(!r.0);
+
+    let e = m.enumerate_actions("C").unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+    assert_eq!(m.mock_calls(), vec!("backend_name", "backend_version", "check_authorization", "check_authorization", "enumerate_actions"));
+}
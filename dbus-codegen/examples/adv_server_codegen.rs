@@ -69,7 +69,7 @@ where
         let d = fclone(minfo);
         d.check()?;
         let rm = minfo.msg.method_return();
-        Ok(vec!(rm))
+        Ok(rm.into())
     };
     let m = factory.method("check", Default::default(), h);
     let i = i.add_m(m);
@@ -1,6 +1,6 @@
 
 use std::{io, error, iter};
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 use dbus::arg::ArgType;
 use xml;
 
@@ -10,6 +10,7 @@ fn find_attr<'a>(a: &'a Vec<xml::attribute::OwnedAttribute>, n: &str) -> Result<
 
 struct Arg {
     name: String,
+    rustname: String,
     typ: String,
     idx: i32,
     is_out: bool,
@@ -20,6 +21,7 @@ struct Method {
     fn_name: String,
     iargs: Vec<Arg>,
     oargs: Vec<Arg>,
+    doc: Option<String>,
 }
 
 struct Prop {
@@ -28,19 +30,37 @@ struct Prop {
     set_fn_name: String,
     typ: String,
     access: String,
+    doc: Option<String>,
 }
 
 struct Signal {
     name: String,
+    structname: String,
     args: Vec<Arg>,
+    doc: Option<String>,
 }
 
 struct Intf {
     origname: String,
     shortname: String,
+    traitname: String,
+    snakename: String,
     methods: Vec<Method>,
     props: Vec<Prop>,
     signals: Vec<Signal>,
+    doc: Option<String>,
+}
+
+const DOC_ANNOTATIONS: [&str; 2] = ["org.freedesktop.DBus.DocString", "org.gtk.GDBus.DocString"];
+
+fn write_doc(s: &mut String, doc: &Option<String>, indent: &str) {
+    let doc = match doc { Some(d) => d, None => return };
+    let lines: Vec<&str> = doc.lines().map(|l| l.trim()).collect();
+    let start = lines.iter().position(|l| !l.is_empty()).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|l| !l.is_empty()).map(|e| e + 1).unwrap_or(start);
+    for l in &lines[start..end] {
+        *s += &if l.is_empty() { format!("{}///\n", indent) } else { format!("{}/// {}\n", indent, l) };
+    }
 }
 
 /// Server access code generation option
@@ -55,6 +75,25 @@ pub enum ServerAccess {
 }
 
 
+/// How D-Bus names in CamelCase are turned into snake_case function and argument names
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SnakeCase {
+    /// An underscore before every uppercase letter following a lowercase one,
+    /// e g "GetSELinuxContext" becomes "get_selinux_context"
+    Simple,
+    /// Also split acronyms from the following word, e g "GetSELinuxContext" becomes "get_se_linux_context"
+    SplitAcronyms,
+}
+
+/// How names that are Rust keywords are escaped
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KeywordStyle {
+    /// Add an underscore, e g "type_"
+    Suffix,
+    /// Use a raw identifier, e g "r#type", where possible
+    Raw,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ConnectionType {
     Ffidisp,
@@ -83,8 +122,29 @@ pub struct GenOpts {
     pub connectiontype: ConnectionType,
     /// interface filter. Only matching interface are generated, if non-empty.
     pub interfaces: Option<HashSet<String>>,
+    /// Interfaces (e g "org.example.Foo") and members (e g "org.example.Foo.Bar") not to generate
+    pub skip: HashSet<String>,
+    /// Names to use instead of the generated ones. The keys are interface names for trait names, and
+    /// interface names followed by member names (e g "org.example.Foo.Bar") for function and struct names.
+    pub renames: HashMap<String, String>,
+    /// How function and argument names are converted to snake_case
+    pub snakecase: SnakeCase,
+    /// How names that are Rust keywords are escaped
+    pub keywords: KeywordStyle,
     /// The command line argument string. This will be inserted into generated source files.
     pub command_line: String,
+    /// Generates a mock implementation of every client trait, for testing without a bus.
+    /// Only supported for blocking and ffidisp clients, without generic variants.
+    pub mock: bool,
+    /// Also generates an async version of every client trait, named with an "Async" suffix,
+    /// for nonblock proxies. Ignored for nonblock clients, since their traits are async already.
+    pub asyncclient: bool,
+    /// Generates a module for every interface, with constants for the interface and member names,
+    /// and a MatchRule for its signals.
+    pub constants: bool,
+    /// For server code, also generates a struct holding the property values of every interface that has properties,
+    /// and a function serving them from that struct. Not supported for the generic "MethodType" method type.
+    pub propstorage: bool,
 }
 
 impl ::std::default::Default for GenOpts {
@@ -93,7 +153,15 @@ impl ::std::default::Default for GenOpts {
         serveraccess: ServerAccess::RefClosure, genericvariant: false, futures: false,
         crhandler: None, connectiontype: ConnectionType::Blocking,
         interfaces: None,
-        command_line: String::new()
+        skip: HashSet::new(),
+        renames: HashMap::new(),
+        snakecase: SnakeCase::Simple,
+        keywords: KeywordStyle::Suffix,
+        command_line: String::new(),
+        mock: false,
+        asyncclient: false,
+        constants: false,
+        propstorage: false,
     }}
 }
 
@@ -174,17 +242,20 @@ fn make_camel(s: &str) -> String {
 }
 
 
-fn make_snake(s: &str, keyword_check: bool) -> String {
+fn make_snake_styled(s: &str, style: SnakeCase) -> String {
     let mut lcase = false;
     let mut r = String::new();
-    for c in s.chars() {
+    let chars: Vec<char> = s.chars().collect();
+    for (idx, &c) in chars.iter().enumerate() {
         match c {
              'a'..='z' | '0'..='9' => {
                   r.push(c);
                   lcase = true;
              }
              'A'..='Z' => {
-                  if lcase { r.push('_'); }
+                  let acronym_end = style == SnakeCase::SplitAcronyms && idx > 0 && chars[idx-1].is_ascii_uppercase() &&
+                      chars.get(idx+1).map(|n| n.is_ascii_lowercase()).unwrap_or(false);
+                  if lcase || acronym_end { r.push('_'); }
                   lcase = false;
                   r.push(c.to_lowercase().next().unwrap());
              }
@@ -195,12 +266,27 @@ fn make_snake(s: &str, keyword_check: bool) -> String {
         }
     }
     if r.len() < 2 { r.push('_'); } // Don't interfere with variable names like 'm' and 'i'
-    if keyword_check && RUST_KEYWORDS.iter().any(|i| i == &r) { r.push('_') };
     r
 }
 
-fn make_fn_name(intf: &Intf, name: &str) -> String {
-    let mut r = make_snake(name, true);
+// A snake_case name according to the options, with keywords escaped.
+fn make_rust_name(s: &str, opts: &GenOpts) -> String {
+    let mut r = make_snake_styled(s, opts.snakecase);
+    if !RUST_KEYWORDS.iter().any(|i| i == &r) { return r }
+    // These cannot be raw identifiers
+    if opts.keywords == KeywordStyle::Raw && !["crate", "self", "Self", "super"].contains(&&*r) { return format!("r#{}", r) }
+    r.push('_');
+    r
+}
+
+// Strips the r# of a raw identifier, to use it as part of another identifier.
+fn unraw(s: &str) -> &str { s.trim_start_matches("r#") }
+
+fn make_fn_name(intf: &Intf, name: &str, opts: &GenOpts) -> String {
+    let mut r = match opts.renames.get(&format!("{}.{}", intf.origname, name)) {
+        Some(r) => r.clone(),
+        None => make_rust_name(name, opts),
+    };
     loop {
         if intf.methods.iter().any(|x| x.fn_name == r) ||
             intf.props.iter().any(|x| x.get_fn_name == r || x.set_fn_name == r) {
@@ -280,8 +366,8 @@ fn make_type(s: &str, out: bool, genvars: &mut Option<GenVars>) -> Result<String
 
 impl Arg {
     fn varname(&self) -> String {
-        if self.name != "" {
-           make_snake(&self.name, true)
+        if self.rustname != "" {
+           self.rustname.clone()
         } else { format!("arg{}", self.idx) }
     }
     fn can_wrap_variant(&self, genvar: bool) -> bool { genvar && self.typ.starts_with("v") }
@@ -373,20 +459,25 @@ fn write_prop_decl(s: &mut String, p: &Prop, opts: &GenOpts, set: bool) -> Resul
     Ok(())
 }
 
-fn write_intf(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box<dyn error::Error>> {
+fn write_intf(s: &mut String, i: &Intf, opts: &GenOpts, suffix: &str) -> Result<(), Box<dyn error::Error>> {
 
-    let iname = make_camel(&i.shortname);
-    *s += &format!("\npub trait {} {{\n", iname);
+    let iname = format!("{}{}", i.traitname, suffix);
+    *s += "\n";
+    write_doc(s, &i.doc, "");
+    *s += &format!("pub trait {} {{\n", iname);
     for m in &i.methods {
+        write_doc(s, &m.doc, "    ");
         write_method_decl(s, &m, opts)?;
         *s += ";\n";
     }
     for p in &i.props {
         if p.can_get() {
+            write_doc(s, &p.doc, "    ");
             write_prop_decl(s, &p, opts, false)?;
             *s += ";\n";
         }
         if p.can_set() {
+            write_doc(s, &p.doc, "    ");
             write_prop_decl(s, &p, opts, true)?;
             *s += ";\n";
         }
@@ -395,7 +486,8 @@ fn write_intf(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box<dyn er
     Ok(())
 }
 
-fn write_intf_client(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box<dyn error::Error>> {
+fn write_intf_client(s: &mut String, i: &Intf, opts: &GenOpts, suffix: &str) -> Result<(), Box<dyn error::Error>> {
+    let iname = format!("{}{}", i.traitname, suffix);
     let (module, proxy) = match opts.connectiontype {
         ConnectionType::Ffidisp => ("ffidisp", "ConnPath"),
         ConnectionType::Blocking => ("blocking", "Proxy"),
@@ -404,13 +496,13 @@ fn write_intf_client(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box
 
     if module == "nonblock" {
         *s += &format!("\nimpl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target=T>> {} for {}::{}<'a, C> {{\n",
-            make_camel(&i.shortname), module, proxy);
+            iname, module, proxy);
     } else if opts.futures {
         *s += &format!("\nimpl<'a> {} for dbusf::ConnPath<'a> {{\n",
-            make_camel(&i.shortname));
+            iname);
     } else {
         *s += &format!("\nimpl<'a, C: ::std::ops::Deref<Target={}::Connection>{}> {} for {}::{}<'a, C> {{\n",
-            module, if module == "nonblock" { " + Clone" } else { "" }, make_camel(&i.shortname), module, proxy);
+            module, if module == "nonblock" { " + Clone" } else { "" }, iname, module, proxy);
     }
     for m in &i.methods {
         *s += "\n";
@@ -466,9 +558,79 @@ fn write_intf_client(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box
 
 }
 
+// The type of a closure taking the input arguments of a method and returning its result.
+fn mock_fn_type(m: &Method, opts: &GenOpts) -> Result<String, Box<dyn error::Error>> {
+    let iargs: Result<Vec<String>, _> = m.iargs.iter().map(|a| a.typename(false).map(|t| t.0)).collect();
+    let r = match m.oargs.len() {
+        0 => "()".to_string(),
+        1 => m.oargs[0].typename(false)?.0,
+        _ => {
+            let v: Result<Vec<String>, _> = m.oargs.iter().map(|z| z.typename(false).map(|t| t.0)).collect();
+            format!("({})", v?.join(", "))
+        }
+    };
+    Ok(format!("Fn({}) -> {} + Send + Sync", iargs?.join(", "), make_result(&r, opts)))
+}
+
+fn write_intf_mock(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box<dyn error::Error>> {
+    let iname = i.traitname.clone();
+    // Properties are mocked as methods without (getter) or with (setter) one input argument.
+    let mut fns = vec!();
+    for m in &i.methods { fns.push((m.fn_name.clone(), mock_fn_type(m, opts)?)); }
+    for p in i.props.iter().filter(|p| p.can_get()) {
+        fns.push((p.get_fn_name.clone(), format!("Fn() -> {} + Send + Sync", make_result(&make_type(&p.typ, true, &mut None)?, opts))));
+    }
+    for p in i.props.iter().filter(|p| p.can_set()) {
+        fns.push((p.set_fn_name.clone(), format!("Fn({}) -> {} + Send + Sync", make_type(&p.typ, true, &mut None)?, make_result("()", opts))));
+    }
+
+    *s += &format!("\n/// A mock implementation of `{}`, for testing code that uses it without a D-Bus connection.\n", iname);
+    *s += "///\n/// Every method returns what the closure set with the corresponding `on_` function returns,\n";
+    *s += "/// or an `org.freedesktop.DBus.Error.NotSupported` error if no closure was set.\n";
+    *s += &format!("#[derive(Default)]\npub struct {}Mock {{\n", iname);
+    *s += "    mock_calls: ::std::sync::Mutex<Vec<&'static str>>,\n";
+    for (name, t) in &fns { *s += &format!("    {}: Option<Box<dyn {}>>,\n", name, t); }
+    *s += "}\n\n";
+
+    *s += &format!("impl {}Mock {{\n", iname);
+    for (name, t) in &fns {
+        *s += &format!("    pub fn on_{}<F: {} + 'static>(mut self, f: F) -> Self {{ self.{} = Some(Box::new(f)); self }}\n", unraw(name), t, name);
+    }
+    *s += "\n    /// Returns the names of the trait functions called so far, in order.\n";
+    *s += "    pub fn mock_calls(&self) -> Vec<&'static str> { self.mock_calls.lock().unwrap().clone() }\n";
+    *s += "}\n\n";
+
+    *s += &format!("impl {} for {}Mock {{\n", iname, iname);
+    let write_fn = |s: &mut String, name: &str, args: &str| {
+        *s += " {\n";
+        *s += &format!("        self.mock_calls.lock().unwrap().push(\"{}\");\n", unraw(name));
+        *s += &format!("        match &self.{} {{\n", name);
+        *s += &format!("            Some(f) => f({}),\n", args);
+        *s += &format!("            None => Err(dbus::Error::new_custom(\"org.freedesktop.DBus.Error.NotSupported\", \"{}::{} is not mocked\")),\n", iname, unraw(name));
+        *s += "        }\n";
+        *s += "    }\n";
+    };
+    for m in &i.methods {
+        write_method_decl(s, m, opts)?;
+        write_fn(s, &m.fn_name, &m.iargs.iter().map(|a| a.varname()).collect::<Vec<_>>().join(", "));
+    }
+    for p in i.props.iter().filter(|p| p.can_get()) {
+        write_prop_decl(s, p, opts, false)?;
+        write_fn(s, &p.get_fn_name, "");
+    }
+    for p in i.props.iter().filter(|p| p.can_set()) {
+        write_prop_decl(s, p, opts, true)?;
+        write_fn(s, &p.set_fn_name, "value");
+    }
+    *s += "}\n";
+    Ok(())
+}
+
 fn write_signal(s: &mut String, i: &Intf, ss: &Signal) -> Result<(), Box<dyn error::Error>> {
-    let structname = format!("{}{}", make_camel(&i.shortname), make_camel(&ss.name));
-    *s += "\n#[derive(Debug)]\n";
+    let structname = &ss.structname;
+    *s += "\n";
+    write_doc(s, &ss.doc, "");
+    *s += "#[derive(Debug)]\n";
     *s += &format!("pub struct {} {{\n", structname);
     for a in ss.args.iter() {
         *s += &format!("    pub {}: {},\n", a.varname(), a.typename(false)?.0);
@@ -505,6 +667,26 @@ fn write_signals(s: &mut String, i: &Intf) -> Result<(), Box<dyn error::Error>>
     Ok(())
 }
 
+fn write_constants(s: &mut String, i: &Intf, opts: &GenOpts) {
+    let name = |n: &str| make_snake_styled(n, opts.snakecase).to_uppercase();
+    *s += &format!("\n/// Names of the `{}` interface and its members.\n", i.origname);
+    *s += &format!("pub mod {} {{\n", unraw(&i.snakename));
+    *s += &format!("    pub const INTERFACE: &str = \"{}\";\n", i.origname);
+    for m in i.methods.iter() { *s += &format!("    pub const METHOD_{}: &str = \"{}\";\n", name(&m.name), m.name); }
+    for p in i.props.iter() { *s += &format!("    pub const PROPERTY_{}: &str = \"{}\";\n", name(&p.name), p.name); }
+    for ss in i.signals.iter() { *s += &format!("    pub const SIGNAL_{}: &str = \"{}\";\n", name(&ss.name), ss.name); }
+    if !i.signals.is_empty() {
+        *s += "\n    /// Matches all signals of this interface.\n";
+        *s += "    pub fn match_rule() -> super::dbus::message::MatchRule<'static> {\n";
+        *s += "        let mut r = super::dbus::message::MatchRule::new();\n";
+        *s += "        r.msg_type = Some(super::dbus::MessageType::Signal);\n";
+        *s += "        r.interface = Some(INTERFACE.into());\n";
+        *s += "        r\n";
+        *s += "    }\n";
+    }
+    *s += "}\n";
+}
+
 fn write_server_access(s: &mut String, i: &Intf, saccess: ServerAccess, minfo_is_ref: bool) {
     let z = if minfo_is_ref {""} else {"&"};
     match saccess {
@@ -513,7 +695,7 @@ fn write_server_access(s: &mut String, i: &Intf, saccess: ServerAccess, minfo_is
             *s += "        let d = dd.as_ref();\n";
         },
         ServerAccess::RefClosure => *s += &format!("        let d = fclone({}minfo);\n", z),
-        ServerAccess::MethodInfo => *s += &format!("        let d: &{} = {}minfo;\n", make_camel(&i.shortname), z),
+        ServerAccess::MethodInfo => *s += &format!("        let d: &{} = {}minfo;\n", i.traitname, z),
     }
 }
 
@@ -530,7 +712,7 @@ fn write_intf_tree(s: &mut String, i: &Intf, mtype: &str, saccess: ServerAccess,
     let treem: String = if hasm { "M".into() } else { format!("tree::{}<D>", mtype) };
 
     *s += &format!("\npub fn {}_server<{}{}D>(factory: &tree::Factory<{}, D>, data: D::Interface{}) -> tree::Interface<{}, D>\n",
-        make_snake(&i.shortname, false), if hasf {"F, T, "} else {""}, if hasm {"M, "} else {""}, treem, if hasf {", f: F"} else {""}, treem);
+        i.snakename, if hasf {"F, T, "} else {""}, if hasm {"M, "} else {""}, treem, if hasf {", f: F"} else {""}, treem);
 
     let mut wheres: Vec<String> = vec!["D: tree::DataType".into(), "D::Method: Default".into()];
     if i.props.len() > 0 {
@@ -544,11 +726,11 @@ fn write_intf_tree(s: &mut String, i: &Intf, mtype: &str, saccess: ServerAccess,
     };
     match saccess {
         ServerAccess::RefClosure => {
-            wheres.push(format!("T: {}", make_camel(&i.shortname)));
+            wheres.push(format!("T: {}", i.traitname));
             wheres.push(format!("F: 'static + for <'z> Fn(& 'z tree::MethodInfo<tree::{}<D>, D>) -> & 'z T", mtype));
         },
         ServerAccess::AsRefClosure => {
-            wheres.push(format!("T: AsRef<dyn {}>", make_camel(&i.shortname)));
+            wheres.push(format!("T: AsRef<dyn {}>", i.traitname));
             wheres.push(format!("F: 'static + Fn(&tree::MethodInfo<tree::{}<D>, D>) -> T", mtype));
         },
         ServerAccess::MethodInfo => {},
@@ -585,7 +767,7 @@ fn write_intf_tree(s: &mut String, i: &Intf, mtype: &str, saccess: ServerAccess,
         for r in &m.oargs {
             *s += &format!("        let rm = rm.append1({});\n", r.varname());
         }
-        *s += "        Ok(vec!(rm))\n";
+        *s += "        Ok(rm.into())\n";
         *s += "    };\n";
         *s += &format!("    let m = factory.method{}(\"{}\", Default::default(), h);\n", if hasm {"_sync"} else {""}, m.name);
         for a in &m.iargs {
@@ -640,12 +822,76 @@ fn write_intf_tree(s: &mut String, i: &Intf, mtype: &str, saccess: ServerAccess,
     Ok(())
 }
 
+fn write_prop_storage(s: &mut String, i: &Intf, mtype: &str) -> Result<(), Box<dyn error::Error>> {
+    let structname = format!("{}Props", i.traitname);
+    let treem = format!("tree::{}<D>", mtype);
+    *s += &format!("\n/// Values of the properties of the `{}` interface.\n", i.origname);
+    *s += "///\n";
+    *s += &format!("/// Serve them with `{}_props_server`, and change them with the setters, which return\n", unraw(&i.snakename));
+    *s += "/// the PropertiesChanged signal to send.\n";
+    *s += "#[derive(Debug)]\n";
+    *s += &format!("pub struct {} {{\n", structname);
+    for p in &i.props {
+        write_doc(s, &p.doc, "    ");
+        *s += &format!("    pub {}: {},\n", p.get_fn_name, make_type(&p.typ, true, &mut None)?);
+    }
+    *s += "}\n\n";
+
+    *s += &format!("impl {} {{\n", structname);
+    for p in &i.props {
+        let value = if p.typ == "v" { format!("self.{}.0.box_clone()", p.get_fn_name) } else { format!("arg::RefArg::box_clone(&self.{})", p.get_fn_name) };
+        *s += &format!("    /// Sets the `{}` property, and returns the PropertiesChanged signal to send for the object at \"path\".\n", p.name);
+        *s += &format!("    pub fn {}(&mut self, value: {}, path: &dbus::Path) -> dbus::Message {{\n", p.set_fn_name, make_type(&p.typ, true, &mut None)?);
+        *s += &format!("        self.{} = value;\n", p.get_fn_name);
+        *s += "        let mut pc = dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged {\n";
+        *s += &format!("            interface_name: \"{}\".into(), changed_properties: Default::default(), invalidated_properties: vec!(),\n", i.origname);
+        *s += "        };\n";
+        *s += &format!("        pc.changed_properties.insert(\"{}\".into(), arg::Variant({}));\n", p.name, value);
+        *s += "        dbus::message::SignalArgs::to_emit_message(&pc, path)\n";
+        *s += "    }\n";
+    }
+    *s += "}\n";
+
+    *s += &format!("\n/// Serves the properties of the `{}` interface from \"props\". Changes made through\n", i.origname);
+    *s += "/// the Set method are written to \"props\", and PropertiesChanged is sent for them.\n";
+    *s += "///\n";
+    *s += "/// The interface has no methods or signals; add them with `add_m` and `add_s` if needed.\n";
+    *s += &format!("pub fn {}_props_server<D>(factory: &tree::Factory<{}, D>, data: D::Interface, props: ::std::sync::Arc<::std::sync::Mutex<{}>>) -> tree::Interface<{}, D>\n",
+        unraw(&i.snakename), treem, structname, treem);
+    *s += "where\n";
+    *s += "    D: tree::DataType,\n";
+    *s += "    D::Property: Default,\n";
+    *s += "{\n";
+    *s += &format!("    let i = factory.interface(\"{}\", data);\n", i.origname);
+    for p in &i.props {
+        *s += &format!("\n    let p = factory.property::<{}, _>(\"{}\", Default::default());\n", make_type(&p.typ, false, &mut None)?, p.name);
+        *s += &format!("    let p = p.access(tree::Access::{});\n", match &*p.access {
+            "read" => "Read",
+            "readwrite" => "ReadWrite",
+            "write" => "Write",
+            _ => return Err(format!("Unexpected access value {}", p.access).into()),
+        });
+        if p.can_get() {
+            *s += "    let pclone = props.clone();\n";
+            *s += &format!("    let p = p.on_get(move |a, _| {{ a.append(&pclone.lock().unwrap().{}); Ok(()) }});\n", p.get_fn_name);
+        }
+        if p.can_set() {
+            *s += "    let pclone = props.clone();\n";
+            *s += &format!("    let p = p.on_set(move |iter, _| {{ pclone.lock().unwrap().{} = iter.read()?; Ok(()) }});\n", p.get_fn_name);
+        }
+        *s += "    let i = i.add_p(p);\n";
+    }
+    *s += "    i\n";
+    *s += "}\n";
+    Ok(())
+}
+
 fn write_intf_crossroads(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(), Box<dyn error::Error>> {
     let crh = opts.crhandler.as_ref().unwrap();
     *s += &format!("\npub fn {}_ifaceinfo<I>() -> cr::IfaceInfo<'static, cr::{}>\n",
-        make_snake(&i.shortname, false), crh);
+        i.snakename, crh);
     *s += &format!("where I: {}{} {{\n",
-        make_camel(&i.shortname), if crh == "Par" { " + Send + Sync + 'static" } else { "" });
+        i.traitname, if crh == "Par" { " + Send + Sync + 'static" } else { "" });
     *s += &format!("    cr::IfaceInfo::new(\"{}\", vec!(\n", i.origname);
 
     for m in &i.methods {
@@ -680,6 +926,13 @@ fn write_intf_crossroads(s: &mut String, i: &Intf, opts: &GenOpts) -> Result<(),
 }
 
 
+// The options for the async traits generated in addition to the client traits, if any.
+fn async_opts(opts: &GenOpts) -> Option<GenOpts> {
+    if !opts.asyncclient || opts.futures || opts.connectiontype == ConnectionType::Nonblock { return None }
+    if opts.methodtype.is_some() || opts.crhandler.is_some() { return None }
+    Some(GenOpts { connectiontype: ConnectionType::Nonblock, asyncclient: false, mock: false, ..opts.clone() })
+}
+
 fn write_module_header(s: &mut String, opts: &GenOpts) {
     *s += &format!("// This code was autogenerated with `dbus-codegen-rust {}`, see https://github.com/diwic/dbus-rs\n", opts.command_line);
     *s += &format!("use {} as dbus;\n", opts.dbuscrate);
@@ -693,6 +946,7 @@ fn write_module_header(s: &mut String, opts: &GenOpts) {
             ConnectionType::Blocking => "blocking",
             ConnectionType::Nonblock => "nonblock",
         });
+        if async_opts(opts).is_some() { *s += &format!("use {}::nonblock;\n", opts.dbuscrate) }
     }
     if opts.crhandler.is_some() { *s += &format!("use {}::crossroads as cr;\n", opts.dbuscrate) }
 }
@@ -702,12 +956,19 @@ pub fn generate(xmldata: &str, opts: &GenOpts) -> Result<String, Box<dyn error::
     use xml::EventReader;
     use xml::reader::XmlEvent;
 
+    if opts.mock && (opts.genericvariant || opts.futures || opts.connectiontype == ConnectionType::Nonblock) {
+        Err("Mocks are only supported for blocking and ffidisp clients, without generic variants")?
+    }
+    if opts.propstorage && opts.methodtype.as_deref() == Some("MethodType") {
+        Err("Property storage is not supported for the generic MethodType")?
+    }
     let mut s = String::new();
     write_module_header(&mut s, opts);
     let mut curintf = None;
     let mut curm = None;
     let mut cursig = None;
     let mut curprop = None;
+    let mut inarg = false;
     let parser = EventReader::new(io::Cursor::new(xmldata));
     for e in parser {
         match e? {
@@ -719,8 +980,12 @@ pub fn generate(xmldata: &str, opts: &GenOpts) -> Result<String, Box<dyn error::
                 if let &Some(ref p) = &opts.skipprefix {
                     if n.len() > p.len() && n.starts_with(p) { n2 = &n[p.len()..]; }
                 }
-                curintf = Some(Intf { origname: n.into(), shortname: n2.into(),
-                    methods: Vec::new(), signals: Vec::new(), props: Vec::new() });
+                let (traitname, snakename) = match opts.renames.get(n) {
+                    Some(r) => (r.clone(), make_snake_styled(r, opts.snakecase)),
+                    None => (make_camel(n2), make_snake_styled(n2, opts.snakecase)),
+                };
+                curintf = Some(Intf { origname: n.into(), shortname: n2.into(), traitname, snakename,
+                    methods: Vec::new(), signals: Vec::new(), props: Vec::new(), doc: None });
             }
             XmlEvent::EndElement { ref name } if &name.local_name == "interface" => {
                 if curm.is_some() { Err("End of Interface inside method")? };
@@ -733,59 +998,80 @@ pub fn generate(xmldata: &str, opts: &GenOpts) -> Result<String, Box<dyn error::
                         continue;
                     }
                 }
-                write_intf(&mut s, &intf, opts)?;
+                if opts.skip.contains(&intf.origname) { continue; }
+                write_intf(&mut s, &intf, opts, "")?;
                 if opts.crhandler.is_some() {
                     write_intf_crossroads(&mut s, &intf, opts)?;
                 } else if let Some(ref mt) = opts.methodtype {
                     write_intf_tree(&mut s, &intf, &mt, opts.serveraccess, opts.genericvariant)?;
+                    if opts.propstorage && !intf.props.is_empty() { write_prop_storage(&mut s, &intf, &mt)?; }
                 } else {
-                    write_intf_client(&mut s, &intf, opts)?;
+                    write_intf_client(&mut s, &intf, opts, "")?;
+                    if opts.mock { write_intf_mock(&mut s, &intf, opts)?; }
+                    if let Some(aopts) = async_opts(opts) {
+                        write_intf(&mut s, &intf, &aopts, "Async")?;
+                        write_intf_client(&mut s, &intf, &aopts, "Async")?;
+                    }
                 }
                 write_signals(&mut s, &intf)?;
+                if opts.constants { write_constants(&mut s, &intf, opts); }
             }
 
             XmlEvent::StartElement { ref name, ref attributes, .. } if &name.local_name == "method" => {
                 if curm.is_some() { Err("Start of method inside method")? };
                 if curintf.is_none() { Err("Start of method outside interface")? };
                 let name = find_attr(attributes, "name")?;
-                curm = Some(Method { name: name.into(), fn_name: make_fn_name(curintf.as_ref().unwrap(), name),
-                    iargs: Vec::new(), oargs: Vec::new() });
+                curm = Some(Method { name: name.into(), fn_name: make_fn_name(curintf.as_ref().unwrap(), name, opts),
+                    iargs: Vec::new(), oargs: Vec::new(), doc: None });
             }
             XmlEvent::EndElement { ref name } if &name.local_name == "method" => {
                 if curm.is_none() { Err("End of method outside method")? };
                 if curintf.is_none() { Err("End of method outside interface")? };
-                curintf.as_mut().unwrap().methods.push(curm.take().unwrap());
+                let (intf, m) = (curintf.as_mut().unwrap(), curm.take().unwrap());
+                if !opts.skip.contains(&format!("{}.{}", intf.origname, m.name)) { intf.methods.push(m) }
             }
 
             XmlEvent::StartElement { ref name, ref attributes, .. } if &name.local_name == "signal" => {
                 if cursig.is_some() { Err("Start of signal inside signal")? };
                 if curintf.is_none() { Err("Start of signal outside interface")? };
-                cursig = Some(Signal { name: find_attr(attributes, "name")?.into(), args: Vec::new() });
+                let name = find_attr(attributes, "name")?;
+                let intf = curintf.as_ref().unwrap();
+                let structname = match opts.renames.get(&format!("{}.{}", intf.origname, name)) {
+                    Some(r) => r.clone(),
+                    None => format!("{}{}", intf.traitname, make_camel(name)),
+                };
+                cursig = Some(Signal { name: name.into(), structname, args: Vec::new(), doc: None });
             }
             XmlEvent::EndElement { ref name } if &name.local_name == "signal" => {
                 if cursig.is_none() { Err("End of signal outside signal")? };
                 if curintf.is_none() { Err("End of signal outside interface")? };
-                curintf.as_mut().unwrap().signals.push(cursig.take().unwrap());
+                let (intf, sig) = (curintf.as_mut().unwrap(), cursig.take().unwrap());
+                if !opts.skip.contains(&format!("{}.{}", intf.origname, sig.name)) { intf.signals.push(sig) }
             }
 
             XmlEvent::StartElement { ref name, ref attributes, .. } if &name.local_name == "property" => {
                 if curprop.is_some() { Err("Start of property inside property")? };
                 if curintf.is_none() { Err("Start of property outside interface")? };
                 let name = find_attr(attributes, "name")?;
-                let get_fn_name = make_fn_name(curintf.as_ref().unwrap(), name);
-                let set_fn_name = make_fn_name(curintf.as_ref().unwrap(), &format!("Set{}", name));
+                let intf = curintf.as_ref().unwrap();
+                let get_fn_name = make_fn_name(intf, name, opts);
+                // A renamed getter "foo" gets the setter "set_foo"
+                let setter = opts.renames.get(&format!("{}.{}", intf.origname, name)).map(|r| format!("set_{}", unraw(r)));
+                let set_fn_name = setter.unwrap_or_else(|| make_fn_name(intf, &format!("Set{}", name), opts));
                 curprop = Some(Prop {
                     name: name.into(),
                     typ: find_attr(attributes, "type")?.into(),
                     access: find_attr(attributes, "access")?.into(),
                     get_fn_name: get_fn_name,
                     set_fn_name: set_fn_name,
+                    doc: None,
                 });
             }
             XmlEvent::EndElement { ref name } if &name.local_name == "property" => {
                 if curprop.is_none() { Err("End of property outside property")? };
                 if curintf.is_none() { Err("End of property outside interface")? };
-                curintf.as_mut().unwrap().props.push(curprop.take().unwrap());
+                let (intf, p) = (curintf.as_mut().unwrap(), curprop.take().unwrap());
+                if !opts.skip.contains(&format!("{}.{}", intf.origname, p.name)) { intf.props.push(p) }
             }
 
 
@@ -801,9 +1087,22 @@ pub fn generate(xmldata: &str, opts: &GenOpts) -> Result<String, Box<dyn error::
                 }};
                 let arr = if let Some(ref mut sig) = cursig { &mut sig.args }
                     else if is_out { &mut curm.as_mut().unwrap().oargs } else { &mut curm.as_mut().unwrap().iargs };
-                let arg = Arg { name: find_attr(attributes, "name").unwrap_or("").into(),
+                let name = find_attr(attributes, "name").unwrap_or("");
+                let arg = Arg { name: name.into(), rustname: if name == "" { String::new() } else { make_rust_name(name, opts) },
                     typ: typ, is_out: is_out, idx: arr.len() as i32 };
                 arr.push(arg);
+                inarg = true;
+            }
+            XmlEvent::EndElement { ref name } if &name.local_name == "arg" => inarg = false,
+
+            XmlEvent::StartElement { ref name, ref attributes, .. } if &name.local_name == "annotation" => {
+                if inarg || !DOC_ANNOTATIONS.contains(&find_attr(attributes, "name")?) { continue; }
+                let doc = if let Some(ref mut p) = curprop { &mut p.doc }
+                    else if let Some(ref mut sig) = cursig { &mut sig.doc }
+                    else if let Some(ref mut m) = curm { &mut m.doc }
+                    else if let Some(ref mut i) = curintf { &mut i.doc }
+                    else { continue };
+                *doc = Some(find_attr(attributes, "value")?.into());
             }
             _ => (),
         }
@@ -933,6 +1232,123 @@ static FROM_DBUS: &'static str = r#"
 </node>
 "#;
 
+    #[test]
+    fn doc_strings() {
+        let xml = r#"<node>
+  <interface name="com.example.Docs">
+    <annotation name="org.freedesktop.DBus.DocString" value="An interface
+      with documentation."/>
+    <method name="Hello">
+      <annotation name="org.gtk.GDBus.DocString" value="Says hello."/>
+      <arg name="name" type="s" direction="in">
+        <annotation name="org.freedesktop.DBus.DocString" value="Not the method."/>
+      </arg>
+    </method>
+    <property name="Count" type="u" access="readwrite">
+      <annotation name="org.freedesktop.DBus.DocString" value="The number of &lt;things&gt;."/>
+    </property>
+    <signal name="Changed">
+      <annotation name="org.freedesktop.DBus.DocString" value="Sent on change."/>
+    </signal>
+    <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+  </interface>
+</node>"#;
+        let s = generate(xml, &GenOpts { methodtype: None, ..Default::default() }).unwrap();
+        assert!(s.contains("\n/// An interface\n/// with documentation.\npub trait ComExampleDocs {\n"));
+        assert!(s.contains("    /// Says hello.\n    fn hello(&self, name: &str)"));
+        assert!(s.contains("    /// The number of <things>.\n    fn count(&self)"));
+        assert!(s.contains("    /// The number of <things>.\n    fn set_count(&self"));
+        assert!(s.contains("\n/// Sent on change.\n#[derive(Debug)]\npub struct ComExampleDocsChanged {"));
+        assert!(!s.contains("Not the method"));
+    }
+
+    #[test]
+    fn renames() {
+        use super::{SnakeCase, KeywordStyle};
+        let xml = r#"<node>
+  <interface name="com.example.Names">
+    <method name="GetSELinuxContext">
+      <arg name="type" type="s" direction="in"/>
+      <arg name="ContextURL" type="s" direction="out"/>
+    </method>
+    <method name="GetURLs"/>
+    <method name="Internal"/>
+    <property name="Type" type="u" access="readwrite"/>
+    <signal name="Changed"/>
+  </interface>
+  <interface name="com.example.Hidden"/>
+</node>"#;
+        let s = generate(xml, &GenOpts { methodtype: None, ..Default::default() }).unwrap();
+        assert!(s.contains("pub trait ComExampleNames {"));
+        assert!(s.contains("fn get_selinux_context(&self, type_: &str) -> Result<String, dbus::Error>"));
+        assert!(s.contains("fn type_(&self)"));
+        assert!(s.contains("fn internal(&self)"));
+        assert!(s.contains("pub struct ComExampleNamesChanged"));
+        assert!(s.contains("pub trait ComExampleHidden {"));
+
+        let opts = GenOpts { methodtype: None, snakecase: SnakeCase::SplitAcronyms, keywords: KeywordStyle::Raw,
+            skip: vec!("com.example.Hidden".into(), "com.example.Names.Internal".into()).into_iter().collect(),
+            renames: vec!(("com.example.Names", "Names"), ("com.example.Names.GetURLs", "urls"), ("com.example.Names.Changed", "NamesChanged"))
+                .into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            mock: true, ..Default::default() };
+        let s = generate(xml, &opts).unwrap();
+        assert!(s.contains("pub trait Names {"));
+        assert!(s.contains("fn get_se_linux_context(&self, r#type: &str) -> Result<String, dbus::Error>"));
+        assert!(s.contains("fn urls(&self)"));
+        assert!(s.contains("fn r#type(&self)"));
+        assert!(s.contains("fn set_type(&self"));
+        assert!(s.contains("pub fn on_type<"));
+        assert!(s.contains("pub struct NamesChanged"));
+        assert!(s.contains("impl<'a, C: ::std::ops::Deref<Target=blocking::Connection>> Names for blocking::Proxy<'a, C>"));
+        assert!(!s.contains("Internal") && !s.contains("internal"));
+        assert!(!s.contains("Hidden"));
+    }
+
+    #[test]
+    fn constants() {
+        let xml = r#"<node>
+  <interface name="com.example.Names">
+    <method name="GetURLs"/>
+    <property name="Type" type="u" access="read"/>
+  </interface>
+  <interface name="com.example.Signals">
+    <signal name="Changed"/>
+  </interface>
+</node>"#;
+        let s = generate(xml, &GenOpts { methodtype: None, constants: true, ..Default::default() }).unwrap();
+        assert!(s.contains("pub mod com_example_names {"));
+        assert!(s.contains("pub const INTERFACE: &str = \"com.example.Names\";"));
+        assert!(s.contains("pub const METHOD_GET_URLS: &str = \"GetURLs\";"));
+        assert!(s.contains("pub const PROPERTY_TYPE: &str = \"Type\";"));
+        assert!(s.contains("pub const SIGNAL_CHANGED: &str = \"Changed\";"));
+        assert_eq!(s.matches("pub fn match_rule()").count(), 1);
+        let s = generate(xml, &GenOpts { methodtype: None, ..Default::default() }).unwrap();
+        assert!(!s.contains("pub mod"));
+    }
+
+    #[test]
+    fn prop_storage() {
+        let xml = r#"<node>
+  <interface name="com.example.Settings">
+    <method name="Reset"/>
+    <property name="Volume" type="i" access="readwrite"/>
+    <property name="Extra" type="v" access="read"/>
+  </interface>
+  <interface name="com.example.NoProps">
+    <method name="Hello"/>
+  </interface>
+</node>"#;
+        let s = generate(xml, &GenOpts { propstorage: true, ..Default::default() }).unwrap();
+        assert!(s.contains("pub struct ComExampleSettingsProps {"));
+        assert!(s.contains("    pub volume: i32,"));
+        assert!(s.contains("pub fn set_volume(&mut self, value: i32, path: &dbus::Path) -> dbus::Message"));
+        assert!(s.contains("arg::Variant(self.extra.0.box_clone())"));
+        assert!(s.contains("pub fn com_example_settings_props_server<D>("));
+        assert_eq!(s.matches("on_set(").count(), 2);
+        assert!(!s.contains("ComExampleNoPropsProps"));
+        assert!(generate(xml, &GenOpts { propstorage: true, methodtype: Some("MethodType".into()), ..Default::default() }).is_err());
+    }
+
     #[test]
     fn from_dbus() {
         let s = generate(FROM_DBUS, &GenOpts { methodtype: Some("MTSync".into()), ..Default::default() }).unwrap();
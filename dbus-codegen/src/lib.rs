@@ -3,5 +3,5 @@ extern crate xml;
 
 mod generate;
 
-pub use crate::generate::{generate, GenOpts, ServerAccess, ConnectionType};
+pub use crate::generate::{generate, GenOpts, ServerAccess, ConnectionType, SnakeCase, KeywordStyle};
 
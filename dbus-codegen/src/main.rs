@@ -6,7 +6,7 @@ mod generate;
 
 use dbus::ffidisp::Connection;
 
-use crate::generate::{ServerAccess, ConnectionType};
+use crate::generate::{ServerAccess, ConnectionType, SnakeCase, KeywordStyle};
 
 // Copy-pasted from the output of this program :-)
 pub trait OrgFreedesktopDBusIntrospectable {
@@ -54,6 +54,24 @@ Defaults to 'RefClosure'."))
 //             .help("Generates code to use with futures 0.3 (experimental)"))
         .arg(clap::Arg::with_name("client").short("c").long("client").takes_value(true).value_name("client")
              .help("Type of client connection. Valid values are: 'blocking', 'nonblock', 'ffidisp'."))
+        .arg(clap::Arg::with_name("skip").long("skip").takes_value(true).value_name("NAMES")
+             .help("Comma separated list of interfaces (e g 'org.example.Foo') and members (e g 'org.example.Foo.Bar') not to generate."))
+        .arg(clap::Arg::with_name("rename").long("rename").takes_value(true).value_name("RENAMES")
+             .help("Comma separated list of renames, e g 'org.example.Foo=Foo,org.example.Foo.GetURLs=urls'. \
+Interfaces are renamed to trait names, members to function (or signal struct) names."))
+        .arg(clap::Arg::with_name("snakecase").long("snake-case").takes_value(true).value_name("Simple")
+             .help("How to convert names to snake_case; valid values are: 'Simple' (GetSELinuxContext -> get_selinux_context) and \
+'SplitAcronyms' (GetSELinuxContext -> get_se_linux_context). Defaults to 'Simple'."))
+        .arg(clap::Arg::with_name("keywords").long("keywords").takes_value(true).value_name("Suffix")
+             .help("How to escape names that are Rust keywords; valid values are: 'Suffix' (type_) and 'Raw' (r#type). Defaults to 'Suffix'."))
+        .arg(clap::Arg::with_name("mock").long("mock")
+             .help("Also generates a mock implementation of every client trait, for testing without a bus. (Ignored if methodtype is specified.)"))
+        .arg(clap::Arg::with_name("async").long("async")
+             .help("Also generates async client traits, with an 'Async' suffix, for nonblock proxies. (Ignored if methodtype is specified, or client is 'nonblock'.)"))
+        .arg(clap::Arg::with_name("constants").long("constants")
+             .help("Also generates a module for every interface, with constants for its interface and member names, and a MatchRule for its signals."))
+        .arg(clap::Arg::with_name("propstorage").long("prop-storage")
+             .help("Also generates a struct holding the property values of every interface, and a function serving them. (Server side only.)"))
         .arg(clap::Arg::with_name("output").short("o").long("output").takes_value(true).value_name("FILE")
              .help("Write output into the specified file"))
         .arg(clap::Arg::with_name("file").long("file").required(false).takes_value(true).value_name("FILE")
@@ -108,6 +126,28 @@ Defaults to 'RefClosure'."))
         _ => panic!("Invalid client connection type specified"),
     };
 
+    let snakecase = matches.value_of("snakecase").map(|s| s.to_lowercase());
+    let snakecase = match snakecase.as_ref().map(|s| &**s) {
+        None | Some("simple") => SnakeCase::Simple,
+        Some("splitacronyms") => SnakeCase::SplitAcronyms,
+        _ => panic!("Invalid snake case specified"),
+    };
+
+    let keywords = matches.value_of("keywords").map(|s| s.to_lowercase());
+    let keywords = match keywords.as_ref().map(|s| &**s) {
+        None | Some("suffix") => KeywordStyle::Suffix,
+        Some("raw") => KeywordStyle::Raw,
+        _ => panic!("Invalid keyword style specified"),
+    };
+
+    let skip = matches.value_of("skip").map(|s| s.split(",").map(|e| e.trim().to_owned()).collect()).unwrap_or_default();
+    let renames = matches.value_of("rename").map(|s| s.split(",").map(|e| {
+        let mut kv = e.splitn(2, '=');
+        let k = kv.next().unwrap().trim().to_owned();
+        let v = kv.next().unwrap_or_else(|| panic!("Invalid rename '{}', expected 'from=to'", e)).trim().to_owned();
+        (k, v)
+    }).collect()).unwrap_or_default();
+
     let interfaces = matches.value_of("interfaces").map(|s| s.split(",").map(|e| e.trim().to_owned()).collect());
 
     let opts = generate::GenOpts { methodtype: mtype.map(|x| x.into()), dbuscrate: dbuscrate.into(),
@@ -117,7 +157,15 @@ Defaults to 'RefClosure'."))
         connectiontype: client,
         crhandler: crhandler.map(|x| x.to_string()),
         interfaces,
-        command_line: std::env::args().skip(1).collect::<Vec<String>>().join(" ")
+        skip,
+        renames,
+        snakecase,
+        keywords,
+        command_line: std::env::args().skip(1).collect::<Vec<String>>().join(" "),
+        mock: matches.is_present("mock"),
+        asyncclient: matches.is_present("async"),
+        constants: matches.is_present("constants"),
+        propstorage: matches.is_present("propstorage"),
     };
 
     let mut h: Box<dyn std::io::Write> = match matches.value_of("output") {
@@ -0,0 +1,134 @@
+// The DbusArg derive macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, parse_quote, Error, DeriveInput, Data, Fields, Field, Meta, NestedMeta, Lit, Type, Ident, Index};
+
+#[derive(Default)]
+struct Opts {
+    flags: bool,
+    lossy: bool,
+    transparent: bool,
+    bits: Option<Ident>,
+}
+
+fn container_opts(item: &DeriveInput) -> Result<Opts, Error> {
+    let mut opts = Opts::default();
+    for attr in item.attrs.iter().filter(|a| a.path.is_ident("dbus")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            x => return Err(Error::new_spanned(x, "expected #[dbus(...)]")),
+        };
+        for n in list.nested {
+            match n {
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("flags") => opts.flags = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("lossy") => opts.lossy = true,
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("transparent") => opts.transparent = true,
+                NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("bits") => match nv.lit {
+                    Lit::Str(ref s) if s.value() == "u32" || s.value() == "u64" => opts.bits = Some(Ident::new(&s.value(), s.span())),
+                    _ => return Err(Error::new_spanned(&nv.lit, "expected \"u32\" or \"u64\"")),
+                },
+                x => return Err(Error::new_spanned(x, "unknown dbus option, expected one of flags, lossy, bits, transparent")),
+            }
+        }
+    }
+    Ok(opts)
+}
+
+// The only field, if there is exactly one.
+fn single_field(item: &DeriveInput) -> Option<&Field> {
+    let fields = match item.data {
+        Data::Struct(ref s) => match s.fields {
+            Fields::Named(ref f) => &f.named,
+            Fields::Unnamed(ref f) => &f.unnamed,
+            Fields::Unit => return None,
+        },
+        _ => return None,
+    };
+    if fields.len() == 1 { fields.first() } else { None }
+}
+
+fn flags_bits(item: &DeriveInput, opts: &Opts) -> Result<Ident, Error> {
+    if let Some(b) = &opts.bits { return Ok(b.clone()) }
+    let mut t = single_field(item).map(|f| &f.ty);
+    // A type from a macro_rules macro, e g bitflags, is wrapped in an invisible group.
+    while let Some(Type::Group(g)) = t { t = Some(&g.elem) }
+    match t {
+        Some(Type::Path(p)) if p.path.is_ident("u32") || p.path.is_ident("u64") => Ok(p.path.get_ident().unwrap().clone()),
+        _ => Err(Error::new_spanned(&item.ident, "cannot tell the size of the flags, add #[dbus(bits = \"u32\")] or #[dbus(bits = \"u64\")]")),
+    }
+}
+
+fn derive_transparent(item: &DeriveInput) -> Result<TokenStream, Error> {
+    let field = single_field(item).ok_or_else(|| Error::new_spanned(&item.ident, "#[dbus(transparent)] needs a struct with exactly one field"))?;
+    let inner = &field.ty;
+    let (access, make) = match &field.ident {
+        Some(name) => (quote!(#name), quote!(|x| Self { #name: x })),
+        None => { let idx = Index::from(0); (quote!(#idx), quote!(Self)) },
+    };
+
+    let ident = &item.ident;
+    let (_, ty_generics, _) = item.generics.split_for_impl();
+    let mut generics = item.generics.clone();
+    generics.make_where_clause().predicates.push(parse_quote!(#inner: dbus::arg::Arg));
+    let (arg_impl_generics, _, arg_where) = generics.split_for_impl();
+    let mut generics = item.generics.clone();
+    generics.make_where_clause().predicates.push(parse_quote!(#inner: dbus::arg::Append));
+    let (append_impl_generics, _, append_where) = generics.split_for_impl();
+    let mut generics = item.generics.clone();
+    generics.params.insert(0, parse_quote!('dbus_a));
+    generics.make_where_clause().predicates.push(parse_quote!(#inner: dbus::arg::Get<'dbus_a>));
+    let (get_impl_generics, _, get_where) = generics.split_for_impl();
+    Ok(quote! {
+        impl #arg_impl_generics dbus::arg::Arg for #ident #ty_generics #arg_where {
+            const ARG_TYPE: dbus::arg::ArgType = <#inner as dbus::arg::Arg>::ARG_TYPE;
+            fn signature() -> dbus::Signature<'static> { <#inner as dbus::arg::Arg>::signature() }
+        }
+
+        impl #append_impl_generics dbus::arg::Append for #ident #ty_generics #append_where {
+            fn append_by_ref(&self, i: &mut dbus::arg::IterAppend) { dbus::arg::Append::append_by_ref(&self.#access, i) }
+        }
+
+        impl #get_impl_generics dbus::arg::Get<'dbus_a> for #ident #ty_generics #get_where {
+            fn get(i: &mut dbus::arg::Iter<'dbus_a>) -> Option<Self> {
+                <#inner as dbus::arg::Get<'dbus_a>>::get(i).map(#make)
+            }
+        }
+    })
+}
+
+pub fn derive_dbus_arg(item: TokenStream) -> Result<TokenStream, Error> {
+    let item: DeriveInput = parse2(item)?;
+    let opts = container_opts(&item)?;
+    match (opts.flags, opts.transparent) {
+        (true, true) => return Err(Error::new_spanned(&item.ident, "flags and transparent cannot be combined")),
+        (false, true) => return derive_transparent(&item),
+        (false, false) => return Err(Error::new_spanned(&item.ident, "expected #[dbus(flags)] or #[dbus(transparent)]")),
+        (true, false) => {},
+    }
+    let bits = flags_bits(&item, &opts)?;
+    let from_bits = if opts.lossy { quote!(Some(Self::from_bits_truncate(b))) } else { quote!(Self::from_bits(b)) };
+
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+    let mut get_generics = item.generics.clone();
+    get_generics.params.insert(0, parse_quote!('dbus_a));
+    let (get_impl_generics, _, _) = get_generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics dbus::arg::Arg for #ident #ty_generics #where_clause {
+            const ARG_TYPE: dbus::arg::ArgType = <#bits as dbus::arg::Arg>::ARG_TYPE;
+            fn signature() -> dbus::Signature<'static> { <#bits as dbus::arg::Arg>::signature() }
+        }
+
+        impl #impl_generics dbus::arg::Append for #ident #ty_generics #where_clause {
+            fn append_by_ref(&self, i: &mut dbus::arg::IterAppend) { i.append(self.bits()) }
+        }
+
+        impl #get_impl_generics dbus::arg::Get<'dbus_a> for #ident #ty_generics #where_clause {
+            fn get(i: &mut dbus::arg::Iter<'dbus_a>) -> Option<Self> {
+                let b: #bits = i.get()?;
+                #from_bits
+            }
+        }
+    })
+}
@@ -0,0 +1,237 @@
+// The dbus_interface attribute macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, Error, LitStr, ItemImpl, ImplItem, ImplItemMethod, FnArg, Pat, Type, ReturnType, PathArguments,
+    GenericArgument, Meta, NestedMeta, Lit, Ident, Signature};
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub (crate) struct Opts {
+    pub skip: bool,
+    pub get: bool,
+    pub set: bool,
+    pub signal: bool,
+    pub name: Option<String>,
+    pub out: Option<Vec<String>>,
+}
+
+pub (crate) fn make_camel(s: &str) -> String {
+    let mut ucase = true;
+    s.chars().filter_map(|c| match c {
+        '_' => { ucase = true; None },
+        c if ucase => { ucase = false; Some(c.to_ascii_uppercase()) },
+        c => Some(c),
+    }).collect()
+}
+
+// Parses the options inside #[dbus(...)] or #[dbus_method(...)].
+pub (crate) fn parse_opts<I: IntoIterator<Item=NestedMeta>>(nested: I, o: &mut Opts) -> Result<(), Error> {
+    for item in nested {
+        match item {
+            NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("skip") => o.skip = true,
+            NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("get") => o.get = true,
+            NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("set") => o.set = true,
+            NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("signal") => o.signal = true,
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("name") || nv.path.is_ident("out") => {
+                let s = match nv.lit { Lit::Str(ref s) => s.value(), _ => return Err(Error::new_spanned(&nv.lit, "expected a string")) };
+                if nv.path.is_ident("name") { o.name = Some(s) }
+                else { o.out = Some(s.split(',').map(|x| x.trim().to_string()).collect()) }
+            },
+            x => return Err(Error::new_spanned(x, "unknown dbus option, expected one of skip, get, set, signal, name, out")),
+        }
+    }
+    Ok(())
+}
+
+// Reads and removes the #[dbus(...)] attributes of a function.
+fn take_opts(m: &mut ImplItemMethod) -> Result<Option<Opts>, Error> {
+    let mut r = None;
+    let mut attrs = vec!();
+    for attr in m.attrs.drain(..) {
+        if !attr.path.is_ident("dbus") { attrs.push(attr); continue; }
+        let o = r.get_or_insert_with(Opts::default);
+        match attr.parse_meta()? {
+            Meta::List(list) => parse_opts(list.nested, o)?,
+            x => return Err(Error::new_spanned(x, "expected #[dbus(...)]")),
+        };
+    }
+    m.attrs = attrs;
+    Ok(r)
+}
+
+// The arguments of the function, except self.
+fn fn_args(sig: &Signature) -> Result<Vec<(Ident, Type)>, Error> {
+    sig.inputs.iter().filter_map(|a| match a {
+        FnArg::Typed(pt) => Some(match *pt.pat {
+            Pat::Ident(ref pi) => Ok((pi.ident.clone(), (*pt.ty).clone())),
+            ref x => Err(Error::new_spanned(x, "expected an argument name")),
+        }),
+        FnArg::Receiver(_) => None,
+    }).collect()
+}
+
+fn has_ref_self(m: &ImplItemMethod) -> bool {
+    match m.sig.inputs.iter().next() {
+        Some(FnArg::Receiver(r)) => r.reference.is_some() && r.mutability.is_none(),
+        _ => false,
+    }
+}
+
+// The T in a Result<T, E> return type.
+fn result_type(sig: &Signature) -> Result<Type, Error> {
+    let err = || Error::new_spanned(sig, "expected the function to return Result<T, E>");
+    let ty = match sig.output { ReturnType::Type(_, ref ty) => ty, _ => return Err(err()) };
+    let seg = match **ty { Type::Path(ref p) => p.path.segments.last().ok_or_else(err)?, _ => return Err(err()) };
+    if seg.ident != "Result" { return Err(err()) };
+    match seg.arguments {
+        PathArguments::AngleBracketed(ref a) => match a.args.iter().next() {
+            Some(GenericArgument::Type(t)) => Ok(t.clone()),
+            _ => Err(err()),
+        },
+        _ => Err(err()),
+    }
+}
+
+// Creates the method. "call" is the function to call, with the arguments read from the message.
+pub (crate) fn method(sig: &Signature, o: &Opts, call: TokenStream) -> Result<TokenStream, Error> {
+    let dname = o.name.clone().unwrap_or_else(|| make_camel(&sig.ident.to_string()));
+    let args = fn_args(sig)?;
+    let (anames, atypes): (Vec<_>, Vec<_>) = args.into_iter().unzip();
+    let astrs: Vec<_> = anames.iter().map(|a| a.to_string()).collect();
+
+    let rtype = result_type(sig)?;
+    let outs: Vec<Type> = match rtype {
+        Type::Tuple(ref t) => t.elems.iter().cloned().collect(),
+        t => vec!(t),
+    };
+    let onames = o.out.clone().unwrap_or_else(|| if outs.len() == 1 { vec!("result".into()) }
+        else { (0..outs.len()).map(|i| format!("result{}", i)).collect() });
+    if onames.len() != outs.len() {
+        return Err(Error::new_spanned(sig, format!("expected {} output argument names", outs.len())));
+    }
+    let appends: Vec<_> = if outs.len() == 1 { vec!(quote!(r)) }
+        else { (0..outs.len()).map(|i| { let i = syn::Index::from(i); quote!(r.#i) }).collect() };
+
+    Ok(quote! {
+        f.method_sync(#dname, Default::default(), move |m| {
+            let mut args = m.msg.iter_init();
+            #( let #anames: #atypes = args.read()?; )*
+            let r = #call(#(#anames),*)?;
+            let rm = m.msg.method_return();
+            #( let rm = rm.append1(#appends); )*
+            Ok(rm.into())
+        }) #( .inarg::<#atypes, _>(#astrs) )* #( .outarg::<#outs, _>(#onames) )*
+    })
+}
+
+fn signal(m: &mut ImplItemMethod, o: &Opts, iface: &LitStr) -> Result<TokenStream, Error> {
+    let dname = o.name.clone().unwrap_or_else(|| make_camel(&m.sig.ident.to_string()));
+    let args = fn_args(&m.sig)?;
+    let (anames, atypes): (Vec<_>, Vec<_>) = args.into_iter().unzip();
+    let astrs: Vec<_> = anames.iter().map(|a| a.to_string()).collect();
+    if !m.block.stmts.is_empty() { return Err(Error::new_spanned(&m.block, "expected an empty body for a signal")) };
+
+    let path: FnArg = syn::parse_quote!(path: &dbus::Path);
+    m.sig.inputs.insert(1, path);
+    m.sig.output = syn::parse_quote!(-> dbus::Message);
+    m.block = syn::parse_quote!({
+        dbus::Message::signal(path, &#iface.into(), &#dname.into()) #( .append1(#anames) )*
+    });
+
+    Ok(quote! {
+        i = i.add_s(f.signal(#dname, Default::default()) #( .sarg::<#atypes, _>(#astrs) )* );
+    })
+}
+
+#[derive(Default)]
+struct Prop {
+    ty: Option<Type>,
+    get: Option<Ident>,
+    set: Option<Ident>,
+}
+
+fn property(p: &Prop, pname: &str) -> TokenStream {
+    let ty = p.ty.as_ref().unwrap();
+    let (get, set) = (&p.get, &p.set);
+    let access = match (get.is_some(), set.is_some()) {
+        (true, true) => quote!(ReadWrite),
+        (false, true) => quote!(Write),
+        _ => quote!(Read),
+    };
+    let get = get.iter().map(|g| quote! {
+        let p = { let this = this.clone(); p.on_get_sync(move |a, _| { a.append(this.#g()?); Ok(()) }) };
+    });
+    let set = set.iter().map(|s| quote! {
+        let p = { let this = this.clone(); p.on_set_sync(move |a, _| { this.#s(a.read()?)?; Ok(()) }) };
+    });
+    let emits = if p.get.is_none() { quote!(.emits_changed(dbus::tree::EmitsChangedSignal::Invalidates)) } else { quote!() };
+    quote! {
+        {
+            let p = f.property::<#ty, _>(#pname, Default::default()).access(dbus::tree::Access::#access) #emits;
+            #( #get )*
+            #( #set )*
+            i = i.add_p(p);
+        }
+    }
+}
+
+pub fn dbus_interface(attr: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let iface: LitStr = parse2(attr).map_err(|e| Error::new(e.span(), "expected the interface name, e g #[dbus_interface(\"com.example.Foo\")]"))?;
+    let mut item: ItemImpl = parse2(item)?;
+    let mut members = vec!();
+    let mut props: BTreeMap<String, Prop> = BTreeMap::new();
+
+    for ii in item.items.iter_mut() {
+        let m = if let ImplItem::Method(m) = ii { m } else { continue };
+        let opts = take_opts(m)?;
+        if !has_ref_self(m) {
+            if opts.is_some() { return Err(Error::new_spanned(&m.sig, "expected the function to take &self")) };
+            continue;
+        }
+        let o = opts.unwrap_or_default();
+        if o.skip { continue; }
+        if o.signal { members.push(signal(m, &o, &iface)?); }
+        else if o.get || o.set {
+            let fname = m.sig.ident.to_string();
+            let (pname, ty) = if o.get { (fname.as_str(), result_type(&m.sig)?) } else {
+                let mut a = fn_args(&m.sig)?;
+                if a.len() != 1 { return Err(Error::new_spanned(&m.sig, "expected a property setter to take one argument")) };
+                (fname.trim_start_matches("set_"), a.remove(0).1)
+            };
+            let pname = o.name.clone().unwrap_or_else(|| make_camel(pname));
+            let p = props.entry(pname).or_insert_with(Default::default);
+            if o.get { p.get = Some(m.sig.ident.clone()) } else { p.set = Some(m.sig.ident.clone()) };
+            // The getter's type takes precedence, since the setter might take a borrowed type.
+            if o.get || p.ty.is_none() { p.ty = Some(ty) };
+        }
+        else {
+            let fname = &m.sig.ident;
+            let me = method(&m.sig, &o, quote!(this.#fname))?;
+            members.push(quote!({ let this = this.clone(); i = i.add_m(#me); }));
+        }
+    }
+    members.extend(props.iter().map(|(pname, p)| property(p, pname)));
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #self_ty #where_clause {
+            /// The name of the D-Bus interface.
+            pub const DBUS_INTERFACE: &'static str = #iface;
+
+            /// Creates a D-Bus interface, which calls this object's methods.
+            pub fn dbus_interface<M, D>(this: &std::sync::Arc<Self>, f: &dbus::tree::Factory<M, D>) -> dbus::tree::Interface<M, D>
+            where M: dbus::tree::MethodType<D>, D: dbus::tree::DataType, D::Interface: Default, D::Method: Default,
+                D::Property: Default, D::Signal: Default, Self: Send + Sync + 'static {
+                #[allow(unused_mut)]
+                let mut i = f.interface(#iface, Default::default());
+                #( #members )*
+                let _ = this;
+                i
+            }
+        }
+    })
+}
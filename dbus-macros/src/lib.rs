@@ -0,0 +1,254 @@
+//! Procedural macros for the dbus crate.
+//!
+//! # Example
+//! ```rust,no_run
+//! use dbus::tree::{Factory, MethodErr};
+//! use dbus_macros::dbus_interface;
+//! use std::sync::{Arc, Mutex};
+//!
+//! #[derive(Default)]
+//! struct Counter { count: Mutex<u32> }
+//!
+//! #[dbus_interface("com.example.Counter")]
+//! impl Counter {
+//!     fn add(&self, amount: u32) -> Result<u32, MethodErr> {
+//!         let mut c = self.count.lock().unwrap();
+//!         *c += amount;
+//!         Ok(*c)
+//!     }
+//!
+//!     #[dbus(get)]
+//!     fn count(&self) -> Result<u32, MethodErr> { Ok(*self.count.lock().unwrap()) }
+//!
+//!     #[dbus(signal)]
+//!     fn overflowed(&self, count: u32) {}
+//! }
+//!
+//! let f = Factory::new_sync::<()>();
+//! let counter = Arc::new(Counter::default());
+//! let t = f.tree(()).add(f.object_path("/counter", ()).introspectable()
+//!     .add(Counter::dbus_interface(&counter, &f)));
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+
+mod arg;
+mod interface;
+mod method;
+mod names;
+mod propmap;
+mod signal;
+
+/// Turns an impl block into a D-Bus interface.
+///
+/// The attribute takes the interface name as argument, and adds a `dbus_interface` function
+/// to the struct, which builds a `tree::Interface` with the struct's methods, properties and signals.
+/// The struct must be `Send + Sync + 'static`, and is called through an `Arc`.
+///
+/// Every function in the impl block taking `&self` becomes a D-Bus method, named as the
+/// function in CamelCase. The arguments are read from the incoming method call, and the function
+/// must return a `Result` where the error type converts into a `MethodErr`. A tuple return value
+/// is returned as several output arguments.
+///
+/// The behaviour can be changed with `#[dbus(...)]` attributes on the functions:
+///
+///  * `#[dbus(skip)]` - do not expose this function on D-Bus.
+///  * `#[dbus(name = "Foo")]` - use a different D-Bus name.
+///  * `#[dbus(out = "a, b")]` - names of the output arguments (default: "result").
+///  * `#[dbus(get)]` - a property getter, i e `fn foo(&self) -> Result<T, MethodErr>`.
+///  * `#[dbus(set)]` - a property setter, i e `fn set_foo(&self, value: T) -> Result<(), MethodErr>`.
+///    Getters and setters with the same property name are combined into one property.
+///  * `#[dbus(signal)]` - a signal. The function should have an empty body; it is rewritten
+///    into a function that takes the object path as an additional first argument,
+///    and returns the signal Message, ready to be sent.
+#[proc_macro_attribute]
+pub fn dbus_interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    interface::dbus_interface(attr.into(), item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Turns a function into a D-Bus method handler.
+///
+/// This adds a function named as the original function with a `_method` suffix, which takes
+/// a `tree::Factory` and creates a `tree::Method`. The input arguments of the method are named
+/// and typed after the arguments of the function, so that introspection data is accurate without
+/// having to add every argument manually. Otherwise, the same rules as for methods in
+/// `dbus_interface` apply, and the `name` and `out` options are supported, e g
+/// `#[dbus_method(name = "Hello", out = "greeting")]`.
+///
+/// # Example
+/// ```rust
+/// use dbus::tree::{Factory, MethodErr};
+/// use dbus_macros::dbus_method;
+///
+/// #[dbus_method(out = "greeting")]
+/// fn hello(name: &str) -> Result<String, MethodErr> { Ok(format!("Hello {}!", name)) }
+///
+/// let f = Factory::new_fn::<()>();
+/// let i = f.interface("com.example.Greeter", ()).add_m(hello_method(&f));
+/// ```
+#[proc_macro_attribute]
+pub fn dbus_method(attr: TokenStream, item: TokenStream) -> TokenStream {
+    method::dbus_method(attr.into(), item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Derives `SignalArgs`, so that a struct can be used as a typed signal.
+///
+/// The fields of the struct are the arguments of the signal, in order. The interface name
+/// is given with `#[dbus(interface = "...")]`, and the signal name defaults to the name of
+/// the struct, but can be changed with `#[dbus(name = "...")]`. `AppendAll` and `ReadAll`
+/// are derived as well, so the signal can be emitted with `to_emit_message` and parsed
+/// from an incoming message with `from_message`.
+///
+/// # Example
+/// ```rust
+/// use dbus::message::SignalArgs;
+/// use dbus_macros::SignalArgs;
+///
+/// #[derive(SignalArgs, Debug, PartialEq)]
+/// #[dbus(interface = "com.example.Counter")]
+/// struct Overflowed { count: u32, message: String }
+///
+/// let s = Overflowed { count: 7, message: "Too many".into() };
+/// let m = s.to_emit_message(&"/counter".into());
+/// assert_eq!(Overflowed::from_message(&m), Some(s));
+/// ```
+#[proc_macro_derive(SignalArgs, attributes(dbus))]
+pub fn derive_signal_args(item: TokenStream) -> TokenStream {
+    signal::derive_signal_args(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Derives `FromPropMap`, so that all properties of an interface can be read into a struct.
+///
+/// Every field is read from the property with the same name as the field, in CamelCase.
+/// A field of type `Option<T>` is `None` if the property is missing; any other missing property,
+/// or a property of another type than its field, is an error. Properties without a field are ignored.
+///
+/// The behaviour can be changed with `#[dbus(...)]` attributes on the fields:
+///
+///  * `#[dbus(name = "Foo")]` - read the field from a property with a different name.
+///  * `#[dbus(skip)]` - do not read this field; it is set to its default value.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::arg::FromPropMap;
+/// use dbus::blocking::Connection;
+/// use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+/// use dbus_macros::FromPropMap;
+/// use std::time::Duration;
+///
+/// #[derive(FromPropMap, Debug)]
+/// struct Battery {
+///     percentage: f64,
+///     #[dbus(name = "IconName")]
+///     icon: String,
+///     vendor: Option<String>,
+/// }
+///
+/// let c = Connection::new_system().unwrap();
+/// let p = c.with_proxy("org.freedesktop.UPower", "/org/freedesktop/UPower/devices/DisplayDevice", Duration::from_secs(5));
+/// let b = Battery::from_prop_map(&p.get_all("org.freedesktop.UPower.Device").unwrap()).unwrap();
+/// println!("{:?}", b);
+/// ```
+#[proc_macro_derive(FromPropMap, attributes(dbus))]
+pub fn derive_from_prop_map(item: TokenStream) -> TokenStream {
+    propmap::derive_from_prop_map(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Derives `Arg`, `Append` and `Get`, so that a type can be used as a D-Bus argument.
+///
+/// With `#[dbus(transparent)]`, the type is a struct with a single field, which is sent as the
+/// type of that field. This is useful for domain types such as `struct DeviceId(String)`.
+///
+/// With `#[dbus(flags)]`, the type is a set of flags in the style of the `bitflags` crate, i e it has
+/// `bits`, `from_bits` and `from_bits_truncate` functions. It is sent as its bits, as a `u` or `t`.
+/// The size is taken from the type of the only field, or can be given with `#[dbus(bits = "u32")]`
+/// or `#[dbus(bits = "u64")]`. When received, unknown bits are a type mismatch error, unless
+/// `#[dbus(lossy)]` is given, in which case they are dropped.
+///
+/// # Example
+/// ```rust
+/// use dbus::Message;
+/// use dbus_macros::DbusArg;
+///
+/// #[derive(DbusArg, Debug, Clone, Copy, PartialEq)]
+/// #[dbus(flags)]
+/// struct MountFlags { bits: u32 }
+///
+/// impl MountFlags {
+///     const READ_ONLY: MountFlags = MountFlags { bits: 1 };
+///     fn bits(&self) -> u32 { self.bits }
+///     fn from_bits(bits: u32) -> Option<Self> { if bits & !1 == 0 { Some(MountFlags { bits }) } else { None } }
+///     fn from_bits_truncate(bits: u32) -> Self { MountFlags { bits: bits & 1 } }
+/// }
+///
+/// #[derive(DbusArg, Debug, PartialEq)]
+/// #[dbus(transparent)]
+/// struct DeviceId(String);
+///
+/// let m = Message::new_signal("/", "com.example.Mounts", "Mounted").unwrap()
+///     .append2(DeviceId("sda1".into()), MountFlags::READ_ONLY);
+/// assert_eq!(m.read2::<DeviceId, MountFlags>().unwrap(), (DeviceId("sda1".into()), MountFlags::READ_ONLY));
+/// ```
+#[proc_macro_derive(DbusArg, attributes(dbus))]
+pub fn derive_dbus_arg(item: TokenStream) -> TokenStream {
+    arg::derive_dbus_arg(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Makes an `Interface<'static>` from a string literal, which is checked at compile time.
+///
+/// # Example
+/// ```rust
+/// use dbus_macros::interface;
+///
+/// let i = interface!("com.example.Counter");
+/// assert_eq!(&*i, "com.example.Counter");
+/// ```
+///
+/// Malformed names are compile errors:
+/// ```compile_fail
+/// let i = dbus_macros::interface!("Counter");
+/// ```
+#[proc_macro]
+pub fn interface(item: TokenStream) -> TokenStream {
+    names::interface(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Makes a `Member<'static>` from a string literal, which is checked at compile time.
+///
+/// # Example
+/// ```rust
+/// use dbus_macros::member;
+///
+/// let m = member!("Add");
+/// assert_eq!(&*m, "Add");
+/// ```
+///
+/// Malformed names are compile errors:
+/// ```compile_fail
+/// let m = dbus_macros::member!("com.example.Add");
+/// ```
+#[proc_macro]
+pub fn member(item: TokenStream) -> TokenStream {
+    names::member(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Makes a `Path<'static>` from a string literal, which is checked at compile time.
+///
+/// # Example
+/// ```rust
+/// use dbus_macros::path;
+///
+/// let p = path!("/com/example/counter");
+/// assert_eq!(&*p, "/com/example/counter");
+/// ```
+///
+/// Malformed paths are compile errors:
+/// ```compile_fail
+/// let p = dbus_macros::path!("/com/example/");
+/// ```
+#[proc_macro]
+pub fn path(item: TokenStream) -> TokenStream {
+    names::path(item.into()).unwrap_or_else(|e| e.to_compile_error()).into()
+}
@@ -0,0 +1,35 @@
+// The dbus_method attribute macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, Error, ItemFn, NestedMeta, Ident, Token};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use crate::interface::{Opts, parse_opts, method};
+
+pub fn dbus_method(attr: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let nested = Punctuated::<NestedMeta, Token![,]>::parse_terminated.parse2(attr)?;
+    let mut o = Opts::default();
+    parse_opts(nested, &mut o)?;
+    let item: ItemFn = parse2(item)?;
+    if o.skip || o.get || o.set || o.signal {
+        return Err(Error::new_spanned(&item.sig, "only the name and out options are supported by dbus_method"));
+    }
+    if !item.sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(&item.sig.generics, "a dbus_method cannot be generic"));
+    }
+    let fname = &item.sig.ident;
+    let me = method(&item.sig, &o, quote!(#fname))?;
+    let mname = Ident::new(&format!("{}_method", fname), fname.span());
+    let vis = &item.vis;
+    let doc = format!("Creates a D-Bus method, which calls `{}`.", fname);
+    Ok(quote! {
+        #item
+
+        #[doc = #doc]
+        #vis fn #mname<M, D>(f: &dbus::tree::Factory<M, D>) -> dbus::tree::Method<M, D>
+        where M: dbus::tree::MethodType<D>, D: dbus::tree::DataType, D::Method: Default {
+            #me
+        }
+    })
+}
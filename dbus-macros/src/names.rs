@@ -0,0 +1,48 @@
+// The interface!, member! and path! macros.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, Error, LitStr, LitByteStr};
+
+fn is_element(s: &str) -> bool { !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') }
+
+fn check_member(s: &str) -> Result<(), &'static str> {
+    if s.len() > 255 { return Err("longer than 255 characters") }
+    if !is_element(s) { return Err("must be non-empty and only contain the characters \"[A-Z][a-z][0-9]_\"") }
+    if s.as_bytes()[0].is_ascii_digit() { return Err("must not begin with a digit") }
+    Ok(())
+}
+
+fn check_interface(s: &str) -> Result<(), &'static str> {
+    if s.len() > 255 { return Err("longer than 255 characters") }
+    let mut count = 0;
+    for e in s.split('.') {
+        check_member(e).map_err(|_| "each element must be non-empty, only contain the characters \"[A-Z][a-z][0-9]_\" and not begin with a digit")?;
+        count += 1;
+    }
+    if count < 2 { return Err("must contain at least one '.'") }
+    Ok(())
+}
+
+fn check_path(s: &str) -> Result<(), &'static str> {
+    if s == "/" { return Ok(()) }
+    if !s.starts_with('/') { return Err("must begin with '/'") }
+    if !s[1..].split('/').all(is_element) { return Err("each element must be non-empty and only contain the characters \"[A-Z][a-z][0-9]_\"") }
+    Ok(())
+}
+
+fn expand(item: TokenStream, ty: TokenStream, what: &str, check: fn(&str) -> Result<(), &'static str>) -> Result<TokenStream, Error> {
+    let lit: LitStr = parse2(item)?;
+    let s = lit.value();
+    check(&s).map_err(|e| Error::new(lit.span(), format!("{} was not valid: '{}' ({})", what, s, e)))?;
+    let mut b = s.into_bytes();
+    b.push(0);
+    let b = LitByteStr::new(&b, lit.span());
+    Ok(quote!(unsafe { #ty::from_slice_unchecked(#b) }))
+}
+
+pub fn interface(item: TokenStream) -> Result<TokenStream, Error> { expand(item, quote!(dbus::strings::Interface), "Interface name", check_interface) }
+
+pub fn member(item: TokenStream) -> Result<TokenStream, Error> { expand(item, quote!(dbus::strings::Member), "Member name", check_member) }
+
+pub fn path(item: TokenStream) -> Result<TokenStream, Error> { expand(item, quote!(dbus::strings::Path), "Object path", check_path) }
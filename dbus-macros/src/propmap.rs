@@ -0,0 +1,66 @@
+// The FromPropMap derive macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, Error, DeriveInput, Data, Fields, Meta, NestedMeta, Lit, Type, Attribute};
+use crate::interface::make_camel;
+
+// The #[dbus(...)] options of a field: Some(name) for the property name, None to skip it.
+fn field_opts(attrs: &[Attribute], default: String) -> Result<Option<String>, Error> {
+    let mut name = Some(default);
+    for attr in attrs.iter().filter(|a| a.path.is_ident("dbus")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            x => return Err(Error::new_spanned(x, "expected #[dbus(...)]")),
+        };
+        for n in list.nested {
+            match n {
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("skip") => name = None,
+                NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("name") => match nv.lit {
+                    Lit::Str(ref s) => name = Some(s.value()),
+                    _ => return Err(Error::new_spanned(&nv.lit, "expected a string")),
+                },
+                x => return Err(Error::new_spanned(x, "unknown dbus option, expected one of name, skip")),
+            }
+        }
+    }
+    Ok(name)
+}
+
+fn is_option(t: &Type) -> bool {
+    match t {
+        Type::Path(p) if p.qself.is_none() => p.path.segments.last().filter(|s| s.ident == "Option").is_some(),
+        _ => false,
+    }
+}
+
+pub fn derive_from_prop_map(item: TokenStream) -> Result<TokenStream, Error> {
+    let item: DeriveInput = parse2(item)?;
+    let fields = match item.data {
+        Data::Struct(ref s) => match s.fields {
+            Fields::Named(ref f) => &f.named,
+            _ => return Err(Error::new_spanned(&item.ident, "FromPropMap can only be derived for structs with named fields")),
+        },
+        _ => return Err(Error::new_spanned(&item.ident, "FromPropMap can only be derived for structs")),
+    };
+    let mut reads = vec!();
+    for f in fields {
+        let ident = f.ident.as_ref().unwrap();
+        let read = match field_opts(&f.attrs, make_camel(&ident.to_string()))? {
+            None => quote!(Default::default()),
+            Some(name) if is_option(&f.ty) => quote!(dbus::arg::PropMapExt::get_typed_opt(map, #name)?),
+            Some(name) => quote!(dbus::arg::PropMapExt::get_typed(map, #name)?),
+        };
+        reads.push(quote!(#ident: #read));
+    }
+
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics dbus::arg::FromPropMap for #ident #ty_generics #where_clause {
+            fn from_prop_map(map: &dbus::arg::PropMap) -> Result<Self, dbus::Error> {
+                Ok(#ident { #( #reads, )* })
+            }
+        }
+    })
+}
@@ -0,0 +1,63 @@
+// The SignalArgs derive macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse2, Error, DeriveInput, Data, Fields, Meta, NestedMeta, Lit, Index};
+
+pub fn derive_signal_args(item: TokenStream) -> Result<TokenStream, Error> {
+    let item: DeriveInput = parse2(item)?;
+    let (mut iface, mut name) = (None, None);
+    for attr in item.attrs.iter().filter(|a| a.path.is_ident("dbus")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            x => return Err(Error::new_spanned(x, "expected #[dbus(...)]")),
+        };
+        for n in list.nested {
+            match n {
+                NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("interface") || nv.path.is_ident("name") => {
+                    let s = match nv.lit { Lit::Str(ref s) => s.clone(), _ => return Err(Error::new_spanned(&nv.lit, "expected a string")) };
+                    if nv.path.is_ident("interface") { iface = Some(s) } else { name = Some(s) }
+                },
+                x => return Err(Error::new_spanned(x, "unknown dbus option, expected one of interface, name")),
+            }
+        }
+    }
+    let iface = iface.ok_or_else(|| Error::new_spanned(&item.ident, "expected #[dbus(interface = \"...\")]"))?;
+    let name = name.map(|n| n.value()).unwrap_or_else(|| item.ident.to_string());
+
+    let fields = match item.data {
+        Data::Struct(ref s) => &s.fields,
+        _ => return Err(Error::new_spanned(&item.ident, "SignalArgs can only be derived for structs")),
+    };
+    let (appends, read) = match fields {
+        Fields::Named(ref f) => {
+            let names: Vec<_> = f.named.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+            (quote!( #( dbus::arg::Append::append_by_ref(&self.#names, i); )* ), quote!({ #( #names: i.read()?, )* }))
+        },
+        Fields::Unnamed(ref f) => {
+            let idx: Vec<_> = (0..f.unnamed.len()).map(Index::from).collect();
+            let reads = idx.iter().map(|_| quote!(i.read()?));
+            (quote!( #( dbus::arg::Append::append_by_ref(&self.#idx, i); )* ), quote!(( #( #reads, )* )))
+        },
+        Fields::Unit => (quote!(), quote!()),
+    };
+
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics dbus::arg::AppendAll for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn append(&self, i: &mut dbus::arg::IterAppend) { #appends }
+        }
+
+        impl #impl_generics dbus::arg::ReadAll for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn read(i: &mut dbus::arg::Iter) -> Result<Self, dbus::arg::TypeMismatchError> { Ok(#ident #read) }
+        }
+
+        impl #impl_generics dbus::message::SignalArgs for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const INTERFACE: &'static str = #iface;
+        }
+    })
+}
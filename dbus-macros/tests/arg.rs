@@ -0,0 +1,94 @@
+use dbus::arg::{Arg, ArgType};
+use dbus::Message;
+use dbus_macros::DbusArg;
+
+bitflags::bitflags! {
+    #[derive(DbusArg)]
+    #[dbus(flags)]
+    struct MountFlags: u32 {
+        const READ_ONLY = 1;
+        const NO_EXEC = 4;
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(DbusArg)]
+    #[dbus(flags, lossy)]
+    struct Capabilities: u64 {
+        const AUDIO = 1 << 40;
+        const VIDEO = 2;
+    }
+}
+
+fn message() -> Message { Message::new_signal("/", "com.example.test", "Test").unwrap() }
+
+#[test]
+fn flags() {
+    assert_eq!(MountFlags::ARG_TYPE, ArgType::UInt32);
+    assert_eq!(&*MountFlags::signature(), "u");
+    assert_eq!(&*Capabilities::signature(), "t");
+
+    let m = message().append2(MountFlags::READ_ONLY | MountFlags::NO_EXEC, Capabilities::AUDIO);
+    assert_eq!(m.get2::<u32, u64>(), (Some(5), Some(1 << 40)));
+    let (f, c): (MountFlags, Capabilities) = m.read2().unwrap();
+    assert_eq!(f, MountFlags::READ_ONLY | MountFlags::NO_EXEC);
+    assert_eq!(c, Capabilities::AUDIO);
+
+    // Unknown bits
+    let m = message().append2(7u32, 7u64);
+    assert!(m.read1::<MountFlags>().is_err());
+    assert_eq!(m.get2::<u32, Capabilities>().1, Some(Capabilities::VIDEO));
+    assert!(m.read1::<Vec<MountFlags>>().is_err());
+
+    let m = message().append1(vec!(MountFlags::NO_EXEC, MountFlags::empty()));
+    assert_eq!(m.read1::<Vec<MountFlags>>().unwrap(), vec!(MountFlags::NO_EXEC, MountFlags::empty()));
+}
+
+#[derive(DbusArg, Debug, Clone, Copy, PartialEq)]
+#[dbus(flags, bits = "u64")]
+struct Wrapped(Capabilities);
+
+impl Wrapped {
+    fn bits(&self) -> u64 { self.0.bits() }
+    fn from_bits(b: u64) -> Option<Self> { Capabilities::from_bits(b).map(Wrapped) }
+}
+
+#[test]
+fn explicit_bits() {
+    let m = message().append1(Wrapped(Capabilities::VIDEO));
+    assert_eq!(&*m.iter_init().signature(), "t");
+    assert_eq!(m.read1::<Wrapped>().unwrap(), Wrapped(Capabilities::VIDEO));
+}
+
+#[derive(DbusArg, Debug, Clone, PartialEq)]
+#[dbus(transparent)]
+struct DeviceId(String);
+
+#[derive(DbusArg, Debug, Clone, Copy, PartialEq)]
+#[dbus(transparent)]
+struct Percent { value: u8 }
+
+#[derive(DbusArg, Debug, PartialEq)]
+#[dbus(transparent)]
+struct Label<'a>(&'a str);
+
+#[derive(DbusArg, Debug, PartialEq)]
+#[dbus(transparent)]
+struct Tagged<T>(Vec<T>);
+
+#[test]
+fn transparent() {
+    assert_eq!(&*DeviceId::signature(), "s");
+    assert_eq!(Percent::ARG_TYPE, ArgType::Byte);
+    assert_eq!(&*Tagged::<DeviceId>::signature(), "as");
+
+    let m = message().append3(DeviceId("sda1".into()), Percent { value: 80 }, Label("disk"))
+        .append1(Tagged(vec!(DeviceId("a".into()), DeviceId("b".into()))));
+    assert_eq!(m.get2::<String, u8>(), (Some("sda1".into()), Some(80)));
+    let (d, p, l, t): (DeviceId, Percent, Label, Tagged<DeviceId>) = m.read4().unwrap();
+    assert_eq!(d, DeviceId("sda1".into()));
+    assert_eq!(p, Percent { value: 80 });
+    assert_eq!(l, Label("disk"));
+    assert_eq!(t.0, vec!(DeviceId("a".into()), DeviceId("b".into())));
+    assert!(m.read1::<Percent>().is_err());
+}
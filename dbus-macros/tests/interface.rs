@@ -0,0 +1,102 @@
+use dbus::blocking::{Connection, LocalConnection};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, Introspectable};
+use dbus::tree::{Factory, MethodErr};
+use dbus_macros::dbus_interface;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+
+#[derive(Default, Debug)]
+struct Counter {
+    count: Mutex<u32>,
+    label: Mutex<String>,
+}
+
+#[dbus_interface("com.example.dbusmacros.Counter")]
+impl Counter {
+    fn add(&self, amount: u32) -> Result<u32, MethodErr> {
+        let mut c = self.count.lock().unwrap();
+        *c += amount;
+        Ok(*c)
+    }
+
+    #[dbus(name = "Split", out = "high, low")]
+    fn split_count(&self, shift: u8) -> Result<(u32, u32), MethodErr> {
+        let c = *self.count.lock().unwrap();
+        Ok((c >> shift, c & ((1 << shift) - 1)))
+    }
+
+    fn fail(&self) -> Result<(), MethodErr> { Err(MethodErr::failed(&"Oops")) }
+
+    #[dbus(get)]
+    fn count(&self) -> Result<u32, MethodErr> { Ok(*self.count.lock().unwrap()) }
+
+    #[dbus(get)]
+    fn label(&self) -> Result<String, MethodErr> { Ok(self.label.lock().unwrap().clone()) }
+
+    #[dbus(set)]
+    fn set_label(&self, value: &str) -> Result<(), MethodErr> { *self.label.lock().unwrap() = value.into(); Ok(()) }
+
+    #[dbus(signal)]
+    fn overflowed(&self, count: u32, message: &str) {}
+
+    #[dbus(skip)]
+    #[allow(dead_code)]
+    fn helper(&self) -> u32 { 5 }
+}
+
+#[test]
+fn signal() {
+    let c = Counter::default();
+    let m = c.overflowed(&"/counter".into(), 7, "Too many");
+    assert_eq!(&*m.interface().unwrap(), Counter::DBUS_INTERFACE);
+    assert_eq!(&*m.member().unwrap(), "Overflowed");
+    assert_eq!(m.get2(), (Some(7u32), Some("Too many")));
+}
+
+#[test]
+fn counter() {
+    let f = Factory::new_fn::<()>();
+    let c = Arc::new(Counter::default());
+    let tree = f.tree(()).add(f.object_path("/counter", ()).introspectable().add(Counter::dbus_interface(&c, &f)));
+    let mut server = LocalConnection::new_session().unwrap();
+    let name = server.unique_name().to_string();
+    tree.start_receive(&server);
+
+    let (tx, rx) = mpsc::channel();
+    let t = std::thread::spawn(move || {
+        let client = Connection::new_session().unwrap();
+        let p = client.with_proxy(name, "/counter", Duration::from_secs(5));
+        let iface = Counter::DBUS_INTERFACE;
+        let (r,): (u32,) = p.method_call(iface, "Add", (5u32,)).unwrap();
+        assert_eq!(r, 5);
+        let (r,): (u32,) = p.method_call(iface, "Add", (6u32,)).unwrap();
+        assert_eq!(r, 11);
+        let r: (u32, u32) = p.method_call(iface, "Split", (2u8,)).unwrap();
+        assert_eq!(r, (2, 3));
+        let e = p.method_call::<(), _, _, _>(iface, "Fail", ()).unwrap_err();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.Failed"));
+
+        assert_eq!(p.get::<u32>(iface, "Count").unwrap(), 11);
+        p.set(iface, "Label", "Hello").unwrap();
+        assert_eq!(p.get::<String>(iface, "Label").unwrap(), "Hello");
+        assert!(p.set(iface, "Count", 5u32).is_err());
+
+        let x = p.introspect().unwrap();
+        assert!(x.contains(r#"<method name="Split">
+      <arg name="shift" type="y" direction="in"/>
+      <arg name="high" type="u" direction="out"/>
+      <arg name="low" type="u" direction="out"/>
+    </method>"#));
+        assert!(x.contains(r#"<property name="Label" type="s" access="readwrite"/>"#));
+        assert!(x.contains(r#"<arg name="message" type="s"/>"#));
+        assert!(!x.contains("Helper"));
+        tx.send(()).unwrap();
+    });
+
+    while rx.try_recv().is_err() {
+        server.process(Duration::from_millis(100)).unwrap();
+        if t.is_finished() { break; }
+    }
+    t.join().unwrap();
+    assert_eq!(*c.label.lock().unwrap(), "Hello");
+}
@@ -0,0 +1,63 @@
+use dbus::blocking::{Connection, LocalConnection};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Introspectable;
+use dbus::tree::{Factory, MethodErr};
+use dbus_macros::dbus_method;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[dbus_method(out = "greeting")]
+fn hello(name: &str, times: u32) -> Result<String, MethodErr> {
+    if times == 0 { return Err(MethodErr::invalid_arg(&times)) }
+    Ok(format!("Hello {}!", name).repeat(times as usize))
+}
+
+#[dbus_method(name = "MinMax")]
+pub fn min_max(values: Vec<i32>) -> Result<(i32, i32), MethodErr> {
+    let min = values.iter().min().ok_or_else(|| MethodErr::invalid_arg(&values))?;
+    Ok((*min, *values.iter().max().unwrap()))
+}
+
+#[test]
+fn free_fn_methods() {
+    let m = hello_method(&Factory::new_sync::<()>());
+    assert_eq!(&**m.get_name(), "Hello");
+
+    let f = Factory::new_fn::<()>();
+    let tree = f.tree(()).add(f.object_path("/test", ()).introspectable()
+        .add(f.interface("com.example.dbusmacros.Test", ()).add_m(hello_method(&f)).add_m(min_max_method(&f))));
+    let mut server = LocalConnection::new_session().unwrap();
+    let name = server.unique_name().to_string();
+    tree.start_receive(&server);
+
+    let (tx, rx) = mpsc::channel();
+    let t = std::thread::spawn(move || {
+        let client = Connection::new_session().unwrap();
+        let p = client.with_proxy(name, "/test", Duration::from_secs(5));
+        let iface = "com.example.dbusmacros.Test";
+        let (r,): (String,) = p.method_call(iface, "Hello", ("world", 2u32)).unwrap();
+        assert_eq!(r, "Hello world!Hello world!");
+        let e = p.method_call::<(String,), _, _, _>(iface, "Hello", ("world", 0u32)).unwrap_err();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+        let r: (i32, i32) = p.method_call(iface, "MinMax", (vec!(5i32, -3, 8),)).unwrap();
+        assert_eq!(r, (-3, 8));
+
+        let x = p.introspect().unwrap();
+        assert!(x.contains(r#"<method name="Hello">
+      <arg name="name" type="s" direction="in"/>
+      <arg name="times" type="u" direction="in"/>
+      <arg name="greeting" type="s" direction="out"/>
+    </method>"#), "{}", x);
+        assert!(x.contains(r#"<method name="MinMax">
+      <arg name="values" type="ai" direction="in"/>
+      <arg name="result0" type="i" direction="out"/>
+      <arg name="result1" type="i" direction="out"/>
+    </method>"#), "{}", x);
+        tx.send(()).unwrap();
+    });
+
+    while rx.try_recv().is_err() {
+        server.process(Duration::from_millis(100)).unwrap();
+        if t.is_finished() { break; }
+    }
+    t.join().unwrap();
+}
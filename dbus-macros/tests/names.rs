@@ -0,0 +1,18 @@
+use dbus::strings::{Interface, Member, Path};
+use dbus::Message;
+use dbus_macros::{interface, member, path};
+
+#[test]
+fn valid_names() {
+    let i: Interface<'static> = interface!("com.example.dbusmacros.Names");
+    let m: Member<'static> = member!("Hello_2");
+    let p: Path<'static> = path!("/com/example/dbus_macros/names0");
+    assert_eq!(i, Interface::new("com.example.dbusmacros.Names").unwrap());
+    assert_eq!(m, Member::new("Hello_2").unwrap());
+    assert_eq!(p, Path::new("/com/example/dbus_macros/names0").unwrap());
+    assert_eq!(path!("/"), Path::default());
+
+    let msg = Message::signal(&p, &i, &m);
+    assert_eq!(msg.interface(), Some(i));
+    assert_eq!(msg.member(), Some(m));
+}
@@ -0,0 +1,78 @@
+use dbus::arg::{FromPropMap, PropMap, Variant};
+use dbus::blocking::{Connection, LocalConnection};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::tree::{Factory, MethodErr};
+use dbus::Message;
+use dbus_macros::{FromPropMap, dbus_interface};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+#[derive(FromPropMap, Debug, PartialEq)]
+struct Device {
+    model: String,
+    #[dbus(name = "Level")]
+    percentage: u8,
+    serial_number: Option<String>,
+    tags: Option<Vec<String>>,
+    #[dbus(skip)]
+    local: u32,
+}
+
+#[test]
+fn from_map() {
+    let mut map = PropMap::new();
+    map.insert("Model".into(), Variant(Box::new("X1".to_string())));
+    map.insert("Level".into(), Variant(Box::new(80u8)));
+    map.insert("Tags".into(), Variant(Box::new(vec!("a".to_string()))));
+    map.insert("Unused".into(), Variant(Box::new(5i32)));
+    // Read it back from a message, so the values are stored as the types get_refarg returns.
+    let m = Message::new_signal("/", "com.example.test", "Test").unwrap().append1(&map);
+    let mut map: PropMap = m.read1().unwrap();
+
+    let d = Device::from_prop_map(&map).unwrap();
+    assert_eq!(d, Device { model: "X1".into(), percentage: 80, serial_number: None, tags: Some(vec!("a".into())), local: 0 });
+
+    map.insert("SerialNumber".into(), Variant(Box::new(5u32)));
+    let e = Device::from_prop_map(&map).unwrap_err();
+    assert_eq!(e.message(), Some("Property 'SerialNumber' has type 'u', expected 's'"));
+    map.remove("SerialNumber");
+    map.remove("Level");
+    let e = Device::from_prop_map(&map).unwrap_err();
+    assert_eq!(e.message(), Some("Property 'Level' not found"));
+}
+
+struct Server;
+
+#[dbus_interface("com.example.dbusmacros.Device")]
+impl Server {
+    #[dbus(get)]
+    fn model(&self) -> Result<String, MethodErr> { Ok("X2".into()) }
+
+    #[dbus(get)]
+    fn level(&self) -> Result<u8, MethodErr> { Ok(42) }
+
+    #[dbus(get)]
+    fn serial_number(&self) -> Result<String, MethodErr> { Ok("1234".into()) }
+}
+
+#[test]
+fn get_all() {
+    let f = Factory::new_fn::<()>();
+    let tree = f.tree(()).add(f.object_path("/device", ()).add(Server::dbus_interface(&Arc::new(Server), &f)));
+    let mut server = LocalConnection::new_session().unwrap();
+    let name = server.unique_name().to_string();
+    tree.start_receive(&server);
+
+    let (tx, rx) = mpsc::channel();
+    let t = std::thread::spawn(move || {
+        let client = Connection::new_session().unwrap();
+        let p = client.with_proxy(name, "/device", Duration::from_secs(5));
+        tx.send(Device::from_prop_map(&p.get_all(Server::DBUS_INTERFACE).unwrap()).unwrap()).unwrap();
+    });
+    let d = loop {
+        if let Ok(d) = rx.try_recv() { break d; }
+        server.process(Duration::from_millis(100)).unwrap();
+    };
+    t.join().unwrap();
+    assert_eq!(d, Device { model: "X2".into(), percentage: 42, serial_number: Some("1234".into()), tags: None, local: 0 });
+}
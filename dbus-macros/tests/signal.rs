@@ -0,0 +1,65 @@
+use dbus::Message;
+use dbus::message::SignalArgs;
+use dbus::blocking::{Connection, LocalConnection};
+use dbus_macros::SignalArgs;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(SignalArgs, Debug, PartialEq, Clone)]
+#[dbus(interface = "com.example.dbusmacros.Jobs")]
+struct JobDone {
+    id: u32,
+    result: String,
+    tags: Vec<String>,
+}
+
+#[derive(SignalArgs, Debug, PartialEq)]
+#[dbus(interface = "com.example.dbusmacros.Jobs", name = "Progress")]
+struct JobProgress(u32, f64);
+
+#[derive(SignalArgs, Debug, PartialEq)]
+#[dbus(interface = "com.example.dbusmacros.Jobs")]
+struct Idle;
+
+#[test]
+fn roundtrip() {
+    assert_eq!(JobDone::NAME, "JobDone");
+    assert_eq!(JobProgress::NAME, "Progress");
+    assert_eq!(JobDone::INTERFACE, "com.example.dbusmacros.Jobs");
+
+    let j = JobDone { id: 5, result: "ok".into(), tags: vec!("a".into(), "b".into()) };
+    let m = j.to_emit_message(&"/jobs".into());
+    assert_eq!(&*m.member().unwrap(), "JobDone");
+    assert_eq!(m.read3::<u32, &str, Vec<&str>>().unwrap(), (5, "ok", vec!("a", "b")));
+    assert_eq!(JobDone::from_message(&m), Some(j));
+    assert_eq!(JobProgress::from_message(&m), None);
+
+    let m = JobProgress(3, 0.5).to_emit_message(&"/jobs".into());
+    assert_eq!(JobProgress::from_message(&m), Some(JobProgress(3, 0.5)));
+    let m = Idle.to_emit_message(&"/jobs".into());
+    assert_eq!(Idle::from_message(&m), Some(Idle));
+
+    let m = Message::new_signal("/jobs", JobDone::INTERFACE, JobDone::NAME).unwrap().append1(5u32);
+    assert_eq!(JobDone::from_message(&m), None);
+}
+
+#[test]
+fn over_the_bus() {
+    let mut receiver = LocalConnection::new_session().unwrap();
+    let received = Rc::new(RefCell::new(vec!()));
+    let r2 = received.clone();
+    let sender = Connection::new_session().unwrap();
+    let proxy = receiver.with_proxy(sender.unique_name(), "/jobs", Duration::from_secs(5));
+    proxy.match_signal(move |j: JobDone, _: &LocalConnection, _: &Message| { r2.borrow_mut().push(j); true }).unwrap();
+
+    let j = JobDone { id: 7, result: "done".into(), tags: vec!() };
+    use dbus::channel::Sender;
+    sender.send(j.to_emit_message(&"/jobs".into())).unwrap();
+
+    for _ in 0..50 {
+        if !received.borrow().is_empty() { break; }
+        receiver.process(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(*received.borrow(), vec!(j));
+}
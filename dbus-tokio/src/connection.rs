@@ -11,12 +11,17 @@ use tokio::io::Registration;
 ///
 /// If you need to ever cancel this resource (i e disconnect from D-Bus),
 /// you need to make this future abortable. If it finishes, you probably lost
-/// contact with the D-Bus server.
+/// contact with the D-Bus server: in that case the error is a `dbus::Error` of kind
+/// `ErrorKind::Disconnected`, so you can e g reconnect or shut down.
 pub struct IOResource<C> {
     connection: Arc<C>,
     registration: Option<(Registration, std::os::unix::io::RawFd)>,
 }
 
+fn disconnected() -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "The connection to D-Bus was closed")
+}
+
 impl<C: AsRef<Channel> + Process> IOResource<C> {
     fn poll_internal(&mut self, ctx: &mut task::Context<'_>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let c: &Channel = (*self.connection).as_ref();
@@ -39,8 +44,9 @@ impl<C: AsRef<Channel> + Process> IOResource<C> {
         if w.read { let _ = r.poll_read_ready(ctx)?; };
         if w.write { let _ = r.poll_write_ready(ctx)?; };
 
-        c.read_write(Some(Default::default())).map_err(|_| Error::new_failed("Read/write failed"))?;
+        c.read_write(Some(Default::default())).map_err(|_| disconnected())?;
         self.connection.process_all();
+        if !c.is_connected() { return Err(Box::new(disconnected())) }
 
         Ok(())
     }
@@ -99,3 +105,94 @@ fn method_call() {
 
     assert_eq!(has_owner, false);
 }
+
+#[test]
+fn ping() {
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_io()
+        .build()
+        .unwrap();
+
+    let local = tokio::task::LocalSet::new();
+
+    let (res, conn) = new_session_local().unwrap();
+    local.spawn_local(async move { panic!("Lost connection to D-Bus: {}", res.await); });
+
+    let fut = async move {
+        let rtt = conn.ping("org.freedesktop.DBus").await.unwrap();
+        let e = conn.ping("com.example.dbusrs.nobody").await.unwrap_err();
+        (rtt < std::time::Duration::from_secs(5), e.kind())
+    };
+    assert_eq!(local.block_on(&mut rt, fut), (true, dbus::ErrorKind::ServiceUnknown));
+}
+
+#[test]
+fn wait_for_signal() {
+    use std::time::Duration;
+    use dbus::message::MatchRule;
+    use dbus::channel::Sender;
+
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let local = tokio::task::LocalSet::new();
+
+    let (res, conn) = new_session_local().unwrap();
+    local.spawn_local(async move { panic!("Lost connection to D-Bus: {}", res.await); });
+
+    let mut mr = MatchRule::new_signal("com.example.dbusrs.Waiting", "Done");
+    mr.sender = Some(conn.unique_name().into_static());
+    let fut = async move {
+        let timed_out = tokio::time::timeout(Duration::from_millis(50), conn.wait_for_signal(mr.clone())).await.is_err();
+        let c2 = conn.clone();
+        let emit = async move {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            c2.send(dbus::Message::new_signal("/waiting", "com.example.dbusrs.Waiting", "Done").unwrap().append1(5u8)).unwrap();
+        };
+        let (m, _) = futures::future::join(conn.wait_for_signal(mr), emit).await;
+        (timed_out, m.unwrap().read1::<u8>().unwrap())
+    };
+
+    assert_eq!(local.block_on(&mut rt, fut), (true, 5));
+}
+
+#[test]
+fn receive_signals() {
+    use std::time::Duration;
+    use dbus::channel::Sender;
+    use dbus::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as Ppc;
+    use futures::StreamExt;
+
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let local = tokio::task::LocalSet::new();
+
+    let (res, conn) = new_session_local().unwrap();
+    local.spawn_local(async move { panic!("Lost connection to D-Bus: {}", res.await); });
+
+    let fut = async move {
+        let proxy = dbus::nonblock::Proxy::new(conn.unique_name().into_static(), "/receive", conn.clone());
+        let mut signals = proxy.receive::<Ppc>().await.unwrap();
+        for i in &["com.example.A", "com.example.B"] {
+            let changed: std::collections::HashMap<&str, dbus::arg::Variant<u8>> = Default::default();
+            let m = dbus::Message::new_signal("/receive", "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap()
+                .append3(*i, changed, Vec::<&str>::new());
+            conn.send(m).unwrap();
+        }
+        let (a, _) = signals.next_signal().await.unwrap();
+        let (b, m) = signals.next().await.unwrap();
+        assert_eq!(&*m.path().unwrap(), "/receive");
+        let timed_out = tokio::time::timeout(Duration::from_millis(50), signals.next_signal()).await.is_err();
+        (a.interface_name, b.interface_name, timed_out)
+    };
+
+    assert_eq!(local.block_on(&mut rt, fut), ("com.example.A".into(), "com.example.B".into(), true));
+}
@@ -4,9 +4,10 @@
 //!
 //!  * Client: Make method calls and wait asynchronously for them to be replied to - see `AConnection::method_call`
 //!  * Get a stream of incoming messages (so you can listen to signals etc) - see `AConnection::messages`
-//!  * Server: Make a tree handle that stream of incoming messages - see `tree::ATreeServer`
-//!  * Server: Add asynchronous methods to the tree - in case you cannot reply right away,
-//!    you can return a future that will reply when that future resolves - see `tree::AFactory::amethod`
+//!  * Server: Make a tree handle incoming method calls, where the methods are asynchronous - in case
+//!    you cannot reply right away, you can return a future that will reply when that future resolves -
+//!    see `tree::start_receive` and `dbus::tree::Factory::new_future`
 //!
 //! For examples to get you started, see the examples directory and the Readme.
 pub mod connection;
+pub mod tree;
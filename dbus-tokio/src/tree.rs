@@ -1,177 +1,79 @@
-/// Async server-side trees
+//! Async server-side trees
 
-use std::{ops, fmt, mem};
-use dbus::tree::{Factory, Tree, MethodType, DataType, MTFn, Method, MethodInfo, MethodErr};
-use dbus::{Message, ffidisp::Connection};
-use dbus::strings::Member;
-use std::marker::PhantomData;
-use std::cell::RefCell;
-use futures::{IntoFuture, Future, Poll, Stream, Async};
-use std::ffi::CString;
+use dbus::tree::{Tree, MTFuture, DataType};
+use dbus::nonblock::LocalConnection;
+use dbus::channel::{MatchingReceiver, Sender, Token};
+use dbus::message::MatchRule;
+use dbus::MessageType;
+use std::sync::Arc;
 
-pub trait ADataType: fmt::Debug + Sized + Default {
-    type ObjectPath: fmt::Debug;
-    type Property: fmt::Debug;
-    type Interface: fmt::Debug + Default;
-    type Method: fmt::Debug + Default;
-    type Signal: fmt::Debug;    
-}
-
-#[derive(Debug, Default)]
-/// A Tree that allows both synchronous and asynchronous methods.
-pub struct ATree<D: ADataType>(RefCell<Option<AMethodResult>>, PhantomData<*const D>);
-
-impl<D: ADataType> ATree<D> {
-    pub fn new() -> Self { Default::default() }
-    fn push(&self, a: AMethodResult) {
-        let mut z = self.0.borrow_mut();
-        assert!(z.is_none(), "Same message handled twice");
-        *z = Some(a);
-    }
-}
-
-impl<D: ADataType> DataType for ATree<D> {
-    type Tree = ATree<D>;
-    type ObjectPath = D::ObjectPath;
-    type Property = D::Property;
-    type Interface = D::Interface;
-    type Method = D::Method;
-    type Signal = D::Signal;
-}
-
-impl ADataType for () {
-    type ObjectPath = ();
-    type Property = ();
-    type Interface = ();
-    type Method = ();
-    type Signal = ();
-}
-
-/// A Tree factory that allows both synchronous and asynchronous methods.
-pub struct AFactory<M: MethodType<D>, D: DataType = ()>(Factory<M, D>);
-
-impl AFactory<MTFn<()>, ()> {
-    pub fn new_afn<D: ADataType>() -> AFactory<MTFn<ATree<D>>, ATree<D>> { AFactory(Factory::new_fn()) }
-}
-
-impl<M: MethodType<D>, D: DataType> ops::Deref for AFactory<M, D> {
-    type Target = Factory<M, D>;
-    fn deref(&self) -> &Self::Target { &self.0 }
-}
-
-impl<D: ADataType> AFactory<MTFn<ATree<D>>, ATree<D>> {
-    /// Creates an async method, for methods whose result cannot be returned immediately.
-    ///
-    /// The method handler supplied to amethod returns a future, which resolves into the method result.
-    /// See the tokio_server example for some hints on how to use it.
-    pub fn amethod<H, R, T>(&self, t: T, data: D::Method, handler: H) -> Method<MTFn<ATree<D>>, ATree<D>>
-    where H: 'static + Fn(&MethodInfo<MTFn<ATree<D>>, ATree<D>>) -> R, T: Into<Member<'static>>,
-        R: 'static + IntoFuture<Item=Vec<Message>, Error=MethodErr> {
-        self.0.method(t, data, move |minfo| {
-            let r = handler(minfo);
-            minfo.tree.get_data().push(AMethodResult::new(r));
-            Ok(Vec::new())
-        })
-    }
-}
-
-/// A Future method result
+/// Connects a LocalConnection with a Tree so that incoming method calls are handled.
 ///
-/// When method results cannot be returned right away, the AMethodResult holds it temporarily
-struct AMethodResult(Box<dyn Future<Item=Vec<Message>, Error=MethodErr>>, Option<Message>);
-
-impl fmt::Debug for AMethodResult {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "AMethodResult({:?})", self.1) }
-}
-
-impl AMethodResult {
-    fn new<F: 'static + IntoFuture<Item=Vec<Message>, Error=MethodErr>>(f: F) -> Self {
-        AMethodResult(Box::new(f.into_future()), None)
-    }
-}
-
-impl Future for AMethodResult {
-    type Item = Vec<Message>;
-    type Error = MethodErr;
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.0.poll()
-    }
-}
-
-
-#[derive(Debug)]
-/// Creates a filter for incoming messages, that handles messages in the tree.
+/// The tree's method handlers return futures, which are spawned onto the current `LocalSet`
+/// (see `tokio::task::spawn_local`). This way a handler can await other D-Bus calls or IO without
+/// blocking other incoming method calls. The reply is sent when the future resolves.
 ///
-/// See the tokio_server example for some hints on how to use it.
-pub struct ATreeServer<C,T,D,S>
-where C: ops::Deref<Target=Connection>,
-      T: ops::Deref<Target=Tree<MTFn<ATree<D>>, ATree<D>>>,
-      D: ADataType {
-   conn: C,
-   tree: T,
-   stream: S,
-   pendingresults: Vec<AMethodResult>,
-}
-
-impl<C,T,D,S> ATreeServer<C,T,D,S>
-where C: ops::Deref<Target=Connection>,
-      T: ops::Deref<Target=Tree<MTFn<ATree<D>>, ATree<D>>>,
-      S: Stream<Item=Message, Error=()>,
-      D: ADataType {
-    pub fn new(c: C, t: T, stream: S) -> Self {
-        ATreeServer { conn: c, tree: t, stream: stream, pendingresults: vec![] }
-    }
-
-    fn spawn_method_results(&mut self, msg: Message) {
-        let v = self.tree.get_data().0.borrow_mut().take();
-        if let Some(mut r) = v {
-            if r.1.is_none() { r.1 = Some(msg); };
-            // println!("Pushing {:?}", r);
-            self.pendingresults.push(r);
+/// Must be called from within a `LocalSet`. Create the tree with `dbus::tree::Factory::new_future`.
+pub fn start_receive<D: DataType + 'static>(tree: Tree<MTFuture<D>, D>, connection: &Arc<LocalConnection>) -> Token {
+    let conn = Arc::downgrade(connection);
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    connection.start_receive(rule, Box::new(move |msg, _| {
+        if let Some(f) = tree.handle_async(msg) {
+            let conn = conn.clone();
+            tokio::task::spawn_local(async move {
+                let replies = f.await;
+                // Ignore send errors, the remote might have disconnected while we were waiting.
+                if let Some(c) = conn.upgrade() { for r in replies { let _ = c.send(r); } }
+            });
         }
-    }
-
-    fn check_pending_results(&mut self) {
-        let v = mem::replace(&mut self.pendingresults, vec!());
-        self.pendingresults = v.into_iter().filter_map(|mut mr| {
-            let z = mr.poll();
-            // println!("Polling {:?} returned {:?}", mr, z);
-            match z {
-                Ok(Async::NotReady) => Some(mr),
-                Ok(Async::Ready(t)) => { for msg in t { self.conn.send(msg).expect("D-Bus send error"); }; None },
-                Err(e) => {
-                    let m = mr.1.take().unwrap(); 
-                    let msg = m.error(&e.errorname(), &CString::new(e.description()).unwrap());
-                    self.conn.send(msg).expect("D-Bus send error");
-                    None
-                }
-            }
-        }).collect();
-    }
+        true
+    }))
 }
 
-impl<C,T,D,S> Stream for ATreeServer<C,T,D,S>
-where C: ops::Deref<Target=Connection>,
-      T: ops::Deref<Target=Tree<MTFn<ATree<D>>, ATree<D>>>,
-      S: Stream<Item=Message, Error=()>,
-      D: ADataType {
-    type Item = Message;
-    type Error = ();
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        loop {
-            self.check_pending_results();
-            let z = self.stream.poll();
-            if let Ok(Async::Ready(Some(m))) = z {
-                // println!("treeserver {:?}", m);
-                let hh = self.tree.handle(&m);
-                // println!("hh: {:?}", hh);
-                if let Some(v) = hh {
-                    self.spawn_method_results(m);
-                    for msg in v { self.conn.send(msg)?; }
-                    // We consumed the message. Poll again
-                } else { return Ok(Async::Ready(Some(m))) }
-            } else { return z }
-        }
-    }
+#[test]
+fn async_method() {
+    use dbus::tree::Factory;
+    use std::time::Duration;
+
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let local = tokio::task::LocalSet::new();
+
+    let (res, server) = crate::connection::new_session_local().unwrap();
+    local.spawn_local(async move { panic!("Lost connection to D-Bus: {}", res.await); });
+    let (res, client) = crate::connection::new_session_local().unwrap();
+    local.spawn_local(async move { panic!("Lost connection to D-Bus: {}", res.await); });
+
+    let f = Factory::new_future::<()>();
+    let t = f.tree(()).add(f.object_path("/sleep", ()).introspectable()
+        .add(f.interface("com.example.dbusrs.sleep", ())
+            .add_m(f.method("Sleep", (), |m| {
+                let ms: Result<u32, _> = m.msg.read1();
+                let mret = m.msg.method_return();
+                async move {
+                    let ms = ms?;
+                    tokio::time::delay_for(Duration::from_millis(ms as u64)).await;
+                    Ok(mret.append1(ms).into())
+                }
+            }).inarg::<u32,_>("ms").outarg::<u32,_>("slept"))
+    ));
+
+    let name = server.unique_name().to_string();
+    let proxy = dbus::nonblock::Proxy::new(name, "/sleep", client);
+    let fut = async move {
+        start_receive(t, &server);
+        let slow = proxy.method_call("com.example.dbusrs.sleep", "Sleep", (200u32,));
+        let fast = proxy.method_call("com.example.dbusrs.sleep", "Sleep", (1u32,));
+        let (fast, slow): (Result<(u32,), _>, Result<(u32,), _>) = futures::future::join(fast, slow).await;
+        let wrong: Result<(u32,), _> = proxy.method_call("com.example.dbusrs.sleep", "Sleep", ("wrong",)).await;
+        (fast.unwrap(), slow.unwrap(), wrong.is_err())
+    };
+
+    let r = local.block_on(&mut rt, fut);
+    assert_eq!(r, ((1,), (200,), true));
 }
-
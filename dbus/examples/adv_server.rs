@@ -120,7 +120,7 @@ fn create_iface(check_complete_s: mpsc::Sender<i32>) -> (Interface<MTFn<TData>,
                 // Tell main thread that we finished
                 ch.send(devindex).unwrap();
             });
-            Ok(vec!(m.msg.method_return()))
+            Ok(m.msg.method_return().into())
         }))
         // Indicate that we send a special signal once checking has completed.
         .add_s(check_complete.clone())
@@ -52,7 +52,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
                 // Two messages will be returned - one is the method return (and should always be there),
                 // and in our case we also have a signal we want to send at the same time.
-                Ok(vec!(mret, sig))
+                Ok(vec!(mret, sig).into())
 
             // Our method has one output argument and one input argument.
             }).outarg::<&str,_>("reply")
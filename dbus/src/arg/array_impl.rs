@@ -4,7 +4,7 @@ use crate::strings::{Signature, Path};
 use std::marker::PhantomData;
 use std::{ptr, mem, any, fmt};
 use super::check;
-use std::ffi::{CString};
+use std::ffi::CStr;
 use std::os::raw::{c_void, c_int};
 use std::collections::HashMap;
 use std::hash::{Hash, BuildHasher};
@@ -30,6 +30,13 @@ impl<'a, T: Arg> Arg for &'a [T] {
     fn signature() -> Signature<'static> { Signature::from(format!("a{}", T::signature())) }
 }
 
+// The element signature of an array signature, i e, everything after the "a".
+fn element_sig<'b>(s: &'b Signature) -> &'b CStr {
+    let b = s.as_cstr().to_bytes_with_nul();
+    debug_assert_eq!(b[0], b'a');
+    unsafe { CStr::from_bytes_with_nul_unchecked(&b[1..]) }
+}
+
 fn array_append<T: Arg, F: FnMut(&T, &mut IterAppend)>(z: &[T], i: &mut IterAppend, mut f: F) {
     let zptr = z.as_ptr();
     let zlen = z.len() as i32;
@@ -38,7 +45,7 @@ fn array_append<T: Arg, F: FnMut(&T, &mut IterAppend)>(z: &[T], i: &mut IterAppe
     let a = (T::ARG_TYPE, mem::size_of::<T>());
     let can_fixed_array = (zlen > 1) && (z.len() == zlen as usize) && FIXED_ARRAY_ALIGNMENTS.iter().any(|&v| v == a);
 
-    i.append_container(ArgType::Array, Some(T::signature().as_cstr()), |s|
+    i.append_container(ArgType::Array, Some(cached_signature::<T>().as_cstr()), |s|
         if can_fixed_array { unsafe { check("dbus_message_iter_append_fixed_array",
             ffi::dbus_message_iter_append_fixed_array(&mut s.0, a.0 as c_int, &zptr as *const _ as *const c_void, zlen)) }}
         else { for arg in z { f(arg, s); }}
@@ -136,7 +143,8 @@ impl<'a, K: DictKey, V: Arg, I> Arg for Dict<'a, K, V, I> {
 impl<'a, K: 'a + DictKey + Append, V: 'a + Append + Arg, I: Iterator<Item=(K, V)> + Clone> Append for Dict<'a, K, V, I> {
     fn append_by_ref(&self, i: &mut IterAppend) {
         let z = self.0.clone();
-        i.append_container(Self::ARG_TYPE, Some(&CString::new(Self::entry_sig()).unwrap()), |s| for (k, v) in z {
+        let sig = cached_signature::<Self>();
+        i.append_container(Self::ARG_TYPE, Some(element_sig(&sig)), |s| for (k, v) in z {
             s.append_container(ArgType::DictEntry, None, |ss| {
                 k.append_by_ref(ss);
                 v.append_by_ref(ss);
@@ -192,8 +200,8 @@ impl<K: DictKey + RefArg + Eq + Hash, V: RefArg + Arg, S: BuildHasher> RefArg fo
     fn arg_type(&self) -> ArgType { ArgType::Array }
     fn signature(&self) -> Signature<'static> { format!("a{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature()).into() }
     fn append(&self, i: &mut IterAppend) {
-        let sig = CString::new(format!("{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature())).unwrap();
-        i.append_container(ArgType::Array, Some(&sig), |s| for (k, v) in self {
+        let sig = cached_signature::<Self>();
+        i.append_container(ArgType::Array, Some(element_sig(&sig)), |s| for (k, v) in self {
             s.append_container(ArgType::DictEntry, None, |ss| {
                 k.append(ss);
                 v.append(ss);
@@ -253,7 +261,7 @@ impl<'a, T: Arg, I> Arg for Array<'a, T, I> {
 impl<'a, T: 'a + Arg + Append, I: Iterator<Item=T> + Clone> Append for Array<'a, T, I> {
     fn append_by_ref(&self, i: &mut IterAppend) {
         let z = self.0.clone();
-        i.append_container(ArgType::Array, Some(T::signature().as_cstr()), |s| for arg in z { arg.append_by_ref(s) });
+        i.append_container(ArgType::Array, Some(cached_signature::<T>().as_cstr()), |s| for arg in z { arg.append_by_ref(s) });
     }
 }
 
@@ -281,7 +289,7 @@ impl<'a, T: 'a + Arg + fmt::Debug + RefArg, I: fmt::Debug + Clone + Iterator<Ite
     fn signature(&self) -> Signature<'static> { Signature::from(format!("a{}", <T as Arg>::signature())) }
     fn append(&self, i: &mut IterAppend) {
         let z = self.0.clone();
-        i.append_container(ArgType::Array, Some(<T as Arg>::signature().as_cstr()), |s|
+        i.append_container(ArgType::Array, Some(cached_signature::<T>().as_cstr()), |s|
             for arg in z { RefArg::append(arg,s) }
         );
     }
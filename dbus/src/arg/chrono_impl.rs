@@ -0,0 +1,61 @@
+// Arg implementations for the chrono crate, enabled with the "chrono" feature.
+
+use super::{Arg, Append, Get, RefArg, DictKey, ArgType, Iter, IterAppend};
+use crate::Signature;
+use std::any;
+use chrono::{DateTime, TimeZone, Utc};
+
+fn from_micros(us: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(us.div_euclid(1_000_000), us.rem_euclid(1_000_000) as u32 * 1000).single()
+}
+
+// Appended as microseconds since the Unix epoch, which is what e g systemd uses for timestamps.
+impl Arg for DateTime<Utc> {
+    const ARG_TYPE: ArgType = ArgType::Int64;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"x\0") } }
+}
+
+impl Append for DateTime<Utc> {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        i.append(self.timestamp() * 1_000_000 + self.timestamp_subsec_micros() as i64)
+    }
+}
+
+impl DictKey for DateTime<Utc> {}
+
+// Reads microseconds since the Unix epoch, either signed or unsigned.
+impl<'a> Get<'a> for DateTime<Utc> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        match i.arg_type() {
+            ArgType::Int64 => from_micros(i.get()?),
+            ArgType::UInt64 => { let u: u64 = i.get()?; if u > i64::MAX as u64 { None } else { from_micros(u as i64) } },
+            _ => None,
+        }
+    }
+}
+
+impl RefArg for DateTime<Utc> {
+    fn arg_type(&self) -> ArgType { ArgType::Int64 }
+    fn signature(&self) -> Signature<'static> { <DateTime<Utc> as Arg>::signature() }
+    fn append(&self, i: &mut IterAppend) { self.append_by_ref(i) }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any { self }
+    #[inline]
+    fn as_i64(&self) -> Option<i64> { Some(self.timestamp() * 1_000_000 + self.timestamp_subsec_micros() as i64) }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { Box::new(*self) }
+}
+
+#[test]
+fn test_chrono() {
+    use crate::Message;
+    let d = Utc.timestamp_opt(1572961020, 123456000).unwrap();
+    let early = Utc.timestamp_opt(-1, 500000000).unwrap();
+    let m = Message::new_signal("/test", "com.example.test", "Test").unwrap().append3(d, early, 1572961020123456u64);
+    assert_eq!(&*m.iter_init().signature(), "x");
+    assert_eq!(m.get1::<i64>(), Some(1572961020123456));
+    let (a, b, c): (Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<DateTime<Utc>>) = m.get3();
+    assert_eq!((a, b, c), (Some(d), Some(early), Some(d)));
+}
@@ -0,0 +1,172 @@
+// Sending large byte payloads in a sealed memfd, instead of in the message body.
+
+use super::{Arg, Append, Get, ArgType, Iter, IterAppend, OwnedFd};
+use crate::Signature;
+use std::{io, ptr, slice, fmt};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Deref;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+
+fn check(r: libc::c_int) -> io::Result<libc::c_int> {
+    if r < 0 { Err(io::Error::last_os_error()) } else { Ok(r) }
+}
+
+/// A byte payload that travels in a sealed memfd, passed as a Unix fd, instead of in the message body.
+///
+/// The bus daemon limits the size of messages (typically to 128 MiB, and much less for the
+/// system bus), and copies every byte of them. For large payloads, `SealedBytes::new` writes the
+/// bytes to a memfd and seals it, so that only the fd and the length, as a `(ht)` struct, go in the message.
+/// The receiving side reads the struct as a `SealedBytes`, and maps the payload with `map`.
+///
+/// `map` refuses fds that are not sealed against writing and shrinking, because the sender could
+/// otherwise change the payload while it is being read, or shrink it and make reading it crash.
+///
+/// Both ends need to support passing Unix fds, which libdbus negotiates when connecting over a Unix socket.
+///
+/// # Example
+/// ```rust
+/// use dbus::arg::SealedBytes;
+/// let payload = vec![7u8; 1 << 20];
+/// let m = dbus::Message::new_signal("/data", "com.example.Data", "Big").unwrap()
+///     .append1(SealedBytes::new(&payload).unwrap());
+///
+/// let received: SealedBytes = m.read1().unwrap();
+/// assert_eq!(&*received.map().unwrap(), &payload[..]);
+/// ```
+#[derive(Clone)]
+pub struct SealedBytes {
+    fd: OwnedFd,
+    len: u64,
+}
+
+impl fmt::Debug for SealedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SealedBytes").field("fd", &self.fd.as_raw_fd()).field("len", &self.len).finish()
+    }
+}
+
+impl SealedBytes {
+    /// Copies "data" into a new memfd, and seals it.
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        let fd = check(unsafe { libc::memfd_create(b"dbus-rs-payload\0".as_ptr() as *const libc::c_char,
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) })?;
+        let mut f = unsafe { File::from_raw_fd(fd) };
+        f.write_all(data)?;
+        let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        check(unsafe { libc::fcntl(f.as_raw_fd(), libc::F_ADD_SEALS, seals) })?;
+        Ok(SealedBytes { fd: unsafe { OwnedFd::new(f.into_raw_fd()) }, len: data.len() as u64 })
+    }
+
+    /// The length of the payload.
+    pub fn len(&self) -> u64 { self.len }
+
+    /// Returns true if the payload is empty.
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// The memfd the payload is in.
+    pub fn fd(&self) -> &OwnedFd { &self.fd }
+
+    /// Maps the payload into memory, read-only.
+    ///
+    /// Fails if the fd is not sealed against writing and shrinking, or is shorter than the length
+    /// given by the sender.
+    pub fn map(&self) -> io::Result<MappedBytes> {
+        let fd = self.fd.as_raw_fd();
+        let needed = libc::F_SEAL_SHRINK | libc::F_SEAL_WRITE;
+        if check(unsafe { libc::fcntl(fd, libc::F_GET_SEALS) })? & needed != needed {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The payload is not sealed against writing and shrinking"))
+        }
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::fstat(fd, &mut st) })?;
+        if (st.st_size as u64) < self.len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The payload is shorter than its stated length"))
+        }
+        let len = usize::try_from(self.len).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "The payload is too large to map"))?;
+        if len == 0 { return Ok(MappedBytes { ptr: ptr::null_mut(), len }) }
+        let p = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, fd, 0) };
+        if p == libc::MAP_FAILED { return Err(io::Error::last_os_error()) }
+        Ok(MappedBytes { ptr: p, len })
+    }
+
+    /// Copies the payload into a Vec.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> { Ok(self.map()?.to_vec()) }
+}
+
+/// A payload from `SealedBytes::map`, mapped into memory until this is dropped.
+pub struct MappedBytes {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only, and the memfd is sealed, so nothing can change it.
+unsafe impl Send for MappedBytes {}
+unsafe impl Sync for MappedBytes {}
+
+impl fmt::Debug for MappedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedBytes").field("len", &self.len).finish()
+    }
+}
+
+impl Deref for MappedBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 { return &[] }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for MappedBytes {
+    fn drop(&mut self) {
+        if self.len > 0 { unsafe { libc::munmap(self.ptr, self.len); } }
+    }
+}
+
+impl Arg for SealedBytes {
+    const ARG_TYPE: ArgType = ArgType::Struct;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"(ht)\0") } }
+}
+
+impl Append for SealedBytes {
+    fn append_by_ref(&self, i: &mut IterAppend) {
+        i.append_struct(|s| { s.append(&self.fd); s.append(self.len); })
+    }
+}
+
+impl<'a> Get<'a> for SealedBytes {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        let mut s = i.recurse(ArgType::Struct)?;
+        let fd = s.read().ok()?;
+        let len = s.read().ok()?;
+        Some(SealedBytes { fd, len })
+    }
+}
+
+#[test]
+fn test_sealed_bytes() {
+    use crate::Message;
+    let data: Vec<u8> = (0..100_000u32).map(|x| x as u8).collect();
+    let m = Message::new_signal("/test", "com.example.test", "Test").unwrap()
+        .append2(SealedBytes::new(&data).unwrap(), SealedBytes::new(&[]).unwrap());
+    assert_eq!(&*m.iter_init().signature(), "(ht)");
+    let (a, b): (SealedBytes, SealedBytes) = m.read2().unwrap();
+    assert_eq!(a.len(), 100_000);
+    assert_eq!(&*a.map().unwrap(), &data[..]);
+    assert!(b.is_empty());
+    assert_eq!(b.to_vec().unwrap(), Vec::<u8>::new());
+
+    // The sender cannot change a sealed payload.
+    let fd = a.fd().as_raw_fd();
+    assert!(unsafe { libc::write(fd, b"x".as_ptr() as *const _, 1) } < 0);
+    assert!(unsafe { libc::ftruncate(fd, 10) } < 0);
+
+    // Unsealed fds are refused.
+    let f = unsafe { File::from_raw_fd(libc::memfd_create(b"test\0".as_ptr() as *const _, libc::MFD_CLOEXEC)) };
+    f.set_len(10).unwrap();
+    let unsealed = SealedBytes { fd: unsafe { OwnedFd::new(f.into_raw_fd()) }, len: 10 };
+    assert_eq!(unsealed.map().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let short = SealedBytes { fd: a.fd().clone(), len: 100_001 };
+    assert_eq!(short.map().unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -9,9 +9,9 @@
 use crate::strings::{Signature, Path, Interface, BusName};
 
 use crate::arg;
-use crate::arg::{Iter, IterAppend, Arg, ArgType, OwnedFd};
+use crate::arg::{Iter, IterAppend, Arg, ArgType, OwnedFd, RefArg};
 use std::ffi::CStr;
-use std::{ops, any};
+use std::{ops, any, fmt};
 
 use crate::{ffidisp::Connection, Message, Error};
 use std::collections::BTreeMap;
@@ -28,6 +28,34 @@ pub enum ArrayError {
     InvalidSignature,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+/// Errors from `MessageItem::checked_signature` and `MessageItem::coerce`.
+pub enum SignatureError {
+    /// A struct has no fields.
+    EmptyStruct,
+    /// The elements of an array, or the keys or values of a dict, are not all of the same type.
+    DifferentElementTypes,
+    /// The signature is longer than the 255 bytes D-Bus allows.
+    TooLong,
+    /// The signature is not valid, e g because containers are nested deeper than D-Bus allows,
+    /// or a dict has a key which is not of a basic type. Contains the reason given by libdbus.
+    Invalid(String),
+    /// The value cannot be converted to the requested type.
+    Mismatch(String),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::EmptyStruct => write!(f, "D-Bus structs must have at least one field"),
+            SignatureError::DifferentElementTypes => write!(f, "Array elements or dict entries have different types"),
+            SignatureError::TooLong => write!(f, "D-Bus signatures cannot be longer than 255 bytes"),
+            SignatureError::Invalid(s) | SignatureError::Mismatch(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 /// An array of MessageItem where every MessageItem is of the same type.
@@ -470,6 +498,148 @@ impl arg::Append for MessageItem {
     }
 }
 
+// Splits "s" into its first complete type and the rest.
+fn split_first_type(s: &str) -> (&str, &str) {
+    let b = s.as_bytes();
+    let mut i = b.iter().take_while(|&&c| c == b'a').count();
+    let mut depth = 0;
+    while i < b.len() {
+        match b[i] {
+            b'(' | b'{' => depth += 1,
+            b')' | b'}' => depth -= 1,
+            _ => {},
+        }
+        i += 1;
+        if depth <= 0 { break }
+    }
+    s.split_at(i)
+}
+
+impl MessageItem {
+    /// Computes the signature of this item, checking that the tree of items is valid for D-Bus.
+    ///
+    /// Unlike `signature`, this does not panic (or let libdbus abort the process, or the bus daemon
+    /// disconnect you, when the item is sent) for invalid trees: structs without fields, arrays whose
+    /// elements have different types, and containers that are nested too deeply or make the signature too long.
+    /// Items inside variants are checked too.
+    pub fn checked_signature(&self) -> Result<Signature<'static>, SignatureError> {
+        let s = self.check_sig()?;
+        if s.len() > 255 { return Err(SignatureError::TooLong) }
+        Signature::new(s).map_err(SignatureError::Invalid)
+    }
+
+    fn check_sig(&self) -> Result<String, SignatureError> {
+        Ok(match self {
+            MessageItem::Array(a) => {
+                let esig = a.element_signature().to_str().unwrap();
+                for x in &a.v { if x.check_sig()? != esig { return Err(SignatureError::DifferentElementTypes) } }
+                a.sig.to_string()
+            }
+            MessageItem::Dict(d) => {
+                let (ksig, vsig) = split_first_type(&d.sig[2..d.sig.len()-1]);
+                for (k, v) in &d.v {
+                    if k.check_sig()? != ksig || v.check_sig()? != vsig { return Err(SignatureError::DifferentElementTypes) }
+                }
+                d.sig.to_string()
+            }
+            MessageItem::Struct(v) => {
+                if v.is_empty() { return Err(SignatureError::EmptyStruct) }
+                let mut s = String::from("(");
+                for x in v { s += &x.check_sig()?; }
+                s + ")"
+            }
+            MessageItem::Variant(x) => { x.checked_signature()?; "v".into() }
+            _ => self.signature().to_string(),
+        })
+    }
+
+    /// Converts this item to the type given by "sig".
+    ///
+    /// The conversions are:
+    ///
+    /// * Integers convert to other integer types, if the value fits, and to doubles.
+    /// * Strings, object paths and signatures convert to each other, if the value is valid for the new type.
+    /// * Anything converts to a variant, and a variant is unwrapped if something else is asked for.
+    /// * Arrays, dicts and structs convert if their contents do. Structs need to have the same number of fields.
+    ///
+    /// Everything else, e g a boolean to an integer, is an error. The result is checked with `checked_signature`.
+    pub fn coerce(self, sig: &Signature) -> Result<MessageItem, SignatureError> {
+        let r = self.coerce_str(sig)?;
+        r.checked_signature()?;
+        Ok(r)
+    }
+
+    fn coerce_str(self, sig: &str) -> Result<MessageItem, SignatureError> {
+        let mismatch = |m: &MessageItem| SignatureError::Mismatch(format!("Cannot convert {:?} to a value of type {}", m.arg_type(), sig));
+        let b = sig.as_bytes();
+        let int = match self {
+            MessageItem::Byte(i) => Some(i as i128),
+            MessageItem::Int16(i) => Some(i as i128),
+            MessageItem::UInt16(i) => Some(i as i128),
+            MessageItem::Int32(i) => Some(i as i128),
+            MessageItem::UInt32(i) => Some(i as i128),
+            MessageItem::Int64(i) => Some(i as i128),
+            MessageItem::UInt64(i) => Some(i as i128),
+            _ => None,
+        };
+        macro_rules! int { ($v: ident, $t: ty) => {
+            int.and_then(|i| <$t>::try_from(i).ok()).map(MessageItem::$v).ok_or_else(|| mismatch(&self))
+        }}
+        if let MessageItem::Variant(x) = self {
+            return if b[0] == b'v' { Ok(MessageItem::Variant(x)) } else { x.coerce_str(sig) }
+        }
+        match b[0] {
+            b'v' => Ok(MessageItem::Variant(Box::new(self))),
+            b'y' => int!(Byte, u8),
+            b'n' => int!(Int16, i16),
+            b'q' => int!(UInt16, u16),
+            b'i' => int!(Int32, i32),
+            b'u' => int!(UInt32, u32),
+            b'x' => int!(Int64, i64),
+            b't' => int!(UInt64, u64),
+            b'd' => match (int, self) {
+                (Some(i), _) => Ok(MessageItem::Double(i as f64)),
+                (_, MessageItem::Double(d)) => Ok(MessageItem::Double(d)),
+                (_, x) => Err(mismatch(&x)),
+            },
+            b'b' | b'h' if self.signature().as_bytes() == b => Ok(self),
+            b's' | b'o' | b'g' => {
+                let s = match self { MessageItem::Str(s) => s, MessageItem::ObjectPath(p) => p.to_string(), MessageItem::Signature(g) => g.to_string(), _ => return Err(mismatch(&self)) };
+                Ok(match b[0] {
+                    b's' => MessageItem::Str(s),
+                    b'o' => MessageItem::ObjectPath(Path::new(s).map_err(SignatureError::Mismatch)?),
+                    _ => MessageItem::Signature(Signature::new(s).map_err(SignatureError::Mismatch)?),
+                })
+            }
+            b'a' if b.get(1) == Some(&b'{') => {
+                let d = if let MessageItem::Dict(d) = self { d } else { return Err(mismatch(&self)) };
+                let (ksig, vsig) = split_first_type(&sig[2..sig.len()-1]);
+                let v = d.v.into_iter().map(|(k, v)| Ok((k.coerce_str(ksig)?, v.coerce_str(vsig)?))).collect::<Result<_, _>>()?;
+                Ok(MessageItem::Dict(MessageItemDict { v, sig: Signature::new(sig).map_err(SignatureError::Invalid)? }))
+            }
+            b'a' => {
+                let a = if let MessageItem::Array(a) = self { a } else { return Err(mismatch(&self)) };
+                let v = a.v.into_iter().map(|x| x.coerce_str(&sig[1..])).collect::<Result<_, _>>()?;
+                Ok(MessageItem::Array(MessageItemArray { v, sig: Signature::new(sig).map_err(SignatureError::Invalid)? }))
+            }
+            b'(' => {
+                let v = if let MessageItem::Struct(v) = self { v } else { return Err(mismatch(&self)) };
+                let mut fields = &sig[1..sig.len()-1];
+                let mut r = vec!();
+                for x in v {
+                    let (f, rest) = split_first_type(fields);
+                    if f.is_empty() { return Err(SignatureError::Mismatch(format!("Too many fields for a struct of type {}", sig))) }
+                    r.push(x.coerce_str(f)?);
+                    fields = rest;
+                }
+                if !fields.is_empty() { return Err(SignatureError::Mismatch(format!("Too few fields for a struct of type {}", sig))) }
+                Ok(MessageItem::Struct(r))
+            }
+            _ => Err(mismatch(&self)),
+        }
+    }
+}
+
 impl<'a> arg::Get<'a> for MessageItem {
     fn get(i: &mut Iter<'a>) -> Option<Self> {
         Some(match i.arg_type() {
@@ -521,7 +691,7 @@ impl<'a> arg::Get<'a> for MessageItem {
     }
 }
 
-impl arg::RefArg for MessageItem {
+impl RefArg for MessageItem {
     fn arg_type(&self) -> ArgType { MessageItem::arg_type(&self) }
     fn signature(&self) -> Signature<'static> { MessageItem::signature(&self) }
     fn append(&self, i: &mut IterAppend) { arg::Append::append_by_ref(self, i) }
@@ -529,8 +699,112 @@ impl arg::RefArg for MessageItem {
     fn as_any(&self) -> &dyn any::Any where Self: 'static { self }
     #[inline]
     fn as_any_mut(&mut self) -> &mut dyn any::Any where Self: 'static { self }
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            MessageItem::Bool(a) => a.as_i64(),
+            MessageItem::Byte(a) => a.as_i64(),
+            MessageItem::Int16(a) => a.as_i64(),
+            MessageItem::UInt16(a) => a.as_i64(),
+            MessageItem::Int32(a) => a.as_i64(),
+            MessageItem::UInt32(a) => a.as_i64(),
+            MessageItem::Int64(a) => a.as_i64(),
+            MessageItem::UnixFd(a) => a.as_i64(),
+            _ => None,
+        }
+    }
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            MessageItem::Bool(a) => a.as_u64(),
+            MessageItem::Byte(a) => a.as_u64(),
+            MessageItem::Int16(a) => a.as_u64(),
+            MessageItem::UInt16(a) => a.as_u64(),
+            MessageItem::Int32(a) => a.as_u64(),
+            MessageItem::UInt32(a) => a.as_u64(),
+            MessageItem::UInt64(a) => a.as_u64(),
+            _ => None,
+        }
+    }
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            MessageItem::Bool(a) => a.as_f64(),
+            MessageItem::Byte(a) => a.as_f64(),
+            MessageItem::Int16(a) => a.as_f64(),
+            MessageItem::UInt16(a) => a.as_f64(),
+            MessageItem::Int32(a) => a.as_f64(),
+            MessageItem::UInt32(a) => a.as_f64(),
+            MessageItem::Double(a) => a.as_f64(),
+            _ => None,
+        }
+    }
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            MessageItem::Str(a) => Some(a),
+            MessageItem::ObjectPath(a) => Some(a),
+            MessageItem::Signature(a) => Some(a),
+            _ => None,
+        }
+    }
+    fn as_iter<'a>(&'a self) -> Option<Box<dyn Iterator<Item=&'a dyn RefArg> + 'a>> {
+        match self {
+            MessageItem::Array(a) => Some(Box::new(a.v.iter().map(|x| x as &dyn RefArg))),
+            MessageItem::Struct(a) => Some(Box::new(a.iter().map(|x| x as &dyn RefArg))),
+            MessageItem::Variant(a) => Some(Box::new(std::iter::once(&**a as &dyn RefArg))),
+            MessageItem::Dict(a) => Some(Box::new(a.v.iter().flat_map(|(k, v)| vec![k as &dyn RefArg, v as &dyn RefArg].into_iter()))),
+            _ => None,
+        }
+    }
     #[inline]
-    fn box_clone(&self) -> Box<dyn arg::RefArg + 'static> { Box::new(self.clone()) }
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { Box::new(self.clone()) }
+}
+
+impl MessageItem {
+    /// Makes a MessageItem with the same type and contents as "r", e g to change or inspect a value
+    /// read as a `Box<dyn RefArg>`. Unix fds are duplicated.
+    ///
+    /// Returns None if "r" does not give access to its contents through `RefArg::as_i64` and the
+    /// other `as_*` methods, as needed for its type.
+    ///
+    /// # Example
+    /// ```
+    /// use dbus::arg::messageitem::MessageItem;
+    /// let v = vec![(1u8, String::from("one")), (2, String::from("two"))];
+    /// let m = MessageItem::from_refarg(&v).unwrap();
+    /// assert_eq!(&*m.signature(), "a(ys)");
+    /// ```
+    pub fn from_refarg(r: &dyn RefArg) -> Option<MessageItem> {
+        Some(match r.arg_type() {
+            ArgType::Boolean => MessageItem::Bool(r.as_u64()? != 0),
+            ArgType::Byte => MessageItem::Byte(r.as_u64()? as u8),
+            ArgType::Int16 => MessageItem::Int16(r.as_i64()? as i16),
+            ArgType::UInt16 => MessageItem::UInt16(r.as_u64()? as u16),
+            ArgType::Int32 => MessageItem::Int32(r.as_i64()? as i32),
+            ArgType::UInt32 => MessageItem::UInt32(r.as_u64()? as u32),
+            ArgType::Int64 => MessageItem::Int64(r.as_i64()?),
+            ArgType::UInt64 => MessageItem::UInt64(r.as_u64()?),
+            ArgType::Double => MessageItem::Double(r.as_f64()?),
+            ArgType::String => MessageItem::Str(r.as_str()?.into()),
+            ArgType::ObjectPath => MessageItem::ObjectPath(Path::new(r.as_str()?).ok()?),
+            ArgType::Signature => MessageItem::Signature(Signature::new(r.as_str()?).ok()?),
+            ArgType::UnixFd => {
+                let fd = unsafe { libc::dup(r.as_i64()? as libc::c_int) };
+                if fd < 0 { return None }
+                MessageItem::UnixFd(unsafe { OwnedFd::new(fd) })
+            }
+            ArgType::Variant => MessageItem::Variant(Box::new(MessageItem::from_refarg(r.as_iter()?.next()?)?)),
+            ArgType::Struct => MessageItem::Struct(r.as_iter()?.map(MessageItem::from_refarg).collect::<Option<_>>()?),
+            ArgType::Array => {
+                let (sig, mut i) = (r.signature(), r.as_iter()?);
+                if sig.as_bytes()[1] == b'{' {
+                    let mut v = vec!();
+                    while let Some(k) = i.next() { v.push((MessageItem::from_refarg(k)?, MessageItem::from_refarg(i.next()?)?)) }
+                    MessageItem::Dict(MessageItemDict { v, sig })
+                } else {
+                    MessageItem::Array(MessageItemArray { v: i.map(MessageItem::from_refarg).collect::<Option<_>>()?, sig })
+                }
+            }
+            ArgType::DictEntry | ArgType::Invalid => return None,
+        })
+    }
 }
 
 
@@ -960,4 +1234,75 @@ mod test {
 
     }
 
+    #[test]
+    fn from_refarg() {
+        use crate::arg::{RefArg, Variant};
+        use std::collections::HashMap;
+        let mut props: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        props.insert("Position".into(), Variant(Box::new((3i32, 4u16, Path::from("/north")))));
+        props.insert("Empty".into(), Variant(Box::new(Vec::<f64>::new())));
+        let m = Message::new_signal("/test", "com.example.test", "Test").unwrap()
+            .append3(props, vec!(true, false), (7u8, 1.5f64, -3i64, u64::MAX));
+
+        let read = m.get_items();
+        let dynamic: Vec<Box<dyn RefArg>> = m.iter_init().collect();
+        assert_eq!(dynamic.len(), 3);
+        for (r, d) in read.iter().zip(&dynamic) {
+            assert_eq!(MessageItem::from_refarg(&**d).as_ref(), Some(r));
+            // And back through MessageItem's own RefArg implementation
+            assert_eq!(MessageItem::from_refarg(r).as_ref(), Some(r));
+        }
+        let pos = read[0].as_dict().unwrap().get("Position").unwrap().as_struct().unwrap();
+        assert_eq!(pos.get(1).unwrap().as_u64(), Some(4));
+        assert_eq!(pos.get(2).unwrap().as_str(), Some("/north"));
+    }
+
+    #[test]
+    fn checked_signature() {
+        use crate::arg::messageitem::SignatureError;
+        let ok = MessageItem::Struct(vec!(5u8.into(), MessageItem::new_array(vec!("a".into(), "b".into())).unwrap()));
+        assert_eq!(&*ok.checked_signature().unwrap(), "(yas)");
+        assert_eq!(MessageItem::Struct(vec!()).checked_signature(), Err(SignatureError::EmptyStruct));
+        let v = MessageItem::Variant(Box::new(MessageItem::Struct(vec!())));
+        assert_eq!(v.checked_signature(), Err(SignatureError::EmptyStruct));
+
+        let mut deep: MessageItem = 1i32.into();
+        for _ in 0..40 { deep = MessageItem::Struct(vec!(deep)); }
+        assert!(matches!(deep.checked_signature(), Err(SignatureError::Invalid(_))));
+        let long = MessageItem::Struct((0..300).map(|i| MessageItem::from(i as u32)).collect());
+        assert_eq!(long.checked_signature(), Err(SignatureError::TooLong));
+    }
+
+    #[test]
+    fn coerce() {
+        use crate::arg::messageitem::SignatureError;
+        let c = |m: MessageItem, s: &str| m.coerce(&Signature::from(s));
+        assert_eq!(c(5i64.into(), "y"), Ok(MessageItem::Byte(5)));
+        assert_eq!(c(5u8.into(), "d"), Ok(MessageItem::Double(5.0)));
+        assert!(matches!(c((-1i32).into(), "t"), Err(SignatureError::Mismatch(_))));
+        assert!(matches!(c(300u32.into(), "y"), Err(SignatureError::Mismatch(_))));
+        assert!(matches!(c(true.into(), "i"), Err(SignatureError::Mismatch(_))));
+        assert!(matches!(c(1.5f64.into(), "i"), Err(SignatureError::Mismatch(_))));
+        assert_eq!(c("/a/b".into(), "o"), Ok(MessageItem::ObjectPath("/a/b".into())));
+        assert!(matches!(c("a b".into(), "o"), Err(SignatureError::Mismatch(_))));
+        assert_eq!(c(7i32.into(), "v"), Ok(MessageItem::Variant(Box::new(7i32.into()))));
+        assert_eq!(c(MessageItem::Variant(Box::new(7i32.into())), "x"), Ok(MessageItem::Int64(7)));
+
+        let s = MessageItem::Struct(vec!(1i32.into(), MessageItem::new_array(vec!(2i32.into(), 3i32.into())).unwrap()));
+        let r = c(s.clone(), "(yad)").unwrap();
+        assert_eq!(&*r.signature(), "(yad)");
+        assert_eq!(r, MessageItem::Struct(vec!(1u8.into(), MessageItem::new_array(vec!(2f64.into(), 3f64.into())).unwrap())));
+        assert!(matches!(c(s.clone(), "(y)"), Err(SignatureError::Mismatch(_))));
+        assert!(matches!(c(s, "(yaiu)"), Err(SignatureError::Mismatch(_))));
+
+        let d = MessageItem::new_dict(vec!(("a".into(), 1u8.into()), ("b".into(), 2u8.into()))).unwrap();
+        let r = c(d, "a{ov}");
+        assert!(matches!(r, Err(SignatureError::Mismatch(_))));
+        let d = MessageItem::new_dict(vec!(("/a".into(), 1u8.into()))).unwrap();
+        let r = c(d, "a{ov}").unwrap();
+        assert_eq!(&*r.signature(), "a{ov}");
+        let m = Message::new_signal("/test", "com.example.test", "Test").unwrap().append1(r.clone());
+        assert_eq!(m.get1::<MessageItem>(), Some(r));
+    }
+
 }
@@ -57,21 +57,42 @@
 //!
 //! `OwnedFd` - a file descriptor sent from the remote side.
 //!
+//! `PropMap` - a D-Bus `a{sv}` dictionary, as used for properties. Use `PropMapExt::get_typed`
+//! to read a single property as a specific type, or `FromPropMap` to read all of them into a struct.
+//!
+//! **With optional features**:
+//!
+//! `uuid::Uuid` (feature `uuid`) - appended as a hyphenated D-Bus string. Can be read from either a
+//! string or an array of 16 bytes.
+//!
+//! `chrono::DateTime<Utc>` (feature `chrono`) - appended as an i64 of microseconds since the Unix epoch.
+//! Can be read from either an i64 or an u64.
+//!
 
 mod msgarg;
 mod basic_impl;
 mod variantstruct_impl;
 mod array_impl;
+mod propmap;
+#[cfg(target_os = "linux")]
+mod memfd;
+#[cfg(feature = "uuid")]
+mod uuid_impl;
+#[cfg(feature = "chrono")]
+mod chrono_impl;
 
 pub mod messageitem;
 
-pub use self::msgarg::{Arg, FixedArray, Get, DictKey, Append, RefArg, AppendAll, ReadAll, ArgAll, cast, cast_mut};
+pub use self::msgarg::{Arg, FixedArray, Get, DictKey, Append, RefArg, StructRef, DictRef, AppendAll, ReadAll, ArgAll, cast, cast_mut, cached_signature};
 pub use self::array_impl::{Array, Dict};
 pub use self::variantstruct_impl::Variant;
+pub use self::propmap::{PropMap, PropMapExt, FromPropMap, prop_cast};
+#[cfg(target_os = "linux")]
+pub use self::memfd::{SealedBytes, MappedBytes};
 
 use std::{fmt, mem, ptr, error};
 use crate::{ffi, Message, Signature, Path};
-use std::ffi::{CStr, CString};
+use std::ffi::CStr;
 use std::os::raw::{c_void, c_int};
 use std::os::unix::io::{RawFd, AsRawFd, FromRawFd, IntoRawFd};
 
@@ -212,8 +233,17 @@ impl<'a> IterAppend<'a> {
     /// In order not to get D-Bus errors: during the call to "f", you should only call "append_dict_entry"
     /// for the subiterator - do this as many times as the number of dict entries.
     pub fn append_dict<F: FnOnce(&mut IterAppend<'a>)>(&mut self, key_sig: &Signature, value_sig: &Signature, f: F) {
-        let sig = format!("{{{}{}}}", key_sig, value_sig);
-        self.append_container(Array::<bool,()>::ARG_TYPE, Some(&CString::new(sig).unwrap()), f);
+        // Most dict signatures are short, so build them on the stack instead of allocating one per dict.
+        // libdbus keeps using the signature until the container is closed.
+        let len = key_sig.len() + value_sig.len() + 3;
+        let (mut buf, mut v) = ([0u8; 64], vec!());
+        let s = if len <= buf.len() { &mut buf[..len] } else { v.resize(len, 0); &mut v[..] };
+        s[0] = b'{';
+        s[1..1+key_sig.len()].copy_from_slice(key_sig.as_bytes());
+        s[1+key_sig.len()..len-2].copy_from_slice(value_sig.as_bytes());
+        s[len-2] = b'}';
+        let sig = CStr::from_bytes_with_nul(s).unwrap();
+        self.append_container(Array::<bool,()>::ARG_TYPE, Some(sig), f);
     }
 }
 
@@ -329,6 +359,19 @@ impl<'a> Iter<'a> {
         Ok(r)
     }
 
+    /// Returns a lazy iterator over the array in the current argument, and advances past it.
+    ///
+    /// Elements are decoded on demand, and types such as `&str` and `&CStr` borrow from the message,
+    /// so huge arrays can be filtered or partially consumed without decoding everything into a Vec.
+    /// Unlike `get`, this also checks the element signature, and returns None if it is not T.
+    pub fn recv_array<T: Arg + Get<'a>>(&mut self) -> Option<Array<'a, T, Iter<'a>>> {
+        if self.arg_type() != ArgType::Array { return None };
+        if self.signature().as_bytes()[1..] != *T::signature().as_bytes() { return None };
+        let r = self.get();
+        self.next();
+        r
+    }
+
     /// If the current argument is a container of the specified arg_type, then a new
     /// Iter is returned which is for iterating over the contents inside the container.
     ///
@@ -497,3 +540,22 @@ fn test_compile() {
     q.append((8u8, &[9u8, 6, 7][..]));
     q.append(Variant((6u8, 7u8)));
 }
+
+#[test]
+fn test_append_dict_sig() {
+    // Both short signatures, and signatures too long for the stack buffer.
+    let long = format!("({})", "s".repeat(100));
+    let mut m = Message::new_signal("/", "com.example.Dicts", "Dicts").unwrap();
+    let mut i = IterAppend::new(&mut m);
+    i.append_dict(&"s".into(), &"a{su}".into(), |d| for n in 0..3u32 {
+        d.append_dict_entry(|e| {
+            e.append(format!("item{}", n));
+            e.append_dict(&"s".into(), &"u".into(), |d| d.append_dict_entry(|e| { e.append("n"); e.append(n) }));
+        })
+    });
+    i.append_dict(&"s".into(), &Signature::new(&*long).unwrap(), |_| {});
+    let mut r = m.iter_init();
+    assert_eq!(&*r.signature(), "a{sa{su}}");
+    r.next();
+    assert_eq!(&*r.signature(), &*format!("a{{s{}}}", long));
+}
@@ -4,6 +4,10 @@ use crate::{Signature, arg::TypeMismatchError};
 use std::{fmt, any};
 use std::sync::Arc;
 use std::rc::Rc;
+use std::sync::RwLock;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::marker::PhantomData;
 
 use super::{Iter, IterAppend, ArgType};
 
@@ -100,6 +104,31 @@ pub trait RefArg: fmt::Debug {
     /// Works for: Array/Dict, Struct, Variant.
     #[inline]
     fn as_iter<'a>(&'a self) -> Option<Box<dyn Iterator<Item=&'a dyn RefArg> + 'a>> { None }
+    /// Try to read the argument as a struct, to get its fields by index.
+    ///
+    /// Works for: Struct, and Variant containing a Struct.
+    fn as_struct(&self) -> Option<StructRef<'_>> {
+        match self.arg_type() {
+            ArgType::Struct => Some(StructRef(self.as_iter()?.collect())),
+            ArgType::Variant => self.as_iter()?.next()?.as_struct(),
+            _ => None,
+        }
+    }
+    /// Try to read the argument as a dict, to get its values by key.
+    ///
+    /// Works for: Dict, and Variant containing a Dict.
+    fn as_dict(&self) -> Option<DictRef<'_>> {
+        match self.arg_type() {
+            ArgType::Array if self.signature().starts_with("a{") => {
+                let mut i = self.as_iter()?;
+                let mut v = vec!();
+                while let (Some(k), Some(x)) = (i.next(), i.next()) { v.push((k, x)) }
+                Some(DictRef(v))
+            }
+            ArgType::Variant => self.as_iter()?.next()?.as_dict(),
+            _ => None,
+        }
+    }
     /// Deep clone of the RefArg, causing the result to be 'static.
     ///
     /// Usable as an escape hatch in case of lifetime problems with RefArg.
@@ -109,6 +138,40 @@ pub trait RefArg: fmt::Debug {
     fn box_clone(&self) -> Box<dyn RefArg + 'static> { unimplemented!() /* Needed for backwards comp */ }
 }
 
+/// The fields of a struct, from `RefArg::as_struct`.
+#[derive(Debug, Clone)]
+pub struct StructRef<'a>(Vec<&'a dyn RefArg>);
+
+impl<'a> StructRef<'a> {
+    /// Returns field number "i", counting from zero.
+    pub fn get(&self, i: usize) -> Option<&'a dyn RefArg> { self.0.get(i).copied() }
+    /// The number of fields.
+    pub fn len(&self) -> usize { self.0.len() }
+    /// Returns true if the struct has no fields.
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+    /// Iterates over the fields.
+    pub fn iter(&self) -> impl Iterator<Item=&'a dyn RefArg> + '_ { self.0.iter().copied() }
+}
+
+/// The entries of a dict, from `RefArg::as_dict`.
+#[derive(Debug, Clone)]
+pub struct DictRef<'a>(Vec<(&'a dyn RefArg, &'a dyn RefArg)>);
+
+impl<'a> DictRef<'a> {
+    /// Returns the value for a string, object path or signature key.
+    pub fn get(&self, key: &str) -> Option<&'a dyn RefArg> { self.get_by(|k| k.as_str() == Some(key)) }
+    /// Returns the value of the first entry whose key matches, e g `d.get_by(|k| k.as_i64() == Some(5))`.
+    pub fn get_by<F: Fn(&dyn RefArg) -> bool>(&self, f: F) -> Option<&'a dyn RefArg> {
+        self.0.iter().find(|(k, _)| f(*k)).map(|(_, v)| *v)
+    }
+    /// The number of entries.
+    pub fn len(&self) -> usize { self.0.len() }
+    /// Returns true if the dict has no entries.
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+    /// Iterates over the keys and values.
+    pub fn iter(&self) -> impl Iterator<Item=(&'a dyn RefArg, &'a dyn RefArg)> + '_ { self.0.iter().copied() }
+}
+
 impl<'a> Get<'a> for Box<dyn RefArg> {
     fn get(i: &mut Iter<'a>) -> Option<Self> { i.get_refarg() }
 }
@@ -125,6 +188,48 @@ pub fn cast<'a, T: 'static>(a: &'a (dyn RefArg + 'static)) -> Option<&'a T> { a.
 #[inline]
 pub fn cast_mut<'a, T: 'static>(a: &'a mut (dyn RefArg + 'static)) -> Option<&'a mut T> { a.as_any_mut().downcast_mut() }
 
+// TypeId::of requires T: 'static, but many Arg types borrow (e g Vec<&str>). Signatures never
+// depend on lifetimes, so it is fine for such types to share an id with their 'static version.
+trait NonStaticAny {
+    fn type_id(&self) -> any::TypeId where Self: 'static;
+}
+
+impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+    fn type_id(&self) -> any::TypeId where Self: 'static { any::TypeId::of::<T>() }
+}
+
+fn type_id_of<T: ?Sized>() -> any::TypeId {
+    let p = PhantomData::<T>;
+    let p: &dyn NonStaticAny = &p;
+    // Only the lifetime is changed, and type_id does not look at the value.
+    let p: &(dyn NonStaticAny + 'static) = unsafe { std::mem::transmute(p) };
+    p.type_id()
+}
+
+/// Returns the signature of T, but only computes it once per container type.
+///
+/// Building the signature for an array, dict or struct means formatting and validating
+/// a new string, so this is worth using when appending such types in a hot path.
+/// Signatures of basic types and variants are static already and are returned directly.
+///
+/// The cached signatures live for the rest of the program, one per container type used.
+pub fn cached_signature<T: Arg + ?Sized>() -> Signature<'static> {
+    static CACHE: RwLock<BTreeMap<any::TypeId, &'static CStr>> = RwLock::new(BTreeMap::new());
+
+    match T::ARG_TYPE {
+        ArgType::Array | ArgType::Struct | ArgType::DictEntry => {},
+        _ => return T::signature(),
+    }
+    let key = type_id_of::<T>();
+    let cached = CACHE.read().unwrap().get(&key).copied();
+    let c = cached.unwrap_or_else(|| {
+        // Don't hold the lock here, T::signature might call cached_signature for its elements.
+        let c: &'static CStr = Box::leak(T::signature().into_cstring().into_boxed_c_str());
+        *CACHE.write().unwrap().entry(key).or_insert(c)
+    });
+    unsafe { Signature::from_slice_unchecked(c.to_bytes_with_nul()) }
+}
+
 /// If a type implements this trait, it means the size and alignment is the same
 /// as in D-Bus. This means that you can quickly append and get slices of this type.
 ///
@@ -281,7 +386,7 @@ argall_impl!(a A str, b B str, c C str, d D str, e E str, f F str, g G str, h H
 #[cfg(test)]
 mod test {
     use crate::{ffidisp::Connection, ffidisp::ConnectionItem, Message, Path, Signature};
-    use crate::arg::{Array, Variant, Dict, Iter, ArgType, TypeMismatchError, RefArg, cast};
+    use crate::arg::{Array, Variant, Dict, Iter, ArgType, TypeMismatchError, RefArg, cast, cached_signature};
 
     use std::collections::HashMap;
 
@@ -407,4 +512,74 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn recv_array() {
+        let m = Message::new_signal("/test", "com.example.test", "Test").unwrap()
+            .append2(vec!("Hello", "world", "foo", "bar"), 5u8);
+        let mut i = m.iter_init();
+        assert!(i.clone().recv_array::<u32>().is_none());
+        let a = i.recv_array::<&str>().unwrap();
+        let v: Vec<&str> = a.filter(|s| s.len() == 3).collect();
+        assert_eq!(v, vec!("foo", "bar"));
+        assert_eq!(i.get::<u8>(), Some(5));
+        assert!(i.recv_array::<u8>().is_none());
+    }
+
+    #[test]
+    fn struct_and_dict_access() {
+        let mut props: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        props.insert("Position", Variant(Box::new((3i32, 4i32, String::from("north")))));
+        props.insert("Name", Variant(Box::new(String::from("Bob"))));
+        let mut ids = HashMap::new();
+        ids.insert(7u16, "seven");
+        let m = Message::new_signal("/test", "com.example.test", "Test").unwrap().append3(props, (1u8, ids), 5u32);
+
+        let args: Vec<Box<dyn RefArg>> = m.iter_init().collect();
+        let d = args[0].as_dict().unwrap();
+        assert_eq!(d.len(), 2);
+        assert_eq!(d.get("Name").unwrap().as_iter().unwrap().next().unwrap().as_str(), Some("Bob"));
+        let pos = d.get("Position").unwrap().as_struct().unwrap();
+        assert_eq!(pos.len(), 3);
+        assert_eq!(pos.get(1).unwrap().as_i64(), Some(4));
+        assert_eq!(pos.get(2).unwrap().as_str(), Some("north"));
+        assert!(pos.get(3).is_none());
+        assert!(d.get("Missing").is_none());
+
+        let s = args[1].as_struct().unwrap();
+        assert_eq!(s.iter().count(), 2);
+        let ids = s.get(1).unwrap().as_dict().unwrap();
+        assert_eq!(ids.get_by(|k| k.as_u64() == Some(7)).unwrap().as_str(), Some("seven"));
+        assert!(s.get(1).unwrap().as_struct().is_none());
+
+        assert!(args[2].as_struct().is_none());
+        assert!(args[2].as_dict().is_none());
+        assert!(vec![1u8].as_dict().is_none());
+    }
+
+    #[test]
+    fn cached_sig() {
+        type T = Vec<(i32, HashMap<String, Variant<u8>>)>;
+        let s1 = cached_signature::<T>();
+        let s2 = cached_signature::<T>();
+        assert_eq!(&*s1, "a(ia{sv})");
+        assert_eq!(s1.as_cstr().as_ptr(), s2.as_cstr().as_ptr());
+        assert_eq!(&*cached_signature::<Vec<u8>>(), "ay");
+        assert_eq!(&*cached_signature::<Vec<i32>>(), "ai");
+        assert_eq!(&*cached_signature::<u8>(), "y");
+
+        // Borrowing types share an entry with their owned counterparts of the same type.
+        let s = String::from("a");
+        fn sig_of<'a>(_: &[&'a str]) -> Signature<'static> { cached_signature::<Vec<&'a str>>() }
+        assert_eq!(&*sig_of(&[&s]), "as");
+        assert_eq!(sig_of(&[&s]).as_cstr().as_ptr(), cached_signature::<Vec<&'static str>>().as_cstr().as_ptr());
+
+        let mut m = Message::new_method_call("a.b", "/a", "a.b", "C").unwrap();
+        m = m.append1(vec![(1i32, HashMap::<&str, Variant<u8>>::new())]);
+        m = m.append1(Dict::new(vec![("x", 5u8)]));
+        let mut i = m.iter_init();
+        assert_eq!(&*i.signature(), "a(ia{sv})");
+        assert!(i.next());
+        assert_eq!(&*i.signature(), "a{sy}");
+    }
 }
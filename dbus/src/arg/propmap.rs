@@ -0,0 +1,109 @@
+use super::{Arg, Get, RefArg, Variant, IterAppend, cast};
+use crate::{Error, Message};
+use std::collections::HashMap;
+
+/// A dictionary of properties, i e the D-Bus type `a{sv}`.
+///
+/// This is what e g `Properties::get_all` returns, and what is used for the changed properties
+/// of a PropertiesChanged signal.
+pub type PropMap = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
+
+/// Looks up a property and casts it to a specific type, in case it is stored as exactly that type.
+///
+/// Returns None if the property is missing, or has another type. Note that types read from a message
+/// are stored as the types returned by `Iter::get_refarg`, so e g an array of strings is a `Vec<String>`.
+pub fn prop_cast<'a, T: 'static>(map: &'a PropMap, key: &str) -> Option<&'a T> { cast(&*map.get(key)?.0) }
+
+/// Typed getters for a PropMap.
+pub trait PropMapExt {
+    /// Looks up a property and converts it to T, which is anything that can be read from a message.
+    ///
+    /// Fails with an error naming the property and its actual signature, if the property is missing
+    /// or cannot be read as T.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dbus::arg::{PropMap, PropMapExt, Variant};
+    /// let mut map = PropMap::new();
+    /// map.insert("Percentage".into(), Variant(Box::new(75u32)));
+    /// assert_eq!(map.get_typed::<u32>("Percentage").unwrap(), 75);
+    /// assert!(map.get_typed::<String>("Percentage").is_err());
+    /// assert!(map.get_typed::<u32>("Energy").is_err());
+    /// ```
+    fn get_typed<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<T, Error>;
+
+    /// Like `get_typed`, but returns `None` instead of failing if the property is missing.
+    fn get_typed_opt<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<Option<T>, Error>;
+}
+
+impl PropMapExt for PropMap {
+    fn get_typed<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<T, Error> {
+        let invalid = |s: String| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &s);
+        let v = self.get(key).ok_or_else(|| invalid(format!("Property '{}' not found", key)))?;
+        // Go through a message, so that every type Get can read from a message is supported.
+        let mut m = Message::new_signal("/", "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap();
+        v.0.append(&mut IterAppend::new(&mut m));
+        m.read1().map_err(|_| invalid(format!("Property '{}' has type '{}', expected '{}'", key, &*v.0.signature(), &*T::signature())))
+    }
+
+    fn get_typed_opt<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<Option<T>, Error> {
+        if self.contains_key(key) { self.get_typed(key).map(Some) } else { Ok(None) }
+    }
+}
+
+/// Types that can be made from a PropMap, e g the result of `Properties::get_all`.
+///
+/// Usually derived with `#[derive(FromPropMap)]` from the dbus-macros crate, which reads every field
+/// of a struct from the property with the same name in CamelCase. Fields of type `Option<T>` are
+/// `None` if the property is missing.
+///
+/// # Example
+/// ```rust
+/// use dbus::arg::{PropMap, FromPropMap, PropMapExt, Variant};
+/// use dbus::Error;
+///
+/// struct Battery { percentage: f64, vendor: Option<String> }
+///
+/// impl FromPropMap for Battery {
+///     fn from_prop_map(map: &PropMap) -> Result<Self, Error> {
+///         Ok(Battery { percentage: map.get_typed("Percentage")?, vendor: map.get_typed_opt("Vendor")? })
+///     }
+/// }
+///
+/// let mut map = PropMap::new();
+/// map.insert("Percentage".into(), Variant(Box::new(75.5f64)));
+/// let b = Battery::from_prop_map(&map).unwrap();
+/// assert_eq!((b.percentage, b.vendor), (75.5, None));
+/// ```
+pub trait FromPropMap: Sized {
+    /// Reads all fields from the properties in "map". Properties not needed are ignored.
+    fn from_prop_map(map: &PropMap) -> Result<Self, Error>;
+}
+
+#[test]
+fn test_propmap() {
+    use crate::Path;
+    let mut map = PropMap::new();
+    map.insert("Percentage".into(), Variant(Box::new(75.5f64)));
+    map.insert("Name".into(), Variant(Box::new("Battery".to_string())));
+    map.insert("Devices".into(), Variant(Box::new(vec!(Path::from("/dev0"), Path::from("/dev1")))));
+
+    // Read it back from a message, so the values are stored as the types get_refarg returns.
+    let m = Message::new_signal("/", "com.example.test", "Test").unwrap().append1(&map);
+    let map: PropMap = m.read1().unwrap();
+
+    assert_eq!(map.get_typed::<f64>("Percentage").unwrap(), 75.5);
+    assert_eq!(map.get_typed::<String>("Name").unwrap(), "Battery");
+    assert_eq!(map.get_typed::<Vec<Path>>("Devices").unwrap(), vec!(Path::from("/dev0"), Path::from("/dev1")));
+    assert_eq!(prop_cast::<String>(&map, "Name").map(|s| &**s), Some("Battery"));
+    assert_eq!(prop_cast::<u32>(&map, "Name"), None);
+    assert_eq!(prop_cast::<f64>(&map, "Voltage"), None);
+
+    let e = map.get_typed::<u32>("Percentage").unwrap_err();
+    assert_eq!(e.message(), Some("Property 'Percentage' has type 'd', expected 'u'"));
+    let e = map.get_typed::<u32>("Voltage").unwrap_err();
+    assert_eq!(e.message(), Some("Property 'Voltage' not found"));
+    assert_eq!(map.get_typed_opt::<u32>("Voltage").unwrap(), None);
+    assert_eq!(map.get_typed_opt::<f64>("Percentage").unwrap(), Some(75.5));
+    assert!(map.get_typed_opt::<u32>("Percentage").is_err());
+}
@@ -0,0 +1,53 @@
+// Arg implementations for the uuid crate, enabled with the "uuid" feature.
+
+use super::{Arg, Append, Get, RefArg, DictKey, ArgType, Iter, IterAppend};
+use crate::Signature;
+use std::any;
+use uuid::Uuid;
+
+// Appended as a hyphenated string, e g "67e55044-10b1-426f-9247-bb680e5fe0c8".
+impl Arg for Uuid {
+    const ARG_TYPE: ArgType = ArgType::String;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"s\0") } }
+}
+
+impl Append for Uuid {
+    fn append_by_ref(&self, i: &mut IterAppend) { i.append(self.to_string()) }
+}
+
+impl DictKey for Uuid {}
+
+// Reads either a string, or an array of 16 bytes, which is what e g systemd uses for its ids.
+impl<'a> Get<'a> for Uuid {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        match i.arg_type() {
+            ArgType::String => i.get::<&str>()?.parse().ok(),
+            ArgType::Array => Uuid::from_slice(i.get::<&[u8]>()?).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl RefArg for Uuid {
+    fn arg_type(&self) -> ArgType { ArgType::String }
+    fn signature(&self) -> Signature<'static> { <Uuid as Arg>::signature() }
+    fn append(&self, i: &mut IterAppend) { self.append_by_ref(i) }
+    #[inline]
+    fn as_any(&self) -> &dyn any::Any { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn any::Any { self }
+    #[inline]
+    fn box_clone(&self) -> Box<dyn RefArg + 'static> { Box::new(*self) }
+}
+
+#[test]
+fn test_uuid() {
+    use crate::Message;
+    let u = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+    let m = Message::new_signal("/test", "com.example.test", "Test").unwrap().append3(u, &u.as_bytes()[..], &[1u8, 2, 3][..]);
+    assert_eq!(&*m.iter_init().signature(), "s");
+    assert_eq!(m.get1::<&str>(), Some("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+    let (a, b, c): (Option<Uuid>, Option<Uuid>, Option<Uuid>) = m.get3();
+    assert_eq!((a, b, c), (Some(u), Some(u), None));
+}
+
@@ -28,7 +28,7 @@ impl<T> Arg for Variant<T> {
 impl<T: Arg + Append> Append for Variant<T> {
     fn append_by_ref(&self, i: &mut IterAppend) {
         let z = &self.0;
-        i.append_container(ArgType::Variant, Some(T::signature().as_cstr()), |s| z.append_by_ref(s));
+        i.append_container(ArgType::Variant, Some(cached_signature::<T>().as_cstr()), |s| z.append_by_ref(s));
     }
 }
 
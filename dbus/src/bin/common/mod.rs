@@ -0,0 +1,197 @@
+// Shared between the dbus-send-rs and dbus-monitor-rs binaries: argument parsing and message printing.
+
+use dbus::{Message, MessageType, Path, Signature};
+use dbus::arg::{ArgType, Iter, IterAppend, RefArg, Variant};
+use dbus::channel::BusType;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt::Write;
+
+/// An argument given on the command line, ready to be appended to a message.
+pub enum Value {
+    Basic(Box<dyn RefArg>),
+    Array(Signature<'static>, Vec<Box<dyn RefArg>>),
+    Dict(Signature<'static>, Signature<'static>, Vec<(Box<dyn RefArg>, Box<dyn RefArg>)>),
+}
+
+impl Value {
+    pub fn append(&self, i: &mut IterAppend) {
+        match self {
+            Value::Basic(v) => v.append(i),
+            Value::Array(sig, items) => i.append_array(sig, |s| for v in items { v.append(s) }),
+            Value::Dict(ksig, vsig, items) => i.append_dict(ksig, vsig, |s| for (k, v) in items {
+                s.append_dict_entry(|e| { k.append(e); v.append(e) })
+            }),
+        }
+    }
+}
+
+fn basic_sig(t: &str) -> Result<&'static str, String> {
+    Ok(match t {
+        "string" => "s", "objpath" => "o", "signature" => "g", "boolean" => "b", "byte" => "y",
+        "int16" => "n", "uint16" => "q", "int32" => "i", "uint32" => "u", "int64" => "x", "uint64" => "t", "double" => "d",
+        _ => return Err(format!("Unknown type '{}'", t)),
+    })
+}
+
+fn parse_num<T: std::str::FromStr + RefArg + 'static>(t: &str, v: &str) -> Result<Box<dyn RefArg>, String> {
+    v.parse::<T>().map(|x| Box::new(x) as Box<dyn RefArg>).map_err(|_| format!("Invalid {} '{}'", t, v))
+}
+
+fn parse_basic(t: &str, v: &str) -> Result<Box<dyn RefArg>, String> {
+    Ok(match basic_sig(t)? {
+        "s" => Box::new(v.to_string()),
+        "o" => Box::new(Path::new(v.to_string())?),
+        "g" => Box::new(Signature::new(v.to_string())?),
+        "b" => match v { "true" => Box::new(true), "false" => Box::new(false), _ => Err(format!("Invalid boolean '{}'", v))? },
+        "y" => parse_num::<u8>(t, v)?,
+        "n" => parse_num::<i16>(t, v)?,
+        "q" => parse_num::<u16>(t, v)?,
+        "i" => parse_num::<i32>(t, v)?,
+        "u" => parse_num::<u32>(t, v)?,
+        "x" => parse_num::<i64>(t, v)?,
+        "t" => parse_num::<u64>(t, v)?,
+        _ => parse_num::<f64>(t, v)?,
+    })
+}
+
+/// Parses an argument in dbus-send syntax: `<type>:<value>`, `variant:<type>:<value>`,
+/// `array:<type>:<value>,<value>...` or `dict:<keytype>:<valuetype>:<key>,<value>,<key>,<value>...`.
+pub fn parse_arg(s: &str) -> Result<Value, String> {
+    let (t, rest) = s.split_at(s.find(':').ok_or_else(|| format!("Missing type in '{}'", s))?);
+    let rest = &rest[1..];
+    let split = |s: &'_ str| if s.is_empty() { vec!() } else { s.split(',').map(String::from).collect::<Vec<_>>() };
+    match t {
+        "variant" => {
+            let (t, v) = rest.split_at(rest.find(':').ok_or("Missing variant type")?);
+            Ok(Value::Basic(Box::new(Variant(parse_basic(t, &v[1..])?))))
+        },
+        "array" => {
+            let (t, v) = rest.split_at(rest.find(':').ok_or("Missing array type")?);
+            let items = split(&v[1..]).iter().map(|v| parse_basic(t, v)).collect::<Result<_, _>>()?;
+            Ok(Value::Array(Signature::from(basic_sig(t)?), items))
+        },
+        "dict" => {
+            let mut parts = rest.splitn(3, ':');
+            let (kt, vt, v) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kt), Some(vt), Some(v)) => (kt, vt, v),
+                _ => return Err("Missing dict types".into()),
+            };
+            let items = split(v);
+            if items.len() % 2 != 0 { return Err("Dict needs an even number of items".into()) }
+            let items = items.chunks(2).map(|kv| Ok((parse_basic(kt, &kv[0])?, parse_basic(vt, &kv[1])?))).collect::<Result<_, String>>()?;
+            Ok(Value::Dict(Signature::from(basic_sig(kt)?), Signature::from(basic_sig(vt)?), items))
+        },
+        _ => Ok(Value::Basic(parse_basic(t, rest)?)),
+    }
+}
+
+/// Handles the `--system` and `--session` options; returns false if "a" is not one of them.
+pub fn parse_bus(a: &str, bus: &mut BusType) -> bool {
+    match a {
+        "--system" => *bus = BusType::System,
+        "--session" => *bus = BusType::Session,
+        _ => return false,
+    }
+    true
+}
+
+fn write_arg(out: &mut String, i: &mut Iter, indent: usize, pad: bool) {
+    if pad { out.push_str(&" ".repeat(indent)) }
+    let t = i.arg_type();
+    let container = |out: &mut String, i: &mut Iter, open: &str, close: &str| {
+        out.push_str(open);
+        out.push('\n');
+        let mut sub = i.recurse(t).unwrap();
+        write_args(out, &mut sub, indent + 3);
+        let _ = writeln!(out, "{}{}", " ".repeat(indent), close);
+    };
+    match t {
+        ArgType::Array => container(out, i, "array [", "]"),
+        ArgType::Struct => container(out, i, "struct {", "}"),
+        ArgType::DictEntry => container(out, i, "dict entry(", ")"),
+        ArgType::Variant => {
+            out.push_str("variant ");
+            write_arg(out, &mut i.recurse(t).unwrap(), indent, false);
+        },
+        ArgType::String => { let _ = writeln!(out, "string {:?}", i.get::<&str>().unwrap()); },
+        ArgType::ObjectPath => { let _ = writeln!(out, "object path {:?}", &*i.get::<Path>().unwrap()); },
+        ArgType::Signature => { let _ = writeln!(out, "signature {:?}", &*i.get::<Signature>().unwrap()); },
+        ArgType::Boolean => { let _ = writeln!(out, "boolean {}", i.get::<bool>().unwrap()); },
+        ArgType::Byte => { let _ = writeln!(out, "byte {}", i.get::<u8>().unwrap()); },
+        ArgType::Int16 => { let _ = writeln!(out, "int16 {}", i.get::<i16>().unwrap()); },
+        ArgType::UInt16 => { let _ = writeln!(out, "uint16 {}", i.get::<u16>().unwrap()); },
+        ArgType::Int32 => { let _ = writeln!(out, "int32 {}", i.get::<i32>().unwrap()); },
+        ArgType::UInt32 => { let _ = writeln!(out, "uint32 {}", i.get::<u32>().unwrap()); },
+        ArgType::Int64 => { let _ = writeln!(out, "int64 {}", i.get::<i64>().unwrap()); },
+        ArgType::UInt64 => { let _ = writeln!(out, "uint64 {}", i.get::<u64>().unwrap()); },
+        ArgType::Double => { let _ = writeln!(out, "double {}", i.get::<f64>().unwrap()); },
+        ArgType::UnixFd => out.push_str("file descriptor\n"),
+        ArgType::Invalid => {},
+    }
+}
+
+fn write_args(out: &mut String, i: &mut Iter, indent: usize) {
+    while i.arg_type() != ArgType::Invalid {
+        write_arg(out, i, indent, true);
+        i.next();
+    }
+}
+
+/// Formats a message like dbus-monitor does: a header line, followed by one line per argument.
+///
+/// Takes a mutable reference since the error name of an error message can only be read through `as_result`.
+pub fn format_message(m: &mut Message) -> String {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut out = format!("{} time={}.{:06} sender={} -> destination={} serial={}",
+        match m.msg_type() {
+            MessageType::MethodCall => "method call",
+            MessageType::MethodReturn => "method return",
+            MessageType::Error => "error",
+            MessageType::Signal => "signal",
+        },
+        t.as_secs(), t.subsec_micros(),
+        m.sender().as_deref().unwrap_or("(null)"), m.destination().as_deref().unwrap_or("(null destination)"),
+        m.get_serial().unwrap_or(0));
+    match m.msg_type() {
+        MessageType::MethodCall | MessageType::Signal => {
+            let _ = write!(out, " path={}; interface={}; member={}", m.path().as_deref().unwrap_or(""),
+                m.interface().as_deref().unwrap_or(""), m.member().as_deref().unwrap_or(""));
+        },
+        MessageType::Error => {
+            let e = m.as_result().err();
+            let _ = write!(out, " error_name={} reply_serial={}", e.as_ref().and_then(|e| e.name()).unwrap_or(""), m.get_reply_serial().unwrap_or(0));
+        },
+        MessageType::MethodReturn => { let _ = write!(out, " reply_serial={}", m.get_reply_serial().unwrap_or(0)); },
+    }
+    out.push('\n');
+    write_args(&mut out, &mut m.iter_init(), 3);
+    out
+}
+
+/// Appends the arguments to a message.
+pub fn append_args(m: &mut Message, args: &[Value]) {
+    let mut i = IterAppend::new(m);
+    for a in args { a.append(&mut i) }
+}
+
+#[test]
+fn test_parse_and_format() {
+    let args: Vec<_> = ["string:hello", "int32:-5", "boolean:true", "objpath:/a/b", "variant:uint64:7",
+        "array:int16:1,2", "array:string:", "dict:string:double:a,1.5,b,2"].iter().map(|a| parse_arg(a).unwrap()).collect();
+    let mut m = Message::new_signal("/test", "com.example.dbusrs.Send", "Test").unwrap();
+    append_args(&mut m, &args);
+    let sig: String = m.iter_init().map(|a| a.signature().to_string()).collect();
+    assert_eq!(sig, "sibovanasa{sd}");
+    let s = format_message(&mut m);
+    let lines: Vec<_> = s.lines().collect();
+    assert!(lines[0].starts_with("signal time="));
+    assert!(lines[0].ends_with("path=/test; interface=com.example.dbusrs.Send; member=Test"));
+    assert_eq!(&lines[1..8], &["   string \"hello\"", "   int32 -5", "   boolean true", "   object path \"/a/b\"",
+        "   variant uint64 7", "   array [", "      int16 1"]);
+    assert!(s.contains("   array [\n   ]\n   array [\n      dict entry(\n         string \"a\"\n         double 1.5\n      )\n"));
+
+    assert!(parse_arg("int32:x").is_err());
+    assert!(parse_arg("float:1").is_err());
+    assert!(parse_arg("dict:string:int32:a").is_err());
+    assert!(parse_arg("hello").is_err());
+}
@@ -0,0 +1,42 @@
+// A dbus-monitor lookalike: prints all messages on the bus, or the ones matching the given rules.
+
+#[allow(dead_code)]
+mod common;
+
+use dbus::Message;
+use dbus::channel::{Channel, BusType};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: dbus-monitor-rs [--system | --session] [watch expressions ...]
+
+Watch expressions are match rules, e g \"type='signal',interface='org.freedesktop.DBus'\".";
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bus = BusType::Session;
+    let mut rules = vec!();
+    for a in args {
+        if common::parse_bus(&a, &mut bus) { continue; }
+        if a.starts_with("--") { Err(format!("Unknown option '{}'", a))? }
+        rules.push(a);
+    }
+    let c = Channel::get_private(bus)?;
+    // After this, the connection can only receive, so nothing else can be sent.
+    let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus.Monitoring", "BecomeMonitor")?
+        .append2(rules, 0u32);
+    c.send_with_reply_and_block(m, Duration::from_secs(5))?;
+    loop {
+        if let Some(mut m) = c.blocking_pop_message(Duration::from_secs(3600))? { println!("{}", common::format_message(&mut m)); }
+    }
+}
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,69 @@
+// A dbus-send lookalike: sends a method call or signal, built from the command line.
+
+mod common;
+
+use dbus::{Message, Error};
+use dbus::channel::{Channel, BusType};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: dbus-send-rs [--system | --session] [--dest=NAME] [--print-reply] [--reply-timeout=MSEC] \
+[--type=TYPE] <path> <interface.member> [contents ...]
+
+TYPE is method_call (the default) or signal. Contents are given as <type>:<value>, where type is one of
+string, objpath, signature, boolean, byte, int16, uint16, int32, uint32, int64, uint64 and double.
+Containers are given as array:<type>:<value>,<value>..., dict:<keytype>:<valuetype>:<key>,<value>,...
+and variant:<type>:<value>.";
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bus = BusType::Session;
+    let (mut dest, mut print_reply, mut timeout, mut signal) = (None, false, Duration::from_millis(25000), false);
+    let mut rest = vec!();
+    for a in args {
+        if !rest.is_empty() || !a.starts_with("--") { rest.push(a); continue; }
+        if common::parse_bus(&a, &mut bus) { continue; }
+        let (k, v) = match a.find('=') { Some(i) => (&a[..i], Some(&a[i+1..])), None => (&*a, None) };
+        match (k, v) {
+            ("--dest", Some(v)) => dest = Some(v.to_string()),
+            ("--print-reply", None) => print_reply = true,
+            ("--reply-timeout", Some(v)) => timeout = Duration::from_millis(v.parse()?),
+            ("--type", Some("method_call")) => signal = false,
+            ("--type", Some("signal")) => signal = true,
+            _ => Err(format!("Unknown option '{}'", a))?,
+        }
+    }
+    if rest.len() < 2 { Err("Missing path or member")? }
+    let name = &rest[1];
+    let (iface, member) = name.split_at(name.rfind('.').ok_or("Member must be given as interface.member")?);
+    let mut m = if signal {
+        let mut m = Message::new_signal(&*rest[0], iface, &member[1..])?;
+        if let Some(d) = dest { m.set_destination(Some(d.into())) }
+        m
+    } else {
+        Message::new_method_call(dest.ok_or("Method calls need --dest")?, &*rest[0], iface, &member[1..])?
+    };
+    let contents = rest[2..].iter().map(|a| common::parse_arg(a)).collect::<Result<Vec<_>, _>>()?;
+    common::append_args(&mut m, &contents);
+
+    let c = Channel::get_private(bus)?;
+    if print_reply && !signal {
+        let mut r = c.send_with_reply_and_block(m, timeout)?;
+        print!("{}", common::format_message(&mut r));
+    } else {
+        if !print_reply { m.set_no_reply(true) }
+        c.send(m).map_err(|_| Error::new_failed("Sending the message failed"))?;
+        c.flush();
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,44 +1,94 @@
 //! Connections and proxies that make blocking method calls.
 
 
-use crate::strings::{BusName, Path, Interface, Member};
+use crate::strings::{BusName, UniqueName, WellKnownName, Path, Interface, Member};
 use crate::arg::{AppendAll, ReadAll, IterAppend};
 use crate::{channel, Error, Message};
 use crate::message::{MatchRule, SignalArgs};
 use crate::channel::{Channel, BusType, Token};
-use std::{cell::RefCell, time::Duration, sync::Mutex};
+use std::{cell::RefCell, time::{Duration, Instant}, sync::{Arc, Mutex}};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
 use crate::filters::Filters;
+use crate::tree::{Credentials, CredentialsSource};
 
 pub mod stdintf;
 
+mod dispatcher;
+pub use self::dispatcher::{Dispatcher, ConnectionId};
+
+mod watcher;
+pub use self::watcher::ServiceWatcher;
+
+mod retry;
+pub use self::retry::Retry;
+
 
 
 /// A connection to D-Bus, thread local + non-async version
 pub struct LocalConnection {
     channel: Channel,
     filters: RefCell<Filters<LocalFilterCb>>,
+    credentials: CredentialsCache,
 }
 
 /// A connection to D-Bus, non-async version where callbacks are Send but not Sync.
 pub struct Connection {
     channel: Channel,
     filters: RefCell<Filters<FilterCb>>,
+    credentials: CredentialsCache,
 }
 
 /// A connection to D-Bus, Send + Sync + non-async version
 pub struct SyncConnection {
     channel: Channel,
-    filters: Mutex<Filters<SyncFilterCb>>
+    filters: Mutex<Filters<SyncFilterCb>>,
+    credentials: CredentialsCache,
 }
 
 use crate::blocking::stdintf::org_freedesktop_dbus;
 
+// Unique names are never reused, so cached credentials never go stale,
+// but connections come and go, so start over once the cache has grown this large.
+const CREDENTIALS_CACHE_SIZE: usize = 256;
+
+#[derive(Default)]
+struct CredentialsCache(Mutex<HashMap<String, Credentials>>);
+
+impl CredentialsCache {
+    fn get<S: BlockingSender>(&self, conn: &S, name: &BusName) -> Result<Credentials, Error> {
+        if let Some(c) = self.0.lock().unwrap().get(&**name) { return Ok(c.clone()) }
+        use crate::blocking::stdintf::org_freedesktop::DBus;
+        let c = Credentials::from_map(&stdintf::proxy(conn).get_connection_credentials(name)?);
+        // Well-known names can change owner, so only cache unique names.
+        if name.is_unique() {
+            let mut cache = self.0.lock().unwrap();
+            if cache.len() >= CREDENTIALS_CACHE_SIZE { cache.clear(); }
+            cache.insert(name.to_string(), c.clone());
+        }
+        Ok(c)
+    }
+}
+
 macro_rules! connimpl {
      ($c: ident, $cb: ident $(, $ss:tt)*) =>  {
 
 type
     $cb = Box<dyn FnMut(Message, &$c) -> bool $(+ $ss)* + 'static>;
 
+impl From<Channel> for $c {
+    fn from(x: Channel) -> Self {
+        $c {
+            channel: x,
+            filters: Default::default(),
+            credentials: Default::default(),
+        }
+    }
+}
+
+impl AsRef<Channel> for $c {
+    fn as_ref(&self) -> &Channel { &self.channel }
+}
 
 impl $c {
 
@@ -46,12 +96,14 @@ impl $c {
     pub fn new_session() -> Result<Self, Error> { Ok($c {
         channel: Channel::get_private(BusType::Session)?,
         filters: Default::default(),
+        credentials: Default::default(),
     })}
 
     /// Create a new connection to the system-wide bus.
     pub fn new_system() -> Result<Self, Error> { Ok($c {
         channel: Channel::get_private(BusType::System)?,
         filters: Default::default(),
+        credentials: Default::default(),
     })}
 
     /// Get the connection's unique name.
@@ -59,12 +111,38 @@ impl $c {
     /// It's usually something like ":1.54"
     pub fn unique_name(&self) -> BusName { self.channel.unique_name().unwrap().into() }
 
+    /// Get the connection's unique name, as a UniqueName.
+    pub fn unique_bus_name(&self) -> UniqueName<'_> { self.channel.unique_name().unwrap().into() }
+
+    /// Returns a cloneable handle for sending messages on this connection from other threads.
+    ///
+    /// See `channel::MsgSender` for details.
+    pub fn msg_sender(&self) -> channel::MsgSender { self.channel.msg_sender() }
+
     /// Create a convenience struct for easier calling of many methods on the same destination and path.
     pub fn with_proxy<'a, 'b, D: Into<BusName<'a>>, P: Into<Path<'a>>>(&'b self, dest: D, path: P, timeout: Duration) ->
     Proxy<'a, &'b Self> {
-        Proxy { connection: self, destination: dest.into(), path: path.into(), timeout }
+        Proxy { connection: self, destination: dest.into(), path: path.into(), timeout, allow_interactive_authorization: false, retry: None }
     }
 
+    /// Create a convenience struct for easier calling of many methods on the same destination and path.
+    ///
+    /// Same as `with_proxy`, but method calls time out after the connection's default timeout.
+    pub fn proxy<'a, 'b, D: Into<BusName<'a>>, P: Into<Path<'a>>>(&'b self, dest: D, path: P) -> Proxy<'a, &'b Self> {
+        self.with_proxy(dest, path, self.default_timeout())
+    }
+
+    /// Sets the timeout for method calls made without an explicit timeout, e g through `proxy`.
+    ///
+    /// Calls to the bus daemon, e g when requesting names and adding matches, keep their
+    /// timeout of 5 seconds.
+    ///
+    /// `None` means to use the global default, see `channel::set_default_timeout`.
+    pub fn set_default_timeout(&mut self, t: Option<Duration>) { self.channel.set_default_timeout(t) }
+
+    /// The timeout for method calls made without an explicit timeout.
+    pub fn default_timeout(&self) -> Duration { self.channel.default_timeout() }
+
 
     /// Request a name on the D-Bus.
     ///
@@ -79,6 +157,25 @@ impl $c {
         org_freedesktop_dbus::release_name(&self.channel, &name.into())
     }
 
+    /// Same as `request_name`, but only accepts well-known names.
+    pub fn request_well_known_name<'a, N: Into<WellKnownName<'a>>>(&self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+    -> Result<org_freedesktop_dbus::RequestNameReply, Error> {
+        let name: WellKnownName = name.into();
+        self.request_name(name, allow_replacement, replace_existing, do_not_queue)
+    }
+
+    /// Same as `release_name`, but only accepts well-known names.
+    pub fn release_well_known_name<'a, N: Into<WellKnownName<'a>>>(&self, name: N) -> Result<org_freedesktop_dbus::ReleaseNameReply, Error> {
+        let name: WellKnownName = name.into();
+        self.release_name(name)
+    }
+
+    /// Sends a method call without waiting for the reply.
+    ///
+    /// The returned PendingCall can be used to wait for the reply, or to cancel the call.
+    #[allow(clippy::result_unit_err)] // Same error type as channel::Sender
+    pub fn send_with_reply(&self, msg: Message) -> Result<channel::PendingCall<'_>, ()> { self.channel.send_with_reply(msg) }
+
     /// Adds a new match to the connection, and sets up a callback when this message arrives.
     ///
     /// The returned value can be used to remove the match. The match is also removed if the callback
@@ -93,6 +190,7 @@ impl $c {
 
     /// Adds a new match to the connection, without setting up a callback when this message arrives.
     pub fn add_match_no_cb(&self, match_str: &str) -> Result<(), Error> {
+        trace_event!(match_rule = match_str, "Adding match");
         use crate::blocking::stdintf::org_freedesktop::DBus;
         let proxy = stdintf::proxy(self);
         proxy.add_match(match_str)
@@ -100,6 +198,7 @@ impl $c {
 
     /// Removes a match from the connection, without removing any callbacks.
     pub fn remove_match_no_cb(&self, match_str: &str) -> Result<(), Error> {
+        trace_event!(match_rule = match_str, "Removing match");
         use crate::blocking::stdintf::org_freedesktop::DBus;
         let proxy = stdintf::proxy(self);
         proxy.remove_match(match_str)
@@ -112,12 +211,74 @@ impl $c {
         self.remove_match_no_cb(&mr.match_str())
     }
 
+    /// Calls org.freedesktop.DBus.Peer.Ping on "dest", and returns how long it took to get a reply.
+    ///
+    /// Useful as a health check. In case of failure, `Error::kind` tells why, e g `ErrorKind::Timeout`
+    /// if there was no reply in time, or `ErrorKind::ServiceUnknown` if nobody owns the name.
+    pub fn ping<'a, D: Into<BusName<'a>>>(&self, dest: D, timeout: Duration) -> Result<Duration, Error> {
+        let start = Instant::now();
+        self.with_proxy(dest, "/", timeout).method_call::<(), _, _, _>("org.freedesktop.DBus.Peer", "Ping", ())?;
+        Ok(start.elapsed())
+    }
+
+    /// Starts calling the callbacks of "w" when the names it watches appear or vanish.
+    ///
+    /// See `ServiceWatcher` for details. Blocking: while asking the bus for the current owners.
+    /// The returned value can be given to `remove_match` to stop watching.
+    pub fn watch_services(&self, w: ServiceWatcher) -> Result<Token, Error> {
+        use stdintf::org_freedesktop::DBusNameOwnerChanged as Noc;
+        let mr = Noc::match_rule(Some(&"org.freedesktop.DBus".into()), None).static_clone();
+        let w2 = w.clone();
+        let token = self.add_match(mr, move |s: Noc, _: &Self, _: &Message| { w2.changed(&s.arg0, &s.arg2); true })?;
+        if let Err(e) = w.probe(self) {
+            let _ = self.remove_match(token);
+            return Err(e)
+        }
+        Ok(token)
+    }
+
+    /// Waits for the first message matching the match rule, and returns it.
+    ///
+    /// The match is added before waiting, and removed again before returning. Other incoming
+    /// messages are dispatched as usual while waiting. Returns an org.freedesktop.DBus.Error.Timeout
+    /// error if no matching message arrived within the timeout.
+    pub fn wait_for_signal(&mut self, match_rule: MatchRule<'static>, timeout: Duration) -> Result<Message, Error> {
+        use channel::MatchingReceiver;
+        let deadline = Instant::now() + timeout;
+        let m = match_rule.match_str();
+        self.add_match_no_cb(&m)?;
+        let found = Arc::new(Mutex::new(None));
+        let found2 = found.clone();
+        let token = self.start_receive(match_rule, Box::new(move |msg, _| { *found2.lock().unwrap() = Some(msg); false }));
+        let r = loop {
+            if let Some(msg) = found.lock().unwrap().take() { break Ok(msg) }
+            let now = Instant::now();
+            if now >= deadline { break Err(Error::new_custom("org.freedesktop.DBus.Error.Timeout", "Timed out waiting for signal")) }
+            if let Err(e) = self.process(deadline - now) { break Err(e) }
+        };
+        self.stop_receive(token);
+        // Failing to clean up must not replace the signal, or the error that ended the wait.
+        if let Err(_e) = self.remove_match_no_cb(&m) {
+            trace_event!(match_rule = %m, error = ?_e, "Removing match failed");
+        }
+        r
+    }
+
     /// Tries to handle an incoming message if there is one. If there isn't one,
     /// it will wait up to timeout
     pub fn process(&mut self, timeout: Duration) -> Result<bool, Error> {
+        Process::process_one(self, timeout)
+    }
+}
+
+impl Process for $c {
+    fn process_one(&self, timeout: Duration) -> Result<bool, Error> {
+        let _guard = ProcessGuard::enter(&self.channel)?;
         if let Some(msg) = self.channel.blocking_pop_message(timeout)? {
+            trace_span!("dispatch", serial = ?msg.get_serial());
             let ff = self.filters_mut().remove_matching(&msg);
             if let Some(mut ff) = ff {
+                trace_event!(token = (ff.0).0, match_rule = %ff.1.match_str(), "Calling message callback");
                 if ff.2(msg, self) {
                     self.filters_mut().insert(ff);
                 }
@@ -129,12 +290,19 @@ impl $c {
             Ok(false)
         }
     }
+
+    fn make_filter<G: FnMut(Message, &Self) -> bool + Send + Sync + 'static>(g: G) -> $cb { Box::new(g) }
 }
 
 impl BlockingSender for $c {
     fn send_with_reply_and_block(&self, msg: Message, timeout: Duration) -> Result<Message, Error> {
         self.channel.send_with_reply_and_block(msg, timeout)
     }
+    fn default_timeout(&self) -> Duration { self.channel.default_timeout() }
+}
+
+impl CredentialsSource for $c {
+    fn credentials(&self, name: &BusName) -> Result<Credentials, Error> { self.credentials.get(self, name) }
 }
 
 impl channel::Sender for $c {
@@ -192,12 +360,18 @@ pub trait BlockingSender {
     ///
     /// Note: In case of an error reply, this is returned as an Err(), not as a Ok(Message) with the error type.
     fn send_with_reply_and_block(&self, msg: Message, timeout: Duration) -> Result<Message, Error>;
+
+    /// The timeout for method calls made without an explicit timeout.
+    ///
+    /// Defaults to the global default, see `channel::default_timeout`.
+    fn default_timeout(&self) -> Duration { channel::default_timeout() }
 }
 
 impl BlockingSender for Channel {
     fn send_with_reply_and_block(&self, msg: Message, timeout: Duration) -> Result<Message, Error> {
         Channel::send_with_reply_and_block(self, msg, timeout)
     }
+    fn default_timeout(&self) -> Duration { Channel::default_timeout(self) }
 }
 
 /// A struct that wraps a connection, destination and path.
@@ -215,13 +389,28 @@ pub struct Proxy<'a, C> {
     pub timeout: Duration,
     /// Some way to send and/or receive messages, either blocking or non-blocking.
     pub connection: C,
+    allow_interactive_authorization: bool,
+    retry: Option<Retry>,
 }
 
 impl<'a, C> Proxy<'a, C> {
     /// Creates a new proxy struct.
     pub fn new<D: Into<BusName<'a>>, P: Into<Path<'a>>>(dest: D, path: P, timeout: Duration, connection: C) -> Self {
-        Proxy { destination: dest.into(), path: path.into(), timeout, connection }
+        Proxy { destination: dest.into(), path: path.into(), timeout, connection, allow_interactive_authorization: false, retry: None }
     }
+
+    /// Sets whether method calls allow the remote side to prompt the user for authorization,
+    /// see `Message::set_allow_interactive_authorization`. Defaults to false.
+    pub fn with_interactive_authorization(mut self, allow: bool) -> Self { self.allow_interactive_authorization = allow; self }
+
+    /// Whether method calls allow the remote side to prompt the user for authorization.
+    pub fn allow_interactive_authorization(&self) -> bool { self.allow_interactive_authorization }
+
+    /// Sets how failed method calls are retried, see `Retry`. By default, they are not retried.
+    pub fn with_retry(mut self, retry: Retry) -> Self { self.retry = Some(retry); self }
+
+    /// How failed method calls are retried, if they are.
+    pub fn retry(&self) -> Option<&Retry> { self.retry.as_ref() }
 }
 
 impl<'a, T: BlockingSender, C: std::ops::Deref<Target=T>> Proxy<'a, C> {
@@ -240,10 +429,18 @@ impl<'a, T: BlockingSender, C: std::ops::Deref<Target=T>> Proxy<'a, C> {
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn method_call<'i, 'm, R: ReadAll, A: AppendAll, I: Into<Interface<'i>>, M: Into<Member<'m>>>(&self, i: I, m: M, args: A) -> Result<R, Error> {
-        let mut msg = Message::method_call(&self.destination, &self.path, &i.into(), &m.into());
-        args.append(&mut IterAppend::new(&mut msg));
-        let r = self.connection.send_with_reply_and_block(msg, self.timeout)?;
-        Ok(R::read(&mut r.iter_init())?)
+        let (i, m) = (i.into(), m.into());
+        let call = || {
+            let mut msg = Message::method_call(&self.destination, &self.path, &i, &m);
+            msg.set_allow_interactive_authorization(self.allow_interactive_authorization);
+            args.append(&mut IterAppend::new(&mut msg));
+            let r = self.connection.send_with_reply_and_block(msg, self.timeout)?;
+            Ok(R::read(&mut r.iter_init())?)
+        };
+        match &self.retry {
+            Some(r) => r.call(call),
+            None => call(),
+        }
     }
 
     /// Starts matching incoming messages on this destination and path.
@@ -258,6 +455,7 @@ impl<'a, T: BlockingSender, C: std::ops::Deref<Target=T>> Proxy<'a, C> {
     where T: channel::MatchingReceiver {
         mr.path = Some(self.path.clone().into_static());
         mr.sender = Some(self.destination.clone().into_static());
+        trace_event!(match_rule = %mr.match_str(), call_add_match, "Starting match");
         if call_add_match {
             use crate::blocking::stdintf::org_freedesktop::DBus;
             let proxy = stdintf::proxy(&*self.connection);
@@ -274,6 +472,7 @@ impl<'a, T: BlockingSender, C: std::ops::Deref<Target=T>> Proxy<'a, C> {
     pub fn match_stop(&self, id: Token, call_remove_match: bool) -> Result<(), Error>
     where T: channel::MatchingReceiver {
         if let Some((mr, _)) = self.connection.stop_receive(id) {
+            trace_event!(match_rule = %mr.match_str(), call_remove_match, "Stopping match");
             if call_remove_match {
                 use crate::blocking::stdintf::org_freedesktop::DBus;
                 let proxy = stdintf::proxy(&*self.connection);
@@ -295,6 +494,105 @@ impl<'a, T: BlockingSender, C: std::ops::Deref<Target=T>> Proxy<'a, C> {
         let ff = f.make(mr.match_str());
         self.match_start(mr, true, ff)
     }
+
+    /// Returns an iterator over incoming signals of type S, from this destination and path.
+    ///
+    /// While waiting for a signal, the iterator dispatches other incoming messages on the connection as
+    /// usual. The iterator ends if nothing arrived within the proxy's timeout, or in case of an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use dbus::blocking::Connection;
+    /// use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+    ///
+    /// let conn = Connection::new_session()?;
+    /// let proxy = conn.with_proxy("com.example.dbusrs", "/hello", std::time::Duration::from_secs(60));
+    /// for (s, _) in proxy.receive::<PropertiesPropertiesChanged>()? {
+    ///     println!("Properties changed on {}: {:?}", s.interface_name, s.changed_properties);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn receive<S: SignalArgs + ReadAll>(&self) -> Result<Signals<'_, T, S>, Error>
+    where T: Process
+    {
+        let mr = S::match_rule(Some(&self.destination), Some(&self.path)).static_clone();
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let q2 = queue.clone();
+        let token = self.match_start(mr, true, T::make_filter(move |msg, _| { q2.lock().unwrap().push_back(msg); true }))?;
+        Ok(Signals { connection: &*self.connection, token, timeout: self.timeout, queue, _s: PhantomData })
+    }
+}
+
+/// An iterator over incoming signals, created by Proxy::receive.
+///
+/// The match is removed when this is dropped.
+pub struct Signals<'c, T: Process, S> {
+    connection: &'c T,
+    token: Token,
+    timeout: Duration,
+    queue: Arc<Mutex<VecDeque<Message>>>,
+    _s: PhantomData<fn() -> S>,
+}
+
+impl<'c, T: Process, S: ReadAll> Iterator for Signals<'c, T, S> {
+    type Item = (S, Message);
+    fn next(&mut self) -> Option<(S, Message)> {
+        loop {
+            let msg = self.queue.lock().unwrap().pop_front();
+            if let Some(msg) = msg {
+                if let Ok(s) = msg.read_all() { return Some((s, msg)) }
+            } else if !self.connection.process_one(self.timeout).unwrap_or(false) { return None }
+        }
+    }
+}
+
+impl<'c, T: Process, S> Drop for Signals<'c, T, S> {
+    fn drop(&mut self) {
+        if let Some((mr, _)) = self.connection.stop_receive(self.token) {
+            trace_event!(match_rule = %mr.match_str(), "Stopping match");
+            use crate::blocking::stdintf::org_freedesktop::DBus;
+            let _ = stdintf::proxy(self.connection).remove_match(&mr.match_str());
+        }
+    }
+}
+
+thread_local! {
+    // The channels this thread is currently dispatching messages of.
+    static PROCESSING: RefCell<Vec<*const Channel>> = const { RefCell::new(Vec::new()) };
+}
+
+// Marks a channel as being processed by this thread, until dropped.
+struct ProcessGuard(*const Channel);
+
+impl ProcessGuard {
+    fn enter(c: &Channel) -> Result<Self, Error> {
+        let c = c as *const Channel;
+        PROCESSING.with(|p| {
+            let mut p = p.borrow_mut();
+            if p.contains(&c) {
+                return Err(Error::new_failed("Cannot process messages recursively, e g from a message callback"))
+            }
+            p.push(c);
+            Ok(ProcessGuard(c))
+        })
+    }
+}
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) { PROCESSING.with(|p| p.borrow_mut().retain(|&c| c != self.0)) }
+}
+
+/// Internal helper trait, implemented for connections that dispatch incoming messages.
+pub trait Process: BlockingSender + channel::MatchingReceiver {
+    /// Dispatches an incoming message, waiting up to timeout for one to arrive.
+    ///
+    /// Returns false if no message arrived. Despite this taking &self and not "&mut self", it cannot be
+    /// called recursively, e g from a message callback: that returns an error.
+    fn process_one(&self, timeout: Duration) -> Result<bool, Error>;
+
+    /// Internal helper function that creates a message callback.
+    fn make_filter<G: FnMut(Message, &Self) -> bool + Send + Sync + 'static>(g: G) -> <Self as channel::MatchingReceiver>::F where Self: Sized;
 }
 
 /// Internal helper trait
@@ -324,6 +622,15 @@ fn test_conn_send_sync() {
     is_send(&c);
 }
 
+#[test]
+fn test_typed_names() {
+    let c = Connection::new_session().unwrap();
+    assert_eq!(&*c.unique_bus_name(), &*c.unique_name());
+    let n = "com.example.dbusrs.typednames";
+    assert_eq!(c.request_well_known_name(n, false, false, true).unwrap(), stdintf::org_freedesktop_dbus::RequestNameReply::PrimaryOwner);
+    assert_eq!(c.release_well_known_name(n).unwrap(), stdintf::org_freedesktop_dbus::ReleaseNameReply::Released);
+}
+
 #[test]
 fn test_peer() {
     let mut c = Connection::new_session().unwrap();
@@ -356,3 +663,163 @@ fn test_peer() {
     assert_eq!(s1, s2);
 
 }
+
+#[test]
+fn test_ping() {
+    use crate::ErrorKind;
+    let c = Connection::new_session().unwrap();
+    let rtt = c.ping("org.freedesktop.DBus", Duration::from_secs(5)).unwrap();
+    assert!(rtt < Duration::from_secs(5));
+    let e = c.ping("com.example.dbusrs.nobody", Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::ServiceUnknown);
+}
+
+#[test]
+fn test_default_timeout() {
+    use crate::ErrorKind;
+    let mut c = Connection::new_session().unwrap();
+    assert_eq!(c.default_timeout(), channel::default_timeout());
+    c.set_default_timeout(Some(Duration::from_millis(200)));
+    assert_eq!(BlockingSender::default_timeout(&c), Duration::from_millis(200));
+    // Calls to the bus daemon are not affected.
+    assert_eq!(stdintf::proxy(&c).timeout, Duration::from_millis(5000));
+
+    // Nobody processes method calls on c2, so there will be no reply.
+    let c2 = Connection::new_session().unwrap();
+    let proxy = c.proxy(c2.unique_name(), "/");
+    assert_eq!(proxy.timeout, Duration::from_millis(200));
+    let start = Instant::now();
+    let e = proxy.method_call::<(), _, _, _>("com.example.dbusrs", "Hang", ()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    c.set_default_timeout(None);
+    assert_eq!(c.proxy("org.freedesktop.DBus", "/").timeout, channel::default_timeout());
+}
+
+#[test]
+fn test_interactive_authorization() {
+    use crate::tree::Factory;
+    let mut c = LocalConnection::new_session().unwrap();
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/interactive", ()).add(f.interface("com.example.dbusrs.Interactive", ())
+        .add_m(f.method("Allowed", (), |m| m.reply((m.allow_interactive_authorization(),))))));
+    let c_name = c.unique_name().into_static();
+    t.start_receive(&c);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let c2 = Connection::new_session().unwrap();
+        let proxy = c2.with_proxy(c_name, "/interactive", Duration::from_secs(5));
+        let (a,): (bool,) = proxy.method_call("com.example.dbusrs.Interactive", "Allowed", ()).unwrap();
+        let proxy = proxy.with_interactive_authorization(true);
+        assert!(proxy.allow_interactive_authorization());
+        let (b,): (bool,) = proxy.method_call("com.example.dbusrs.Interactive", "Allowed", ()).unwrap();
+        tx.send((a, b)).unwrap();
+    });
+    for _ in 0..30 {
+        c.process(Duration::from_millis(100)).unwrap();
+        if let Ok(r) = rx.try_recv() { assert_eq!(r, (false, true)); return; }
+    }
+    panic!("No reply");
+}
+
+#[test]
+fn test_sender_credentials() {
+    use crate::tree::Factory;
+    use std::rc::Rc;
+    let c = Rc::new(Connection::new_session().unwrap());
+    let c2 = c.clone();
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/creds", ()).add(f.interface("com.example.dbusrs.Creds", ())
+        .add_m(f.method("Pid", (), move |m| m.reply((m.sender_pid(&*c2)?,))))));
+
+    let mut m = Message::new_method_call("com.example.dbusrs", "/creds", "com.example.dbusrs.Creds", "Pid").unwrap();
+    m.set_sender(Some(c.unique_name()));
+    crate::message::message_set_serial(&mut m, 1);
+    for _ in 0..2 {
+        let r = t.handle(&m).unwrap().into_iter().next().unwrap();
+        assert_eq!(r.read1::<u32>().unwrap(), std::process::id());
+    }
+    assert_eq!(c.credentials.0.lock().unwrap().len(), 1);
+
+    // Credentials of well-known names are looked up every time.
+    let d = c.credentials(&"org.freedesktop.DBus".into()).unwrap();
+    assert!(d.uid.is_some());
+    assert_eq!(c.credentials.0.lock().unwrap().len(), 1);
+
+    m.set_sender(None);
+    assert!(t.handle(&m).unwrap().into_iter().next().unwrap().as_result().is_err());
+}
+
+#[test]
+fn test_wait_for_signal() {
+    let mut c = Connection::new_session().unwrap();
+    let mut mr = MatchRule::new_signal("com.example.dbusrs.Waiting", "Done");
+    mr.sender = Some(c.unique_name().into_static());
+    let e = c.wait_for_signal(mr.clone(), Duration::from_millis(50)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.Timeout"));
+
+    let mut mr = MatchRule::new_signal("com.example.dbusrs.Waiting", "Done");
+    let c2 = Connection::new_session().unwrap();
+    mr.sender = Some(c2.unique_name().into_static());
+    let t = std::thread::spawn(move || {
+        // Give the match some time to be added.
+        std::thread::sleep(Duration::from_millis(200));
+        use channel::Sender;
+        c2.send(Message::new_signal("/waiting", "com.example.dbusrs.Waiting", "Done").unwrap().append1(5u8)).unwrap();
+        c2.channel.flush();
+    });
+    let m = c.wait_for_signal(mr, Duration::from_secs(5)).unwrap();
+    assert_eq!(m.read1::<u8>().unwrap(), 5);
+    assert!(c.filters_mut().remove_matching(&m).is_none());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_receive() {
+    use self::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as Ppc;
+    let c = Connection::new_session().unwrap();
+    let c2 = Connection::new_session().unwrap();
+    let proxy = c.with_proxy(c2.unique_name(), "/receive", Duration::from_millis(500));
+    let mut signals = proxy.receive::<Ppc>().unwrap();
+
+    use channel::Sender;
+    for (p, i) in &[("/receive", "com.example.A"), ("/other", "com.example.B"), ("/receive", "com.example.C")] {
+        let mut m = Message::new_signal(*p, "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap();
+        let changed: std::collections::HashMap<&str, crate::arg::Variant<u8>> = Default::default();
+        m = m.append3(*i, changed, Vec::<&str>::new());
+        c2.send(m).unwrap();
+    }
+    c2.send(Message::new_signal("/receive", "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap().append1(5u8)).unwrap();
+    c2.channel.flush();
+
+    let (s, m) = signals.next().unwrap();
+    assert_eq!(s.interface_name, "com.example.A");
+    assert_eq!(&*m.path().unwrap(), "/receive");
+    assert_eq!(signals.next().unwrap().0.interface_name, "com.example.C");
+    assert!(signals.next().is_none());
+    drop(signals);
+    assert!(c.filters_mut().remove_matching(&m).is_none());
+}
+
+#[test]
+fn test_process_recursive() {
+    use std::{rc::Rc, cell::Cell};
+    let mut c = LocalConnection::new_session().unwrap();
+    let mut rule = MatchRule::new_signal("com.example.dbusrs.Recursive", "Ping");
+    rule.sender = Some(c.unique_name().into_static());
+    let result = Rc::new(Cell::new(None));
+    let r2 = result.clone();
+    c.add_match(rule, move |_: (), c: &LocalConnection, _| { r2.set(Some(c.process_one(Duration::from_millis(0)).is_err())); true }).unwrap();
+
+    use channel::Sender;
+    c.send(Message::new_signal("/", "com.example.dbusrs.Recursive", "Ping").unwrap()).unwrap();
+    for _ in 0..50 {
+        if result.get().is_some() { break }
+        c.process(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(result.get(), Some(true));
+    // Processing works again once the callback has returned.
+    assert!(c.process(Duration::from_millis(0)).is_ok());
+}
@@ -0,0 +1,173 @@
+// Driving several connections from one thread.
+
+use super::Process;
+use crate::Error;
+use crate::channel::Channel;
+use std::time::{Duration, Instant};
+use std::os::raw::c_int;
+
+/// Identifies a connection added to a `Dispatcher`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(usize);
+
+/// Dispatches incoming messages of several connections from one thread.
+///
+/// Without this, every connection needs its own thread calling `process`, or an async runtime.
+/// Each connection dispatches its messages just like `process` would, i e to its own message callbacks,
+/// such as a tree set up with `Tree::start_receive`, so the connections can be served by different trees.
+///
+/// Connections that are disconnected (e g a peer-to-peer client that went away) are removed
+/// from the dispatcher and dropped.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::blocking::{LocalConnection, Dispatcher};
+/// use dbus::tree::Factory;
+/// use std::time::Duration;
+/// let f = Factory::new_fn::<()>();
+/// let system = LocalConnection::new_system().unwrap();
+/// f.tree(()).add(f.object_path("/system", ()).introspectable()).start_receive(&system);
+/// let session = LocalConnection::new_session().unwrap();
+/// f.tree(()).add(f.object_path("/session", ()).introspectable()).start_receive(&session);
+///
+/// let mut d = Dispatcher::new();
+/// d.add(system);
+/// d.add(session);
+/// loop { d.process(Duration::from_millis(1000)).unwrap(); }
+/// ```
+#[derive(Debug)]
+pub struct Dispatcher<C> {
+    conns: Vec<(ConnectionId, C)>,
+    next_id: usize,
+}
+
+impl<C> Default for Dispatcher<C> {
+    fn default() -> Self { Dispatcher { conns: vec!(), next_id: 0 } }
+}
+
+impl<C: Process + AsRef<Channel>> Dispatcher<C> {
+    /// Creates a new dispatcher, without any connections.
+    pub fn new() -> Self { Default::default() }
+
+    /// Adds a connection, which will have its incoming messages dispatched by `process`.
+    pub fn add(&mut self, c: C) -> ConnectionId {
+        let id = ConnectionId(self.next_id);
+        self.next_id += 1;
+        self.conns.push((id, c));
+        id
+    }
+
+    /// Removes a connection, and hands it back.
+    ///
+    /// Returns None if the connection was not found, e g because it has been disconnected.
+    pub fn remove(&mut self, id: ConnectionId) -> Option<C> {
+        let idx = self.conns.iter().position(|(i, _)| *i == id)?;
+        Some(self.conns.remove(idx).1)
+    }
+
+    /// Gets a reference to a connection.
+    pub fn get(&self, id: ConnectionId) -> Option<&C> {
+        self.conns.iter().find(|(i, _)| *i == id).map(|(_, c)| c)
+    }
+
+    /// Iterates over all connections.
+    pub fn iter(&self) -> impl Iterator<Item=(ConnectionId, &C)> + '_ { self.conns.iter().map(|(i, c)| (*i, c)) }
+
+    /// Returns the number of connections.
+    pub fn len(&self) -> usize { self.conns.len() }
+
+    /// Returns true if there are no connections (left).
+    pub fn is_empty(&self) -> bool { self.conns.is_empty() }
+
+    // Dispatches the messages that have already arrived, and drops disconnected connections.
+    fn dispatch_ready(&mut self) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut idx = 0;
+        while idx < self.conns.len() {
+            let c = &self.conns[idx].1;
+            let r = loop {
+                match c.process_one(Duration::from_millis(0)) {
+                    Ok(true) => count += 1,
+                    Ok(false) => break Ok(()),
+                    Err(e) => break Err(e),
+                }
+            };
+            if !c.as_ref().is_connected() {
+                trace_event!(id = (self.conns[idx].0).0, "Removing disconnected connection");
+                self.conns.remove(idx);
+                continue;
+            }
+            r?;
+            c.as_ref().flush();
+            idx += 1;
+        }
+        Ok(count)
+    }
+
+    /// Dispatches incoming messages on all connections, waiting up to "timeout" for a message to arrive.
+    ///
+    /// Returns the number of messages dispatched, which is zero if the timeout expired.
+    /// It is a logic error to call this recursively, e g from a message callback.
+    pub fn process(&mut self, timeout: Duration) -> Result<usize, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let count = self.dispatch_ready()?;
+            if count > 0 { return Ok(count) }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) { return Ok(0) }
+
+            let mut fds: Vec<_> = self.conns.iter().filter_map(|(_, c)| c.as_ref().unix_fd())
+                .map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect();
+            // Round up, so that we don't wake up just before the deadline.
+            let ms = (left + Duration::from_micros(999)).as_millis().min(c_int::MAX as u128) as c_int;
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted { return Err(Error::new_failed(&e.to_string())) }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dispatcher() {
+    use super::{LocalConnection, Connection};
+    use crate::tree::Factory;
+
+    let f = Factory::new_fn::<()>();
+    let mut d = Dispatcher::new();
+    let mut names = vec!();
+    for s in &["first", "second"] {
+        let c = LocalConnection::new_session().unwrap();
+        names.push(c.unique_name().into_static());
+        let reply = s.to_string();
+        f.tree(()).add(f.object_path("/dispatcher", ()).add(f.interface("com.example.dbusrs.Dispatcher", ())
+            .add_m(f.method("Name", (), move |m| m.reply((&*reply,)))))).start_receive(&c);
+        d.add(c);
+    }
+    assert_eq!(d.len(), 2);
+    // Both connections get a NameAcquired signal
+    while d.process(Duration::from_millis(50)).unwrap() > 0 {}
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let c = Connection::new_session().unwrap();
+        for n in names {
+            let p = c.with_proxy(n, "/dispatcher", Duration::from_secs(5));
+            let (s,): (String,) = p.method_call("com.example.dbusrs.Dispatcher", "Name", ()).unwrap();
+            tx.send(s).unwrap();
+        }
+    });
+    let mut replies = vec!();
+    for _ in 0..30 {
+        d.process(Duration::from_millis(100)).unwrap();
+        replies.extend(rx.try_iter());
+        if replies.len() == 2 { break; }
+    }
+    assert_eq!(replies, vec!("first", "second"));
+
+    let id = d.iter().next().unwrap().0;
+    assert!(d.get(id).is_some());
+    assert!(d.remove(id).is_some());
+    assert!(d.get(id).is_none());
+    assert_eq!(d.len(), 1);
+}
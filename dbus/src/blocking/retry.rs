@@ -0,0 +1,170 @@
+// Retrying method calls that failed for reasons that might go away by themselves.
+
+use crate::{Error, ErrorKind};
+use std::{fmt, thread};
+use std::sync::Arc;
+use std::time::Duration;
+
+type FailureHook = Arc<dyn Fn(u32, &Error, Option<Duration>) + Send + Sync>;
+
+/// When and how often to retry a method call that failed, with exponential backoff.
+///
+/// By default, calls are attempted at most three times, waiting 100 ms before the first retry,
+/// and twice as long before each following retry, but at most 5 seconds. Only errors of the
+/// kinds that are likely to be transient are retried: `Timeout` (which includes NoReply),
+/// `ServiceUnknown` (e g while the service is being activated) and `LimitsExceeded`.
+/// A message with more Unix fds than the connection allows is never retried, as it would
+/// fail the same way again.
+///
+/// Note that a method call that timed out might still have been carried out by the service,
+/// so only retry on timeouts if calling the method twice does no harm.
+///
+/// Use `Proxy::with_retry` to make a proxy retry its method calls, or use `call` directly.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::blocking::{Connection, Retry};
+/// use std::time::Duration;
+///
+/// let conn = Connection::new_session()?;
+/// let proxy = conn.with_proxy("com.example.dbusrs", "/hello", Duration::from_secs(5))
+///     .with_retry(Retry::new().attempts(5).on_failure(|attempt, e, next| match next {
+///         Some(d) => eprintln!("Attempt {} failed: {:?}, retrying in {:?}", attempt, e, d),
+///         None => eprintln!("Attempt {} failed: {:?}, giving up", attempt, e),
+///     }));
+/// let (s,): (String,) = proxy.method_call("com.example.dbusrs", "Hello", ())?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct Retry {
+    attempts: u32,
+    delay: Duration,
+    max_delay: Duration,
+    kinds: Vec<ErrorKind>,
+    on_failure: Option<FailureHook>,
+}
+
+impl fmt::Debug for Retry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Retry").field("attempts", &self.attempts).field("delay", &self.delay)
+            .field("max_delay", &self.max_delay).field("kinds", &self.kinds).finish()
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            kinds: vec!(ErrorKind::Timeout, ErrorKind::ServiceUnknown, ErrorKind::LimitsExceeded),
+            on_failure: None,
+        }
+    }
+}
+
+impl Retry {
+    /// Creates a new Retry with the default settings.
+    pub fn new() -> Self { Default::default() }
+
+    /// Builder function that sets how many times a call is attempted in total, including the first time.
+    pub fn attempts(mut self, n: u32) -> Self { self.attempts = n.max(1); self }
+
+    /// Builder function that sets how long to wait before the first retry, and the longest wait
+    /// between retries.
+    pub fn delay(mut self, first: Duration, max: Duration) -> Self { self.delay = first; self.max_delay = max; self }
+
+    /// Builder function that sets which kinds of errors are retried, replacing the defaults.
+    pub fn retry_on(mut self, kinds: &[ErrorKind]) -> Self { self.kinds = kinds.into(); self }
+
+    /// Builder function that sets a function to be called every time an attempt fails,
+    /// e g for logging.
+    ///
+    /// It gets the number of the attempt (starting at 1), the error, and how long it will be
+    /// until the next attempt, or None if the call will not be retried.
+    pub fn on_failure<F: Fn(u32, &Error, Option<Duration>) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.on_failure = Some(Arc::new(f)); self
+    }
+
+    /// Returns true if "e" is of a kind that is retried.
+    pub fn is_retried(&self, e: &Error) -> bool { self.kinds.contains(&e.kind()) && !e.is_too_many_unix_fds() }
+
+    /// How long to wait after the given attempt (starting at 1) has failed.
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay)
+    }
+
+    /// Calls "f" until it succeeds, fails with an error that is not retried, or has been
+    /// attempted as many times as allowed. Returns the result of the last attempt.
+    pub fn call<R, F: FnMut() -> Result<R, Error>>(&self, mut f: F) -> Result<R, Error> {
+        let mut attempt = 1;
+        loop {
+            let e = match f() { Ok(r) => return Ok(r), Err(e) => e };
+            let next = if attempt < self.attempts && self.is_retried(&e) { Some(self.delay_after(attempt)) } else { None };
+            trace_event!(attempt, error = ?e.name(), retry_in = ?next, "Method call failed");
+            if let Some(h) = &self.on_failure { h(attempt, &e, next) }
+            match next {
+                Some(d) => thread::sleep(d),
+                None => return Err(e),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[test]
+fn test_retry() {
+    use std::sync::Mutex;
+    let log = Arc::new(Mutex::new(vec!()));
+    let log2 = log.clone();
+    let r = Retry::new().delay(Duration::from_millis(1), Duration::from_millis(3))
+        .on_failure(move |attempt, e, next| log2.lock().unwrap().push((attempt, e.kind(), next)));
+    assert_eq!((r.delay_after(1), r.delay_after(2), r.delay_after(3), r.delay_after(100)),
+        (Duration::from_millis(1), Duration::from_millis(2), Duration::from_millis(3), Duration::from_millis(3)));
+
+    let timeout = || Error::new_custom("org.freedesktop.DBus.Error.NoReply", "No reply");
+    let mut n = 0;
+    assert_eq!(r.call(|| { n += 1; if n < 3 { Err(timeout()) } else { Ok(n) } }).unwrap(), 3);
+    assert_eq!(&*log.lock().unwrap(), &[(1, ErrorKind::Timeout, Some(Duration::from_millis(1))),
+        (2, ErrorKind::Timeout, Some(Duration::from_millis(2)))]);
+
+    log.lock().unwrap().clear();
+    assert_eq!(r.call::<(), _>(|| Err(timeout())).unwrap_err().kind(), ErrorKind::Timeout);
+    assert_eq!(log.lock().unwrap().len(), 3);
+    assert_eq!(log.lock().unwrap()[2], (3, ErrorKind::Timeout, None));
+
+    // Errors that are not transient are not retried.
+    log.lock().unwrap().clear();
+    assert!(r.call::<(), _>(|| Err(Error::new_failed("Oops"))).is_err());
+    assert_eq!(&*log.lock().unwrap(), &[(1, ErrorKind::Failed, None)]);
+    let r = r.retry_on(&[ErrorKind::Failed]).attempts(2);
+    assert!(r.call::<(), _>(|| Err(Error::new_failed("Oops"))).is_err());
+    assert_eq!(log.lock().unwrap().len(), 3);
+
+    // Through a proxy
+    let c = super::Connection::new_session().unwrap();
+    log.lock().unwrap().clear();
+    let p = c.with_proxy("com.example.dbusrs.nobody", "/", Duration::from_secs(5)).with_retry(r.clone().retry_on(&[ErrorKind::ServiceUnknown]));
+    let e = p.method_call::<(), _, _, _>("com.example.dbusrs", "Hello", ()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::ServiceUnknown);
+    assert_eq!(log.lock().unwrap().len(), 2);
+
+    // Too many Unix fds is refused before sending, so it is not retried, unlike LimitsExceeded from the daemon.
+    use crate::arg::OwnedFd;
+    use std::os::unix::io::IntoRawFd;
+    let limits = || Error::new_custom("org.freedesktop.DBus.Error.LimitsExceeded", "Quota");
+    assert!(Retry::new().is_retried(&limits()));
+    let mut ch = crate::channel::Channel::get_private(crate::channel::BusType::Session).unwrap();
+    ch.set_max_message_unix_fds(0);
+    let c = super::Connection::from(ch);
+    log.lock().unwrap().clear();
+    let name = c.unique_name().to_string();
+    let p = c.with_proxy(name, "/", Duration::from_secs(5)).with_retry(r.retry_on(&[ErrorKind::LimitsExceeded]));
+    assert!(p.retry().is_some());
+    let fd = unsafe { OwnedFd::new(std::fs::File::open("/dev/null").unwrap().into_raw_fd()) };
+    let e = p.method_call::<(), _, _, _>("com.example.dbusrs", "Fds", (fd,)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::LimitsExceeded);
+    assert!(!Retry::new().is_retried(&e));
+    assert_eq!(&*log.lock().unwrap(), &[(1, ErrorKind::LimitsExceeded, None)]);
+}
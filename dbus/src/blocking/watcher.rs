@@ -0,0 +1,150 @@
+// Watching bus names come and go.
+
+use super::BlockingSender;
+use crate::Error;
+use crate::strings::WellKnownName;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::fmt;
+
+type AppearedFn = Box<dyn FnMut(&str, &str) + Send + 'static>;
+type VanishedFn = Box<dyn FnMut(&str) + Send + 'static>;
+
+#[derive(Default)]
+struct State {
+    // None until the name has been probed.
+    owners: BTreeMap<String, Option<Option<String>>>,
+    appeared: Option<AppearedFn>,
+    vanished: Option<VanishedFn>,
+}
+
+/// Calls back when well-known bus names appear (get an owner) or vanish (lose their owner).
+///
+/// This is the usual way of waiting for a service to start, and of noticing that it has gone away
+/// (or has been restarted, in which case it appears again with a new owner). Start watching with
+/// the `watch_services` method of a connection, which first asks the bus for the current owner of every
+/// name, and calls either "appeared" or "vanished" for each of them. After that, the callbacks are
+/// called from `process` whenever a NameOwnerChanged signal reports a change.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::blocking::{Connection, ServiceWatcher};
+/// use std::time::Duration;
+/// let mut c = Connection::new_session().unwrap();
+/// let w = ServiceWatcher::new(vec!("org.freedesktop.Notifications"))
+///     .on_appeared(|name, owner| println!("{} is now owned by {}", name, owner))
+///     .on_vanished(|name| println!("{} is gone", name));
+/// c.watch_services(w.clone()).unwrap();
+/// while w.owner("org.freedesktop.Notifications").is_none() { c.process(Duration::from_millis(1000)).unwrap(); }
+/// ```
+#[derive(Clone, Default)]
+pub struct ServiceWatcher(Arc<Mutex<State>>);
+
+impl fmt::Debug for ServiceWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ServiceWatcher").field(&self.0.lock().unwrap().owners).finish()
+    }
+}
+
+impl ServiceWatcher {
+    /// Creates a watcher for the given well-known names.
+    pub fn new<I: IntoIterator<Item=N>, N: Into<WellKnownName<'static>>>(names: I) -> Self {
+        let owners = names.into_iter().map(|n| (n.into().to_string(), None)).collect();
+        ServiceWatcher(Arc::new(Mutex::new(State { owners, ..Default::default() })))
+    }
+
+    /// Builder function that sets the callback to call with the name and its new owner (a unique name)
+    /// when a name appears.
+    pub fn on_appeared<F: FnMut(&str, &str) + Send + 'static>(self, f: F) -> Self {
+        self.0.lock().unwrap().appeared = Some(Box::new(f)); self
+    }
+
+    /// Builder function that sets the callback to call with the name when a name vanishes.
+    pub fn on_vanished<F: FnMut(&str) + Send + 'static>(self, f: F) -> Self {
+        self.0.lock().unwrap().vanished = Some(Box::new(f)); self
+    }
+
+    /// Returns the current owner of a watched name, or None if it has no owner (or is not watched).
+    pub fn owner(&self, name: &str) -> Option<String> {
+        self.0.lock().unwrap().owners.get(name).cloned().flatten().flatten()
+    }
+
+    /// Returns the watched names.
+    pub fn names(&self) -> Vec<String> { self.0.lock().unwrap().owners.keys().cloned().collect() }
+
+    // Takes an empty owner to mean that the name vanished, just like NameOwnerChanged does.
+    pub (super) fn changed(&self, name: &str, owner: &str) {
+        let mut s = self.0.lock().unwrap();
+        let new = if owner.is_empty() { None } else { Some(owner.to_string()) };
+        match s.owners.get_mut(name) {
+            Some(old) if old.as_ref() != Some(&new) => *old = Some(new.clone()),
+            _ => return,
+        }
+        trace_event!(name, owner = ?new, "Watched name changed owner");
+        // Call without holding the lock, in case the callback looks at the watcher.
+        match new {
+            Some(owner) => if let Some(mut f) = s.appeared.take() {
+                drop(s);
+                f(name, &owner);
+                self.0.lock().unwrap().appeared.get_or_insert(f);
+            },
+            None => if let Some(mut f) = s.vanished.take() {
+                drop(s);
+                f(name);
+                self.0.lock().unwrap().vanished.get_or_insert(f);
+            },
+        }
+    }
+
+    // Asks the bus for the current owner of every name that has not been heard from yet.
+    pub (super) fn probe<C: BlockingSender>(&self, c: &C) -> Result<(), Error> {
+        use super::stdintf::org_freedesktop::DBus;
+        let unknown: Vec<_> = self.0.lock().unwrap().owners.iter().filter(|(_, o)| o.is_none()).map(|(n, _)| n.clone()).collect();
+        let proxy = super::stdintf::proxy(c);
+        for name in unknown {
+            match proxy.get_name_owner(&name) {
+                Ok(owner) => self.changed(&name, &owner),
+                Err(e) if e.kind() == crate::ErrorKind::NameHasNoOwner => self.changed(&name, ""),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_service_watcher() {
+    use super::{LocalConnection, Connection};
+    use std::time::Duration;
+
+    let events = Arc::new(Mutex::new(vec!()));
+    let (e1, e2) = (events.clone(), events.clone());
+    let name = "com.example.dbusrs.watched";
+    let w = ServiceWatcher::new(vec!(name))
+        .on_appeared(move |n, o| e1.lock().unwrap().push(format!("{} {}", n, o)))
+        .on_vanished(move |n| e2.lock().unwrap().push(n.to_string()));
+    let mut c = LocalConnection::new_session().unwrap();
+    let token = c.watch_services(w.clone()).unwrap();
+    assert_eq!(*events.lock().unwrap(), vec!(name.to_string()));
+    assert_eq!(w.owner(name), None);
+    assert_eq!(w.names(), vec!(name.to_string()));
+
+    let service = Connection::new_session().unwrap();
+    service.request_name(name, false, true, false).unwrap();
+    let owner = service.unique_name().to_string();
+    let wait = |c: &mut LocalConnection, count| {
+        for _ in 0..30 {
+            if events.lock().unwrap().len() >= count { break; }
+            c.process(Duration::from_millis(100)).unwrap();
+        }
+    };
+    wait(&mut c, 2);
+    assert_eq!(events.lock().unwrap()[1], format!("{} {}", name, owner));
+    assert_eq!(w.owner(name), Some(owner));
+
+    service.release_name(name).unwrap();
+    wait(&mut c, 3);
+    assert_eq!(events.lock().unwrap()[2], name);
+    assert_eq!(w.owner(name), None);
+    c.remove_match(token).unwrap();
+}
@@ -0,0 +1,637 @@
+//! A message bus, for running a private bus without a dbus-daemon.
+//!
+//! A `Broker` listens on an address with a `channel::Server`, and routes messages between the
+//! connections it accepts, just like the bus daemon does: clients connect with `Channel::open_private`
+//! and `Channel::register` (which calls `Hello` and gets a unique name), take well-known names, add
+//! match rules for the signals they want, and call methods on each other.
+//! This is enough to act as a private session bus, e g for tests, in containers or on embedded systems.
+//!
+//! The `org.freedesktop.DBus` interface is implemented with the exception of:
+//!
+//! * Queueing for bus names: RequestName never returns InQueue, but Exists.
+//! * Activation: there are no activatable names, and StartServiceByName is not implemented.
+//! * Eavesdropping, and match rules with argument matches, i e only the keys supported by `MatchRule::parse`.
+//! * Environment, SELinux and AppArmor related methods.
+//!
+//! Like the bus daemon, the broker disconnects a client that does not read its messages, once more than
+//! `Broker::max_outgoing_bytes` are waiting to be written to it.
+//!
+//! Unless a `BusPolicy` says otherwise, only clients running as the same user as the broker can connect,
+//! and they can own any name, and send any message.
+//!
+//! This module needs the "broker" feature.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use dbus::broker::Broker;
+//! use dbus::channel::Channel;
+//! use dbus::blocking::Connection;
+//!
+//! let broker = Broker::new("unix:tmpdir=/tmp")?;
+//! let address = broker.address();
+//! std::thread::spawn(move || broker.run());
+//!
+//! let mut channel = Channel::open_private(&address)?;
+//! channel.register()?;
+//! let c: Connection = channel.into();
+//! c.request_name("com.example.dbustest", false, true, false)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{Message, MessageType, Error};
+use crate::channel::{self, Channel, Server, OutgoingLimit, Overflow};
+use crate::message::{MatchRule, message_set_serial};
+use crate::arg::{PropMap, Variant};
+use crate::tree::{MethodErr, StopHandle};
+use crate::busmethods::{self, BUS_NAME, NameTable, error_reply, no_owner};
+use std::collections::HashMap;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod policy;
+pub use self::policy::BusPolicy;
+use self::policy::Identity;
+
+// How often "run" checks whether it has been asked to stop.
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+// With a policy: how many method calls from one connection can wait for a reply, and for how long.
+// A reply that comes later is not a requested reply.
+const MAX_PENDING_REPLIES: usize = 1024;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+
+// The default for how many bytes can wait to be written to one connection, same as the bus daemon's.
+const MAX_OUTGOING_BYTES: usize = 127 * 1024 * 1024;
+
+#[derive(Debug)]
+struct Peer {
+    channel: Channel,
+    // The unique name, once the peer has called Hello.
+    name: Option<String>,
+    // Match rules added with AddMatch
+    matches: Vec<MatchRule<'static>>,
+    identity: Identity,
+}
+
+/// A message bus, see the module documentation.
+#[derive(Debug)]
+pub struct Broker {
+    server: Server,
+    id: String,
+    peers: Vec<Peer>,
+    next_id: u32,
+    names: NameTable,
+    stop: StopHandle,
+    policy: Option<Arc<BusPolicy>>,
+    // Method calls waiting for a reply: caller -> (callee, serial) -> deadline. Only tracked with a policy.
+    expected: HashMap<String, HashMap<(String, u32), Instant>>,
+    max_outgoing_bytes: usize,
+}
+
+// Another reference to the same message, to send it on several connections.
+fn share(m: &Message) -> Message { Message::from_ptr(m.ptr(), true) }
+
+// A copy of a received message, that can be forwarded by the bus: with the sender set,
+// and with the serial the peer gave it, since replies refer to that serial.
+fn forwardable(m: &Message, sender: &str) -> Message {
+    let p = unsafe { ffi::dbus_message_copy(m.ptr()) };
+    if p.is_null() { panic!("D-Bus error: dbus_message_copy failed") }
+    let mut c = Message::from_ptr(p, false);
+    c.set_sender(Some(sender.into()));
+    if let Some(s) = m.get_serial() { message_set_serial(&mut c, s) }
+    c
+}
+
+fn is_hello(m: &Message) -> bool {
+    m.msg_type() == MessageType::MethodCall && m.destination().as_deref() == Some(BUS_NAME) &&
+        m.interface().as_deref().unwrap_or(BUS_NAME) == BUS_NAME && m.member().as_deref() == Some("Hello")
+}
+
+// Messages on the local interface or path only make sense within a connection, e g the Disconnected
+// signal libdbus generates when a peer goes away. They are never routed.
+fn is_local(m: &Message) -> bool {
+    m.path().as_deref() == Some("/org/freedesktop/DBus/Local") || m.interface().as_deref() == Some("org.freedesktop.DBus.Local")
+}
+
+fn unix_user(c: &Channel) -> Option<u32> {
+    let mut uid: c_ulong = 0;
+    if unsafe { ffi::dbus_connection_get_unix_user(c.conn(), &mut uid) } != 0 { Some(uid as u32) } else { None }
+}
+
+// Lets the policy decide which users may connect, instead of libdbus.
+fn set_allowed_users(c: &Channel, p: Arc<BusPolicy>) {
+    extern "C" fn allow_cb(_: *mut ffi::DBusConnection, uid: c_ulong, data: *mut c_void) -> u32 {
+        let p: &BusPolicy = unsafe { &*(data as *const BusPolicy) };
+        p.can_connect(uid as u32) as u32
+    }
+    extern "C" fn free_cb(data: *mut c_void) { unsafe { drop(Arc::from_raw(data as *const BusPolicy)) } }
+    let data = Arc::into_raw(p) as *mut c_void;
+    unsafe { ffi::dbus_connection_set_unix_user_function(c.conn(), Some(allow_cb), data, Some(free_cb)) }
+}
+
+fn unix_process_id(c: &Channel) -> Option<u32> {
+    let mut pid: c_ulong = 0;
+    if unsafe { ffi::dbus_connection_get_unix_process_id(c.conn(), &mut pid) } != 0 { Some(pid as u32) } else { None }
+}
+
+impl Broker {
+    /// Starts listening on "address", see `channel::Server::listen`.
+    pub fn new(address: &str) -> Result<Broker, Error> {
+        let server = Server::listen(address)?;
+        let id = server.id();
+        Ok(Broker { server, id, peers: vec!(), next_id: 0, names: Default::default(), stop: Default::default(),
+            policy: None, expected: Default::default(), max_outgoing_bytes: MAX_OUTGOING_BYTES })
+    }
+
+    /// Builder function that sets the policy, i e who may connect, own names, and send and receive messages.
+    ///
+    /// Connections accepted before this is called are not checked against the policy when connecting.
+    pub fn policy(mut self, p: BusPolicy) -> Self { self.policy = Some(Arc::new(p)); self }
+
+    /// Builder function that sets how many bytes can wait to be written to a connection.
+    ///
+    /// A connection that does not read its messages, so that more than this piles up for it, is
+    /// disconnected. The default is 127 MiB, like the bus daemon's `max_outgoing_bytes`.
+    pub fn max_outgoing_bytes(mut self, n: usize) -> Self { self.max_outgoing_bytes = n; self }
+
+    /// The address clients can connect to.
+    pub fn address(&self) -> String { self.server.address() }
+
+    /// Returns a handle that makes `run` return.
+    pub fn stop_handle(&self) -> StopHandle { self.stop.clone() }
+
+    /// Routes messages until asked to stop.
+    pub fn run(mut self) -> Result<(), Error> {
+        while !self.stop.is_stopped() { self.process(STOP_INTERVAL)?; }
+        Ok(())
+    }
+
+    /// Accepts new connections and routes incoming messages, waiting up to "timeout" for a message to arrive.
+    ///
+    /// Returns the number of messages routed, which is zero if the timeout expired.
+    pub fn process(&mut self, timeout: Duration) -> Result<usize, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            while let Some(mut channel) = self.server.accept(Duration::from_millis(0))? {
+                trace_event!(fd = ?channel.unix_fd(), "Accepted connection");
+                if let Some(p) = &self.policy { set_allowed_users(&channel, p.clone()) }
+                channel.set_outgoing_limit(Some(OutgoingLimit { max_bytes: Some(self.max_outgoing_bytes), max_messages: None, overflow: Overflow::Error }));
+                self.peers.push(Peer { channel, name: None, matches: vec!(), identity: Default::default() });
+            }
+            let count = self.process_ready();
+            if count > 0 { return Ok(count) }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) { return Ok(0) }
+
+            let mut fds: Vec<_> = self.server.watch_fds().into_iter()
+                .map(|w| libc::pollfd { fd: w.fd, events: libc::POLLIN, revents: 0 }).collect();
+            fds.extend(self.peers.iter().filter_map(|p| {
+                let out = unsafe { ffi::dbus_connection_has_messages_to_send(p.channel.conn()) } != 0;
+                p.channel.unix_fd().map(|fd| libc::pollfd { fd, events: libc::POLLIN | if out { libc::POLLOUT } else { 0 }, revents: 0 })
+            }));
+            // Round up, so that we don't wake up just before the deadline.
+            let ms = (left + Duration::from_micros(999)).as_millis().min(c_int::MAX as u128) as c_int;
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted { return Err(Error::new_failed(&e.to_string())) }
+            }
+        }
+    }
+
+    // Routes the messages that have already arrived, and drops disconnected peers.
+    fn process_ready(&mut self) -> usize {
+        let mut msgs = vec!();
+        for (i, p) in self.peers.iter().enumerate() {
+            let _ = p.channel.read_write(Some(Duration::from_millis(0)));
+            while let Some(m) = p.channel.pop_message() { msgs.push((i, m)) }
+        }
+        let count = msgs.len();
+        for (i, m) in msgs { self.handle(i, m) }
+
+        let mut i = 0;
+        while i < self.peers.len() {
+            if self.peers[i].channel.is_connected() { i += 1; continue }
+            let p = self.peers.remove(i);
+            if let Some(name) = p.name {
+                trace_event!(name = %name, "Peer disconnected");
+                busmethods::disconnected(self, &name);
+                self.expected.remove(&name);
+                for e in self.expected.values_mut() { e.retain(|(callee, _), _| *callee != name) }
+            }
+        }
+        for p in &self.peers { let _ = p.channel.read_write(Some(Duration::from_millis(0))); }
+        count
+    }
+
+    fn handle(&mut self, from: usize, m: Message) {
+        if is_local(&m) { return }
+        let sender = match &self.peers[from].name {
+            Some(n) => n.clone(),
+            None if is_hello(&m) => return self.hello(from, &m),
+            None => {
+                if m.msg_type() == MessageType::MethodCall && !m.get_no_reply() {
+                    let _ = self.peers[from].channel.send(error_reply(&m, "org.freedesktop.DBus.Error.AccessDenied",
+                        "Client tried to send a message other than Hello without being registered"));
+                }
+                return
+            }
+        };
+        let m = forwardable(&m, &sender);
+        if m.destination().as_deref() == Some(BUS_NAME) {
+            let allowed = self.policy.as_ref().map(|p| p.can_send(&self.peers[from].identity, &[BUS_NAME.into()], &m)) != Some(false);
+            if allowed { self.bus_call(&m, &sender) } else { self.deny(&m, BUS_NAME) }
+            return
+        }
+        self.deliver(Some(from), m);
+    }
+
+    fn hello(&mut self, from: usize, m: &Message) {
+        self.next_id += 1;
+        let name = format!(":1.{}", self.next_id);
+        trace_event!(name = %name, "Peer registered");
+        self.peers[from].name = Some(name.clone());
+        if self.policy.is_some() { self.peers[from].identity = Identity::for_uid(unix_user(&self.peers[from].channel)) }
+        let m = forwardable(m, &name);
+        self.send_from_bus(m.method_return().append1(&name));
+        busmethods::connected(self, &name);
+    }
+
+    fn peer(&self, name: &str) -> Option<usize> {
+        let unique = if name.starts_with(':') { name } else { self.names.owner(name)? };
+        self.peers.iter().position(|p| p.name.as_deref() == Some(unique))
+    }
+
+    fn name_owner(&self, name: &str) -> Option<String> { self.peers[self.peer(name)?].name.clone() }
+
+    // The unique name and the well-known names of a peer.
+    fn owned_names(&self, p: &Peer) -> Vec<String> {
+        let unique = p.name.clone().unwrap_or_default();
+        let mut v: Vec<_> = self.names.owned_by(&unique).map(String::from).collect();
+        v.push(unique);
+        v
+    }
+
+    // Checks the policy for a message from peer "from" to peer "to".
+    fn allowed(&self, from: usize, to: usize, m: &Message) -> bool {
+        let p = match &self.policy { Some(p) => p, None => return true };
+        let (f, t) = (&self.peers[from], &self.peers[to]);
+        p.can_send(&f.identity, &self.owned_names(t), m) && p.can_receive(&t.identity, &self.owned_names(f), m)
+    }
+
+    fn deny(&mut self, m: &Message, dest: &str) {
+        trace_event!(sender = ?m.sender(), destination = %dest, member = ?m.member(), "Message denied by policy");
+        if m.msg_type() == MessageType::MethodCall && !m.get_no_reply() {
+            self.send_from_bus(error_reply(m, "org.freedesktop.DBus.Error.AccessDenied",
+                &format!("The policy does not allow {} to send this message to {}", m.sender().as_deref().unwrap_or(""), dest)));
+        }
+    }
+
+    fn rule_matches(&self, r: &MatchRule, m: &Message) -> bool {
+        if let Some(s) = &r.sender {
+            let owner = if &**s == BUS_NAME { Some(BUS_NAME.to_string()) } else { self.name_owner(s) };
+            if owner.as_deref() != m.sender().as_deref() { return false }
+        }
+        r.matches(m)
+    }
+
+    // Sends a message, which has its sender set, to its destination or to everyone with a matching rule.
+    // "from" is the peer that sent it, or None if it comes from the bus itself.
+    fn deliver(&mut self, from: Option<usize>, m: Message) {
+        let dest = match m.destination() {
+            Some(d) => d.into_static(),
+            None => {
+                for (i, p) in self.peers.iter().enumerate() {
+                    if !p.matches.iter().any(|r| self.rule_matches(r, &m)) { continue }
+                    if from.map(|f| self.allowed(f, i, &m)) != Some(false) { self.send_to(i, share(&m)) }
+                }
+                return
+            }
+        };
+        let to = match self.peer(&dest) {
+            Some(to) => to,
+            None => {
+                if m.msg_type() == MessageType::MethodCall && !m.get_no_reply() {
+                    self.send_from_bus(error_reply(&m, "org.freedesktop.DBus.Error.ServiceUnknown", &format!("The name {} is not owned", dest)));
+                }
+                return
+            }
+        };
+        if let (Some(f), Some(_)) = (from, &self.policy) {
+            let (sender, receiver) = (self.peers[f].name.clone().unwrap_or_default(), self.peers[to].name.clone().unwrap_or_default());
+            let now = Instant::now();
+            let requested = match (m.msg_type(), m.get_reply_serial()) {
+                (MessageType::MethodReturn, Some(s)) | (MessageType::Error, Some(s)) => self.expected.get_mut(&receiver)
+                    .and_then(|e| e.remove(&(sender.clone(), s))).map(|deadline| deadline > now).unwrap_or(false),
+                _ => false,
+            };
+            if !requested && !self.allowed(f, to, &m) { return self.deny(&m, &dest) }
+            if let (MessageType::MethodCall, false, Some(s)) = (m.msg_type(), m.get_no_reply(), m.get_serial()) {
+                let e = self.expected.entry(sender).or_default();
+                if e.len() >= MAX_PENDING_REPLIES { e.retain(|_, deadline| *deadline > now) }
+                if e.len() >= MAX_PENDING_REPLIES {
+                    return self.send_from_bus(error_reply(&m, "org.freedesktop.DBus.Error.LimitsExceeded",
+                        "Too many method calls are waiting for a reply"));
+                }
+                e.insert((receiver, s), now + REPLY_TIMEOUT);
+            }
+        }
+        self.send_to(to, m);
+    }
+
+    // Disconnects the peer if its outgoing queue is full, like the bus daemon does, instead of
+    // letting messages for a peer that does not read them pile up without bounds.
+    fn send_to(&self, to: usize, m: Message) {
+        let c = &self.peers[to].channel;
+        if c.send(m).is_err() && c.outgoing_size() >= self.max_outgoing_bytes {
+            trace_event!(name = ?self.peers[to].name, bytes = c.outgoing_size(), "Outgoing queue full, disconnecting peer");
+            unsafe { ffi::dbus_connection_close(c.conn()) };
+        }
+    }
+
+    fn send_from_bus(&mut self, mut m: Message) {
+        m.set_sender(Some(BUS_NAME.into()));
+        self.deliver(None, m);
+    }
+
+    fn bus_call(&mut self, m: &Message, sender: &str) {
+        if m.msg_type() != MessageType::MethodCall { return }
+        let r = if m.interface().as_deref().unwrap_or(BUS_NAME) == BUS_NAME {
+            self.bus_method(m, sender).unwrap_or_else(|e| e.to_message(m))
+        } else { channel::default_reply(m).unwrap() };
+        if !m.get_no_reply() { self.send_from_bus(r) }
+    }
+
+    fn bus_method(&mut self, m: &Message, sender: &str) -> Result<Message, MethodErr> {
+        let member = m.member().ok_or_else(MethodErr::no_arg)?;
+        let r = m.method_return();
+        Ok(match &*member {
+            "Hello" => Err(MethodErr::failed("Already handled an Hello message"))?,
+            "ListActivatableNames" => r.append1(vec!(BUS_NAME)),
+            "GetId" => r.append1(&self.id),
+            "GetConnectionUnixUser" | "GetConnectionUnixProcessID" | "GetConnectionCredentials" => {
+                let name: &str = m.read1()?;
+                let c = &self.peers[self.peer(name).ok_or_else(|| no_owner(name))?].channel;
+                let (uid, pid) = (unix_user(c), unix_process_id(c));
+                let unknown = || MethodErr::failed(&format!("Could not determine the credentials of {}", name));
+                match &*member {
+                    "GetConnectionUnixUser" => r.append1(uid.ok_or_else(unknown)?),
+                    "GetConnectionUnixProcessID" => r.append1(pid.ok_or_else(unknown)?),
+                    _ => {
+                        let mut map = PropMap::new();
+                        if let Some(uid) = uid { map.insert("UnixUserID".into(), Variant(Box::new(uid))); }
+                        if let Some(pid) = pid { map.insert("ProcessID".into(), Variant(Box::new(pid))); }
+                        r.append1(map)
+                    }
+                }
+            },
+            _ => return busmethods::bus_method(self, m, sender),
+        })
+    }
+}
+
+impl busmethods::Bus for Broker {
+    fn unique_names(&self) -> Vec<String> { self.peers.iter().filter_map(|p| p.name.clone()).collect() }
+
+    fn with_names<R, F: FnOnce(&mut NameTable) -> R>(&mut self, f: F) -> R { f(&mut self.names) }
+
+    fn with_matches<R, F: FnOnce(&mut Vec<MatchRule<'static>>) -> R>(&mut self, unique: &str, f: F) -> Option<R> {
+        self.peers.iter_mut().find(|p| p.name.as_deref() == Some(unique)).map(|p| f(&mut p.matches))
+    }
+
+    fn can_own(&self, unique: &str, name: &str) -> bool {
+        let p = match &self.policy { Some(p) => p, None => return true };
+        self.peer(unique).map(|i| p.can_own(&self.peers[i].identity, name)) == Some(true)
+    }
+
+    fn emit(&mut self, m: Message) { self.send_from_bus(m) }
+}
+
+#[test]
+fn test_broker() {
+    use crate::blocking::{Connection, LocalConnection};
+    use crate::blocking::stdintf::org_freedesktop_dbus::{RequestNameReply, ReleaseNameReply};
+    use crate::busmethods::BUS_PATH;
+    use crate::tree::Factory;
+    use std::sync::{Arc, Mutex};
+
+    let broker = Broker::new("unix:tmpdir=/tmp").unwrap();
+    let (address, stop) = (broker.address(), broker.stop_handle());
+    let t = std::thread::spawn(move || broker.run());
+    let connect = |address: &str| {
+        let mut c = Channel::open_private(address).unwrap();
+        c.register().unwrap();
+        c
+    };
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let server_stop = StopHandle::default();
+    let (a2, ss2) = (address.clone(), server_stop.clone());
+    let st = std::thread::spawn(move || {
+        let mut server: LocalConnection = connect(&a2).into();
+        assert!(server.unique_name().starts_with(":1."));
+        assert_eq!(server.request_name("com.example.dbusrs.Broker", false, false, false).unwrap(), RequestNameReply::PrimaryOwner);
+        let f = Factory::new_fn::<()>();
+        f.tree(()).add(f.object_path("/greeter", ()).introspectable().add(f.interface("com.example.dbusrs.Broker", ())
+            .add_m(f.method("Greet", (), |m| {
+                let name: &str = m.msg.read1()?;
+                let s = Message::new_signal("/greeter", "com.example.dbusrs.Broker", "Greeted").unwrap().append1(name);
+                Ok(vec!(m.msg.method_return().append1(format!("Hello {}!", name)), s).into())
+            }))
+        )).start_receive(&server);
+        tx.send(()).unwrap();
+        while !ss2.is_stopped() { server.process(Duration::from_millis(20)).unwrap(); }
+    });
+    rx.recv().unwrap();
+
+    let mut client: Connection = connect(&address).into();
+    let greeted = Arc::new(Mutex::new(vec!()));
+    let g2 = greeted.clone();
+    let mut mr = MatchRule::new_signal("com.example.dbusrs.Broker", "Greeted");
+    mr.sender = Some("com.example.dbusrs.Broker".into());
+    client.add_match(mr, move |(name,): (String,), _, _| { g2.lock().unwrap().push(name); true }).unwrap();
+    let (r,): (String,) = client.with_proxy("com.example.dbusrs.Broker", "/greeter", Duration::from_secs(5))
+        .method_call("com.example.dbusrs.Broker", "Greet", ("world",)).unwrap();
+    assert_eq!(r, "Hello world!");
+    for _ in 0..10 { if greeted.lock().unwrap().len() > 0 { break }; client.process(Duration::from_millis(100)).unwrap(); }
+    assert_eq!(*greeted.lock().unwrap(), vec!("world".to_string()));
+
+    // Errors
+    let p = client.with_proxy("com.example.dbusrs.Broker", "/greeter", Duration::from_secs(5));
+    let e = p.method_call::<(), _, _, _>("com.example.dbusrs.Broker", "Wave", ()).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.UnknownMethod"));
+    let p2 = client.with_proxy("com.example.dbusrs.Nobody", "/", Duration::from_secs(5));
+    let e = p2.method_call::<(), _, _, _>("com.example.dbusrs.Nobody", "Hello", ()).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+
+    // Names and credentials
+    assert_eq!(client.request_name("com.example.dbusrs.Broker", false, true, false).unwrap(), RequestNameReply::Exists);
+    assert_eq!(client.release_name("com.example.dbusrs.Broker").unwrap(), ReleaseNameReply::NotOwner);
+    let bus = client.with_proxy(BUS_NAME, BUS_PATH, Duration::from_secs(5));
+    let (names,): (Vec<String>,) = bus.method_call(BUS_NAME, "ListNames", ()).unwrap();
+    assert!(names.contains(&"com.example.dbusrs.Broker".to_string()));
+    assert!(names.contains(&client.unique_name().to_string()));
+    let (uid,): (u32,) = bus.method_call(BUS_NAME, "GetConnectionUnixUser", ("com.example.dbusrs.Broker",)).unwrap();
+    assert_eq!(uid, unsafe { libc::getuid() });
+    let (pid,): (u32,) = bus.method_call(BUS_NAME, "GetConnectionUnixProcessID", (&*client.unique_name(),)).unwrap();
+    assert_eq!(pid, std::process::id());
+    bus.method_call::<(), _, _, _>("org.freedesktop.DBus.Peer", "Ping", ()).unwrap();
+
+    // The name goes away with the connection that owns it.
+    server_stop.stop();
+    st.join().unwrap();
+    let mut has_owner = true;
+    for _ in 0..50 {
+        has_owner = bus.method_call::<(bool,), _, _, _>(BUS_NAME, "NameHasOwner", ("com.example.dbusrs.Broker",)).unwrap().0;
+        if !has_owner { break }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!has_owner);
+    let e = p.method_call::<(String,), _, _, _>("com.example.dbusrs.Broker", "Greet", ("world",)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+
+    stop.stop();
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_broker_policy() {
+    use crate::busmethods::BUS_PATH;
+    let policy = BusPolicy::parse(r#"<busconfig>
+  <policy context="default">
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+    <allow own_prefix="com.example.dbusrs"/>
+    <deny own="com.example.dbusrs.Forbidden"/>
+    <deny send_interface="com.example.dbusrs.Secret"/>
+    <deny receive_member="Hidden"/>
+    <deny receive_type="method_return"/>
+  </policy>
+</busconfig>"#).unwrap();
+    let broker = Broker::new("unix:tmpdir=/tmp").unwrap().policy(policy);
+    let (address, stop) = (broker.address(), broker.stop_handle());
+    let t = std::thread::spawn(move || broker.run());
+    let connect = || {
+        let mut c = Channel::open_private(&address).unwrap();
+        c.register().unwrap();
+        c
+    };
+    let bus_call = |c: &Channel, member: &str, arg: &str| {
+        let mut m = Message::new_method_call(BUS_NAME, BUS_PATH, BUS_NAME, member).unwrap().append1(arg);
+        if member == "RequestName" { m = m.append1(0u32) }
+        c.send_with_reply_and_block(m, Duration::from_secs(5))
+    };
+
+    let (server, client) = (connect(), connect());
+    assert!(bus_call(&server, "RequestName", "com.example.dbusrs.Policy").is_ok());
+    let e = bus_call(&server, "RequestName", "com.example.dbusrs.Forbidden").unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    let e = bus_call(&server, "RequestName", "com.example.other").unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    bus_call(&client, "AddMatch", "type='signal',interface='com.example.dbusrs.Public'").unwrap();
+
+    // Requested replies are let through, even though the policy does not allow receiving method returns.
+    let call = |iface: &str| Message::new_method_call("com.example.dbusrs.Policy", "/", iface, "Hello").unwrap();
+    let pending = client.send_with_reply(call("com.example.dbusrs.Public")).unwrap();
+    client.flush();
+    let m = loop {
+        if let Some(m) = server.blocking_pop_message(Duration::from_secs(5)).unwrap() {
+            if m.msg_type() == MessageType::MethodCall { break m }
+        }
+    };
+    assert_eq!(m.sender().as_deref(), client.unique_name());
+    server.send(m.method_return().append1("Hi")).unwrap();
+    server.flush();
+    assert_eq!(pending.wait(Duration::from_secs(5)).unwrap().read1::<&str>().unwrap(), "Hi");
+    // But only once.
+    server.send(m.method_return().append1("Again")).unwrap();
+
+    let e = client.send_with_reply_and_block(call("com.example.dbusrs.Secret"), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+
+    for member in &["Hidden", "Shown"] {
+        server.send(Message::new_signal("/", "com.example.dbusrs.Public", *member).unwrap()).unwrap();
+    }
+    server.flush();
+    let m = loop {
+        if let Some(m) = client.blocking_pop_message(Duration::from_secs(5)).unwrap() {
+            assert_ne!(m.msg_type(), MessageType::MethodReturn);
+            if m.interface().as_deref() == Some("com.example.dbusrs.Public") { break m }
+        }
+    };
+    assert_eq!(m.member().as_deref(), Some("Shown"));
+
+    // The number of method calls waiting for a reply is limited.
+    for _ in 0..MAX_PENDING_REPLIES { client.send(call("com.example.dbusrs.Public")).unwrap(); }
+    let e = client.send_with_reply_and_block(call("com.example.dbusrs.Public"), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.LimitsExceeded"));
+
+    stop.stop();
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_broker_local_messages() {
+    use crate::busmethods::BUS_PATH;
+    let broker = Broker::new("unix:tmpdir=/tmp").unwrap();
+    let (address, stop) = (broker.address(), broker.stop_handle());
+    let t = std::thread::spawn(move || broker.run());
+    let connect = || {
+        let mut c = Channel::open_private(&address).unwrap();
+        c.register().unwrap();
+        c
+    };
+    let watcher = connect();
+    let m = Message::new_method_call(BUS_NAME, BUS_PATH, BUS_NAME, "AddMatch").unwrap().append1("type='signal'");
+    watcher.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+
+    // The Disconnected signal of a peer that goes away must not reach others.
+    let peer = connect();
+    let name = peer.unique_name().unwrap().to_string();
+    drop(peer);
+    loop {
+        let m = watcher.blocking_pop_message(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(!is_local(&m), "{:?}", m);
+        if m.member().as_deref() == Some("NameOwnerChanged") && m.read3::<&str, &str, &str>().unwrap() == (&*name, &*name, "") { break }
+    }
+    assert!(watcher.is_connected());
+
+    stop.stop();
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_broker_slow_reader() {
+    use crate::busmethods::BUS_PATH;
+    let broker = Broker::new("unix:tmpdir=/tmp").unwrap().max_outgoing_bytes(1_000_000);
+    let (address, stop) = (broker.address(), broker.stop_handle());
+    let t = std::thread::spawn(move || broker.run());
+    let connect = || {
+        let mut c = Channel::open_private(&address).unwrap();
+        c.register().unwrap();
+        c
+    };
+    let add_match = |c: &Channel, rule: &str| {
+        let m = Message::new_method_call(BUS_NAME, BUS_PATH, BUS_NAME, "AddMatch").unwrap().append1(rule);
+        c.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+    };
+    let (sender, watcher, slow) = (connect(), connect(), connect());
+    add_match(&watcher, "type='signal',interface='org.freedesktop.DBus'");
+    add_match(&slow, "type='signal',interface='com.example.dbusrs.Flood'");
+    let name = slow.unique_name().unwrap().to_string();
+
+    // A peer that asks for signals, but never reads them, is disconnected once too much piles up for it.
+    for _ in 0..100 {
+        sender.send(Message::new_signal("/", "com.example.dbusrs.Flood", "Data").unwrap().append1(vec![0u8; 100_000])).unwrap();
+        sender.flush();
+    }
+    loop {
+        let m = watcher.blocking_pop_message(Duration::from_secs(5)).unwrap().unwrap();
+        if m.member().as_deref() == Some("NameOwnerChanged") && m.read3::<&str, &str, &str>().unwrap() == (&*name, &*name, "") { break }
+    }
+    assert!(sender.is_connected());
+
+    stop.stop();
+    t.join().unwrap().unwrap();
+}
@@ -0,0 +1,517 @@
+// Access control for the Broker, read from the <policy> elements of dbus-daemon configuration files.
+
+use crate::{Message, MessageType, Error, c_str_to_slice, to_c_str};
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Context {
+    Default,
+    Group(u32),
+    User(u32),
+    Mandatory,
+}
+
+// The message part of a send or receive rule. None and "*" match anything.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Filter {
+    // send_destination or receive_sender: a name owned by the other side.
+    name: Option<String>,
+    name_is_prefix: bool,
+    interface: Option<String>,
+    member: Option<String>,
+    path: Option<String>,
+    msg_type: Option<MessageType>,
+    error: Option<String>,
+    // *_requested_reply="false": an allow rule that also matches replies that were not requested.
+    any_reply: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum What {
+    Own(String, bool),
+    Send(Filter),
+    Receive(Filter),
+    // Who may connect; None for "*".
+    User(Option<u32>),
+    Group(Option<u32>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    allow: bool,
+    what: What,
+}
+
+/// Who a connection is, as far as the policy is concerned.
+#[derive(Clone, Debug, Default)]
+pub (super) struct Identity {
+    pub uid: Option<u32>,
+    pub gids: Vec<u32>,
+}
+
+impl Identity {
+    pub fn for_uid(uid: Option<u32>) -> Identity {
+        Identity { uid, gids: uid.map(groups).unwrap_or_default() }
+    }
+}
+
+/// Access control rules for a `Broker`: who may connect, own names, and send and receive messages.
+///
+/// The rules are read from the `<policy>` elements of a dbus-daemon configuration file (a `busconfig`
+/// document), and work like they do in dbus-daemon: everything is denied unless allowed by a rule,
+/// and when several rules match, the last one wins. Rules in `<policy context="default">` are
+/// checked first, then those for the groups and the user of the connection, then those in
+/// `<policy context="mandatory">`.
+///
+/// `send_destination` and `receive_sender` match any name the other connection owns, and rules
+/// that name an interface only deny (not allow) messages without one. Replies to method calls
+/// that the broker has forwarded are always allowed, as are messages from the bus itself and
+/// the `Hello` call. Other method returns and errors are denied, unless allowed by a rule with
+/// `send_requested_reply="false"` or `receive_requested_reply="false"`. A method call stops
+/// waiting for its reply after 5 minutes, and a connection can have at most 1024 method calls
+/// waiting; further calls fail with a LimitsExceeded error. The user and group rules about who
+/// may connect are taken from the default and mandatory contexts; without any, only the user
+/// the broker runs as may connect.
+///
+/// Elements other than `<policy>` (e g `<listen>` and `<include>`) are ignored, and so are the
+/// `eavesdrop` and `log` attributes. `at_console` policies are not supported.
+/// Users and groups that do not exist are ignored, like dbus-daemon does.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::broker::{Broker, BusPolicy};
+/// let policy = BusPolicy::parse(r#"<busconfig>
+///   <policy context="default">
+///     <allow user="*"/>
+///     <allow send_destination="*"/>
+///     <allow receive_sender="*"/>
+///     <allow own_prefix="com.example"/>
+///     <deny send_interface="com.example.Admin"/>
+///   </policy>
+///   <policy user="root">
+///     <allow send_interface="com.example.Admin"/>
+///   </policy>
+/// </busconfig>"#)?;
+/// let broker = Broker::new("unix:path=/run/example/bus")?.policy(policy);
+/// broker.run()?;
+/// # Ok::<(), dbus::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BusPolicy {
+    policies: Vec<(Context, Vec<Rule>)>,
+}
+
+fn own_matches(pattern: &str, is_prefix: bool, name: &str) -> bool {
+    if pattern == "*" { return true }
+    if is_prefix { name == pattern || (name.starts_with(pattern) && name[pattern.len()..].starts_with('.')) }
+    else { name == pattern }
+}
+
+fn error_name(m: &Message) -> Option<String> {
+    let p = unsafe { ffi::dbus_message_get_error_name(m.ptr()) };
+    c_str_to_slice(&p).map(|s| s.to_string())
+}
+
+impl Filter {
+    fn matches(&self, allow: bool, names: &[String], m: &Message) -> bool {
+        fn field(rule: &Option<String>) -> Option<&str> { rule.as_deref().filter(|r| *r != "*") }
+        if let Some(n) = field(&self.name) {
+            if !names.iter().any(|x| own_matches(n, self.name_is_prefix, x)) { return false }
+        }
+        if let Some(i) = field(&self.interface) {
+            match m.interface() {
+                Some(mi) => if &*mi != i { return false },
+                None => if allow { return false },
+            }
+        }
+        if let Some(x) = field(&self.member) { if m.member().as_deref() != Some(x) { return false } }
+        if let Some(x) = field(&self.path) { if m.path().as_deref() != Some(x) { return false } }
+        if let Some(x) = field(&self.error) { if error_name(m).as_deref() != Some(x) { return false } }
+        if let Some(t) = self.msg_type { if m.msg_type() != t { return false } }
+        let is_reply = matches!(m.msg_type(), MessageType::MethodReturn | MessageType::Error);
+        if allow && is_reply && !self.any_reply { return false }
+        true
+    }
+}
+
+impl BusPolicy {
+    /// Reads the policy from a dbus-daemon configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BusPolicy, Error> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(|e| Error::new_failed(&format!("{}: {}", path.display(), e)))?;
+        Self::parse(&s)
+    }
+
+    /// Reads the policy from the contents of a dbus-daemon configuration file.
+    pub fn parse(xml: &str) -> Result<BusPolicy, Error> {
+        let mut p = BusPolicy::default();
+        let mut stack: Vec<String> = vec!();
+        // The context of the current <policy> element, None for users and groups that do not exist.
+        let mut context = None;
+        for tag in tags(xml)? {
+            let err = |s: &str| Error::new_failed(&format!("Line {}: {}", tag.line, s));
+            if tag.end {
+                if stack.pop().as_deref() != Some(&*tag.name) { return Err(err(&format!("Unexpected </{}>", tag.name))) }
+                continue;
+            }
+            match (stack.len(), &*tag.name) {
+                (0, "busconfig") => {},
+                (0, _) => return Err(err("The root element must be <busconfig>")),
+                (1, "policy") => {
+                    context = match (tag.attrs.first().map(|(k, v)| (&**k, &**v)), tag.attrs.len()) {
+                        (Some(("context", "default")), 1) => Some(Context::Default),
+                        (Some(("context", "mandatory")), 1) => Some(Context::Mandatory),
+                        (Some(("user", u)), 1) => user_id(u).map(Context::User),
+                        (Some(("group", g)), 1) => group_id(g).map(Context::Group),
+                        (Some(("at_console", _)), 1) => return Err(err("at_console policies are not supported")),
+                        _ => return Err(err("<policy> must have exactly one of the context, user or group attributes")),
+                    };
+                    if let Some(c) = &context { p.policies.push((c.clone(), vec!())) }
+                },
+                (2, "allow") | (2, "deny") if stack[1] == "policy" => {
+                    let what = what(&tag.attrs).map_err(|e| err(&e))?;
+                    if let (Some(_), Some(what)) = (&context, what) {
+                        p.policies.last_mut().unwrap().1.push(Rule { allow: tag.name == "allow", what });
+                    }
+                },
+                (2, _) if stack[1] == "policy" => return Err(err(&format!("Unexpected <{}> in <policy>", tag.name))),
+                _ => {},
+            }
+            if !tag.empty { stack.push(tag.name) }
+        }
+        if !stack.is_empty() { return Err(Error::new_failed(&format!("Missing </{}>", stack.last().unwrap()))) }
+        Ok(p)
+    }
+
+    // The rules that apply to "id", in the order they are checked.
+    fn rules<'a>(&'a self, id: &'a Identity) -> impl Iterator<Item=&'a Rule> + 'a {
+        let ctx = move |pred: fn(&Context, &Identity) -> bool| self.policies.iter().filter(move |(c, _)| pred(c, id)).flat_map(|(_, r)| r);
+        ctx(|c, _| *c == Context::Default)
+            .chain(ctx(|c, id| if let Context::Group(g) = c { id.gids.contains(g) } else { false }))
+            .chain(ctx(|c, id| if let Context::User(u) = c { id.uid == Some(*u) } else { false }))
+            .chain(ctx(|c, _| *c == Context::Mandatory))
+    }
+
+    fn check<F: Fn(&What) -> bool>(&self, id: &Identity, f: F) -> bool {
+        self.rules(id).filter(|r| f(&r.what)).last().map(|r| r.allow) == Some(true)
+    }
+
+    /// Returns true if a connection of user "uid" may connect.
+    pub fn can_connect(&self, uid: u32) -> bool {
+        let id = Identity::for_uid(Some(uid));
+        let rules = self.policies.iter().filter(|(c, _)| *c == Context::Default || *c == Context::Mandatory).flat_map(|(_, r)| r);
+        rules.rev().find(|r| match r.what {
+            What::User(u) => u.is_none() || u == Some(uid),
+            What::Group(g) => g.map(|g| id.gids.contains(&g)) != Some(false),
+            _ => false,
+        }).map_or(uid == unsafe { libc::getuid() }, |r| r.allow)
+    }
+
+    pub (super) fn can_own(&self, id: &Identity, name: &str) -> bool {
+        self.check(id, |w| if let What::Own(p, prefix) = w { own_matches(p, *prefix, name) } else { false })
+    }
+
+    // "to" are the names owned by the receiving connection.
+    // Requested replies are not checked, so "m" is never one.
+    pub (super) fn can_send(&self, id: &Identity, to: &[String], m: &Message) -> bool {
+        self.rules(id).filter(|r| if let What::Send(f) = &r.what { f.matches(r.allow, to, m) } else { false })
+            .last().map(|r| r.allow) == Some(true)
+    }
+
+    // "from" are the names owned by the sending connection.
+    pub (super) fn can_receive(&self, id: &Identity, from: &[String], m: &Message) -> bool {
+        self.rules(id).filter(|r| if let What::Receive(f) = &r.what { f.matches(r.allow, from, m) } else { false })
+            .last().map(|r| r.allow) == Some(true)
+    }
+}
+
+// Returns None for rules about users and groups that do not exist.
+fn what(attrs: &[(String, String)]) -> Result<Option<What>, String> {
+    let (mut own, mut send, mut receive, mut conn) = (None, Filter::default(), Filter::default(), None);
+    let (mut has_send, mut has_receive, mut eavesdrop) = (false, false, false);
+    let msg_type = |v: &str| Ok(match v {
+        "*" => None,
+        "method_call" => Some(MessageType::MethodCall),
+        "method_return" => Some(MessageType::MethodReturn),
+        "signal" => Some(MessageType::Signal),
+        "error" => Some(MessageType::Error),
+        _ => return Err(format!("Invalid message type {:?}", v)),
+    });
+    let requested_reply = |v: &str| match v {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("Invalid value {:?}, expected true or false", v)),
+    };
+    for (k, v) in attrs {
+        let s = Some(v.clone());
+        match &**k {
+            "own" => own = Some(What::Own(v.clone(), false)),
+            "own_prefix" => own = Some(What::Own(v.clone(), true)),
+            "user" if v == "*" => conn = Some(Some(What::User(None))),
+            "user" => conn = Some(user_id(v).map(|u| What::User(Some(u)))),
+            "group" if v == "*" => conn = Some(Some(What::Group(None))),
+            "group" => conn = Some(group_id(v).map(|g| What::Group(Some(g)))),
+            "send_destination" => send.name = s,
+            "send_destination_prefix" => { send.name = s; send.name_is_prefix = true },
+            "send_interface" => send.interface = s,
+            "send_member" => send.member = s,
+            "send_path" => send.path = s,
+            "send_error" => send.error = s,
+            "send_type" => send.msg_type = msg_type(v)?,
+            "send_requested_reply" => send.any_reply = !requested_reply(v)?,
+            "receive_sender" => receive.name = s,
+            "receive_interface" => receive.interface = s,
+            "receive_member" => receive.member = s,
+            "receive_path" => receive.path = s,
+            "receive_error" => receive.error = s,
+            "receive_type" => receive.msg_type = msg_type(v)?,
+            "receive_requested_reply" => receive.any_reply = !requested_reply(v)?,
+            "eavesdrop" => { eavesdrop = true; continue },
+            "log" => continue,
+            _ => return Err(format!("Unsupported attribute {:?}", k)),
+        }
+        if k.starts_with("send_") { has_send = true }
+        if k.starts_with("receive_") { has_receive = true }
+    }
+    // <allow eavesdrop="true"/> is a receive rule that matches everything.
+    let eavesdrop_only = eavesdrop && own.is_none() && conn.is_none() && !has_send && !has_receive;
+    let mut w = vec!();
+    if let Some(o) = own { w.push(Some(o)) }
+    if let Some(c) = conn { w.push(c) }
+    if has_send { w.push(Some(What::Send(send))) }
+    if has_receive || eavesdrop_only { w.push(Some(What::Receive(receive))) }
+    match w.len() {
+        1 => Ok(w.pop().unwrap()),
+        0 => Err("Rule without attributes".into()),
+        _ => Err("Rule mixes attributes for different kinds of rules".into()),
+    }
+}
+
+// Calls "f", one of the getpw*_r or getgr*_r functions, with "buf" as its buffer. The buffer starts out
+// with the size sysconf suggests for "size_name", and grows for as long as "f" says it is too small.
+// Returns the error number from the last call to "f".
+fn with_buffer<F: FnMut(&mut [c_char]) -> c_int>(buf: &mut Vec<c_char>, size_name: c_int, mut f: F) -> c_int {
+    // The suggested size is -1 if there is no suggestion, and it is not a maximum either.
+    let size = unsafe { libc::sysconf(size_name) };
+    buf.resize(if size > 0 { size as usize } else { 1024 }, 0);
+    loop {
+        let r = f(buf);
+        if r != libc::ERANGE { return r }
+        let n = buf.len() * 2;
+        buf.resize(n, 0);
+    }
+}
+
+// Resolves a user name (or number) to a user id.
+fn user_id(s: &str) -> Option<u32> {
+    if let Ok(u) = s.parse() { return Some(u) }
+    let (name, mut buf) = (to_c_str(s), vec!());
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let r = with_buffer(&mut buf, libc::_SC_GETPW_R_SIZE_MAX, |b| unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut pwd, b.as_mut_ptr(), b.len(), &mut result)
+    });
+    if r != 0 || result.is_null() { None } else { Some(pwd.pw_uid) }
+}
+
+// Resolves a group name (or number) to a group id.
+fn group_id(s: &str) -> Option<u32> {
+    if let Ok(g) = s.parse() { return Some(g) }
+    let (name, mut buf) = (to_c_str(s), vec!());
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let r = with_buffer(&mut buf, libc::_SC_GETGR_R_SIZE_MAX, |b| unsafe {
+        libc::getgrnam_r(name.as_ptr(), &mut grp, b.as_mut_ptr(), b.len(), &mut result)
+    });
+    if r != 0 || result.is_null() { None } else { Some(grp.gr_gid) }
+}
+
+// The groups user "uid" is a member of, including its primary group.
+fn groups(uid: u32) -> Vec<u32> {
+    let mut buf = vec!();
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let r = with_buffer(&mut buf, libc::_SC_GETPW_R_SIZE_MAX, |b| unsafe {
+        libc::getpwuid_r(uid, &mut pwd, b.as_mut_ptr(), b.len(), &mut result)
+    });
+    if r != 0 || result.is_null() { return vec!() }
+    let mut gids = vec![0 as libc::gid_t; 64];
+    loop {
+        let mut n = gids.len() as c_int;
+        if unsafe { libc::getgrouplist(pwd.pw_name, pwd.pw_gid, gids.as_mut_ptr(), &mut n) } >= 0 {
+            gids.truncate(n as usize);
+            return gids
+        }
+        gids.resize((n as usize).max(gids.len() * 2), 0);
+    }
+}
+
+#[derive(Debug)]
+struct Tag {
+    name: String,
+    attrs: Vec<(String, String)>,
+    // <tag/>
+    empty: bool,
+    // </tag>
+    end: bool,
+    line: usize,
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut r = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        r.push_str(&rest[..i]);
+        let end = rest[i..].find(';').ok_or_else(|| format!("Unterminated entity in {:?}", s))? + i;
+        let c = match &rest[i+1..end] {
+            "lt" => '<', "gt" => '>', "amp" => '&', "quot" => '"', "apos" => '\'',
+            e if e.starts_with("#x") => u32::from_str_radix(&e[2..], 16).ok().and_then(std::char::from_u32).ok_or_else(|| format!("Invalid entity &{};", e))?,
+            e if e.starts_with('#') => e[1..].parse().ok().and_then(std::char::from_u32).ok_or_else(|| format!("Invalid entity &{};", e))?,
+            e => return Err(format!("Unknown entity &{};", e)),
+        };
+        r.push(c);
+        rest = &rest[end+1..];
+    }
+    r.push_str(rest);
+    Ok(r)
+}
+
+// Splits a busconfig document into tags. Text, comments, processing instructions and the doctype are skipped.
+fn tags(xml: &str) -> Result<Vec<Tag>, Error> {
+    let mut v = vec!();
+    let mut pos = 0;
+    while let Some(i) = xml[pos..].find('<') {
+        let start = pos + i;
+        let line = xml[..start].matches('\n').count() + 1;
+        let err = |s: &str| Error::new_failed(&format!("Line {}: {}", line, s));
+        let rest = &xml[start..];
+        let skip_to = |end: &str| rest.find(end).map(|e| start + e + end.len()).ok_or_else(|| err("Unterminated markup"));
+        if rest.starts_with("<!--") { pos = skip_to("-->")?; continue }
+        if rest.starts_with("<![CDATA[") { pos = skip_to("]]>")?; continue }
+        if rest.starts_with("<?") { pos = skip_to("?>")?; continue }
+        if rest.starts_with("<!") { pos = skip_to(">")?; continue }
+        let end = rest.find('>').ok_or_else(|| err("Unterminated tag"))?;
+        pos = start + end + 1;
+        let mut body = &rest[1..end];
+        let is_end = body.starts_with('/');
+        if is_end { body = &body[1..] }
+        let empty = body.ends_with('/');
+        if empty { body = &body[..body.len()-1] }
+        let name_end = body.find(|c: char| c.is_whitespace()).unwrap_or(body.len());
+        let name = body[..name_end].to_string();
+        if name.is_empty() { return Err(err("Tag without a name")) }
+        let mut attrs = vec!();
+        let mut a = body[name_end..].trim_start();
+        while !a.is_empty() {
+            let eq = a.find('=').ok_or_else(|| err("Expected '=' after attribute name"))?;
+            let key = a[..eq].trim().to_string();
+            a = a[eq+1..].trim_start();
+            let q = a.chars().next().filter(|&c| c == '"' || c == '\'').ok_or_else(|| err("Expected a quoted attribute value"))?;
+            let close = a[1..].find(q).ok_or_else(|| err("Unterminated attribute value"))? + 1;
+            attrs.push((key, unescape(&a[1..close]).map_err(|e| err(&e))?));
+            a = a[close+1..].trim_start();
+        }
+        if is_end && (empty || !attrs.is_empty()) { return Err(err("Malformed end tag")) }
+        v.push(Tag { name, attrs, empty, end: is_end, line });
+    }
+    Ok(v)
+}
+
+#[test]
+fn test_bus_policy() {
+    let p = BusPolicy::parse(r#"<?xml version="1.0"?>
+<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>system</type>
+  <!-- Comments are <ignored/> -->
+  <policy context="default">
+    <allow user="*"/>
+    <deny user="4711"/>
+    <allow own_prefix="com.example"/>
+    <deny own="com.example.Admin"/>
+    <allow send_destination="*" eavesdrop="true"/>
+    <deny send_destination="com.example.Admin" send_interface="com.example.Admin"/>
+    <allow receive_sender="*"/>
+    <allow receive_type="error" receive_requested_reply="false"/>
+    <deny receive_type="error" receive_error="com.example.Error.Secret"/>
+  </policy>
+  <policy user="0">
+    <allow own="com.example.Admin"/>
+    <allow send_destination="com.example.Admin" send_member="Reboot"/>
+  </policy>
+  <policy user="no-such-user-dbus-rs">
+    <allow own="*"/>
+  </policy>
+  <policy context="mandatory">
+    <deny send_path="/com/example/Forbidden"/>
+  </policy>
+</busconfig>
+"#).unwrap();
+    let (user, root) = (Identity { uid: Some(1000), gids: vec!() }, Identity { uid: Some(0), gids: vec!(0) });
+    assert!(p.can_connect(1000));
+    assert!(!p.can_connect(4711));
+    assert!(p.can_own(&user, "com.example"));
+    assert!(p.can_own(&user, "com.example.Foo"));
+    assert!(!p.can_own(&user, "com.examples"));
+    assert!(!p.can_own(&user, "com.example.Admin"));
+    assert!(p.can_own(&root, "com.example.Admin"));
+
+    let call = |path: &str, iface: &str, member: &str| Message::new_method_call("com.example.Admin", path, iface, member).unwrap();
+    let admin = vec!(":1.5".to_string(), "com.example.Admin".to_string());
+    let other = vec!(":1.6".to_string());
+    assert!(!p.can_send(&user, &admin, &call("/", "com.example.Admin", "Reboot")));
+    assert!(p.can_send(&root, &admin, &call("/", "com.example.Admin", "Reboot")));
+    assert!(!p.can_send(&root, &admin, &call("/", "com.example.Admin", "Shutdown")));
+    assert!(p.can_send(&user, &admin, &call("/", "com.example.Foo", "Reboot")));
+    assert!(p.can_send(&user, &other, &call("/", "com.example.Admin", "Reboot")));
+    assert!(!p.can_send(&root, &admin, &call("/com/example/Forbidden", "com.example.Admin", "Reboot")));
+    // A deny rule on an interface also applies to messages without one.
+    let m = call("/", "com.example.Foo", "Reboot");
+    unsafe { ffi::dbus_message_set_interface(m.ptr(), std::ptr::null()) };
+    assert!(m.interface().is_none());
+    assert!(!p.can_send(&user, &admin, &m));
+    assert!(p.can_send(&user, &other, &m));
+
+    let mut c = call("/", "com.example.Admin", "Reboot");
+    crate::message::message_set_serial(&mut c, 1);
+    let e = c.error(&"com.example.Error.Secret".into(), &to_c_str("Secret"));
+    assert!(!p.can_receive(&user, &admin, &e));
+    assert!(p.can_receive(&user, &admin, &c.error(&"com.example.Error.Other".into(), &to_c_str("Other"))));
+    // Only errors may be received without being requested, not method returns.
+    assert!(!p.can_receive(&user, &admin, &c.method_return()));
+    assert!(p.can_receive(&user, &admin, &Message::new_signal("/", "com.example.Foo", "Bar").unwrap()));
+
+    // Nothing is allowed by default.
+    let empty = BusPolicy::parse("<busconfig/>").unwrap();
+    assert!(!empty.can_own(&user, "com.example"));
+    assert!(!empty.can_send(&user, &other, &c));
+    assert!(!empty.can_receive(&user, &other, &c));
+    assert!(empty.can_connect(unsafe { libc::getuid() }));
+
+    for (xml, e) in &[
+        ("<policy/>", "Line 1: The root element must be <busconfig>"),
+        ("<busconfig>\n<policy context=\"default\"><allow/></policy></busconfig>", "Line 2: Rule without attributes"),
+        ("<busconfig><policy context=\"default\"><allow own=\"a\" send_destination=\"b\"/></policy></busconfig>",
+            "Line 1: Rule mixes attributes for different kinds of rules"),
+        ("<busconfig><policy context=\"default\"><allow own_name=\"a\"/></policy></busconfig>", "Line 1: Unsupported attribute \"own_name\""),
+        ("<busconfig><policy at_console=\"true\"></policy></busconfig>", "Line 1: at_console policies are not supported"),
+        ("<busconfig><policy context=\"default\"></busconfig>", "Line 1: Unexpected </busconfig>"),
+        ("<busconfig><policy context=\"default\"/>", "Missing </busconfig>"),
+        ("<busconfig><policy context='default'><allow send_type=\"call\"/></policy></busconfig>", "Line 1: Invalid message type \"call\""),
+        ("<busconfig><policy context='default'><allow send_requested_reply=\"no\"/></policy></busconfig>",
+            "Line 1: Invalid value \"no\", expected true or false"),
+    ] {
+        assert_eq!(BusPolicy::parse(xml).unwrap_err().message(), Some(*e));
+    }
+    assert_eq!(unescape("a&lt;&amp;&#65;&#x42;").unwrap(), "a<&AB");
+
+    // Entries that do not fit into the suggested buffer size are still found.
+    let mut buf = vec!();
+    assert_eq!(with_buffer(&mut buf, libc::_SC_GETPW_R_SIZE_MAX, |b| if b.len() < 100_000 { libc::ERANGE } else { 0 }), 0);
+    assert!(buf.len() >= 100_000);
+    assert_eq!(user_id("root"), Some(0));
+    assert_eq!(group_id("root"), Some(0));
+    assert!(groups(0).contains(&0));
+}
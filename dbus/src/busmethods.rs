@@ -0,0 +1,170 @@
+// The org.freedesktop.DBus interface, as implemented by the message buses in this crate
+// (LocalBus and Broker): who owns which name, and which match rules each connection has.
+
+use crate::Message;
+use crate::message::MatchRule;
+use crate::strings::{BusName, ErrorName};
+use crate::tree::MethodErr;
+use crate::blocking::stdintf::org_freedesktop_dbus::{RequestNameReply, ReleaseNameReply};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+pub (crate) const BUS_NAME: &str = "org.freedesktop.DBus";
+pub (crate) const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+pub (crate) fn error_reply(m: &Message, name: &str, text: &str) -> Message {
+    m.error(&ErrorName::from(name), &CString::new(text).unwrap())
+}
+
+pub (crate) fn no_owner(name: &str) -> MethodErr {
+    MethodErr::from(("org.freedesktop.DBus.Error.NameHasNoOwner", format!("The name {} is not owned", name)))
+}
+
+/// The well-known names of a bus.
+#[derive(Debug, Default)]
+pub (crate) struct NameTable(
+    // Well-known name -> (unique name of owner, allow replacement)
+    BTreeMap<String, (String, bool)>
+);
+
+impl NameTable {
+    /// The unique name of the owner of a well-known name.
+    pub fn owner(&self, name: &str) -> Option<&str> { self.0.get(name).map(|x| &*x.0) }
+
+    /// The well-known names owned by the connection with this unique name.
+    pub fn owned_by<'a>(&'a self, unique: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.0.iter().filter(move |(_, (owner, _))| owner == unique).map(|(n, _)| &**n)
+    }
+}
+
+/// What the bus methods need from a bus.
+pub (crate) trait Bus {
+    /// The unique names of the connections that have one.
+    fn unique_names(&self) -> Vec<String>;
+
+    /// Calls "f" with the well-known names of the bus.
+    fn with_names<R, F: FnOnce(&mut NameTable) -> R>(&mut self, f: F) -> R;
+
+    /// Calls "f" with the match rules of the connection with this unique name, if it is still there.
+    fn with_matches<R, F: FnOnce(&mut Vec<MatchRule<'static>>) -> R>(&mut self, unique: &str, f: F) -> Option<R>;
+
+    /// Returns false if the connection with this unique name may not own "name".
+    fn can_own(&self, _unique: &str, _name: &str) -> bool { true }
+
+    /// Sends a signal from the bus, to its destination or to everyone with a matching rule.
+    fn emit(&mut self, m: Message);
+}
+
+/// Handles the methods of org.freedesktop.DBus that all buses have in common, i e everything except Hello.
+pub (crate) fn bus_method<B: Bus>(bus: &mut B, m: &Message, sender: &str) -> Result<Message, MethodErr> {
+    let member = m.member().ok_or_else(MethodErr::no_arg)?;
+    let r = m.method_return();
+    Ok(match &*member {
+        "RequestName" => {
+            let (name, flags): (&str, u32) = m.read2()?;
+            r.append1(request_name(bus, name, flags, sender)? as u32)
+        },
+        "ReleaseName" => {
+            let name: &str = m.read1()?;
+            r.append1(release_name(bus, name, sender) as u32)
+        },
+        "GetNameOwner" => {
+            let name: &str = m.read1()?;
+            r.append1(name_owner(bus, name).ok_or_else(|| no_owner(name))?)
+        },
+        "NameHasOwner" => {
+            let name: &str = m.read1()?;
+            r.append1(name_owner(bus, name).is_some())
+        },
+        "ListNames" => {
+            let mut v = vec!(BUS_NAME.to_string());
+            v.extend(bus.unique_names());
+            bus.with_names(|t| v.extend(t.0.keys().cloned()));
+            r.append1(v)
+        },
+        "AddMatch" => {
+            let mr = MatchRule::parse(m.read1()?)?;
+            bus.with_matches(sender, |v| v.push(mr));
+            r
+        },
+        "RemoveMatch" => {
+            let mstr = MatchRule::parse(m.read1()?)?.match_str();
+            let removed = bus.with_matches(sender, |v| {
+                let i = v.iter().position(|mr| mr.match_str() == mstr)?;
+                Some(v.remove(i))
+            });
+            removed.flatten().ok_or_else(||
+                MethodErr::from(("org.freedesktop.DBus.Error.MatchRuleNotFound", "The given match rule wasn't found")))?;
+            r
+        },
+        _ => return Err(MethodErr::no_method(&member)),
+    })
+}
+
+/// The unique name of the owner of "name", which can also be a unique name or the name of the bus.
+pub (crate) fn name_owner<B: Bus>(bus: &mut B, name: &str) -> Option<String> {
+    if name == BUS_NAME { Some(BUS_NAME.into()) }
+    else if name.starts_with(':') { bus.unique_names().into_iter().find(|n| n == name) }
+    else { bus.with_names(|t| t.owner(name).map(String::from)) }
+}
+
+/// Announces that a connection got its unique name.
+pub (crate) fn connected<B: Bus>(bus: &mut B, unique: &str) {
+    name_changed(bus, unique, "", unique);
+}
+
+/// Releases the names of a connection that went away, and announces that.
+pub (crate) fn disconnected<B: Bus>(bus: &mut B, unique: &str) {
+    let owned = bus.with_names(|t| {
+        let owned: Vec<String> = t.owned_by(unique).map(String::from).collect();
+        for n in &owned { t.0.remove(n); }
+        owned
+    });
+    for n in owned { name_changed(bus, &n, unique, "") }
+    name_changed(bus, unique, unique, "");
+}
+
+fn request_name<B: Bus>(bus: &mut B, name: &str, flags: u32, sender: &str) -> Result<RequestNameReply, MethodErr> {
+    if name.starts_with(':') || name == BUS_NAME || BusName::new(name).is_err() { Err(MethodErr::invalid_arg(&name))? }
+    if !bus.can_own(sender, name) {
+        Err(MethodErr::from(("org.freedesktop.DBus.Error.AccessDenied", format!("Connection {} is not allowed to own the name {}", sender, name))))?
+    }
+    let (allow_replacement, replace_existing) = (flags & 1 != 0, flags & 2 != 0);
+    let old = bus.with_names(|t| {
+        let old = match t.0.get(name) {
+            Some((owner, _)) if owner == sender => return Err(RequestNameReply::AlreadyOwner),
+            Some((_, false)) => return Err(RequestNameReply::Exists),
+            Some(_) if !replace_existing => return Err(RequestNameReply::Exists),
+            Some((owner, _)) => owner.clone(),
+            None => String::new(),
+        };
+        t.0.insert(name.into(), (sender.into(), allow_replacement));
+        Ok(old)
+    });
+    match old {
+        Ok(old) => name_changed(bus, name, &old, sender),
+        Err(reply) => return Ok(reply),
+    }
+    Ok(RequestNameReply::PrimaryOwner)
+}
+
+fn release_name<B: Bus>(bus: &mut B, name: &str, sender: &str) -> ReleaseNameReply {
+    let r = bus.with_names(|t| match t.owner(name) {
+        None => ReleaseNameReply::NonExistent,
+        Some(owner) if owner != sender => ReleaseNameReply::NotOwner,
+        Some(_) => { t.0.remove(name); ReleaseNameReply::Released }
+    });
+    if r == ReleaseNameReply::Released { name_changed(bus, name, sender, "") }
+    r
+}
+
+// Sends NameLost to the old owner, NameAcquired to the new owner, and NameOwnerChanged to everyone interested.
+fn name_changed<B: Bus>(bus: &mut B, name: &str, old: &str, new: &str) {
+    for (member, dest) in &[("NameLost", old), ("NameAcquired", new)] {
+        if dest.is_empty() { continue }
+        let mut s = Message::new_signal(BUS_PATH, BUS_NAME, *member).unwrap().append1(name);
+        s.set_destination(Some((*dest).into()));
+        bus.emit(s);
+    }
+    bus.emit(Message::new_signal(BUS_PATH, BUS_NAME, "NameOwnerChanged").unwrap().append3(name, old, new));
+}
@@ -3,13 +3,50 @@
 //! Contains some helper structs and traits common to all Connection types.-
 
 use crate::{Error, Message, to_c_str, c_str_to_slice, MessageType};
-use std::{str, time::Duration, collections::HashMap};
-use std::sync::{Mutex, atomic::AtomicU8, atomic::Ordering};
+use std::{str, ptr, time::Duration, time::Instant, collections::HashMap, collections::VecDeque};
+use std::{future, pin::Pin, task};
+use std::sync::{Arc, Mutex, atomic::AtomicU8, atomic::AtomicU64, atomic::Ordering};
 use std::ffi::CStr;
 use std::os::raw::{c_void, c_int};
-use crate::message::MatchRule;
+use crate::message::{MatchRule, MessageLog, LatencyTracker, Direction};
+use crate::metrics::{Metrics, SharedMetrics};
 use std::os::unix::io::RawFd;
 
+mod eventloop;
+pub use self::eventloop::{EventLoop, WatchId, TimeoutId};
+
+mod msgsender;
+pub use self::msgsender::MsgSender;
+
+mod outgoing;
+pub use self::outgoing::{OutgoingLimit, Overflow};
+
+mod incoming;
+pub use self::incoming::IncomingLimits;
+
+mod congestion;
+
+mod unixfd;
+
+mod keepalive;
+pub use self::keepalive::{Keepalive, Health};
+
+mod server;
+pub use self::server::Server;
+
+// The same as the default timeout of libdbus, until changed with set_default_timeout.
+static DEFAULT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(25_000);
+
+/// Sets the timeout used for method calls on connections without a default timeout of their own.
+///
+/// See `Channel::set_default_timeout`. This affects all connections, so it is best done once, at startup.
+pub fn set_default_timeout(t: Duration) { DEFAULT_TIMEOUT_MS.store(t.as_millis() as u64, Ordering::Relaxed) }
+
+/// The timeout used for method calls on connections without a default timeout of their own.
+///
+/// Unless changed with `set_default_timeout`, this is 25 seconds, like in libdbus.
+pub fn default_timeout() -> Duration { Duration::from_millis(DEFAULT_TIMEOUT_MS.load(Ordering::Relaxed)) }
+
 #[derive(Debug)]
 struct ConnHandle(*mut ffi::DBusConnection, bool);
 
@@ -156,11 +193,48 @@ impl Drop for WatchMap {
 pub struct Channel {
     handle: ConnHandle,
     watchmap: Option<Box<WatchMap>>,
+    log: Option<Arc<MessageLog>>,
+    pending: Mutex<PendingReplies>,
+    eventloop: Option<Box<eventloop::EventLoopData>>,
+    outgoing: outgoing::Outgoing,
+    incoming: IncomingLimits,
+    reply_limit: congestion::ReplyLimit,
+    keepalive: Mutex<Option<keepalive::KeepaliveState>>,
+    metrics: Option<SharedMetrics>,
+    latency: Option<Arc<LatencyTracker>>,
+    default_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct PendingReplies {
+    // Messages taken from libdbus while looking for replies, to be returned by pop_message.
+    queue: VecDeque<Message>,
+    replies: HashMap<u32, PendingReply>,
+}
+
+#[derive(Debug)]
+enum PendingReply {
+    Waiting(Option<task::Waker>),
+    Received(Message),
+    // The reply is thrown away if it arrives before the deadline, after that the entry is removed.
+    Cancelled(Instant),
+}
+
+impl PendingReplies {
+    // Removes cancelled calls whose replies are no longer expected, or all of them if `now` is `None`.
+    fn remove_cancelled(&mut self, now: Option<Instant>) {
+        self.replies.retain(|_, r| match (r, now) {
+            (PendingReply::Cancelled(deadline), Some(now)) => *deadline > now,
+            (PendingReply::Cancelled(_), None) => false,
+            _ => true,
+        });
+    }
 }
 
 impl Drop for Channel {
     fn drop(&mut self) {
         self.set_watch_enabled(false); // Make sure "watchmap" is destroyed before "handle" is
+        self.eventloop = None; // Ditto
     }
 }
 
@@ -176,7 +250,7 @@ impl Channel {
         /* No, we don't want our app to suddenly quit if dbus goes down */
         unsafe { ffi::dbus_connection_set_exit_on_disconnect(ptr, 0) };
 
-        let c = Channel { handle, watchmap: None };
+        let c = Channel { handle, watchmap: None, log: None, pending: Default::default(), eventloop: None, outgoing: Default::default(), incoming: Default::default(), reply_limit: Default::default(), keepalive: Default::default(), metrics: None, latency: None, default_timeout: None };
 
         Ok(c)
     }
@@ -233,6 +307,15 @@ impl Channel {
         unsafe { ffi::dbus_connection_get_is_connected(self.conn()) != 0 }
     }
 
+    /// Gets the file descriptor of the connection's socket, if it has one.
+    ///
+    /// This is the descriptor to poll for reading, in case several connections need to be waited for
+    /// at once (see `blocking::Dispatcher`). To integrate with other event loops, see `watch` or `set_event_loop`.
+    pub fn unix_fd(&self) -> Option<RawFd> {
+        let mut fd = -1;
+        if unsafe { ffi::dbus_connection_get_unix_fd(self.conn(), &mut fd) } != 0 { Some(fd) } else { None }
+    }
+
     /// Get the connection's unique name.
     ///
     /// It's usually something like ":1.54"
@@ -251,12 +334,13 @@ impl Channel {
     /// Note: usually the message is sent when this call happens, but in
     /// case internal D-Bus buffers are full, it will be left in the out queue.
     /// Call "flush" or "read_write" to retry flushing the out queue.
-    pub fn send(&self, msg: Message) -> Result<u32, ()> {
-        let mut serial = 0u32;
-        let r = unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), &mut serial) };
-        if r == 0 { return Err(()); }
-        Ok(serial)
-    }
+    ///
+    /// In case the outgoing queue is full (see `set_outgoing_limit`), this might block, fail, or throw away
+    /// the message and return 0.
+    pub fn send(&self, msg: Message) -> Result<u32, ()> { send_on(self.conn(), self.log.as_deref(), self.metrics.as_ref(), self.latency.as_deref(), &self.outgoing, msg) }
+
+    /// Returns a handle that can send messages on this channel, see `MsgSender`.
+    pub fn msg_sender(&self) -> MsgSender { msgsender::new(self.conn(), self.log.clone(), self.metrics.clone(), self.latency.clone(), self.outgoing.clone()) }
 
     /// Sends a message over the D-Bus and waits for a reply. This is used for method calls.
     ///
@@ -264,20 +348,124 @@ impl Channel {
     ///
     /// Note: In case of an error reply, this is returned as an Err(), not as a Ok(Message) with the error type.
     ///
+    /// In case the outgoing queue is full (see `set_outgoing_limit`), this might block before sending, or fail
+    /// with an error of the LimitsExceeded kind.
+    ///
     /// Note: In case pop_message and send_with_reply_and_block is called in parallel from different threads,
     /// they might race to retreive the reply message from the internal queue.
     pub fn send_with_reply_and_block(&self, msg: Message, timeout: Duration) -> Result<Message, Error> {
-        let mut e = Error::empty();
+        trace_span!("method_call", destination = ?msg.destination(), path = ?msg.path(), interface = ?msg.interface(), member = ?msg.member());
+        // Released when returning, also if the call is not sent.
+        let _slot = match self.reply_limit.try_reserve(&self.pending.lock().unwrap()) {
+            Some(slot) => slot,
+            None => {
+                trace_event!("Method call not sent, too many pending replies");
+                return Err(Error::new_congested());
+            }
+        };
+        unixfd::check(self.conn(), &msg)?;
+        // Method calls are never thrown away, so there is no Ok(false) to handle.
+        if self.outgoing.reserve(self.conn(), &msg).is_err() {
+            trace_event!("Method call not sent, outgoing queue full");
+            return Err(Error::new_outgoing_full());
+        }
+        // Same as dbus_connection_send_with_reply_and_block, except that we get to see the reply
+        // if it is an error, so that the error's additional arguments are not lost.
+        let mut pending = ptr::null_mut();
+        let r = unsafe { ffi::dbus_connection_send_with_reply(self.conn(), msg.ptr(), &mut pending, timeout.as_millis() as c_int) };
+        if r == 0 { return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Failed to send message")) }
+        if let Some(log) = &self.log { log.record(Direction::Sent, &msg) }
+        if let Some(m) = &self.metrics { m.sent(&msg, self.outgoing_size()) }
+        if let Some(l) = &self.latency { l.record(Direction::Sent, &msg) }
+        if pending.is_null() {
+            trace_event!("Method call failed, disconnected");
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection was disconnected before a reply was received"));
+        }
         let response = unsafe {
-            ffi::dbus_connection_send_with_reply_and_block(self.conn(), msg.ptr(),
-                timeout.as_millis() as c_int, e.get_mut())
+            ffi::dbus_pending_call_block(pending);
+            let response = ffi::dbus_pending_call_steal_reply(pending);
+            ffi::dbus_pending_call_unref(pending);
+            response
         };
         if response.is_null() {
-            return Err(e);
+            // Only happens if libdbus runs out of memory while generating a timeout or disconnect error.
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Failed to receive reply"));
+        }
+        let mut r = Message::from_ptr(response, false);
+        crate::message::message_set_received(&mut r, Instant::now());
+        if let Some(log) = &self.log { log.record(Direction::Received, &r) }
+        if let Some(m) = &self.metrics { m.received(&r) }
+        if let Some(l) = &self.latency { l.record(Direction::Received, &r) }
+        // A reply over the incoming limits is thrown away, and the call fails with a LimitsExceeded error.
+        match self.incoming.check(&r).and_then(|_| r.set_error_from_msg()) {
+            Ok(()) => {
+                trace_event!(reply_serial = ?r.get_reply_serial(), "Received method return");
+                Ok(r)
+            }
+            Err(e) => {
+                trace_event!(error = ?e.name(), message = ?e.message(), "Method call failed");
+                Err(e)
+            }
         }
-        Ok(Message::from_ptr(response, false))
     }
 
+    /// Sends a message over the D-Bus without waiting for the reply. This is used for method calls.
+    ///
+    /// The reply can be retrieved through the returned PendingCall, and it will not be returned
+    /// by pop_message. Dropping the PendingCall cancels the call, i e the reply is thrown away.
+    ///
+    /// Fails if the limit set by `set_max_pending_replies` is reached.
+    #[allow(clippy::result_unit_err)] // Same error type as send
+    pub fn send_with_reply(&self, msg: Message) -> Result<PendingCall<'_>, ()> {
+        // Hold a slot while waiting for room in the outgoing queue, which might block, so do that before locking.
+        let slot = match self.reply_limit.try_reserve(&self.pending.lock().unwrap()) {
+            Some(slot) => slot,
+            None => {
+                trace_event!("Method call not sent, too many pending replies");
+                return Err(())
+            }
+        };
+        if !reserve_on(self.conn(), &self.outgoing, &msg)? { return Err(()) }
+        // Keep the lock while sending, so the reply cannot be popped before we wait for it.
+        let mut pending = self.pending.lock().unwrap();
+        pending.remove_cancelled(Some(Instant::now()));
+        let serial = send_reserved(self.conn(), self.log.as_deref(), self.metrics.as_ref(), self.latency.as_deref(), msg)?;
+        pending.replies.insert(serial, PendingReply::Waiting(None));
+        // The call is now counted in the table instead.
+        drop(slot);
+        self.report_pending(&pending);
+        Ok(PendingCall { channel: self, serial })
+    }
+
+    /// Records all messages sent and received through this channel in the given log,
+    /// or stops recording if `None` is given.
+    pub fn set_message_log(&mut self, log: Option<Arc<MessageLog>>) { self.log = log; }
+
+    /// Returns the log set by `set_message_log`, if any.
+    pub fn message_log(&self) -> Option<&Arc<MessageLog>> { self.log.as_ref() }
+
+    /// Reports metrics about messages sent and received through this channel,
+    /// or stops reporting if `None` is given. See the `metrics` module for details.
+    ///
+    /// `MsgSender`s already created keep reporting to the old metrics.
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) { self.metrics = metrics.map(SharedMetrics); }
+
+    /// Measures the round-trip times of method calls sent through this channel,
+    /// or stops measuring if `None` is given. See `LatencyTracker` for details.
+    pub fn set_latency_tracker(&mut self, tracker: Option<Arc<LatencyTracker>>) { self.latency = tracker; }
+
+    /// Returns the tracker set by `set_latency_tracker`, if any.
+    pub fn latency_tracker(&self) -> Option<&Arc<LatencyTracker>> { self.latency.as_ref() }
+
+    /// Sets the timeout for method calls made through this channel without an explicit timeout,
+    /// e g through the property helpers and proxies made with `proxy` on the blocking connections.
+    ///
+    /// `None` means to use the global default, see `channel::set_default_timeout`.
+    pub fn set_default_timeout(&mut self, t: Option<Duration>) { self.default_timeout = t; }
+
+    /// The timeout for method calls made through this channel without an explicit timeout.
+    pub fn default_timeout(&self) -> Duration { self.default_timeout.unwrap_or_else(default_timeout) }
+
     /// Flush the queue of outgoing messages.
     ///
     /// Blocking: until the outgoing queue is empty.
@@ -290,8 +478,14 @@ impl Channel {
     /// Blocking: If there are no messages, for up to timeout, or forever if timeout is None.
     /// For non-blocking behaviour, set timeout to Some(0).
     pub fn read_write(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        let timeout = match (timeout, self.check_keepalive()) {
+            (Some(t), Some(k)) => Some(t.min(k)),
+            (t, k) => t.or(k),
+        };
         let t = timeout.map_or(-1, |t| t.as_millis() as c_int);
         if unsafe { ffi::dbus_connection_read_write(self.conn(), t) == 0 } {
+            // Disconnected, so no more replies will arrive
+            self.pending.lock().unwrap().remove_cancelled(None);
             Err(())
         } else {
             Ok(())
@@ -304,22 +498,58 @@ impl Channel {
     /// For unhandled messages, please call MessageDispatcher::default_dispatch to return
     /// default replies for method calls.
     pub fn pop_message(&self) -> Option<Message> {
-        let mptr = unsafe { ffi::dbus_connection_pop_message(self.conn()) };
-        if mptr.is_null() {
-            None
-        } else {
-            let msg = Message::from_ptr(mptr, false);
-            // println!("Incoming: {:?}", msg);
-            Some(msg)
+        if let Some(msg) = self.pending.lock().unwrap().queue.pop_front() { return Some(msg) }
+        while let Some(msg) = self.pop_from_libdbus() {
+            if let Some(msg) = self.take_reply(msg) { return Some(msg) }
+        }
+        None
+    }
+
+    fn pop_from_libdbus(&self) -> Option<Message> {
+        loop {
+            let mptr = unsafe { ffi::dbus_connection_pop_message(self.conn()) };
+            if mptr.is_null() { return None }
+            let mut msg = Message::from_ptr(mptr, false);
+            crate::message::message_set_received(&mut msg, Instant::now());
+            trace_event!(serial = ?msg.get_serial(), msg_type = ?msg.msg_type(), sender = ?msg.sender(), path = ?msg.path(),
+                interface = ?msg.interface(), member = ?msg.member(), "Received message");
+            if let Some(log) = &self.log { log.record(Direction::Received, &msg) }
+            if let Some(m) = &self.metrics { m.received(&msg) }
+            if let Some(l) = &self.latency { l.record(Direction::Received, &msg) }
+            if let Some(msg) = self.check_incoming(msg) {
+                if !self.keepalive_reply(&msg) { return Some(msg) }
+            }
         }
     }
 
+    // Hands over replies to pending calls, and returns all other messages.
+    fn take_reply(&self, msg: Message) -> Option<Message> {
+        let serial = match (msg.msg_type(), msg.get_reply_serial()) {
+            (MessageType::MethodReturn, Some(s)) | (MessageType::Error, Some(s)) => s,
+            _ => return Some(msg),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        let waker = match pending.replies.get_mut(&serial) {
+            Some(r @ PendingReply::Waiting(_)) => match std::mem::replace(r, PendingReply::Received(msg)) {
+                PendingReply::Waiting(w) => w,
+                _ => unreachable!(),
+            },
+            Some(PendingReply::Cancelled(_)) => { pending.replies.remove(&serial); None },
+            _ => return Some(msg),
+        };
+        // Wake after unlocking, the woken task might be polled on another thread right away
+        drop(pending);
+        if let Some(waker) = waker { waker.wake() }
+        None
+    }
+
     /// Removes a message from the incoming queue, or waits until timeout if the queue is empty.
     ///
     pub fn blocking_pop_message(&self, timeout: Duration) -> Result<Option<Message>, Error> {
         if let Some(msg) = self.pop_message() { return Ok(Some(msg)) }
+        // read_write only fails if we're disconnected.
         self.read_write(Some(timeout)).map_err(|_|
-            Error::new_failed("Failed to read/write data, disconnected from D-Bus?")
+            Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Failed to read/write data, disconnected from D-Bus")
         )?;
         Ok(self.pop_message())
     }
@@ -332,6 +562,7 @@ impl Channel {
     pub fn set_watch_enabled(&mut self, enable: bool) {
         if enable == self.watchmap.is_some() { return }
         if enable {
+            self.eventloop = None;
             self.watchmap = Some(WatchMap::new(ConnHandle(self.conn(), false)));
         } else {
             self.watchmap = None;
@@ -379,6 +610,131 @@ impl Channel {
     }
 }
 
+fn send_on(conn: *mut ffi::DBusConnection, log: Option<&MessageLog>, metrics: Option<&SharedMetrics>, latency: Option<&LatencyTracker>,
+    outgoing: &outgoing::Outgoing, msg: Message) -> Result<u32, ()> {
+    if !reserve_on(conn, outgoing, &msg)? { return Ok(0) }
+    send_reserved(conn, log, metrics, latency, msg)
+}
+
+// The part of sending that might block, or fail because of the outgoing limits.
+// Returns Ok(false) if the message should be thrown away instead of sent.
+fn reserve_on(conn: *mut ffi::DBusConnection, outgoing: &outgoing::Outgoing, msg: &Message) -> Result<bool, ()> {
+    unixfd::check(conn, msg).map_err(|_e| {
+        trace_event!(msg_type = ?msg.msg_type(), path = ?msg.path(), member = ?msg.member(), error = ?_e.message(), "Not sending message");
+    })?;
+    outgoing.reserve(conn, msg)
+}
+
+// Hands over a message to libdbus, once reserve_on has made room for it. Does not block.
+fn send_reserved(conn: *mut ffi::DBusConnection, log: Option<&MessageLog>, metrics: Option<&SharedMetrics>, latency: Option<&LatencyTracker>,
+    msg: Message) -> Result<u32, ()> {
+    let mut serial = 0u32;
+    let r = unsafe { ffi::dbus_connection_send(conn, msg.ptr(), &mut serial) };
+    if r == 0 {
+        trace_event!(msg_type = ?msg.msg_type(), path = ?msg.path(), member = ?msg.member(), "Failed to send message");
+        return Err(());
+    }
+    trace_event!(serial, msg_type = ?msg.msg_type(), destination = ?msg.destination(), path = ?msg.path(),
+        interface = ?msg.interface(), member = ?msg.member(), "Sent message");
+    if let Some(log) = log { log.record(Direction::Sent, &msg) }
+    if let Some(m) = metrics { m.sent(&msg, unsafe { ffi::dbus_connection_get_outgoing_size(conn) as usize }) }
+    if let Some(l) = latency { l.record(Direction::Sent, &msg) }
+    Ok(serial)
+}
+
+/// A method call sent with `Channel::send_with_reply`, for which the reply has not yet been retrieved.
+///
+/// Dropping it (or calling `cancel`) abandons the call: the reply is thrown away when it arrives.
+///
+/// The reply can either be waited for with `wait`, which reads from the connection itself, or
+/// by awaiting the PendingCall, which relies on something else (e g an async connection's
+/// I/O loop) reading messages through `pop_message`.
+#[derive(Debug)]
+pub struct PendingCall<'a> {
+    channel: &'a Channel,
+    serial: u32,
+}
+
+impl PendingCall<'_> {
+    /// The serial of the method call.
+    pub fn serial(&self) -> u32 { self.serial }
+
+    /// Returns true if the reply has arrived.
+    ///
+    /// Non-blocking: reads what is available on the connection, but does not wait for more.
+    pub fn is_completed(&self) -> bool {
+        let _ = self.channel.read_write(Some(Duration::from_millis(0)));
+        self.collect();
+        matches!(self.channel.pending.lock().unwrap().replies.get(&self.serial), Some(PendingReply::Received(_)))
+    }
+
+    /// Cancels the call. The reply is thrown away when it arrives.
+    pub fn cancel(self) {}
+
+    /// Waits for the reply.
+    ///
+    /// Blocking: until the reply arrives or the timeout expires. Other messages read meanwhile
+    /// are kept for pop_message.
+    ///
+    /// Note: In case of an error reply, this is returned as an Err(), not as a Ok(Message) with the error type.
+    /// If the timeout expires, the call is cancelled and a NoReply error is returned.
+    pub fn wait(self, timeout: Duration) -> Result<Message, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.collect();
+            if let Some(r) = self.take(None) { return r }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new_custom("org.freedesktop.DBus.Error.NoReply", "Did not receive a reply within the timeout"))
+            }
+            self.channel.read_write(Some(deadline - now)).map_err(|_|
+                Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Failed to read/write data, disconnected from D-Bus")
+            )?;
+        }
+    }
+
+    // Moves the messages libdbus has read into our queue, picking up the reply on the way.
+    fn collect(&self) {
+        while let Some(msg) = self.channel.pop_from_libdbus() {
+            if let Some(msg) = self.channel.take_reply(msg) { self.channel.pending.lock().unwrap().queue.push_back(msg) }
+        }
+    }
+
+    // Returns the reply if it has arrived, otherwise remembers the waker (if any) to wake when it does.
+    fn take(&self, waker: Option<&task::Waker>) -> Option<Result<Message, Error>> {
+        let mut pending = self.channel.pending.lock().unwrap();
+        let msg = match pending.replies.get_mut(&self.serial) {
+            Some(PendingReply::Waiting(w)) => { if let Some(waker) = waker { *w = Some(waker.clone()) }; return None },
+            Some(PendingReply::Received(_)) => match pending.replies.remove(&self.serial) {
+                Some(PendingReply::Received(msg)) => msg,
+                _ => unreachable!(),
+            },
+            _ => return Some(Err(Error::new_custom("org.freedesktop.DBus.Error.Failed", "Reply was already retrieved"))),
+        };
+        Some(msg.set_error_from_msg().map(|_| msg))
+    }
+}
+
+impl future::Future for PendingCall<'_> {
+    type Output = Result<Message, Error>;
+    fn poll(self: Pin<&mut Self>, ctx: &mut task::Context) -> task::Poll<Self::Output> {
+        match self.take(Some(ctx.waker())) {
+            Some(r) => task::Poll::Ready(r),
+            None => task::Poll::Pending,
+        }
+    }
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.channel.pending.lock().unwrap();
+        let deadline = Instant::now() + self.channel.default_timeout();
+        if let Some(r @ PendingReply::Waiting(_)) = pending.replies.get_mut(&self.serial) { *r = PendingReply::Cancelled(deadline) }
+        else { pending.replies.remove(&self.serial); }
+        self.channel.report_pending(&pending);
+    }
+}
+
 /// Abstraction over different connections that send data
 pub trait Sender {
     /// Schedules a message for sending.
@@ -492,6 +848,81 @@ fn channel_simple_test() {
     }
 }
 
+#[test]
+fn test_pending_call() {
+    use std::future::Future;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    fn name_has_owner(c: &Channel) -> Message {
+        Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "NameHasOwner").unwrap().append1(c.unique_name().unwrap())
+    }
+    let mut c = Channel::get_private(BusType::Session).unwrap();
+    c.register().unwrap();
+
+    let p1 = c.send_with_reply(name_has_owner(&c)).unwrap();
+    let p2 = c.send_with_reply(name_has_owner(&c)).unwrap();
+    let s1 = p1.serial();
+    p1.cancel();
+    let m = p2.wait(Duration::from_secs(5)).unwrap();
+    assert_eq!(m.read1::<bool>().unwrap(), true);
+    c.read_write(Some(Duration::from_millis(100))).unwrap();
+    while let Some(m) = c.pop_message() { assert_ne!(m.get_reply_serial(), Some(s1)); }
+
+    // Nobody answers calls to ourselves, so this times out. The call itself is left for pop_message.
+    let m = Message::new_method_call(c.unique_name().unwrap(), "/", "com.example.dbusrs.Pending", "Hello").unwrap();
+    c.set_default_timeout(Some(Duration::from_millis(50)));
+    let p = c.send_with_reply(m).unwrap();
+    let s2 = p.serial();
+    assert!(!p.is_completed());
+    let e = p.wait(Duration::from_millis(200)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NoReply"));
+    let m = c.pop_message().unwrap();
+    assert_eq!(&*m.member().unwrap(), "Hello");
+    // The reply will never come, so the cancelled call is forgotten after the default timeout.
+    assert!(c.pending.lock().unwrap().replies.contains_key(&s2));
+    std::thread::sleep(Duration::from_millis(100));
+    c.send_with_reply(name_has_owner(&c)).unwrap().wait(Duration::from_secs(5)).unwrap();
+    assert!(!c.pending.lock().unwrap().replies.contains_key(&s2));
+    c.set_default_timeout(None);
+
+    // The reply is handed over when someone else reads messages.
+    fn raw() -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+    let waker = unsafe { Waker::from_raw(raw()) };
+    let mut p = c.send_with_reply(name_has_owner(&c)).unwrap();
+    assert!(Pin::new(&mut p).poll(&mut Context::from_waker(&waker)).is_pending());
+    c.flush();
+    assert!(c.blocking_pop_message(Duration::from_secs(5)).unwrap().is_none());
+    match Pin::new(&mut p).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(Ok(m)) => assert_eq!(m.read1::<bool>().unwrap(), true),
+        _ => panic!("Expected a reply"),
+    }
+}
+
+#[test]
+fn test_send_with_reply_blocked() {
+    use outgoing::{OutgoingLimit, Overflow};
+    // Someone who never reads what we send, so the outgoing queue stays full.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stuck");
+    let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let mut c = Channel::open_private(&format!("unix:path={}", path.display())).unwrap();
+    let call = || Message::new_method_call("com.example.dbusrs", "/stuck", "com.example.dbusrs.Stuck", "Call").unwrap();
+    c.set_outgoing_limit(Some(OutgoingLimit { max_bytes: None, max_messages: Some(1), overflow: Overflow::Block(Duration::from_millis(500)) }));
+    let _p = c.send_with_reply(call()).unwrap();
+
+    // While a call waits for room in the queue, the pending replies can still be accessed.
+    std::thread::scope(|s| {
+        let t = s.spawn(|| c.send_with_reply(call()).map(|_| ()));
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        // The waiting call already counts, it holds on to its place below the reply limit.
+        assert_eq!(c.pending_replies(), 2);
+        assert!(c.pop_message().is_none());
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert!(t.join().unwrap().is_err());
+    });
+}
+
 #[test]
 fn test_bus_type_is_compatible_with_set() {
     use std::collections::HashSet;
@@ -0,0 +1,138 @@
+use super::{PendingReplies, PendingReply};
+use crate::metrics::PENDING_REPLIES;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Default)]
+pub (super) struct ReplyLimit {
+    max: Option<usize>,
+    // Method calls not in the table of pending replies: blocking calls, and calls still being sent.
+    reserved: AtomicUsize,
+}
+
+/// Counts as a method call waiting for its reply, until dropped.
+pub (super) struct ReplySlot<'a>(&'a AtomicUsize);
+
+impl Drop for ReplySlot<'_> {
+    fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
+}
+
+impl ReplyLimit {
+    // Takes a slot unless the limit is reached. "p" must stay locked while doing so,
+    // otherwise two method calls could both take the last slot.
+    pub (super) fn try_reserve(&self, p: &PendingReplies) -> Option<ReplySlot<'_>> {
+        if self.is_full(p, 0) { return None }
+        self.reserved.fetch_add(1, Ordering::SeqCst);
+        Some(ReplySlot(&self.reserved))
+    }
+
+    // Cancelled calls are not counted, their replies are thrown away as they arrive.
+    pub (super) fn count(&self, p: &PendingReplies) -> usize {
+        let waiting = p.replies.values().filter(|r| !matches!(r, PendingReply::Cancelled(_))).count();
+        waiting + self.reserved.load(Ordering::SeqCst)
+    }
+
+    pub (super) fn is_full(&self, p: &PendingReplies, extra: usize) -> bool {
+        self.max.filter(|&max| self.count(p) + extra >= max).is_some()
+    }
+}
+
+impl super::Channel {
+    /// Sets the maximum number of method calls that can wait for a reply at the same time,
+    /// or removes the limit if `None` is given.
+    ///
+    /// Without a limit, calling a service that has stopped responding makes the table of pending
+    /// replies grow until the calls time out, or forever for calls without a timeout. With a limit,
+    /// method calls made while it is reached fail right away, with an error of the `Congested` kind
+    /// (see `ErrorKind`). `send_with_reply` fails with `Err(())` instead; use `is_congested` to tell
+    /// that apart from other failures.
+    ///
+    /// This counts blocking method calls, calls made with `send_with_reply`, and the method calls
+    /// of a nonblocking connection built on top of this channel. A method call waiting for room in the
+    /// outgoing queue (see `set_outgoing_limit`) counts already.
+    pub fn set_max_pending_replies(&mut self, max: Option<usize>) { self.reply_limit.max = max; }
+
+    /// Returns the limit set by `set_max_pending_replies`, if any.
+    pub fn max_pending_replies(&self) -> Option<usize> { self.reply_limit.max }
+
+    /// Returns the number of method calls made through this channel that are waiting for a reply.
+    ///
+    /// This does not include the method calls of a nonblocking connection, which keeps track of those itself.
+    pub fn pending_replies(&self) -> usize { self.reply_limit.count(&self.pending.lock().unwrap()) }
+
+    /// Returns true if no more method calls can be made until some replies have arrived,
+    /// see `set_max_pending_replies`.
+    pub fn is_congested(&self) -> bool { self.is_congested_with(0) }
+
+    pub (super) fn report_pending(&self, p: &PendingReplies) {
+        if let Some(m) = &self.metrics { m.0.gauge(PENDING_REPLIES, &[], self.reply_limit.count(p) as f64) }
+    }
+
+    // Includes "extra" method calls waiting for replies elsewhere, i e in a nonblocking connection.
+    pub (crate) fn is_congested_with(&self, extra: usize) -> bool {
+        self.reply_limit.is_full(&self.pending.lock().unwrap(), extra)
+    }
+}
+
+#[test]
+fn test_max_pending_replies() {
+    use crate::{Message, ErrorKind};
+    use std::time::Duration;
+
+    let server = super::Channel::get_private(super::BusType::Session).unwrap();
+    let server_name = server.unique_name().unwrap().to_string();
+    let mut client = super::Channel::get_private(super::BusType::Session).unwrap();
+    let call = || Message::new_method_call(&*server_name, "/congestion", "com.example.dbusrs.Congestion", "Hang").unwrap();
+
+    assert_eq!(client.max_pending_replies(), None);
+    client.set_max_pending_replies(Some(2));
+    let first = client.send_with_reply(call()).unwrap();
+    let second = client.send_with_reply(call()).unwrap();
+    assert_eq!(client.pending_replies(), 2);
+    assert!(client.is_congested());
+    assert!(client.send_with_reply(call()).is_err());
+    let e = client.send_with_reply_and_block(call(), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Congested);
+
+    // Cancelled calls do not count
+    drop(first);
+    assert_eq!(client.pending_replies(), 1);
+    assert!(!client.is_congested());
+    let third = client.send_with_reply(call()).unwrap();
+    assert!(client.is_congested());
+
+    // Once the server replies, there is room again
+    let mut replied = 0;
+    for _ in 0..30 {
+        if let Some(m) = server.blocking_pop_message(Duration::from_millis(100)).unwrap() {
+            if m.msg_type() == crate::MessageType::MethodCall { server.send(m.method_return()).unwrap(); replied += 1; }
+        }
+        if replied == 3 { break; }
+    }
+    assert!(second.wait(Duration::from_secs(5)).is_ok());
+    assert!(third.wait(Duration::from_secs(5)).is_ok());
+    assert_eq!(client.pending_replies(), 0);
+    assert!(!client.is_congested());
+
+    // Method calls made at the same time can't get past the limit together
+    let sent = std::sync::atomic::AtomicUsize::new(0);
+    let all_tried = std::sync::Barrier::new(8);
+    std::thread::scope(|s| for i in 0..8 {
+        let (client, sent, all_tried) = (&client, &sent, &all_tried);
+        s.spawn(move || {
+            let _r = if i % 2 == 0 {
+                let e = client.send_with_reply_and_block(call(), Duration::from_millis(500)).unwrap_err();
+                if e.kind() != ErrorKind::Congested { sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst); }
+                None
+            } else {
+                let r = client.send_with_reply(call()).ok();
+                if r.is_some() { sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst); }
+                r
+            };
+            all_tried.wait();
+        });
+    });
+    assert_eq!(sent.into_inner(), 2);
+    assert_eq!(client.pending_replies(), 0);
+    client.set_max_pending_replies(None);
+    assert!(!client.is_congested_with(100));
+}
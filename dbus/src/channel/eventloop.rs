@@ -0,0 +1,209 @@
+use super::{Channel, ConnHandle, Watch};
+use std::collections::HashSet;
+use std::os::raw::{c_void, c_uint};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Identifies a file descriptor watch handed out through `EventLoop`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WatchId(usize);
+
+/// Identifies a timeout handed out through `EventLoop`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimeoutId(usize);
+
+/// Callbacks that let an external event loop (e g GLib's main loop, calloop, or the event loop of
+/// a GUI toolkit) drive a Channel, instead of a dedicated thread.
+///
+/// Install with `Channel::set_event_loop`. When a watched file descriptor becomes ready, call
+/// `Channel::handle_watch`, and when a timeout expires, call `Channel::handle_timeout`. Then process
+/// the messages that have arrived, e g with `Channel::pop_message` or the `process` method of a connection.
+///
+/// The callbacks are called from within the D-Bus library, and must not call back into the channel.
+pub trait EventLoop {
+    /// A file descriptor to watch. If it is not enabled, it should not be polled until it is toggled.
+    fn add_watch(&mut self, id: WatchId, watch: Watch, enabled: bool);
+    /// The watch was enabled or disabled.
+    fn toggle_watch(&mut self, id: WatchId, watch: Watch, enabled: bool);
+    /// The watch should no longer be polled.
+    fn remove_watch(&mut self, id: WatchId);
+    /// A timeout that should expire after interval, and then again every interval, until removed.
+    /// If it is not enabled, it should not expire until it is toggled.
+    fn add_timeout(&mut self, id: TimeoutId, interval: Duration, enabled: bool);
+    /// The timeout was enabled or disabled. If enabled, it starts over from the full interval.
+    fn toggle_timeout(&mut self, id: TimeoutId, interval: Duration, enabled: bool);
+    /// The timeout should no longer expire.
+    fn remove_timeout(&mut self, id: TimeoutId);
+}
+
+/// This struct must be boxed as it is called from D-Bus callbacks!
+pub (super) struct EventLoopData {
+    conn: ConnHandle,
+    hooks: Mutex<Box<dyn EventLoop + Send>>,
+    watches: Mutex<HashSet<usize>>,
+    timeouts: Mutex<HashSet<usize>>,
+}
+
+impl std::fmt::Debug for EventLoopData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "EventLoopData") }
+}
+
+fn interval(t: *mut ffi::DBusTimeout) -> Duration { Duration::from_millis(unsafe { ffi::dbus_timeout_get_interval(t) } as u64) }
+
+impl EventLoopData {
+    pub (super) fn new(conn: ConnHandle, hooks: Box<dyn EventLoop + Send>) -> Box<EventLoopData> {
+        extern "C" fn add_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) -> u32 { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            d.watches.lock().unwrap().insert(watch as usize);
+            let (w, enabled) = Watch::from_raw_enabled(watch);
+            d.hooks.lock().unwrap().add_watch(WatchId(watch as usize), w, enabled);
+            1
+        }}
+        extern "C" fn remove_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            d.watches.lock().unwrap().remove(&(watch as usize));
+            d.hooks.lock().unwrap().remove_watch(WatchId(watch as usize));
+        }}
+        extern "C" fn toggled_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            let (w, enabled) = Watch::from_raw_enabled(watch);
+            d.hooks.lock().unwrap().toggle_watch(WatchId(watch as usize), w, enabled);
+        }}
+        extern "C" fn add_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) -> u32 { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            d.timeouts.lock().unwrap().insert(t as usize);
+            d.hooks.lock().unwrap().add_timeout(TimeoutId(t as usize), interval(t), ffi::dbus_timeout_get_enabled(t) != 0);
+            1
+        }}
+        extern "C" fn remove_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            d.timeouts.lock().unwrap().remove(&(t as usize));
+            d.hooks.lock().unwrap().remove_timeout(TimeoutId(t as usize));
+        }}
+        extern "C" fn toggled_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) { unsafe {
+            let d: &EventLoopData = &*(data as *mut _);
+            d.hooks.lock().unwrap().toggle_timeout(TimeoutId(t as usize), interval(t), ffi::dbus_timeout_get_enabled(t) != 0);
+        }}
+
+        let d = Box::new(EventLoopData { conn, hooks: Mutex::new(hooks), watches: Default::default(), timeouts: Default::default() });
+        let dptr: &EventLoopData = &d;
+        let dptr = dptr as *const _ as *mut _;
+        if unsafe { ffi::dbus_connection_set_watch_functions(d.conn.0,
+            Some(add_watch_cb), Some(remove_watch_cb), Some(toggled_watch_cb), dptr, None) } == 0 {
+                panic!("Cannot set watch functions (OOM?)")
+        }
+        if unsafe { ffi::dbus_connection_set_timeout_functions(d.conn.0,
+            Some(add_timeout_cb), Some(remove_timeout_cb), Some(toggled_timeout_cb), dptr, None) } == 0 {
+                panic!("Cannot set timeout functions (OOM?)")
+        }
+        d
+    }
+}
+
+impl Drop for EventLoopData {
+    fn drop(&mut self) {
+        let dptr: &EventLoopData = self;
+        let dptr = dptr as *const _ as *mut _;
+        if unsafe { ffi::dbus_connection_set_watch_functions(self.conn.0, None, None, None, dptr, None) } == 0 {
+            panic!("Cannot remove watch functions (OOM?)")
+        }
+        if unsafe { ffi::dbus_connection_set_timeout_functions(self.conn.0, None, None, None, dptr, None) } == 0 {
+            panic!("Cannot remove timeout functions (OOM?)")
+        }
+    }
+}
+
+impl Channel {
+    /// Lets an external event loop drive this channel, see `EventLoop`. Call with None to stop.
+    ///
+    /// This replaces watch tracking, i e `set_watch_enabled(false)` is called.
+    pub fn set_event_loop(&mut self, hooks: Option<Box<dyn EventLoop + Send>>) {
+        self.set_watch_enabled(false);
+        self.eventloop = None;
+        self.eventloop = hooks.map(|h| EventLoopData::new(ConnHandle(self.conn(), false), h));
+    }
+
+    /// Reads and/or writes, after the event loop found the watch's file descriptor ready.
+    ///
+    /// Returns false if the watch is unknown (e g it has been removed), or we're out of memory.
+    /// Incoming messages are put in the internal queue, see `pop_message`.
+    pub fn handle_watch(&self, id: WatchId, readable: bool, writable: bool) -> bool {
+        let known = match &self.eventloop { Some(d) => d.watches.lock().unwrap().contains(&id.0), None => false };
+        if !known { return false }
+        let mut flags = 0;
+        if readable { flags |= ffi::DBUS_WATCH_READABLE as c_uint }
+        if writable { flags |= ffi::DBUS_WATCH_WRITABLE as c_uint }
+        unsafe { ffi::dbus_watch_handle(id.0 as *mut ffi::DBusWatch, flags) != 0 }
+    }
+
+    /// Handles the timeout, after the event loop found it expired.
+    ///
+    /// Returns false if the timeout is unknown (e g it has been removed), or we're out of memory.
+    pub fn handle_timeout(&self, id: TimeoutId) -> bool {
+        let known = match &self.eventloop { Some(d) => d.timeouts.lock().unwrap().contains(&id.0), None => false };
+        if !known { return false }
+        unsafe { ffi::dbus_timeout_handle(id.0 as *mut ffi::DBusTimeout) != 0 }
+    }
+}
+
+#[test]
+fn test_event_loop() {
+    use super::BusType;
+    use crate::Message;
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct State {
+        watches: Vec<(WatchId, Watch, bool)>,
+        timeouts: Vec<TimeoutId>,
+        removed_timeouts: usize,
+    }
+    struct Hooks(Arc<Mutex<State>>);
+    impl EventLoop for Hooks {
+        fn add_watch(&mut self, id: WatchId, watch: Watch, enabled: bool) { self.0.lock().unwrap().watches.push((id, watch, enabled)) }
+        fn toggle_watch(&mut self, id: WatchId, _: Watch, enabled: bool) {
+            for w in self.0.lock().unwrap().watches.iter_mut() { if w.0 == id { w.2 = enabled } }
+        }
+        fn remove_watch(&mut self, id: WatchId) { self.0.lock().unwrap().watches.retain(|w| w.0 != id) }
+        fn add_timeout(&mut self, id: TimeoutId, _: Duration, _: bool) { self.0.lock().unwrap().timeouts.push(id) }
+        fn toggle_timeout(&mut self, _: TimeoutId, _: Duration, _: bool) {}
+        fn remove_timeout(&mut self, _: TimeoutId) { self.0.lock().unwrap().removed_timeouts += 1 }
+    }
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let mut c = Channel::get_private(BusType::Session).unwrap();
+    c.set_event_loop(Some(Box::new(Hooks(state.clone()))));
+    let fd = {
+        let s = state.lock().unwrap();
+        assert!(!s.watches.is_empty());
+        s.watches[0].1.fd
+    };
+    assert!(state.lock().unwrap().watches.iter().all(|w| w.1.fd == fd));
+
+    // Blocking method calls add a timeout while waiting for the reply.
+    let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "ListNames").unwrap();
+    c.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+    {
+        let s = state.lock().unwrap();
+        assert_eq!(s.timeouts.len(), 1);
+        assert_eq!(s.removed_timeouts, 1);
+        assert!(!c.handle_timeout(s.timeouts[0]));
+    }
+
+    // Let the event loop do the reading.
+    let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetId").unwrap();
+    let serial = c.send(m).unwrap();
+    c.flush();
+    let reply = 'outer: loop {
+        while let Some(m) = c.pop_message() { if m.get_reply_serial() == Some(serial) { break 'outer m } }
+        let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        assert_eq!(unsafe { libc::poll(&mut pfd, 1, 5000) }, 1);
+        let ids: Vec<_> = state.lock().unwrap().watches.iter().filter(|w| w.2 && w.1.read).map(|w| w.0).collect();
+        for id in ids { assert!(c.handle_watch(id, true, false)); }
+    };
+    assert_eq!(reply.get_reply_serial(), Some(serial));
+
+    c.set_event_loop(None);
+    assert!(state.lock().unwrap().watches.is_empty());
+    assert!(!c.handle_watch(WatchId(0), true, false));
+}
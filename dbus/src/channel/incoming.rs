@@ -0,0 +1,178 @@
+use crate::{Message, MessageType, Error};
+use crate::arg::{ArgType, Iter};
+use std::os::raw::c_long;
+
+/// Limits on incoming messages, to protect against peers sending huge or deeply nested messages.
+///
+/// This is mostly relevant for peer-to-peer connections, since a bus daemon already enforces its own limits.
+/// Set with `Channel::set_incoming_limits`. Fields that are `None` are not checked, apart from
+/// the limits of the D-Bus specification which libdbus always enforces (e g at most 32 levels
+/// of nested arrays, and at most 64 MiB of data in an array).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IncomingLimits {
+    /// The maximum size of a message, in bytes. Receiving a larger message disconnects the peer.
+    pub max_message_size: Option<usize>,
+    /// The maximum size, in bytes, of messages that have been read but not yet processed.
+    /// Once reached, no more messages are read until some have been processed.
+    pub max_received_size: Option<usize>,
+    /// The maximum number of elements in an array or dictionary.
+    pub max_array_len: Option<usize>,
+    /// The maximum nesting depth of arrays, structs, dictionary entries and variants.
+    pub max_depth: Option<usize>,
+}
+
+const CONTAINER_CHARS: &[u8] = b"a(v{";
+
+impl IncomingLimits {
+    /// Checks that the arguments of the message are within `max_array_len` and `max_depth`.
+    ///
+    /// Returns a LimitsExceeded error otherwise. This is called by the channel for every
+    /// incoming message, but can also be useful for messages made with `Message::demarshal`.
+    pub fn check(&self, msg: &Message) -> Result<(), Error> {
+        if self.max_array_len.is_none() && self.max_depth.is_none() { return Ok(()) }
+        self.walk(&mut msg.iter_init(), 0, false).map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.LimitsExceeded", &e))
+    }
+
+    fn walk(&self, i: &mut Iter, depth: usize, in_array: bool) -> Result<(), String> {
+        let mut count = 0;
+        loop {
+            let t = i.arg_type();
+            if t == ArgType::Invalid { break; }
+            count += 1;
+            if let Some(max) = self.max_array_len.filter(|&max| in_array && count > max) {
+                return Err(format!("Array has more than {} elements", max));
+            }
+            if [ArgType::Array, ArgType::Struct, ArgType::DictEntry, ArgType::Variant].contains(&t) {
+                if let Some(max) = self.max_depth.filter(|&max| depth >= max) {
+                    return Err(format!("Arguments are nested more than {} levels deep", max));
+                }
+                // Arrays of basic types do not need to be looked into, unless the elements need counting.
+                let flat = t == ArgType::Array && !i.signature().as_bytes()[1..].iter().any(|c| CONTAINER_CHARS.contains(c));
+                if !flat || self.max_array_len.is_some() {
+                    self.walk(&mut i.recurse(t).unwrap(), depth + 1, t == ArgType::Array)?;
+                }
+            }
+            i.next();
+        }
+        Ok(())
+    }
+}
+
+impl super::Channel {
+    /// Sets limits on incoming messages.
+    ///
+    /// See `IncomingLimits` for details. Messages that are too large for `max_array_len` or `max_depth` are
+    /// thrown away, and if they are method calls, the caller gets a LimitsExceeded error reply.
+    /// If they are replies, they are replaced by a LimitsExceeded error reply, so that the method call
+    /// made does not wait for a reply that never comes.
+    pub fn set_incoming_limits(&mut self, limits: IncomingLimits) {
+        unsafe {
+            if let Some(s) = limits.max_message_size { ffi::dbus_connection_set_max_message_size(self.conn(), s as c_long) }
+            if let Some(s) = limits.max_received_size { ffi::dbus_connection_set_max_received_size(self.conn(), s as c_long) }
+        }
+        self.incoming = limits;
+    }
+
+    /// Returns the limits on incoming messages.
+    ///
+    /// The size limits are read from libdbus, so they show its defaults unless set with `set_incoming_limits`.
+    pub fn incoming_limits(&self) -> IncomingLimits {
+        let (size, received) = unsafe {
+            (ffi::dbus_connection_get_max_message_size(self.conn()), ffi::dbus_connection_get_max_received_size(self.conn()))
+        };
+        IncomingLimits { max_message_size: Some(size as usize), max_received_size: Some(received as usize), ..self.incoming }
+    }
+
+    // Returns None if the message should be thrown away, or the message to use in its place.
+    pub (super) fn check_incoming(&self, msg: Message) -> Option<Message> {
+        let e = match self.incoming.check(&msg) { Ok(()) => return Some(msg), Err(e) => e };
+        trace_event!(serial = ?msg.get_serial(), sender = ?msg.sender(), error = ?e.message(), "Dropping incoming message");
+        match msg.msg_type() {
+            MessageType::MethodCall if !msg.get_no_reply() => {
+                let _ = self.send(msg.error(&e.name().unwrap().into(), &crate::to_c_str(e.message().unwrap_or(""))));
+                None
+            },
+            MessageType::MethodReturn | MessageType::Error => error_reply(&msg, &e),
+            _ => None,
+        }
+    }
+}
+
+// Makes an error reply that takes the place of a reply that was thrown away.
+fn error_reply(msg: &Message, e: &Error) -> Option<Message> {
+    let serial = msg.get_reply_serial()?;
+    // dbus_message_new_error needs the method call, so make a stand-in with the same serial.
+    let mut call = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "LimitsExceeded").ok()?;
+    crate::message::message_set_serial(&mut call, serial);
+    let mut r = call.error(&e.name().unwrap().into(), &crate::to_c_str(e.message().unwrap_or("")));
+    r.set_sender(msg.sender());
+    r.set_destination(msg.destination());
+    Some(r)
+}
+
+#[test]
+fn test_incoming_limits() {
+    use crate::arg::Variant;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    let l = IncomingLimits { max_array_len: Some(3), max_depth: Some(2), ..Default::default() };
+    let m = |args: &dyn Fn(Message) -> Message| args(Message::new_signal("/limits", "com.example.dbusrs.Limits", "Test").unwrap());
+    assert!(l.check(&m(&|m| m.append2(5u8, "Hello"))).is_ok());
+    assert!(l.check(&m(&|m| m.append1(vec![1u8, 2, 3]))).is_ok());
+    assert!(l.check(&m(&|m| m.append1(vec![1u8, 2, 3, 4]))).is_err());
+    assert!(l.check(&m(&|m| m.append1(vec![vec![1u8], vec![2]]))).is_ok());
+    assert!(l.check(&m(&|m| m.append1(vec![vec![1u8, 2, 3, 4]]))).is_err());
+    assert!(l.check(&m(&|m| m.append1(Variant((5u8, "Hello"))))).is_ok());
+    let e = l.check(&m(&|m| m.append1(Variant((5u8, Variant("Hello")))))).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.LimitsExceeded"));
+    let mut d = HashMap::new();
+    d.insert("a", 1u8);
+    assert!(l.check(&m(&|m| m.append1(&d))).is_ok());
+    let depth_only = IncomingLimits { max_depth: Some(1), ..Default::default() };
+    assert!(depth_only.check(&m(&|m| m.append1(vec![1u8; 100]))).is_ok());
+    assert!(depth_only.check(&m(&|m| m.append1(&d))).is_err());
+    assert!(IncomingLimits::default().check(&m(&|m| m.append1(vec![1u8; 100]))).is_ok());
+
+    // Through a real connection
+    let mut server = super::Channel::get_private(super::BusType::Session).unwrap();
+    server.set_incoming_limits(IncomingLimits { max_message_size: Some(1 << 20), ..l });
+    assert_eq!(server.incoming_limits().max_message_size, Some(1 << 20));
+    assert_eq!(server.incoming_limits().max_array_len, Some(3));
+    let server_name = server.unique_name().unwrap().to_string();
+    let client = super::Channel::get_private(super::BusType::Session).unwrap();
+    let call = |v: Vec<u8>| Message::new_method_call(&*server_name, "/limits", "com.example.dbusrs.Limits", "Test").unwrap().append1(v);
+    let reply = client.send_with_reply(call(vec![1, 2, 3, 4])).unwrap();
+    let ok = client.send_with_reply(call(vec![1, 2, 3])).unwrap();
+    let mut received = vec!();
+    for _ in 0..30 {
+        if let Some(m) = server.blocking_pop_message(Duration::from_millis(100)).unwrap() {
+            if m.msg_type() == MessageType::MethodCall { received.push(m.get1::<Vec<u8>>().unwrap()); }
+        }
+        if !received.is_empty() { break; }
+    }
+    assert_eq!(received, vec!(vec!(1, 2, 3)));
+    let e = reply.wait(Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.LimitsExceeded"));
+    drop(ok);
+
+    // Replies over the limit become LimitsExceeded errors, both for pending and blocking calls
+    let mut client = client;
+    client.set_incoming_limits(l);
+    let client_name = client.unique_name().unwrap().to_string();
+    let t = std::thread::spawn(move || {
+        let mut replied = 0;
+        while replied < 2 {
+            if let Some(m) = server.blocking_pop_message(Duration::from_millis(100)).unwrap() {
+                if m.msg_type() != MessageType::MethodCall || m.sender().as_deref() != Some(&*client_name) { continue }
+                server.send(m.method_return().append1(vec![1u8, 2, 3, 4])).unwrap();
+                replied += 1;
+            }
+        }
+    });
+    let e = client.send_with_reply(call(vec!())).unwrap().wait(Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.LimitsExceeded"));
+    let e = client.send_with_reply_and_block(call(vec!()), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.LimitsExceeded"));
+    t.join().unwrap();
+}
@@ -0,0 +1,229 @@
+use crate::{Message, MessageType};
+use crate::strings::BusName;
+use std::time::{Duration, Instant};
+use std::fmt;
+
+/// The health of a connection, as seen by its keepalive. See `Keepalive::on_health`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Health {
+    /// This many pings in a row have not been answered (yet).
+    Missed(u32),
+    /// A ping was answered again, after one or more were missed.
+    Recovered,
+    /// Too many pings were missed. The connection has been closed.
+    Dead,
+}
+
+type HealthFn = Box<dyn FnMut(Health) + Send + 'static>;
+
+/// Periodically pings the other end of a connection, to find out whether it is still there.
+///
+/// A peer-to-peer connection over TCP can stay silent for a long time after the peer has gone away,
+/// e g because the network went down, so method calls just time out one by one. With a keepalive,
+/// the connection is closed after a number of missed pings instead, so that proxies and reconnection
+/// logic can find out quickly.
+///
+/// Set with `Channel::set_keepalive`. The pings are sent from `Channel::read_write`, which therefore
+/// returns early when the next ping is due. Anything that does not call `read_write` regularly,
+/// e g a custom event loop, should call `Channel::check_keepalive` instead. The replies to the pings
+/// are handled by the channel and not returned by `pop_message`.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::channel::{Channel, Keepalive, Health};
+/// use std::time::Duration;
+/// let mut c = Channel::open_private("tcp:host=192.168.0.2,port=12345").unwrap();
+/// c.set_keepalive(Some(Keepalive::new(Duration::from_secs(10), 3).on_health(|h| {
+///     if h == Health::Dead { eprintln!("Peer went away") }
+/// })));
+/// ```
+pub struct Keepalive {
+    interval: Duration,
+    max_missed: u32,
+    destination: Option<BusName<'static>>,
+    on_health: Option<HealthFn>,
+}
+
+impl fmt::Debug for Keepalive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keepalive").field("interval", &self.interval).field("max_missed", &self.max_missed)
+            .field("destination", &self.destination).finish()
+    }
+}
+
+impl Keepalive {
+    /// Creates a keepalive that pings every "interval", and gives up after "max_missed" unanswered pings.
+    ///
+    /// By default, the bus daemon is pinged on bus connections, and the peer on peer-to-peer connections.
+    pub fn new(interval: Duration, max_missed: u32) -> Self {
+        Keepalive { interval, max_missed: max_missed.max(1), destination: None, on_health: None }
+    }
+
+    /// Builder function that sets the bus name to ping, instead of the bus daemon.
+    pub fn destination<N: Into<BusName<'static>>>(self, name: N) -> Self {
+        Keepalive { destination: Some(name.into()), ..self }
+    }
+
+    /// Builder function that sets a callback to call when the health of the connection changes.
+    ///
+    /// The callback is called from `read_write` or `check_keepalive`, so it should not block.
+    pub fn on_health<F: FnMut(Health) + Send + 'static>(self, f: F) -> Self {
+        Keepalive { on_health: Some(Box::new(f)), ..self }
+    }
+}
+
+#[derive(Debug)]
+pub (super) struct KeepaliveState {
+    config: Keepalive,
+    next: Instant,
+    // Serials of unanswered pings; zero if sending failed.
+    outstanding: Vec<u32>,
+    missed: u32,
+    dead: bool,
+}
+
+impl super::Channel {
+    /// Starts pinging the other end of the connection regularly, or stops if `None` is given.
+    ///
+    /// See `Keepalive` for details. The first ping is sent after one interval.
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        *self.keepalive.lock().unwrap() = keepalive.map(|config| KeepaliveState {
+            next: Instant::now() + config.interval, config, outstanding: vec!(), missed: 0, dead: false,
+        });
+    }
+
+    /// Returns the number of pings in a row that have not been answered, or None if there is no keepalive.
+    pub fn keepalive_missed(&self) -> Option<u32> { self.keepalive.lock().unwrap().as_ref().map(|k| k.missed) }
+
+    /// Sends a ping if one is due, and closes the connection if too many have been missed.
+    ///
+    /// Returns the time until this needs to be called again, or None if there is no (living) keepalive.
+    /// This is called by `read_write`, so there is usually no need to call it directly.
+    pub fn check_keepalive(&self) -> Option<Duration> {
+        let mut guard = self.keepalive.lock().unwrap();
+        let k = guard.as_mut().filter(|k| !k.dead)?;
+        let now = Instant::now();
+        if now < k.next { return Some(k.next - now) }
+        let mut events = vec!();
+        if !k.outstanding.is_empty() {
+            k.missed += 1;
+            events.push(Health::Missed(k.missed));
+            if k.missed >= k.config.max_missed {
+                k.dead = true;
+                events.push(Health::Dead);
+            }
+        }
+        let ping = if k.dead { None } else {
+            let mut m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus.Peer", "Ping").unwrap();
+            // On a peer-to-peer connection, there is nobody else to send it to.
+            if let Some(d) = &k.config.destination { m.set_destination(Some(d.clone())) }
+            else if self.unique_name().is_none() { m.set_destination(None) }
+            k.next = now + k.config.interval;
+            Some(m)
+        };
+        let (dead, next) = (k.dead, k.next);
+        let f = k.config.on_health.take();
+        drop(guard);
+        // Sending might block on the outgoing limits, so it is done without holding the lock.
+        if let Some(m) = ping {
+            let serial = self.send(m).unwrap_or(0);
+            if let Some(k) = self.keepalive.lock().unwrap().as_mut() { k.outstanding.push(serial) }
+        }
+        trace_event!(events = ?events, "Keepalive");
+        self.report_health(f, events);
+        if dead {
+            unsafe { ffi::dbus_connection_close(self.conn()) };
+            return None
+        }
+        Some(next.saturating_duration_since(Instant::now()))
+    }
+
+    fn report_health(&self, f: Option<HealthFn>, events: Vec<Health>) {
+        let mut f = match f { Some(f) => f, None => return };
+        for e in events { f(e) }
+        if let Some(k) = self.keepalive.lock().unwrap().as_mut() {
+            if k.config.on_health.is_none() { k.config.on_health = Some(f) }
+        }
+    }
+
+    // Returns true if the message was the reply to a ping, and should be thrown away.
+    pub (super) fn keepalive_reply(&self, msg: &Message) -> bool {
+        let serial = match (msg.msg_type(), msg.get_reply_serial()) {
+            (MessageType::MethodReturn, Some(s)) | (MessageType::Error, Some(s)) => s,
+            _ => return false,
+        };
+        let mut guard = self.keepalive.lock().unwrap();
+        let k = match guard.as_mut() { Some(k) if k.outstanding.contains(&serial) => k, _ => return false };
+        k.outstanding.clear();
+        let recovered = k.missed > 0 && !k.dead;
+        if recovered { k.missed = 0; }
+        let f = if recovered { k.config.on_health.take() } else { None };
+        drop(guard);
+        self.report_health(f, vec!(Health::Recovered));
+        true
+    }
+}
+
+#[test]
+fn test_keepalive() {
+    use super::{Channel, BusType};
+    use std::sync::{Arc, Mutex};
+
+    // The bus daemon answers
+    let mut c = Channel::get_private(BusType::Session).unwrap();
+    let events = Arc::new(Mutex::new(vec!()));
+    let events2 = events.clone();
+    c.set_keepalive(Some(Keepalive::new(Duration::from_millis(50), 2).on_health(move |h| events2.lock().unwrap().push(h))));
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(300) {
+        c.read_write(Some(Duration::from_millis(500))).unwrap();
+        while let Some(m) = c.pop_message() { assert_ne!(m.msg_type(), MessageType::MethodReturn); }
+    }
+    assert_eq!(c.keepalive_missed(), Some(0));
+    assert!(c.is_connected());
+    assert!(events.lock().unwrap().is_empty());
+    c.set_keepalive(None);
+    assert_eq!(c.check_keepalive(), None);
+
+    // Someone who never answers
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("silent");
+    let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let mut c = Channel::open_private(&format!("unix:path={}", path.display())).unwrap();
+    let events2 = events.clone();
+    c.set_keepalive(Some(Keepalive::new(Duration::from_millis(50), 2).on_health(move |h| events2.lock().unwrap().push(h))));
+    let start = Instant::now();
+    while c.is_connected() && start.elapsed() < Duration::from_secs(5) {
+        // The socket is writable all the time, so this might not wait at all.
+        if c.read_write(Some(Duration::from_millis(100))).is_err() { break; }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!c.is_connected());
+    assert_eq!(*events.lock().unwrap(), vec!(Health::Missed(1), Health::Missed(2), Health::Dead));
+    assert_eq!(c.check_keepalive(), None);
+}
+
+#[test]
+fn test_keepalive_blocked() {
+    use super::{Channel, OutgoingLimit, Overflow};
+
+    // Someone who never reads what we send, so the outgoing queue stays full.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stuck");
+    let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let mut c = Channel::open_private(&format!("unix:path={}", path.display())).unwrap();
+    c.set_outgoing_limit(Some(OutgoingLimit { max_bytes: None, max_messages: Some(1), overflow: Overflow::Block(Duration::from_millis(500)) }));
+    c.send(Message::new_signal("/stuck", "com.example.dbusrs.Stuck", "Data").unwrap()).unwrap();
+    c.set_keepalive(Some(Keepalive::new(Duration::from_millis(10), 3)));
+    std::thread::sleep(Duration::from_millis(20));
+
+    // While the ping waits for room in the queue, the keepalive can still be accessed.
+    std::thread::scope(|s| {
+        let t = s.spawn(|| c.check_keepalive());
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        assert_eq!(c.keepalive_missed(), Some(0));
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert!(t.join().unwrap().is_some());
+    });
+}
@@ -0,0 +1,99 @@
+use super::{Sender, send_on, outgoing::Outgoing};
+use crate::Message;
+use crate::message::{MessageLog, LatencyTracker};
+use crate::metrics::SharedMetrics;
+use std::sync::Arc;
+use std::fmt;
+
+#[derive(Debug)]
+struct SenderConn(*mut ffi::DBusConnection);
+
+unsafe impl Send for SenderConn {}
+unsafe impl Sync for SenderConn {}
+
+impl Drop for SenderConn {
+    fn drop(&mut self) { unsafe { ffi::dbus_connection_unref(self.0) } }
+}
+
+/// A handle for sending messages on a connection, from any thread.
+///
+/// Get one from `Channel::msg_sender`, or the `msg_sender` method of a connection.
+/// It is cheap to clone, and it is `Send` and `Sync` even if the connection is not, so worker threads
+/// can emit signals and send replies while another thread receives and dispatches incoming messages.
+///
+/// Sending puts the message in the outgoing queue of the connection, and tries to write it right away.
+/// In case the receiving thread is in the middle of reading from the connection at the time,
+/// the message is written once it is done.
+///
+/// The handle keeps the underlying connection alive, but not open: once the connection itself
+/// has been dropped, messages are silently thrown away.
+#[derive(Clone)]
+pub struct MsgSender {
+    conn: Arc<SenderConn>,
+    log: Option<Arc<MessageLog>>,
+    metrics: Option<SharedMetrics>,
+    latency: Option<Arc<LatencyTracker>>,
+    outgoing: Outgoing,
+}
+
+impl fmt::Debug for MsgSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "MsgSender({:?})", self.conn.0) }
+}
+
+pub (super) fn new(conn: *mut ffi::DBusConnection, log: Option<Arc<MessageLog>>, metrics: Option<SharedMetrics>,
+    latency: Option<Arc<LatencyTracker>>, outgoing: Outgoing) -> MsgSender {
+    MsgSender { conn: Arc::new(SenderConn(unsafe { ffi::dbus_connection_ref(conn) })), log, metrics, latency, outgoing }
+}
+
+impl MsgSender {
+    /// Gets whether the connection is still open.
+    pub fn is_connected(&self) -> bool {
+        unsafe { ffi::dbus_connection_get_is_connected(self.conn.0) != 0 }
+    }
+}
+
+impl Sender for MsgSender {
+    fn send(&self, msg: Message) -> Result<u32, ()> { send_on(self.conn.0, self.log.as_deref(), self.metrics.as_ref(), self.latency.as_deref(), &self.outgoing, msg) }
+}
+
+#[test]
+fn test_msg_sender() {
+    use crate::blocking::LocalConnection;
+    use crate::message::SignalArgs;
+    use crate::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as Ppc;
+    use std::time::Duration;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn is_send_sync<T: Send + Sync + Clone>(_: &T) {}
+
+    let mut receiver = LocalConnection::new_session().unwrap();
+    let mut mr = Ppc::match_rule(None, None);
+    let c = LocalConnection::new_session().unwrap();
+    mr.sender = Some(c.unique_name().into_static());
+    let got = Rc::new(Cell::new(0));
+    let got2 = got.clone();
+    receiver.add_match(mr, move |p: Ppc, _, _| { got2.set(got2.get() + p.changed_properties.len()); true }).unwrap();
+
+    let s = c.msg_sender();
+    is_send_sync(&s);
+    let threads: Vec<_> = (0..3).map(|_| {
+        let s = s.clone();
+        std::thread::spawn(move || {
+            let mut p = Ppc { interface_name: "com.example.dbusrs".into(), changed_properties: Default::default(), invalidated_properties: vec!() };
+            p.changed_properties.insert("Count".into(), crate::arg::Variant(Box::new(5u32)));
+            s.send(p.to_emit_message(&"/sender".into())).unwrap();
+        })
+    }).collect();
+    for t in threads { t.join().unwrap(); }
+    assert!(s.is_connected());
+
+    for _ in 0..30 {
+        receiver.process(Duration::from_millis(100)).unwrap();
+        if got.get() == 3 { break; }
+    }
+    assert_eq!(got.get(), 3);
+
+    drop(c);
+    assert!(!s.is_connected());
+}
@@ -0,0 +1,168 @@
+use crate::{Message, MessageType};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::os::raw::c_int;
+
+/// What to do when a message is sent while the outgoing queue is full, see `OutgoingLimit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Write to the connection until there is room, for up to the given time. Then fail.
+    Block(Duration),
+    /// Fail right away.
+    Error,
+    /// Throw away new signals, but still queue method calls, replies and errors.
+    ///
+    /// Messages already in the queue cannot be removed from it, so it is the new signal that is thrown away,
+    /// not the oldest one.
+    DropNewSignals,
+}
+
+/// Limits on the outgoing message queue of a channel.
+///
+/// Messages are normally written to the connection as they are sent, but if the other side does not keep up
+/// with reading them, they pile up in memory. Set a limit with `Channel::set_outgoing_limit` to decide what
+/// happens instead. The limits apply to `Channel::send` and everything built on top of it, such as the `send`
+/// method of connections and `MsgSender`, and to method calls. A method call that does not fit fails with
+/// an error of the LimitsExceeded kind.
+///
+/// The size in bytes is exact. The number of messages is counted from the last time the queue was empty,
+/// so it might include some messages that have already been written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutgoingLimit {
+    /// The queue is full when it contains this many bytes.
+    pub max_bytes: Option<usize>,
+    /// The queue is full when it contains this many messages.
+    pub max_messages: Option<usize>,
+    /// What to do when the queue is full.
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    limit: Option<OutgoingLimit>,
+    count: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub (super) struct Outgoing(Arc<Mutex<State>>);
+
+fn queued_bytes(conn: *mut ffi::DBusConnection) -> usize { unsafe { ffi::dbus_connection_get_outgoing_size(conn) as usize } }
+
+impl Outgoing {
+    pub (super) fn set_limit(&self, limit: Option<OutgoingLimit>) { self.0.lock().unwrap().limit = limit; }
+
+    pub (super) fn limit(&self) -> Option<OutgoingLimit> { self.0.lock().unwrap().limit }
+
+    /// Returns Ok(true) if msg can be sent, and Ok(false) if it should be thrown away.
+    ///
+    /// The lock is not held while blocking, so that other senders, and changes to the limit, don't have to wait.
+    pub (super) fn reserve(&self, conn: *mut ffi::DBusConnection, msg: &Message) -> Result<bool, ()> {
+        let start = Instant::now();
+        loop {
+            let mut s = self.0.lock().unwrap();
+            let limit = match s.limit { Some(l) => l, None => return Ok(true) };
+            if unsafe { ffi::dbus_connection_has_messages_to_send(conn) } == 0 { s.count = 0; }
+            let over_bytes = limit.max_bytes.filter(|&b| queued_bytes(conn) >= b).is_some();
+            let over_count = limit.max_messages.filter(|&m| s.count >= m).is_some();
+            match limit.overflow {
+                _ if !over_bytes && !over_count => {},
+                Overflow::Error => {
+                    trace_event!(bytes = queued_bytes(conn), count = s.count, "Outgoing queue full");
+                    return Err(())
+                },
+                Overflow::DropNewSignals if msg.msg_type() == MessageType::Signal => {
+                    trace_event!(path = ?msg.path(), member = ?msg.member(), "Outgoing queue full, dropping signal");
+                    return Ok(false)
+                },
+                Overflow::DropNewSignals => {},
+                Overflow::Block(t) => {
+                    drop(s);
+                    let left = t.checked_sub(start.elapsed()).ok_or(())?;
+                    let ms = left.as_millis().clamp(1, 100) as c_int;
+                    if unsafe { ffi::dbus_connection_read_write(conn, ms) } == 0 { return Err(()) }
+                    continue
+                },
+            }
+            s.count += 1;
+            return Ok(true)
+        }
+    }
+}
+
+impl super::Channel {
+    /// Sets limits on the outgoing message queue, or removes them if `None` is given.
+    ///
+    /// See `OutgoingLimit` for details. The limits are shared with all `MsgSender`s of this channel.
+    pub fn set_outgoing_limit(&mut self, limit: Option<OutgoingLimit>) { self.outgoing.set_limit(limit) }
+
+    /// Returns the limits set by `set_outgoing_limit`, if any.
+    pub fn outgoing_limit(&self) -> Option<OutgoingLimit> { self.outgoing.limit() }
+
+    /// Returns the size, in bytes, of the messages waiting to be written to the connection.
+    pub fn outgoing_size(&self) -> usize { queued_bytes(self.conn()) }
+
+    /// Writes to the connection until at most "watermark" bytes are waiting to be written.
+    ///
+    /// Returns false if that did not happen within the timeout. Unlike `flush`, this makes it
+    /// possible to wait for a slow reader without waiting forever.
+    ///
+    /// Blocking: until the watermark is reached, or the timeout expires.
+    pub fn flush_until(&self, watermark: usize, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.outgoing_size() > watermark {
+            let left = match timeout.checked_sub(start.elapsed()) { Some(l) => l, None => return false };
+            if self.read_write(Some(left.min(Duration::from_millis(100)))).is_err() { return false }
+        }
+        true
+    }
+}
+
+#[test]
+fn test_outgoing_limit() {
+    use super::Channel;
+
+    // Someone who never reads what we send, not even the authentication handshake.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stuck");
+    let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let mut c = Channel::open_private(&format!("unix:path={}", path.display())).unwrap();
+
+    let signal = || Message::new_signal("/stuck", "com.example.dbusrs.Stuck", "Data").unwrap().append1(vec![0u8; 100_000]);
+    let call = || Message::new_method_call("com.example.dbusrs", "/stuck", "com.example.dbusrs.Stuck", "Call").unwrap();
+
+    c.set_outgoing_limit(Some(OutgoingLimit { max_bytes: None, max_messages: Some(2), overflow: Overflow::Error }));
+    assert!(c.send(signal()).is_ok());
+    assert!(c.send(signal()).is_ok());
+    assert!(c.send(signal()).is_err());
+    assert!(c.outgoing_size() >= 100_000);
+    let e = c.send_with_reply_and_block(call(), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.kind(), crate::ErrorKind::LimitsExceeded);
+
+    let limit = OutgoingLimit { max_bytes: Some(100_000), max_messages: None, overflow: Overflow::DropNewSignals };
+    c.set_outgoing_limit(Some(limit));
+    assert_eq!(c.outgoing_limit(), Some(limit));
+    let before = c.outgoing_size();
+    assert_eq!(c.send(signal()), Ok(0));
+    assert_eq!(c.outgoing_size(), before);
+    assert!(c.send(call()).unwrap() > 0);
+    assert!(c.outgoing_size() > before);
+
+    c.set_outgoing_limit(Some(OutgoingLimit { overflow: Overflow::Block(Duration::from_millis(100)), ..limit }));
+    let start = Instant::now();
+    assert!(c.send(call()).is_err());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    // Waiting for room does not keep others from using the limits.
+    c.set_outgoing_limit(Some(OutgoingLimit { overflow: Overflow::Block(Duration::from_millis(500)), ..limit }));
+    std::thread::scope(|s| {
+        let t = s.spawn(|| c.send(call()));
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(c.outgoing_limit().is_some());
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert!(t.join().unwrap().is_err());
+    });
+
+    assert!(!c.flush_until(0, Duration::from_millis(50)));
+    c.set_outgoing_limit(None);
+    assert!(c.send(signal()).is_ok());
+}
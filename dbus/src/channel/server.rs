@@ -0,0 +1,176 @@
+// Listening for incoming connections, for peer-to-peer connections and message buses.
+
+use super::{Channel, Watch, WatchHandle};
+use crate::{Error, to_c_str, c_str_to_slice};
+use std::collections::VecDeque;
+use std::os::raw::{c_void, c_int, c_uint};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct ServerData {
+    watches: Mutex<Vec<WatchHandle>>,
+    accepted: Mutex<VecDeque<Channel>>,
+}
+
+/// Listens on an address, and accepts incoming connections as Channels.
+///
+/// The accepted channels are peer-to-peer connections: they are not registered with any bus, so
+/// messages on them have no sender or destination unless the other side sets one.
+/// The other side connects with `Channel::open_private`, which blocks until this side has
+/// authenticated it, i e until `accept` returns its Channel, or the Channel's `read_write` is called.
+///
+/// Only clients running as the same user as this process are accepted.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::channel::Server;
+/// use std::time::Duration;
+/// let server = Server::listen("unix:tmpdir=/tmp").unwrap();
+/// println!("Listening on {}", server.address());
+/// loop {
+///     if let Some(c) = server.accept(Duration::from_millis(1000)).unwrap() {
+///         // Serve "c", e g by converting it into a blocking::Connection
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    ptr: *mut ffi::DBusServer,
+    data: Box<ServerData>,
+}
+
+// libdbus servers are thread safe, since init_dbus is always called before creating one.
+unsafe impl Send for Server {}
+
+fn take_c_string(p: *mut std::os::raw::c_char) -> String {
+    let s = c_str_to_slice(&(p as *const _)).unwrap_or("").to_string();
+    unsafe { ffi::dbus_free(p as *mut c_void) };
+    s
+}
+
+impl Server {
+    /// Starts listening on "address", e g "unix:path=/run/example/socket" or "tcp:host=localhost,port=0".
+    ///
+    /// Addresses can be given that let libdbus pick, e g "unix:tmpdir=/tmp"; `address` returns the actual one.
+    pub fn listen(address: &str) -> Result<Server, Error> {
+        extern "C" fn new_connection_cb(_: *mut ffi::DBusServer, conn: *mut ffi::DBusConnection, data: *mut c_void) {
+            let sd: &ServerData = unsafe { &*(data as *const ServerData) };
+            let conn = unsafe { ffi::dbus_connection_ref(conn) };
+            if let Ok(c) = Channel::conn_from_ptr(conn) { sd.accepted.lock().unwrap().push_back(c) }
+        }
+        extern "C" fn add_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) -> u32 {
+            let sd: &ServerData = unsafe { &*(data as *const ServerData) };
+            sd.watches.lock().unwrap().push(WatchHandle(watch));
+            1
+        }
+        extern "C" fn remove_watch_cb(watch: *mut ffi::DBusWatch, data: *mut c_void) {
+            let sd: &ServerData = unsafe { &*(data as *const ServerData) };
+            sd.watches.lock().unwrap().retain(|w| w.0 != watch);
+        }
+        extern "C" fn toggled_watch_cb(_: *mut ffi::DBusWatch, _: *mut c_void) {}
+
+        crate::init_dbus();
+        let mut e = Error::empty();
+        let ptr = unsafe { ffi::dbus_server_listen(to_c_str(address).as_ptr(), e.get_mut()) };
+        if ptr.is_null() { return Err(e) }
+        let data: Box<ServerData> = Default::default();
+        let dptr = &*data as *const ServerData as *mut c_void;
+        let s = Server { ptr, data };
+        unsafe { ffi::dbus_server_set_new_connection_function(s.ptr, Some(new_connection_cb), dptr, None) };
+        if unsafe { ffi::dbus_server_set_watch_functions(s.ptr, Some(add_watch_cb), Some(remove_watch_cb),
+            Some(toggled_watch_cb), dptr, None) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Cannot enable watch tracking"))
+        }
+        Ok(s)
+    }
+
+    /// The address clients can connect to, including the server's id.
+    pub fn address(&self) -> String { take_c_string(unsafe { ffi::dbus_server_get_address(self.ptr) }) }
+
+    /// The id of the server, a hex string unique to each Server.
+    pub fn id(&self) -> String { take_c_string(unsafe { ffi::dbus_server_get_id(self.ptr) }) }
+
+    /// The file descriptors to wait for, in case the server is driven by an event loop
+    /// (or a loop that also waits for other file descriptors). When any of them is ready,
+    /// call `accept` with a zero timeout.
+    pub fn watch_fds(&self) -> Vec<Watch> {
+        self.data.watches.lock().unwrap().iter().filter_map(|w| {
+            let (w, enabled) = unsafe { Watch::from_raw_enabled(w.0) };
+            if enabled { Some(w) } else { None }
+        }).collect()
+    }
+
+    /// Accepts an incoming connection, waiting up to "timeout" for one to arrive.
+    ///
+    /// Returns None if the timeout expired.
+    pub fn accept(&self, timeout: Duration) -> Result<Option<Channel>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(c) = self.data.accepted.lock().unwrap().pop_front() { return Ok(Some(c)) }
+            let watches: Vec<_> = self.data.watches.lock().unwrap().iter().map(|w| w.0).collect();
+            let mut fds: Vec<_> = watches.iter().map(|&w| {
+                let (w, enabled) = unsafe { Watch::from_raw_enabled(w) };
+                let events = if !enabled { 0 } else { (if w.read { libc::POLLIN } else { 0 }) | (if w.write { libc::POLLOUT } else { 0 }) };
+                libc::pollfd { fd: w.fd, events, revents: 0 }
+            }).collect();
+            let left = deadline.saturating_duration_since(Instant::now());
+            let ms = (left + Duration::from_micros(999)).as_millis().min(c_int::MAX as u128) as c_int;
+            let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) };
+            if r < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted { return Err(Error::new_failed(&e.to_string())) }
+            }
+            for (w, p) in watches.into_iter().zip(&fds) {
+                let mut flags = 0;
+                if p.revents & libc::POLLIN != 0 { flags |= ffi::DBUS_WATCH_READABLE }
+                if p.revents & libc::POLLOUT != 0 { flags |= ffi::DBUS_WATCH_WRITABLE }
+                if p.revents & libc::POLLERR != 0 { flags |= ffi::DBUS_WATCH_ERROR }
+                if p.revents & libc::POLLHUP != 0 { flags |= ffi::DBUS_WATCH_HANGUP }
+                // The watch might have been removed by handling an earlier one.
+                if flags != 0 && self.data.watches.lock().unwrap().contains(&WatchHandle(w)) {
+                    unsafe { ffi::dbus_watch_handle(w, flags as c_uint) };
+                }
+            }
+            if left == Duration::from_millis(0) {
+                return Ok(self.data.accepted.lock().unwrap().pop_front())
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::dbus_server_disconnect(self.ptr);
+            ffi::dbus_server_unref(self.ptr);
+        }
+    }
+}
+
+#[test]
+fn test_server() {
+    use crate::Message;
+    let server = Server::listen("unix:tmpdir=/tmp").unwrap();
+    let address = server.address();
+    assert!(address.starts_with("unix:"));
+    assert!(address.contains(&server.id()));
+    assert_eq!(server.watch_fds().len(), 1);
+    assert!(server.accept(Duration::from_millis(0)).unwrap().is_none());
+
+    let t = std::thread::spawn(move || {
+        let c = Channel::open_private(&address).unwrap();
+        let m = Message::new_method_call("com.example.dbusrs", "/", "com.example.dbusrs.Server", "Hello").unwrap();
+        let r = c.send_with_reply_and_block(m, Duration::from_secs(5)).unwrap();
+        r.read1::<String>().unwrap()
+    });
+    let c = server.accept(Duration::from_secs(5)).unwrap().unwrap();
+    let m = loop {
+        if let Some(m) = c.blocking_pop_message(Duration::from_secs(5)).unwrap() { break m }
+    };
+    assert_eq!(&*m.member().unwrap(), "Hello");
+    assert!(m.sender().is_none());
+    c.send(m.method_return().append1("Hi there")).unwrap();
+    c.flush();
+    assert_eq!(t.join().unwrap(), "Hi there");
+}
@@ -0,0 +1,87 @@
+// Checking that outgoing messages with Unix fds can be sent on the connection.
+
+use crate::{Message, Error};
+use crate::arg::{ArgType, Iter};
+use std::os::raw::{c_int, c_long};
+
+fn count(i: &mut Iter) -> usize {
+    let mut n = 0;
+    loop {
+        match i.arg_type() {
+            ArgType::Invalid => return n,
+            ArgType::UnixFd => n += 1,
+            t @ ArgType::Array | t @ ArgType::Struct | t @ ArgType::DictEntry | t @ ArgType::Variant => {
+                if let Some(mut s) = i.recurse(t) { n += count(&mut s) }
+            }
+            _ => {},
+        }
+        i.next();
+    }
+}
+
+pub (super) fn check(conn: *mut ffi::DBusConnection, msg: &Message) -> Result<(), Error> {
+    if unsafe { ffi::dbus_message_contains_unix_fds(msg.ptr()) } == 0 { return Ok(()) }
+    if unsafe { ffi::dbus_connection_can_send_type(conn, ffi::DBUS_TYPE_UNIX_FD as c_int) } == 0 {
+        return Err(Error::new_unix_fds_not_supported())
+    }
+    let (n, max) = (count(&mut msg.iter_init()), unsafe { ffi::dbus_connection_get_max_message_unix_fds(conn) } as usize);
+    if n > max { return Err(Error::new_too_many_unix_fds(n, max)) }
+    Ok(())
+}
+
+impl super::Channel {
+    /// Returns true if Unix fds (arguments of type `h`) can be sent and received on this connection.
+    ///
+    /// This is negotiated with the other side when connecting, and requires a Unix socket,
+    /// so e g TCP connections cannot pass Unix fds.
+    pub fn can_pass_unix_fds(&self) -> bool {
+        unsafe { ffi::dbus_connection_can_send_type(self.conn(), ffi::DBUS_TYPE_UNIX_FD as c_int) != 0 }
+    }
+
+    /// The maximum number of Unix fds in a single message.
+    ///
+    /// Incoming messages with more fds disconnect the peer, and outgoing ones are refused with
+    /// a LimitsExceeded error before they are sent. The bus daemon has limits of its own, typically lower
+    /// on the system bus; errors from the daemon about these are of the LimitsExceeded kind too.
+    pub fn max_message_unix_fds(&self) -> usize {
+        unsafe { ffi::dbus_connection_get_max_message_unix_fds(self.conn()) as usize }
+    }
+
+    /// Sets the maximum number of Unix fds in a single message, see `max_message_unix_fds`.
+    pub fn set_max_message_unix_fds(&mut self, n: usize) {
+        unsafe { ffi::dbus_connection_set_max_message_unix_fds(self.conn(), n as c_long) }
+    }
+
+    /// Checks that "msg" can be sent on this connection as far as Unix fds are concerned.
+    ///
+    /// Fails with an error of the UnixFdsNotSupported kind if the message contains Unix fds
+    /// and the connection cannot pass them, or of the LimitsExceeded kind if it contains more than
+    /// `max_message_unix_fds`. This is checked for all messages sent, but `send` cannot say why it failed.
+    pub fn check_unix_fds(&self, msg: &Message) -> Result<(), Error> { check(self.conn(), msg) }
+}
+
+#[test]
+fn test_unix_fds() {
+    use crate::arg::OwnedFd;
+    use crate::ErrorKind;
+    use std::time::Duration;
+    use std::os::unix::io::IntoRawFd;
+
+    let mut c = super::Channel::get_private(super::BusType::Session).unwrap();
+    assert!(c.can_pass_unix_fds());
+    let fd = || unsafe { OwnedFd::new(std::fs::File::open("/dev/null").unwrap().into_raw_fd()) };
+    let name = c.unique_name().unwrap().to_string();
+    let m = |fds: Vec<OwnedFd>| Message::new_method_call(&*name, "/", "com.example.test", "Fds").unwrap()
+        .append2(fds, crate::arg::Variant(fd()));
+    c.check_unix_fds(&m(vec!())).unwrap();
+    c.check_unix_fds(&m(vec!(fd(), fd()))).unwrap();
+
+    c.set_max_message_unix_fds(2);
+    assert_eq!(c.max_message_unix_fds(), 2);
+    assert!(c.check_unix_fds(&m(vec!(fd()))).is_ok());
+    assert_eq!(c.check_unix_fds(&m(vec!(fd(), fd()))).unwrap_err().kind(), ErrorKind::LimitsExceeded);
+    assert!(c.send(m(vec!(fd(), fd()))).is_err());
+    let e = c.send_with_reply_and_block(m(vec!(fd(), fd())), Duration::from_secs(5)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::LimitsExceeded);
+    assert!(c.check_unix_fds(&Message::new_signal("/", "com.example.test", "NoFds").unwrap().append1(5u32)).is_ok());
+}
@@ -1,10 +1,12 @@
 use std::ptr;
 use crate::{tree, arg, to_c_str, c_str_to_slice, init_dbus};
 use crate::strings::ErrorName;
+use crate::arg::messageitem::MessageItem;
 
 /// D-Bus Error wrapper.
 pub struct Error {
     e: ffi::DBusError,
+    args: Vec<MessageItem>,
 }
 
 unsafe impl Send for Error {}
@@ -32,6 +34,31 @@ impl Error {
         Error::new_custom("org.freedesktop.DBus.Error.Failed", message)
     }
 
+    /// Creates an error of the Congested kind, see `Channel::set_max_pending_replies`.
+    pub (crate) fn new_congested() -> Error {
+        Error::new_custom(CONGESTED, "Too many method calls are waiting for a reply")
+    }
+
+    /// Creates an error of the UnixFdsNotSupported kind, see `Channel::can_pass_unix_fds`.
+    pub (crate) fn new_unix_fds_not_supported() -> Error {
+        Error::new_custom(UNIX_FDS_NOT_SUPPORTED, "The message contains Unix fds, but the connection cannot pass them")
+    }
+
+    /// Creates an error of the LimitsExceeded kind, for a message with more Unix fds than the
+    /// connection allows. Unlike the bus daemon's errors of this kind, it is raised before sending.
+    pub (crate) fn new_too_many_unix_fds(n: usize, max: usize) -> Error {
+        Error::new_custom(TOO_MANY_UNIX_FDS, &format!("The message contains {} Unix fds, but at most {} are allowed", n, max))
+    }
+
+    /// Creates an error of the LimitsExceeded kind, for a method call that did not fit into
+    /// the outgoing queue, see `Channel::set_outgoing_limit`.
+    pub (crate) fn new_outgoing_full() -> Error {
+        Error::new_custom(OUTGOING_FULL, "The outgoing message queue is full")
+    }
+
+    /// Returns true if this is the error from `new_too_many_unix_fds`.
+    pub (crate) fn is_too_many_unix_fds(&self) -> bool { self.name() == Some(TOO_MANY_UNIX_FDS) }
+
     pub (crate) fn empty() -> Error {
         init_dbus();
         let mut e = ffi::DBusError {
@@ -41,7 +68,7 @@ impl Error {
             padding1: ptr::null()
         };
         unsafe { ffi::dbus_error_init(&mut e); }
-        Error{ e: e }
+        Error{ e: e, args: vec!() }
     }
 
     /// Error name/type, e g 'org.freedesktop.DBus.Error.Failed'
@@ -54,9 +81,118 @@ impl Error {
         c_str_to_slice(&self.e.message)
     }
 
+    /// The kind of error, as determined by its name.
+    ///
+    /// Use this instead of comparing error names, e g to decide whether to retry a method call.
+    pub fn kind(&self) -> ErrorKind {
+        self.name().map(ErrorKind::from_name).unwrap_or(ErrorKind::Other)
+    }
+
+    /// Additional arguments of the error reply, after the error message.
+    ///
+    /// D-Bus allows error replies to carry more arguments than just the error message,
+    /// e g a machine-readable error code. See `MethodErr::with_arg` for the server side.
+    pub fn args(&self) -> &[MessageItem] { &self.args }
+
+    /// Reads the additional arguments of the error reply (see `args`) as the given types.
+    pub fn read_args<R: arg::ReadAll>(&self) -> Result<R, arg::TypeMismatchError> {
+        let mut m = crate::Message::new_signal("/", "org.freedesktop.DBus.Error", "Args").unwrap();
+        m.append_items(&self.args);
+        R::read(&mut m.iter_init())
+    }
+
+    /// Sets the additional arguments of the error reply.
+    pub fn set_args(&mut self, args: Vec<MessageItem>) { self.args = args; }
+
     pub (crate) fn get_mut(&mut self) -> &mut ffi::DBusError { &mut self.e }
 }
 
+// Not sent over the bus, only used for method calls that failed before being sent.
+const CONGESTED: &str = "rs.dbus.Error.Congested";
+const UNIX_FDS_NOT_SUPPORTED: &str = "rs.dbus.Error.UnixFdsNotSupported";
+const TOO_MANY_UNIX_FDS: &str = "rs.dbus.Error.TooManyUnixFds";
+const OUTGOING_FULL: &str = "rs.dbus.Error.OutgoingQueueFull";
+
+/// The kind of a D-Bus error, see Error::kind.
+///
+/// More kinds might be added in the future. Errors with names not known to this
+/// enum are of the "Other" kind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// No reply was received in time (Timeout, TimedOut or NoReply).
+    Timeout,
+    /// The connection is disconnected (Disconnected or NoServer).
+    Disconnected,
+    /// The requested bus name has no owner (NameHasNoOwner).
+    NameHasNoOwner,
+    /// The requested bus name could not be activated, or is not known (ServiceUnknown).
+    ServiceUnknown,
+    /// The security policy does not allow the operation (AccessDenied or AuthFailed).
+    AccessDenied,
+    /// Invalid arguments were passed (InvalidArgs or InvalidSignature).
+    InvalidArgs,
+    /// The object path does not exist (UnknownObject).
+    UnknownObject,
+    /// The interface does not exist on this object path (UnknownInterface).
+    UnknownInterface,
+    /// The method does not exist on this interface (UnknownMethod).
+    UnknownMethod,
+    /// The property does not exist on this interface (UnknownProperty).
+    UnknownProperty,
+    /// The property cannot be set (PropertyReadOnly).
+    PropertyReadOnly,
+    /// The operation is not supported (NotSupported).
+    NotSupported,
+    /// Some limit was exceeded (LimitsExceeded), e g a message had more Unix fds than allowed,
+    /// the outgoing queue was full, or the bus daemon's quota of Unix fds for the connection was reached.
+    LimitsExceeded,
+    /// Out of memory (NoMemory).
+    NoMemory,
+    /// Something went wrong when reading or writing (IOError).
+    IO,
+    /// The generic error (Failed).
+    Failed,
+    /// Too many method calls are waiting for a reply, so the method call was not sent.
+    /// See `Channel::set_max_pending_replies`.
+    Congested,
+    /// The message contains Unix fds, but the connection cannot pass them, e g because it is a TCP
+    /// connection. The message was not sent. See `Channel::can_pass_unix_fds`.
+    UnixFdsNotSupported,
+    /// An error name not known to this enum, e g an application specific error.
+    Other,
+}
+
+impl ErrorKind {
+    /// Returns the kind of an error with this name.
+    pub fn from_name(name: &str) -> ErrorKind {
+        use self::ErrorKind::*;
+        if name == CONGESTED { return Congested }
+        if name == UNIX_FDS_NOT_SUPPORTED { return UnixFdsNotSupported }
+        if name == TOO_MANY_UNIX_FDS || name == OUTGOING_FULL { return LimitsExceeded }
+        let name = match name.strip_prefix("org.freedesktop.DBus.Error.") { Some(x) => x, None => return Other };
+        match name {
+            "Timeout" | "TimedOut" | "NoReply" => Timeout,
+            "Disconnected" | "NoServer" => Disconnected,
+            "NameHasNoOwner" => NameHasNoOwner,
+            "ServiceUnknown" => ServiceUnknown,
+            "AccessDenied" | "AuthFailed" => AccessDenied,
+            "InvalidArgs" | "InvalidSignature" => InvalidArgs,
+            "UnknownObject" => UnknownObject,
+            "UnknownInterface" => UnknownInterface,
+            "UnknownMethod" => UnknownMethod,
+            "UnknownProperty" => UnknownProperty,
+            "PropertyReadOnly" => PropertyReadOnly,
+            "NotSupported" => NotSupported,
+            "LimitsExceeded" => LimitsExceeded,
+            "NoMemory" => NoMemory,
+            "IOError" => IO,
+            "Failed" => Failed,
+            _ => Other,
+        }
+    }
+}
+
 impl Drop for Error {
     fn drop(&mut self) {
         unsafe { ffi::dbus_error_free(&mut self.e); }
@@ -91,7 +227,32 @@ impl From<arg::TypeMismatchError> for Error {
 
 impl From<tree::MethodErr> for Error {
     fn from(t: tree::MethodErr) -> Error {
-        Error::new_custom(t.errorname(), t.description())
+        let mut e = Error::new_custom(t.errorname(), t.description());
+        e.args = t.args().to_vec();
+        e
     }
 }
 
+
+#[test]
+fn test_error_kind() {
+    assert_eq!(Error::new_failed("Oops").kind(), ErrorKind::Failed);
+    let e = Error::new_custom("org.freedesktop.DBus.Error.NoReply", "Did not receive a reply");
+    assert_eq!(e.kind(), ErrorKind::Timeout);
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NoReply"));
+    assert_eq!(e.message(), Some("Did not receive a reply"));
+    assert_eq!(Error::new_custom("com.example.Error.Failed", "Oops").kind(), ErrorKind::Other);
+    assert_eq!(Error::new_congested().kind(), ErrorKind::Congested);
+    assert_eq!(Error::new_unix_fds_not_supported().kind(), ErrorKind::UnixFdsNotSupported);
+    assert_eq!(Error::from(tree::MethodErr::invalid_arg(&5)).kind(), ErrorKind::InvalidArgs);
+    assert_eq!(ErrorKind::from_name("org.freedesktop.DBus.Error.ServiceUnknown"), ErrorKind::ServiceUnknown);
+}
+
+#[test]
+fn test_error_args() {
+    let e = Error::from(tree::MethodErr::failed("Out of cheese").with_arg(42u32).with_arg("cheese"));
+    assert_eq!(e.args(), &[MessageItem::UInt32(42), MessageItem::Str("cheese".into())]);
+    assert_eq!(e.read_args::<(u32, String)>().unwrap(), (42, "cheese".into()));
+    assert!(e.read_args::<(String,)>().is_err());
+    assert!(Error::new_failed("Oops").args().is_empty());
+}
@@ -13,7 +13,7 @@ pub mod stdintf;
 
 mod connection;
 
-pub use connection::{Connection, ConnMsgs};
+pub use connection::{Connection, ConnMsgs, NameGuard, Wakeup};
 
 /// A convenience struct that wraps connection, destination and path.
 ///
@@ -85,6 +85,9 @@ pub use crate::ffi::DBusBusType as BusType;
 mod watch;
 
 pub use self::watch::{Watch, WatchEvent};
+
+mod record;
+pub use self::record::{Recorder, Replay};
 use watch::WatchList;
 
 #[repr(C)]
@@ -118,12 +121,24 @@ pub enum ConnectionItem {
     Signal(Message),
     /// Incoming method return, including method return errors (mostly used for Async I/O)
     MethodReturn(Message),
+    /// The connection was closed, e g because the D-Bus server went down. This is the last item.
+    ///
+    /// The reason is an error of kind `ErrorKind::Disconnected`.
+    Disconnected(Error),
+}
+
+/// The error used for ConnectionItem::Disconnected.
+fn disconnected_error() -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "The connection to D-Bus was closed")
 }
 
 impl From<Message> for ConnectionItem {
     fn from(m: Message) -> Self {
         let mtype = m.msg_type();
         match mtype {
+            // libdbus emits this signal locally when the connection is lost.
+            MessageType::Signal if m.interface().map(|i| &*i == "org.freedesktop.DBus.Local").unwrap_or(false)
+                && m.member().map(|i| &*i == "Disconnected").unwrap_or(false) => ConnectionItem::Disconnected(disconnected_error()),
             MessageType::Signal => ConnectionItem::Signal(m),
             MessageType::MethodReturn => ConnectionItem::MethodReturn(m),
             MessageType::Error => ConnectionItem::MethodReturn(m),
@@ -262,6 +277,26 @@ mod test {
 
     }
 
+    #[test]
+    fn try_iter() {
+        use std::time::{Duration, Instant};
+        let c = Connection::get_private(BusType::Session).unwrap();
+        // The NameAcquired signal might arrive at any point.
+        let acquired = |m: &Message| &*m.member().unwrap() == "NameAcquired";
+        let start = Instant::now();
+        assert!(c.try_iter().all(|m| acquired(&m)));
+        assert!(c.next_timeout(Duration::from_millis(50)).map_or(true, |m| acquired(&m)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut m = Message::new_signal("/test", "com.example.test", "TryIter").unwrap();
+        m.set_destination(Some(c.unique_name().into()));
+        c.send(m).unwrap();
+        let m = c.next_timeout(Duration::from_secs(5)).unwrap();
+        let m = if acquired(&m) { c.next_timeout(Duration::from_secs(5)).unwrap() } else { m };
+        assert_eq!(&*m.member().unwrap(), "TryIter");
+        assert!(c.try_iter().all(|m| acquired(&m)));
+    }
+
     #[test]
     fn register_name() {
         let c = Connection::get_private(BusType::Session).unwrap();
@@ -270,6 +305,79 @@ mod test {
         assert_eq!(c.release_name(&n).unwrap(), ReleaseNameReply::Released);
     }
 
+    #[test]
+    fn wakeup() {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let (wakeup, events) = c.wakeup();
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            wakeup.clone().send(5u32).unwrap();
+            wakeup.send(6).unwrap();
+        });
+        let start = std::time::Instant::now();
+        let mut got = vec!();
+        for item in c.iter(10000) {
+            if let ConnectionItem::Nothing = item { got.extend(events.try_iter()) }
+            if got.len() == 2 { break }
+        }
+        assert_eq!(got, vec!(5, 6));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        t.join().unwrap();
+        let (wakeup, events) = c.wakeup::<()>();
+        drop(events);
+        assert!(wakeup.send(()).is_err());
+    }
+
+    #[test]
+    fn wakeup_queued() {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let (_wakeup, _events) = c.wakeup::<()>();
+        for item in c.iter(100) { if let ConnectionItem::Nothing = item { break } }
+        let c2 = Connection::get_private(BusType::Session).unwrap();
+        for i in 0..2u32 {
+            let mut m = Message::new_signal("/test", "com.example.dbusrs.Wakeup", "Ping").unwrap().append1(i);
+            m.set_destination(Some(c.unique_name().into()));
+            c2.send(m).unwrap();
+        }
+        // Let both messages arrive, so that libdbus reads them in one go.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let start = std::time::Instant::now();
+        let mut got = vec!();
+        for item in c.iter(2000) {
+            if let ConnectionItem::Signal(m) = item {
+                if &*m.member().unwrap() == "Ping" { got.push(m.read1::<u32>().unwrap()) }
+            }
+            if got.len() == 2 { break }
+        }
+        assert_eq!(got, vec!(0, 1));
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn request_name_scoped() {
+        use crate::tree::Factory;
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let c2 = Connection::get_private(BusType::Session).unwrap();
+        let n = "com.example.hello.test.request_name_scoped";
+        let f = Factory::new_fn::<()>();
+        let tree = f.tree(()).add(f.object_path("/scoped", ()).introspectable());
+        tree.set_registered(&c, true).unwrap();
+        {
+            let g = c.request_name_scoped(n, NameFlag::DoNotQueue.value()).unwrap().unregister_tree(&tree);
+            assert_eq!(g.reply(), RequestNameReply::PrimaryOwner);
+            assert_eq!(g.name(), n);
+            let g2 = c2.request_name_scoped(n, NameFlag::DoNotQueue.value()).unwrap().unregister_tree(&tree);
+            assert_eq!(g2.reply(), RequestNameReply::Exists);
+            drop(g2);
+            assert!(!c.list_registered_object_paths("/").is_empty());
+        }
+        assert!(c.list_registered_object_paths("/").is_empty());
+        let g = c2.request_name_scoped(n, NameFlag::DoNotQueue.value()).unwrap();
+        assert_eq!(g.reply(), RequestNameReply::PrimaryOwner);
+        assert_eq!(g.release().unwrap(), ReleaseNameReply::Released);
+        assert_eq!(c.release_name(n).unwrap(), ReleaseNameReply::NonExistent);
+    }
+
     #[test]
     fn signal() {
         let c = Connection::get_private(BusType::Session).unwrap();
@@ -298,6 +406,27 @@ mod test {
     }
 
 
+    #[test]
+    fn disconnected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let c = Connection::open_private(&format!("unix:path={}", path.display())).unwrap();
+        // Closing the socket before authentication has finished disconnects the client.
+        drop(listener.accept().unwrap());
+
+        let items: Vec<_> = c.iter(1000).collect();
+        assert_eq!(items.len(), 1);
+        match items[0] {
+            ConnectionItem::Disconnected(ref e) => assert_eq!(e.kind(), crate::ErrorKind::Disconnected),
+            ref x => panic!("Expected Disconnected, got {:?}", x),
+        }
+        assert!(!c.is_connected());
+        // The iterator ends also if it would otherwise never end.
+        let items: Vec<_> = super::connection::ConnectionItems::new(&c, None, false).collect();
+        assert_eq!(items.len(), 1);
+    }
+
     #[test]
     fn watch() {
         let c = Connection::get_private(BusType::Session).unwrap();
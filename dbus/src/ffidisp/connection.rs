@@ -3,11 +3,12 @@
 use crate::{Error, ffi, to_c_str, c_str_to_slice, Message, MessageType};
 use crate::ffidisp::ConnPath;
 use std::{fmt, mem, ptr, thread, panic, ops};
-use std::{collections::VecDeque, time::Duration};
+use std::{collections::VecDeque, time::Duration, time::Instant, sync::mpsc, sync::Arc};
 use std::cell::{Cell, RefCell};
 use std::os::unix::io::RawFd;
 use std::os::raw::{c_void, c_char, c_int, c_uint};
 use crate::strings::{BusName, Path};
+use crate::tree::{Tree, MethodType, DataType};
 use super::{Watch, WatchList, MessageCallback, ConnectionItem, MsgHandler, MsgHandlerList, MessageReply, BusType};
 
 
@@ -22,6 +23,7 @@ struct IConnection {
 
     filter_cb: RefCell<Option<MessageCallback>>,
     filter_cb_panic: RefCell<thread::Result<()>>,
+    wakeup: RefCell<Option<Arc<WakeupPipe>>>,
 }
 
 /// A D-Bus connection. Start here if you want to get on the D-Bus!
@@ -50,7 +52,8 @@ extern "C" fn filter_message_cb(conn: *mut ffi::DBusConnection, msg: *mut ffi::D
 
     let fcb = panic::AssertUnwindSafe(&i.filter_cb);
     let r = panic::catch_unwind(|| {
-        let m = Message::from_ptr(msg, true);
+        let mut m = Message::from_ptr(msg, true);
+        crate::message::message_set_received(&mut m, Instant::now());
         let mut cb = fcb.borrow_mut().take().unwrap(); // Take the callback out while we call it.
         let r = cb(connref.0, m);
         let mut cb2 = fcb.borrow_mut(); // If the filter callback has not been replaced, put it back in.
@@ -95,6 +98,7 @@ impl Connection {
             handlers: RefCell::new(vec!()),
             filter_cb: RefCell::new(Some(Box::new(default_filter_callback))),
             filter_cb_panic: RefCell::new(Ok(())),
+            wakeup: RefCell::new(None),
         })};
 
         /* No, we don't want our app to suddenly quit if dbus goes down */
@@ -167,7 +171,9 @@ impl Connection {
         if response.is_null() {
             return Err(e);
         }
-        Ok(Message::from_ptr(response, false))
+        let mut r = Message::from_ptr(response, false);
+        crate::message::message_set_received(&mut r, Instant::now());
+        Ok(r)
     }
 
     /// Sends a message over the D-Bus without waiting. Useful for sending signals and method call replies.
@@ -237,6 +243,35 @@ impl Connection {
         ConnectionItems::new(self, Some(timeout_ms), false)
     }
 
+    /// Creates a handle that other threads can use to wake up the ConnectionItems iterator, and hand over events.
+    ///
+    /// The events are received through the returned Receiver. Each event makes the iterator
+    /// yield a `ConnectionItem::Nothing`, so that a loop over it can check the Receiver.
+    pub fn wakeup<E: Send>(&self) -> (Wakeup<E>, mpsc::Receiver<E>) {
+        let (tx, rx) = mpsc::channel();
+        let pipe = self.i.wakeup.borrow_mut().get_or_insert_with(|| Arc::new(WakeupPipe::new())).clone();
+        (Wakeup { pipe, tx }, rx)
+    }
+
+    // Waits for the connection or a wakeup. Returns false if woken up.
+    fn wait_or_wakeup(&self, timeout_ms: i32) -> bool {
+        let pipe = match &*self.i.wakeup.borrow() { Some(p) => p.0, None => return true };
+        // Messages libdbus has already read would never make the socket readable again.
+        let queued = || unsafe { ffi::dbus_connection_get_dispatch_status(self.conn()) == ffi::DBusDispatchStatus::DataRemains };
+        if queued() { return true }
+        if unsafe { ffi::dbus_connection_has_messages_to_send(self.conn()) } != 0 {
+            unsafe { ffi::dbus_connection_read_write(self.conn(), 0) };
+            if queued() { return true }
+        }
+        let mut fds: Vec<_> = self.watch_fds().iter().map(|w| w.to_pollfd()).collect();
+        fds.push(libc::pollfd { fd: pipe, events: libc::POLLIN, revents: 0 });
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        if fds.last().unwrap().revents == 0 { return true }
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(pipe, buf.as_mut_ptr() as *mut c_void, buf.len()) } > 0 {}
+        false
+    }
+
     /// Check if there are new incoming events
     ///
     /// Supersedes "iter".
@@ -244,6 +279,22 @@ impl Connection {
         ConnMsgs { conn: &self, timeout_ms: Some(timeout_ms) }
     }
 
+    /// Returns the messages that have already arrived, without waiting for more.
+    ///
+    /// The iterator ends as soon as there is nothing left to read, so it can be called once per
+    /// frame from e g a game loop. Messages handled by a MsgHandler or a registered callback
+    /// are not returned.
+    pub fn try_iter(&self) -> ConnMsgs<&Self> {
+        ConnMsgs { conn: self, timeout_ms: Some(0) }
+    }
+
+    /// Returns the next incoming message, waiting for at most "timeout" for it to arrive.
+    ///
+    /// Returns None if no message arrived in time, or if the connection is closed.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Message> {
+        ConnMsgs { conn: self, timeout_ms: Some(timeout.as_millis().min(i32::MAX as u128) as u32) }.next()
+    }
+
     /// Register an object path.
     pub fn register_object_path(&self, path: &str) -> Result<(), Error> {
         let mut e = Error::empty();
@@ -299,6 +350,14 @@ impl Connection {
         if r == -1 { Err(e) } else { Ok(unsafe { mem::transmute(r) }) }
     }
 
+    /// Register a name, which is released when the returned guard is dropped.
+    ///
+    /// Check `NameGuard::reply` to see whether we actually became the owner of the name.
+    pub fn request_name_scoped(&self, name: &str, flags: u32) -> Result<NameGuard<'_>, Error> {
+        let reply = self.register_name(name, flags)?;
+        Ok(NameGuard { conn: self, name: name.into(), reply, on_release: None })
+    }
+
     /// Release a name.
     pub fn release_name(&self, name: &str) -> Result<super::ReleaseNameReply, Error> {
         let mut e = Error::empty();
@@ -470,6 +529,78 @@ impl fmt::Debug for Connection {
     }
 }
 
+/// Keeps a name registered on the bus, as long as it is alive.
+///
+/// Created by `Connection::request_name_scoped`. When dropped, the name is released, so a daemon
+/// or test that goes away does not leave the name behind.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use dbus::ffidisp::{Connection, BusType, NameFlag};
+/// use dbus::tree::Factory;
+///
+/// let c = Connection::get_private(BusType::Session)?;
+/// let f = Factory::new_fn::<()>();
+/// let tree = f.tree(()).add(f.object_path("/hello", ()).introspectable());
+/// tree.set_registered(&c, true)?;
+/// let _guard = c.request_name_scoped("com.example.dbustest", NameFlag::DoNotQueue.value())?.unregister_tree(&tree);
+/// // ...
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct NameGuard<'a> {
+    conn: &'a Connection,
+    name: String,
+    reply: super::RequestNameReply,
+    on_release: Option<ReleaseCallback<'a>>,
+}
+
+type ReleaseCallback<'a> = Box<dyn FnOnce(&Connection) + 'a>;
+
+impl<'a> NameGuard<'a> {
+    /// The name this guard releases.
+    pub fn name(&self) -> &str { &self.name }
+
+    /// The reply from the bus when the name was requested.
+    pub fn reply(&self) -> super::RequestNameReply { self.reply }
+
+    /// Calls `f` just before the name is released.
+    ///
+    /// `f` is only called if `reply` is `PrimaryOwner` or `AlreadyOwner`.
+    pub fn on_release<F: FnOnce(&Connection) + 'a>(mut self, f: F) -> Self { self.on_release = Some(Box::new(f)); self }
+
+    /// Unregisters the object paths of the tree just before the name is released,
+    /// so that the tree stops answering method calls.
+    pub fn unregister_tree<M: MethodType<D>, D: DataType>(self, tree: &'a Tree<M, D>) -> Self {
+        self.on_release(move |c| { let _ = tree.set_registered(c, false); })
+    }
+
+    /// Releases the name now, returning the reply from the bus.
+    pub fn release(mut self) -> Result<super::ReleaseNameReply, Error> { self.do_release() }
+
+    fn do_release(&mut self) -> Result<super::ReleaseNameReply, Error> {
+        use super::RequestNameReply::*;
+        let f = self.on_release.take();
+        // Only undo the registration if we actually owned the name; otherwise the
+        // callback would e g unregister a tree that another guard is still serving.
+        if let (Some(f), PrimaryOwner | AlreadyOwner) = (f, self.reply) { f(self.conn) };
+        let name = mem::take(&mut self.name);
+        self.conn.release_name(&name)
+    }
+}
+
+impl Drop for NameGuard<'_> {
+    fn drop(&mut self) {
+        if !self.name.is_empty() { let _ = self.do_release(); }
+    }
+}
+
+impl fmt::Debug for NameGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "NameGuard({}, {:?})", self.name, self.reply)
+    }
+}
+
 impl crate::channel::Sender for Connection {
     fn send(&self, msg: Message) -> Result<u32, ()> { Connection::send(self, msg) }
 }
@@ -497,12 +628,74 @@ fn msghandler_process(v: &mut MsgHandlerList, m: &Message, c: &Connection) -> bo
     }
 }
 
+// A pipe that is polled together with the connection, so that other threads can interrupt the poll.
+#[derive(Debug)]
+struct WakeupPipe(RawFd, RawFd);
+
+impl WakeupPipe {
+    fn new() -> Self {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 { panic!("Cannot create wakeup pipe") }
+        for &fd in &fds { unsafe {
+            libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }}
+        WakeupPipe(fds[0], fds[1])
+    }
+}
+
+impl Drop for WakeupPipe {
+    fn drop(&mut self) { unsafe { libc::close(self.0); libc::close(self.1); } }
+}
+
+/// Wakes up a Connection's ConnectionItems iterator from another thread, handing over an event.
+///
+/// Created by `Connection::wakeup`. This lets a single threaded server react to things happening
+/// outside of D-Bus, e g emit a signal or shut down, without having to poll with a short timeout.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use dbus::ffidisp::{Connection, BusType};
+///
+/// let c = Connection::get_private(BusType::Session)?;
+/// let (wakeup, events) = c.wakeup();
+/// std::thread::spawn(move || { let _ = wakeup.send("shutdown"); });
+/// for _item in c.iter(60000) {
+///     if let Ok("shutdown") = events.try_recv() { break }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Wakeup<E> {
+    pipe: Arc<WakeupPipe>,
+    tx: mpsc::Sender<E>,
+}
+
+impl<E> Wakeup<E> {
+    /// Hands over the event, and wakes up the iterator.
+    ///
+    /// Fails if the Receiver has been dropped.
+    pub fn send(&self, event: E) -> Result<(), E> {
+        // The event must be there before the iterator wakes up.
+        self.tx.send(event).map_err(|e| e.0)?;
+        // If the pipe is full, the iterator is going to wake up anyway.
+        unsafe { libc::write(self.pipe.1, [0u8].as_ptr() as *const c_void, 1) };
+        Ok(())
+    }
+}
+
+impl<E> Clone for Wakeup<E> {
+    fn clone(&self) -> Self { Wakeup { pipe: self.pipe.clone(), tx: self.tx.clone() } }
+}
+
 /// ConnectionItem iterator
 pub struct ConnectionItems<'a> {
     c: &'a Connection,
     timeout_ms: Option<i32>,
     end_on_timeout: bool,
     handlers: MsgHandlerList,
+    disconnected: bool,
 }
 
 impl<'a> ConnectionItems<'a> {
@@ -519,7 +712,7 @@ impl<'a> ConnectionItems<'a> {
             ConnectionItem::MethodReturn(ref msg) => msg,
             ConnectionItem::Signal(ref msg) => msg,
             ConnectionItem::MethodCall(ref msg) => msg,
-            ConnectionItem::Nothing => return false,
+            ConnectionItem::Nothing | ConnectionItem::Disconnected(_) => return false,
         };
 
         msghandler_process(&mut self.handlers, m, &self.c)
@@ -544,8 +737,16 @@ impl<'a> ConnectionItems<'a> {
             timeout_ms: io_timeout,
             end_on_timeout: end_on_timeout,
             handlers: Vec::new(),
+            disconnected: false,
         }
     }
+
+    // Yields ConnectionItem::Disconnected once, then ends the iterator.
+    fn disconnected_item(&mut self) -> Option<ConnectionItem> {
+        if self.disconnected { return None }
+        self.disconnected = true;
+        Some(ConnectionItem::Disconnected(super::disconnected_error()))
+    }
 }
 
 impl<'a> Iterator for ConnectionItems<'a> {
@@ -555,14 +756,20 @@ impl<'a> Iterator for ConnectionItems<'a> {
             if self.c.i.filter_cb.borrow().is_none() { panic!("ConnectionItems::next called recursively or with a MessageCallback set to None"); }
             let i: Option<ConnectionItem> = self.c.next_msg().map(|x| x.into());
             if let Some(ci) = i {
+                if let ConnectionItem::Disconnected(_) = ci {
+                    if self.disconnected { continue }
+                    self.disconnected = true;
+                }
                 if !self.process_handlers(&ci) { return Some(ci); }
             }
 
-            if let Some(t) = self.timeout_ms {
+            if let Some(mut t) = self.timeout_ms {
+                if t != 0 && !self.c.wait_or_wakeup(t) { return Some(ConnectionItem::Nothing) }
+                if self.c.i.wakeup.borrow().is_some() { t = 0 }
                 let r = unsafe { ffi::dbus_connection_read_write_dispatch(self.c.conn(), t as c_int) };
                 self.c.check_panic();
                 if !self.c.i.pending_items.borrow().is_empty() { continue };
-                if r == 0 { return None; }
+                if r == 0 { return self.disconnected_item(); }
             }
 
             let r = unsafe { ffi::dbus_connection_dispatch(self.c.conn()) };
@@ -570,7 +777,11 @@ impl<'a> Iterator for ConnectionItems<'a> {
 
             if !self.c.i.pending_items.borrow().is_empty() { continue };
             if r == ffi::DBusDispatchStatus::DataRemains { continue };
-            if r == ffi::DBusDispatchStatus::Complete { return if self.end_on_timeout { None } else { Some(ConnectionItem::Nothing) } };
+            if r == ffi::DBusDispatchStatus::Complete {
+                // Everything is dispatched, so there is no Disconnected signal left in the queue.
+                if !self.c.is_connected() { return self.disconnected_item() }
+                return if self.end_on_timeout { None } else { Some(ConnectionItem::Nothing) }
+            };
             panic!("dbus_connection_dispatch failed");
         }
     }
@@ -0,0 +1,147 @@
+// Recording and replaying of ConnectionItem streams.
+
+use super::{ConnectionItem, disconnected_error};
+use crate::Message;
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::path::Path;
+
+// File format: the header, then for every item a kind byte and, for messages,
+// the length (u32, little endian) and the marshalled message.
+const HEADER: &[u8] = b"dbus-rs ConnectionItems v1\n";
+const NOTHING: u8 = b'N';
+const MESSAGE: u8 = b'M';
+const DISCONNECTED: u8 = b'D';
+
+fn invalid(s: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, s) }
+
+/// Writes ConnectionItems in a format that can be read back by `Replay`.
+///
+/// Useful for capturing the traffic that triggers a bug, so it can be replayed in a test.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use dbus::ffidisp::{Connection, BusType, Recorder};
+///
+/// let c = Connection::get_private(BusType::Session)?;
+/// let mut rec = Recorder::create("trace.bin")?;
+/// for item in c.iter(1000).take(100) {
+///     rec.record(&item)?;
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Recorder<W: Write> {
+    w: W,
+}
+
+impl Recorder<File> {
+    /// Creates a new file and starts a recording in it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> { Recorder::new(File::create(path)?) }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording by writing the header.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(HEADER)?;
+        Ok(Recorder { w })
+    }
+
+    /// Appends an item to the recording.
+    pub fn record(&mut self, item: &ConnectionItem) -> io::Result<()> {
+        let m = match item {
+            ConnectionItem::Nothing => return self.w.write_all(&[NOTHING]),
+            ConnectionItem::Disconnected(_) => return self.w.write_all(&[DISCONNECTED]),
+            ConnectionItem::MethodCall(m) | ConnectionItem::Signal(m) | ConnectionItem::MethodReturn(m) => m,
+        };
+        let data = m.marshal().map_err(|e| io::Error::new(io::ErrorKind::OutOfMemory, e.to_string()))?;
+        self.w.write_all(&[MESSAGE])?;
+        self.w.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.w.write_all(&data)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> { self.w.flush() }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W { self.w }
+}
+
+/// An iterator over ConnectionItems read from a recording made by `Recorder`.
+///
+/// This can be used instead of `Connection::iter`, e g together with `Tree::run`.
+/// The replay ends after the last recorded item.
+pub struct Replay {
+    items: std::vec::IntoIter<ConnectionItem>,
+}
+
+impl Replay {
+    /// Reads a recording from a file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> { Replay::new(File::open(path)?) }
+
+    /// Reads a recording. The whole recording is read and checked before this returns.
+    pub fn new<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut data = vec!();
+        r.read_to_end(&mut data)?;
+        if !data.starts_with(HEADER) { return Err(invalid("Not a recording of ConnectionItems")) }
+        let mut data = &data[HEADER.len()..];
+        let mut items = vec!();
+        while let Some((&kind, rest)) = data.split_first() {
+            data = rest;
+            items.push(match kind {
+                NOTHING => ConnectionItem::Nothing,
+                DISCONNECTED => ConnectionItem::Disconnected(disconnected_error()),
+                MESSAGE => {
+                    if data.len() < 4 { return Err(invalid("Truncated recording")) }
+                    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                    if data.len() < 4 + len { return Err(invalid("Truncated recording")) }
+                    let m = Message::demarshal(&data[4..4+len]).map_err(|e| invalid(&e.to_string()))?;
+                    data = &data[4+len..];
+                    m.into()
+                },
+                _ => return Err(invalid("Invalid item in recording")),
+            });
+        }
+        Ok(Replay { items: items.into_iter() })
+    }
+}
+
+impl Iterator for Replay {
+    type Item = ConnectionItem;
+    fn next(&mut self) -> Option<ConnectionItem> { self.items.next() }
+}
+
+#[test]
+fn test_record_replay() {
+    let mut call = Message::new_method_call("com.example.dbusrs", "/record", "com.example.dbusrs.Record", "Play").unwrap().append2("Hello", 5u32);
+    crate::message::message_set_serial(&mut call, 1);
+    let mut signal = Message::new_signal("/record", "com.example.dbusrs.Record", "Played").unwrap();
+    crate::message::message_set_serial(&mut signal, 2);
+
+    let mut rec = Recorder::new(vec!()).unwrap();
+    for item in vec!(ConnectionItem::MethodCall(call), ConnectionItem::Nothing, ConnectionItem::Signal(signal),
+        ConnectionItem::Disconnected(disconnected_error())) {
+        rec.record(&item).unwrap();
+    }
+    let data = rec.into_inner();
+
+    let items: Vec<_> = Replay::new(&data[..]).unwrap().collect();
+    assert_eq!(items.len(), 4);
+    match &items[0] {
+        ConnectionItem::MethodCall(m) => {
+            assert_eq!(&*m.member().unwrap(), "Play");
+            assert_eq!(m.get_serial(), Some(1));
+            assert_eq!(m.read2::<&str, u32>().unwrap(), ("Hello", 5));
+        },
+        _ => panic!("Expected a method call"),
+    }
+    assert!(matches!(items[1], ConnectionItem::Nothing));
+    assert!(matches!(items[2], ConnectionItem::Signal(_)));
+    match &items[3] {
+        ConnectionItem::Disconnected(e) => assert_eq!(e.kind(), crate::ErrorKind::Disconnected),
+        _ => panic!("Expected disconnected"),
+    }
+
+    assert!(Replay::new(&data[..data.len()-5]).is_err());
+    assert!(Replay::new(&b"Hello"[..]).is_err());
+}
@@ -0,0 +1,140 @@
+//! Entry points for fuzzing the parsers used by this crate.
+//!
+//! Enabled with the `fuzzing` feature. The functions take arbitrary bytes, so they can be called
+//! directly from a cargo-fuzz target:
+//!
+//! ```ignore
+//! #![no_main]
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| { let _ = dbus::fuzz::message(data); });
+//! ```
+//!
+//! Both the message and the address parsers are the ones in libdbus, since that is what this crate uses
+//! to read from the wire. The SASL authentication code of libdbus is not exposed, so it cannot be fuzzed from here.
+
+use crate::{Error, Message, to_c_str, c_str_to_slice};
+use crate::arg::{ArgType, Iter};
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Limits applied when parsing a message, so that fuzzing does not run out of memory or stack.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum size of the message, in bytes.
+    pub max_size: usize,
+    /// Maximum nesting of containers (arrays, structs, dict entries and variants).
+    pub max_depth: usize,
+    /// Maximum number of arguments, including the ones inside containers.
+    pub max_args: usize,
+}
+
+impl Default for Limits {
+    /// One MiB, a nesting depth of 64 (the D-Bus specification allows 32 arrays and 32 structs),
+    /// and 65536 arguments.
+    fn default() -> Self { Limits { max_size: 1 << 20, max_depth: 64, max_args: 1 << 16 } }
+}
+
+fn invalid(s: &str) -> Error { Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", s) }
+
+/// Parses a message in D-Bus wire format and reads all its arguments, using the default limits.
+pub fn message(data: &[u8]) -> Result<Message, Error> { message_with_limits(data, &Limits::default()) }
+
+/// Parses a message in D-Bus wire format and reads all its arguments.
+///
+/// Fails if the data is not exactly one message, or if the message exceeds the limits.
+pub fn message_with_limits(data: &[u8], limits: &Limits) -> Result<Message, Error> {
+    if data.len() > limits.max_size { return Err(invalid("Message too large")) }
+    let needed = unsafe { ffi::dbus_message_demarshal_bytes_needed(data.as_ptr() as *const _, data.len() as c_int) };
+    if needed <= 0 || needed as usize != data.len() { return Err(invalid("Not exactly one message")) }
+    let m = Message::demarshal(data)?;
+    let mut count = 0;
+    read_args(&mut m.iter_init(), 0, limits, &mut count)?;
+    Ok(m)
+}
+
+fn read_args(i: &mut Iter, depth: usize, limits: &Limits, count: &mut usize) -> Result<(), Error> {
+    loop {
+        let t = i.arg_type();
+        if t == ArgType::Invalid { return Ok(()) }
+        *count += 1;
+        if *count > limits.max_args { return Err(invalid("Too many arguments")) }
+        match t {
+            ArgType::Array | ArgType::Struct | ArgType::DictEntry | ArgType::Variant => {
+                if depth >= limits.max_depth { return Err(invalid("Containers nested too deeply")) }
+                let mut sub = i.recurse(t).ok_or_else(|| invalid("Invalid container"))?;
+                read_args(&mut sub, depth + 1, limits, count)?;
+            },
+            // Demarshalled messages carry no file descriptors.
+            ArgType::UnixFd => {},
+            _ => { i.get_refarg().ok_or_else(|| invalid("Invalid argument"))?; },
+        }
+        if !i.next() { return Ok(()) }
+    }
+}
+
+const ADDRESS_KEYS: [&str; 12] = ["path", "abstract", "dir", "tmpdir", "runtime", "guid", "host", "bind", "port", "family", "noncefile", "env"];
+
+/// One entry of a parsed server address: the transport name, and its keys with their values.
+pub type AddressEntry = (String, Vec<(String, String)>);
+
+/// Parses a D-Bus server address, e g "unix:path=/run/dbus/system_bus_socket;tcp:host=localhost,port=1234".
+///
+/// Returns the transport name and the well-known keys of each entry, with their unescaped values.
+pub fn address(data: &[u8]) -> Result<Vec<AddressEntry>, Error> {
+    if data.contains(&0) { return Err(invalid("Address contains a nul byte")) }
+    let s = std::str::from_utf8(data).map_err(|_| invalid("Address is not valid UTF-8"))?;
+    let mut e = Error::empty();
+    let mut entries = ptr::null_mut();
+    let mut len = 0;
+    if unsafe { ffi::dbus_parse_address(to_c_str(s).as_ptr(), &mut entries, &mut len, e.get_mut()) } == 0 { return Err(e) }
+    let mut r = vec!();
+    for idx in 0..len as usize {
+        let entry = unsafe { *entries.add(idx) };
+        let method = unsafe { ffi::dbus_address_entry_get_method(entry) };
+        let method = c_str_to_slice(&method).unwrap_or("").to_string();
+        let values = ADDRESS_KEYS.iter().filter_map(|k| {
+            let v = unsafe { ffi::dbus_address_entry_get_value(entry, to_c_str(k).as_ptr()) };
+            c_str_to_slice(&v).map(|v| (k.to_string(), v.to_string()))
+        }).collect();
+        r.push((method, values));
+    }
+    unsafe { ffi::dbus_address_entries_free(entries) };
+    Ok(r)
+}
+
+#[test]
+fn test_fuzz_message() {
+    use crate::arg::Variant;
+    let mut m = Message::new_signal("/fuzz", "com.example.dbusrs.Fuzz", "Fuzzed").unwrap()
+        .append3("Hello", vec!((1u8, 2u32)), Variant(Variant(Variant(5u8))));
+    crate::message::message_set_serial(&mut m, 1);
+    let data = m.marshal().unwrap();
+    let m2 = message(&data).unwrap();
+    assert_eq!(&*m2.member().unwrap(), "Fuzzed");
+
+    assert!(message(&data[..data.len()-1]).is_err());
+    let mut data2 = data.clone();
+    data2.push(0);
+    assert!(message(&data2).is_err());
+    assert!(message(&[]).is_err());
+    assert!(message(&[0xff; 64]).is_err());
+
+    let l = Limits { max_depth: 2, ..Default::default() };
+    assert!(message_with_limits(&data, &l).is_err());
+    let l = Limits { max_args: 4, ..Default::default() };
+    assert!(message_with_limits(&data, &l).is_err());
+    let l = Limits { max_size: 16, ..Default::default() };
+    assert!(message_with_limits(&data, &l).is_err());
+}
+
+#[test]
+fn test_fuzz_address() {
+    let a = address(b"unix:path=/tmp/dbus%20socket,guid=1234;tcp:host=localhost,port=4711").unwrap();
+    assert_eq!(a.len(), 2);
+    assert_eq!(a[0].0, "unix");
+    assert_eq!(a[0].1, vec!(("path".to_string(), "/tmp/dbus socket".to_string()), ("guid".into(), "1234".into())));
+    assert_eq!(a[1].1, vec!(("host".to_string(), "localhost".to_string()), ("port".into(), "4711".into())));
+    assert!(address(b"unix").is_err());
+    assert!(address(b"unix:path=\0").is_err());
+    assert!(address(b"").is_err());
+    assert!(address(&[0xff, b':']).is_err());
+}
@@ -20,6 +20,9 @@ extern crate libc;
 #[allow(missing_docs)]
 extern crate libdbus_sys as ffi;
 
+#[macro_use]
+mod trace;
+
 pub use crate::message::{Message, MessageType};
 
 pub mod message;
@@ -27,7 +30,7 @@ pub mod message;
 pub mod ffidisp;
 
 mod error;
-pub use error::Error;
+pub use error::{Error, ErrorKind};
 
 pub mod channel;
 
@@ -44,6 +47,23 @@ pub mod arg;
 
 pub mod tree;
 
+#[cfg(any(test, feature = "localbus"))]
+pub mod localbus;
+
+#[cfg(any(test, feature = "localbus", feature = "broker"))]
+mod busmethods;
+
+#[cfg(any(test, feature = "testbus"))]
+pub mod testbus;
+
+#[cfg(feature = "broker")]
+pub mod broker;
+
+pub mod metrics;
+
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+
 static INITDBUS: std::sync::Once = std::sync::Once::new();
 
 use std::ffi::{CString, CStr};
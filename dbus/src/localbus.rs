@@ -0,0 +1,406 @@
+//! An in-process message router, for unit testing services without a D-Bus server.
+//!
+//! A `LocalBus` routes messages between the connections attached to it, within the same thread
+//! and without any sockets involved. It implements the parts of the org.freedesktop.DBus interface
+//! that most services need: name registration, match rules for signal delivery, and routing of
+//! method calls and their replies.
+//!
+//! Everything happens synchronously: a blocking method call on one connection dispatches incoming
+//! messages on the other connections, until the reply arrives. Other messages (e g signals) are
+//! dispatched when `LocalBus::process_all` or `Connection::process` is called.
+//!
+//! Not supported: queueing for bus names (RequestName never returns InQueue), activation,
+//! eavesdropping, and match rules with argument matches.
+//!
+//! This module is only available with the `localbus` feature, typically enabled for dev-dependencies only.
+//!
+//! # Example
+//!
+//! ```
+//! use dbus::localbus::LocalBus;
+//! use dbus::tree::Factory;
+//! use std::time::Duration;
+//!
+//! let bus = LocalBus::new();
+//! let server = bus.connect();
+//! server.request_name("com.example.dbustest", false, true, false)?;
+//! let f = Factory::new_fn::<()>();
+//! let tree = f.tree(()).add(f.object_path("/hello", ()).add(f.interface("com.example.dbustest", ())
+//!     .add_m(f.method("Hello", (), |m| Ok(m.msg.method_return().append1("Hello!").into()))
+//!         .outarg::<&str,_>("reply"))
+//! ));
+//! tree.start_receive(&server);
+//!
+//! let client = bus.connect();
+//! let proxy = client.with_proxy("com.example.dbustest", "/hello", Duration::from_secs(1));
+//! let (reply,): (String,) = proxy.method_call("com.example.dbustest", "Hello", ())?;
+//! assert_eq!(reply, "Hello!");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{Message, MessageType, Error};
+use crate::channel::{self, Token};
+use crate::blocking::{BlockingSender, Process, Proxy, MakeSignal};
+use crate::blocking::stdintf::org_freedesktop_dbus::{self, RequestNameReply, ReleaseNameReply};
+use crate::message::{MatchRule, message_set_serial};
+use crate::strings::{BusName, Path};
+use crate::arg::ReadAll;
+use crate::filters::Filters;
+use crate::busmethods::{self, BUS_NAME, NameTable, error_reply};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+type FilterCb = Box<dyn FnMut(Message, &Connection) -> bool + 'static>;
+
+/// An in-process message bus.
+///
+/// Clones refer to the same bus.
+#[derive(Clone, Default)]
+pub struct LocalBus(Rc<BusInner>);
+
+#[derive(Default)]
+struct BusInner {
+    next_id: Cell<u32>,
+    serial: Cell<u32>,
+    conns: RefCell<Vec<Weak<ConnInner>>>,
+    names: RefCell<NameTable>,
+}
+
+/// A connection to a LocalBus.
+///
+/// This works like a blocking `LocalConnection`, except that it never blocks:
+/// waiting means dispatching messages on the other connections of the bus.
+pub struct Connection(Rc<ConnInner>);
+
+struct ConnInner {
+    bus: LocalBus,
+    name: BusName<'static>,
+    serial: Cell<u32>,
+    queue: RefCell<VecDeque<Message>>,
+    // Match rules added with AddMatch
+    matches: RefCell<Vec<MatchRule<'static>>>,
+    filters: RefCell<Filters<FilterCb>>,
+    dispatching: Cell<bool>,
+}
+
+fn copy_message(m: &Message) -> Message { Message::demarshal(&m.marshal().unwrap()).unwrap() }
+
+impl LocalBus {
+    /// Creates a new bus, with no connections attached.
+    pub fn new() -> Self { Default::default() }
+
+    /// Attaches a new connection to the bus.
+    pub fn connect(&self) -> Connection {
+        let id = self.0.next_id.get() + 1;
+        self.0.next_id.set(id);
+        let c = Rc::new(ConnInner {
+            bus: self.clone(),
+            name: BusName::new(format!(":1.{}", id)).unwrap(),
+            serial: Cell::new(0),
+            queue: Default::default(),
+            matches: Default::default(),
+            filters: Default::default(),
+            dispatching: Cell::new(false),
+        });
+        self.0.conns.borrow_mut().push(Rc::downgrade(&c));
+        busmethods::connected(&mut self.clone(), &c.name);
+        Connection(c)
+    }
+
+    /// Dispatches incoming messages on all connections, until there are no more messages to dispatch.
+    ///
+    /// Returns the number of messages dispatched.
+    pub fn process_all(&self) -> usize {
+        let mut count = 0;
+        loop {
+            let n = self.process_round(None);
+            if n == 0 { return count }
+            count += n;
+        }
+    }
+
+    // Dispatches at most one message on each connection except "skip".
+    fn process_round(&self, skip: Option<&BusName>) -> usize {
+        self.connections().into_iter().filter(|c| Some(&c.name) != skip)
+            .filter(|c| Connection(c.clone()).dispatch_one()).count()
+    }
+
+    fn connections(&self) -> Vec<Rc<ConnInner>> {
+        let mut conns = self.0.conns.borrow_mut();
+        conns.retain(|c| c.strong_count() > 0);
+        conns.iter().filter_map(|c| c.upgrade()).collect()
+    }
+
+    fn connection(&self, unique_name: &str) -> Option<Rc<ConnInner>> {
+        self.connections().into_iter().find(|c| &*c.name == unique_name)
+    }
+
+    fn next_serial(&self) -> u32 {
+        let s = self.0.serial.get() + 1;
+        self.0.serial.set(s);
+        s
+    }
+
+    fn send_from_bus(&self, mut m: Message) {
+        m.set_sender(Some(BUS_NAME.into()));
+        message_set_serial(&mut m, self.next_serial());
+        self.route(m);
+    }
+
+    fn route(&self, m: Message) {
+        let dest = match m.destination() {
+            Some(d) => d.into_static(),
+            None => {
+                for c in self.connections() {
+                    if c.matches.borrow().iter().any(|mr| mr.matches(&m)) { c.queue.borrow_mut().push_back(copy_message(&m)) }
+                }
+                return;
+            }
+        };
+        if &*dest == BUS_NAME { return self.handle_bus_call(m) }
+        match busmethods::name_owner(&mut self.clone(), &dest).and_then(|n| self.connection(&n)) {
+            Some(c) => c.queue.borrow_mut().push_back(m),
+            None => if m.msg_type() == MessageType::MethodCall && !m.get_no_reply() {
+                self.send_from_bus(error_reply(&m, "org.freedesktop.DBus.Error.ServiceUnknown", &format!("The name {} is not owned", dest)));
+            }
+        }
+    }
+
+    fn handle_bus_call(&self, m: Message) {
+        if m.msg_type() != MessageType::MethodCall { return }
+        let sender = m.sender().map(|s| s.to_string()).unwrap_or_default();
+        let r = if m.member().as_deref() == Some("Hello") { Ok(m.method_return().append1(&sender)) }
+            else { busmethods::bus_method(&mut self.clone(), &m, &sender) };
+        let r = r.unwrap_or_else(|e| e.to_message(&m));
+        if !m.get_no_reply() { self.send_from_bus(r) }
+    }
+}
+
+impl busmethods::Bus for LocalBus {
+    fn unique_names(&self) -> Vec<String> { self.connections().iter().map(|c| c.name.to_string()).collect() }
+
+    fn with_names<R, F: FnOnce(&mut NameTable) -> R>(&mut self, f: F) -> R { f(&mut self.0.names.borrow_mut()) }
+
+    fn with_matches<R, F: FnOnce(&mut Vec<MatchRule<'static>>) -> R>(&mut self, unique: &str, f: F) -> Option<R> {
+        self.connection(unique).map(|c| f(&mut c.matches.borrow_mut()))
+    }
+
+    fn emit(&mut self, m: Message) { self.send_from_bus(m) }
+}
+
+impl Drop for ConnInner {
+    fn drop(&mut self) { busmethods::disconnected(&mut self.bus.clone(), &self.name) }
+}
+
+impl Connection {
+    /// Get the connection's unique name.
+    pub fn unique_name(&self) -> BusName<'_> { self.0.name.clone() }
+
+    /// The bus this connection is attached to.
+    pub fn bus(&self) -> &LocalBus { &self.0.bus }
+
+    /// Create a convenience struct for easier calling of many methods on the same destination and path.
+    pub fn with_proxy<'a, 'b, D: Into<BusName<'a>>, P: Into<Path<'a>>>(&'b self, dest: D, path: P, timeout: Duration) ->
+    Proxy<'a, &'b Self> {
+        Proxy::new(dest, path, timeout, self)
+    }
+
+    /// Request a name on the bus.
+    ///
+    /// Since there is no queueing, InQueue is never returned.
+    pub fn request_name<'a, N: Into<BusName<'a>>>(&self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+    -> Result<RequestNameReply, Error> {
+        org_freedesktop_dbus::request_name(self, &name.into(), allow_replacement, replace_existing, do_not_queue)
+    }
+
+    /// Release a previously requested name on the bus.
+    pub fn release_name<'a, N: Into<BusName<'a>>>(&self, name: N) -> Result<ReleaseNameReply, Error> {
+        org_freedesktop_dbus::release_name(self, &name.into())
+    }
+
+    /// Adds a new match to the connection, and sets up a callback when this message arrives.
+    ///
+    /// The returned value can be used to remove the match. The match is also removed if the callback
+    /// returns "false".
+    pub fn add_match<S: ReadAll, F>(&self, match_rule: MatchRule<'static>, f: F) -> Result<Token, Error>
+    where F: FnMut(S, &Self, &Message) -> bool + 'static {
+        let m = match_rule.match_str();
+        self.add_match_no_cb(&m)?;
+        use channel::MatchingReceiver;
+        Ok(self.start_receive(match_rule, MakeSignal::make(f, m)))
+    }
+
+    /// Adds a new match to the connection, without setting up a callback when this message arrives.
+    pub fn add_match_no_cb(&self, match_str: &str) -> Result<(), Error> {
+        self.with_proxy(BUS_NAME, "/org/freedesktop/DBus", Duration::from_secs(5)).method_call(BUS_NAME, "AddMatch", (match_str,))
+    }
+
+    /// Removes a match from the connection, without removing any callbacks.
+    pub fn remove_match_no_cb(&self, match_str: &str) -> Result<(), Error> {
+        self.with_proxy(BUS_NAME, "/org/freedesktop/DBus", Duration::from_secs(5)).method_call(BUS_NAME, "RemoveMatch", (match_str,))
+    }
+
+    /// Removes a previously added match and callback from the connection.
+    pub fn remove_match(&self, id: Token) -> Result<(), Error> {
+        use channel::MatchingReceiver;
+        let (mr, _) = self.stop_receive(id).ok_or_else(|| Error::new_failed("No match with that id found"))?;
+        self.remove_match_no_cb(&mr.match_str())
+    }
+
+    /// Dispatches an incoming message.
+    ///
+    /// If there is no incoming message, messages on the other connections are dispatched until
+    /// one arrives. Returns false if nothing arrived.
+    pub fn process(&self) -> bool {
+        loop {
+            if self.dispatch_one() { return true }
+            if self.0.dispatching.get() || self.0.bus.process_round(Some(&self.0.name)) == 0 { return false }
+        }
+    }
+
+    fn dispatch_one(&self) -> bool {
+        if self.0.dispatching.get() { return false }
+        let msg = match self.0.queue.borrow_mut().pop_front() { Some(m) => m, None => return false };
+        self.0.dispatching.set(true);
+        let ff = self.0.filters.borrow_mut().remove_matching(&msg);
+        if let Some(mut ff) = ff {
+            if ff.2(msg, self) { self.0.filters.borrow_mut().insert(ff) }
+        } else if let Some(reply) = channel::default_reply(&msg) {
+            let _ = channel::Sender::send(self, reply);
+        }
+        self.0.dispatching.set(false);
+        true
+    }
+}
+
+impl channel::Sender for Connection {
+    fn send(&self, mut msg: Message) -> Result<u32, ()> {
+        let serial = self.0.serial.get() + 1;
+        self.0.serial.set(serial);
+        msg.set_sender(Some(self.0.name.clone()));
+        message_set_serial(&mut msg, serial);
+        self.0.bus.route(msg);
+        Ok(serial)
+    }
+}
+
+impl BlockingSender for Connection {
+    /// Sends a method call, then dispatches messages on the other connections until the reply arrives.
+    ///
+    /// The timeout is ignored: if there is nothing more to dispatch and still no reply,
+    /// a org.freedesktop.DBus.Error.NoReply error is returned.
+    fn send_with_reply_and_block(&self, msg: Message, _timeout: Duration) -> Result<Message, Error> {
+        let serial = channel::Sender::send(self, msg).unwrap();
+        loop {
+            let reply = {
+                let mut q = self.0.queue.borrow_mut();
+                q.iter().position(|m| m.get_reply_serial() == Some(serial)).and_then(|i| q.remove(i))
+            };
+            if let Some(r) = reply {
+                r.set_error_from_msg()?;
+                return Ok(r);
+            }
+            if self.0.bus.process_round(Some(&self.0.name)) == 0 {
+                return Err(Error::new_custom("org.freedesktop.DBus.Error.NoReply", "Did not receive a reply"));
+            }
+        }
+    }
+}
+
+impl channel::MatchingReceiver for Connection {
+    type F = FilterCb;
+    fn start_receive(&self, m: MatchRule<'static>, f: Self::F) -> Token {
+        self.0.filters.borrow_mut().add(m, f)
+    }
+    fn stop_receive(&self, id: Token) -> Option<(MatchRule<'static>, Self::F)> {
+        self.0.filters.borrow_mut().remove(id)
+    }
+}
+
+impl Process for Connection {
+    fn process_one(&self, _timeout: Duration) -> Result<bool, Error> { Ok(self.process()) }
+
+    fn make_filter<G: FnMut(Message, &Self) -> bool + Send + Sync + 'static>(g: G) -> FilterCb { Box::new(g) }
+}
+
+impl<S: ReadAll, F: FnMut(S, &Connection, &Message) -> bool + 'static> MakeSignal<FilterCb, S, Connection> for F {
+    fn make(mut self, mstr: String) -> FilterCb {
+        Box::new(move |msg: Message, conn: &Connection| {
+            if let Ok(s) = S::read(&mut msg.iter_init()) {
+                if self(s, conn, &msg) { return true };
+                let _ = conn.remove_match_no_cb(&mstr);
+                false
+            } else { true }
+        })
+    }
+}
+
+#[test]
+fn test_localbus() {
+    use crate::tree::Factory;
+    use std::sync::{Arc, Mutex};
+
+    let bus = LocalBus::new();
+    let server = bus.connect();
+    assert_eq!(server.request_name("com.example.dbusrs.LocalBus", false, false, false).unwrap(), RequestNameReply::PrimaryOwner);
+    let f = Factory::new_fn::<()>();
+    let tree = f.tree(()).add(f.object_path("/greeter", ()).add(f.interface("com.example.dbusrs.LocalBus", ())
+        .add_m(f.method("Greet", (), |m| {
+            let name: &str = m.msg.read1()?;
+            let s = Message::new_signal("/greeter", "com.example.dbusrs.LocalBus", "Greeted").unwrap().append1(name);
+            Ok(vec!(m.msg.method_return().append1(format!("Hello {}!", name)), s).into())
+        }))
+    ));
+    tree.start_receive(&server);
+
+    let client = bus.connect();
+    let listener = bus.connect();
+    let bystander = bus.connect();
+    let greeted = Arc::new(Mutex::new(vec!()));
+    let g2 = greeted.clone();
+    let mr = MatchRule::new_signal("com.example.dbusrs.LocalBus", "Greeted");
+    listener.add_match(mr, move |(name,): (String,), _, _| { g2.lock().unwrap().push(name); true }).unwrap();
+    let other = Arc::new(Mutex::new(0));
+    let o2 = other.clone();
+    // Everyone has been sent NameAcquired for its unique name, like on a real bus.
+    bus.process_all();
+    channel::MatchingReceiver::start_receive(&bystander, MatchRule::new(), Box::new(move |_, _| { *o2.lock().unwrap() += 1; true }));
+
+    let p = client.with_proxy("com.example.dbusrs.LocalBus", "/greeter", Duration::from_secs(1));
+    let (r,): (String,) = p.method_call("com.example.dbusrs.LocalBus", "Greet", ("world",)).unwrap();
+    assert_eq!(r, "Hello world!");
+    bus.process_all();
+    assert_eq!(*greeted.lock().unwrap(), vec!("world".to_string()));
+    assert_eq!(*other.lock().unwrap(), 0);
+
+    // Errors
+    let e = p.method_call::<(), _, _, _>("com.example.dbusrs.LocalBus", "Wave", ()).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.UnknownMethod"));
+    let p2 = client.with_proxy("com.example.dbusrs.Nobody", "/", Duration::from_secs(1));
+    let e = p2.method_call::<(), _, _, _>("com.example.dbusrs.Nobody", "Hello", ()).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+
+    // Names
+    assert_eq!(client.request_name("com.example.dbusrs.LocalBus", false, true, false).unwrap(), RequestNameReply::Exists);
+    assert_eq!(client.release_name("com.example.dbusrs.LocalBus").unwrap(), ReleaseNameReply::NotOwner);
+    let names: (Vec<String>,) = client.with_proxy(BUS_NAME, "/", Duration::from_secs(1)).method_call(BUS_NAME, "ListNames", ()).unwrap();
+    assert!(names.0.contains(&"com.example.dbusrs.LocalBus".to_string()));
+    let owned = Arc::new(Mutex::new(vec!()));
+    let o3 = owned.clone();
+    let mut mr = MatchRule::new();
+    mr.interface = Some(BUS_NAME.into());
+    channel::MatchingReceiver::start_receive(&client, mr, Box::new(move |m, _| {
+        let name: &str = m.read1().unwrap();
+        if !name.starts_with(':') { o3.lock().unwrap().push(format!("{} {}", m.member().unwrap(), name)) }
+        true
+    }));
+    assert_eq!(client.request_name("com.example.dbusrs.Other", false, false, false).unwrap(), RequestNameReply::PrimaryOwner);
+    assert_eq!(client.release_name("com.example.dbusrs.Other").unwrap(), ReleaseNameReply::Released);
+    bus.process_all();
+    assert_eq!(*owned.lock().unwrap(), vec!("NameAcquired com.example.dbusrs.Other", "NameLost com.example.dbusrs.Other"));
+    drop(server);
+    let e = p.method_call::<(String,), _, _, _>("com.example.dbusrs.LocalBus", "Greet", ("world",)).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+}
@@ -1,6 +1,7 @@
 //! Contains structs and traits closely related to D-Bus messages.
 
 use std::{fmt, ptr};
+use std::time::Instant;
 use super::{ffi, Error, libc, to_c_str, c_str_to_slice, init_dbus};
 use crate::strings::{BusName, Path, Interface, Member, ErrorName};
 use std::ffi::CStr;
@@ -26,11 +27,24 @@ pub use self::signalargs::SignalArgs;
 mod matchrule;
 pub use self::matchrule::MatchRule;
 
+mod log;
+pub use self::log::{MessageLog, LoggedMessage, Direction};
+
+mod latency;
+pub use self::latency::{LatencyTracker, LatencySummary, CallKey};
+
+mod template;
+pub use self::template::MessageTemplate;
+
+mod diff;
+pub use self::diff::MessageDiff;
+
 
 /// A D-Bus message. A message contains headers - usually destination address, path, interface and member,
 /// and a list of arguments.
 pub struct Message {
     msg: *mut ffi::DBusMessage,
+    received: Option<Instant>,
 }
 
 unsafe impl Send for Message {}
@@ -45,7 +59,7 @@ impl Message {
             ffi::dbus_message_new_method_call(d.as_ref().as_ptr(), p.as_ref().as_ptr(), i.as_ref().as_ptr(), m.as_ref().as_ptr())
         };
         if ptr.is_null() { Err("D-Bus error: dbus_message_new_method_call failed".into()) }
-        else { Ok(Message { msg: ptr}) }
+        else { Ok(Message { msg: ptr, received: None }) }
     }
 
     /// Creates a new method call message.
@@ -56,7 +70,7 @@ impl Message {
                 iface.as_ref().as_ptr(), name.as_ref().as_ptr())
         };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_method_call failed") }
-        Message { msg: ptr}
+        Message { msg: ptr, received: None }
     }
 
     /// Creates a new method call message.
@@ -81,7 +95,7 @@ impl Message {
             ffi::dbus_message_new_signal(p.as_ref().as_ptr(), i.as_ref().as_ptr(), m.as_ref().as_ptr())
         };
         if ptr.is_null() { Err("D-Bus error: dbus_message_new_signal failed".into()) }
-        else { Ok(Message { msg: ptr}) }
+        else { Ok(Message { msg: ptr, received: None }) }
     }
 
     /// Creates a new signal message.
@@ -91,20 +105,20 @@ impl Message {
             ffi::dbus_message_new_signal(path.as_ref().as_ptr(), iface.as_ref().as_ptr(), name.as_ref().as_ptr())
         };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_signal failed") }
-        Message { msg: ptr}
+        Message { msg: ptr, received: None }
     }
 
     /// Creates a method reply for this method call.
     pub fn new_method_return(m: &Message) -> Option<Message> {
         let ptr = unsafe { ffi::dbus_message_new_method_return(m.msg) };
-        if ptr.is_null() { None } else { Some(Message { msg: ptr} ) }
+        if ptr.is_null() { None } else { Some(Message { msg: ptr, received: None } ) }
     }
 
     /// Creates a method return (reply) for this method call.
     pub fn method_return(&self) -> Message {
         let ptr = unsafe { ffi::dbus_message_new_method_return(self.msg) };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_method_return failed") }
-        Message {msg: ptr}
+        Message { msg: ptr, received: None }
     }
 
     /// The old way to create a new error reply
@@ -112,14 +126,14 @@ impl Message {
     pub fn new_error(m: &Message, error_name: &str, error_message: &str) -> Option<Message> {
         let (en, em) = (to_c_str(error_name), to_c_str(error_message));
         let ptr = unsafe { ffi::dbus_message_new_error(m.msg, en.as_ptr(), em.as_ptr()) };
-        if ptr.is_null() { None } else { Some(Message { msg: ptr} ) }
+        if ptr.is_null() { None } else { Some(Message { msg: ptr, received: None } ) }
     }
 
     /// Creates a new error reply
     pub fn error(&self, error_name: &ErrorName, error_message: &CStr) -> Message {
         let ptr = unsafe { ffi::dbus_message_new_error(self.msg, error_name.as_ref().as_ptr(), error_message.as_ptr()) };
         if ptr.is_null() { panic!("D-Bus error: dbus_message_new_error failed") }
-        Message { msg: ptr}
+        Message { msg: ptr, received: None }
     }
 
     /// Get the MessageItems that make up the message.
@@ -139,6 +153,12 @@ impl Message {
         if x == 0 { None } else { Some(x) }
     }
 
+    /// Returns the time this message was received, or None if it was not received from a connection.
+    ///
+    /// This is taken from a monotonic clock as the channel takes the message from the connection's incoming queue,
+    /// which is usually right after reading it from the socket. Use it to measure latency, e g with `elapsed()`.
+    pub fn timestamp(&self) -> Option<Instant> { self.received }
+
     /// Get the serial of the message this message is a reply to, if present.
     pub fn get_reply_serial(&self) -> Option<u32> {
         let s = unsafe { ffi::dbus_message_get_reply_serial(self.msg) };
@@ -165,6 +185,19 @@ impl Message {
         unsafe { ffi::dbus_message_set_auto_start(self.msg, if v { 1 } else { 0 }) }
     }
 
+    /// Returns true if the caller is prepared to wait for an interactive authorization
+    /// (e g a password prompt) while the method call is handled.
+    pub fn get_allow_interactive_authorization(&self) -> bool {
+        unsafe { ffi::dbus_message_get_allow_interactive_authorization(self.msg) != 0 }
+    }
+
+    /// Sets whether or not the caller is prepared to wait for an interactive authorization.
+    ///
+    /// Defaults to false.
+    pub fn set_allow_interactive_authorization(&mut self, v: bool) {
+        unsafe { ffi::dbus_message_set_allow_interactive_authorization(self.msg, if v { 1 } else { 0 }) }
+    }
+
     /// Add one or more MessageItems to this Message.
     ///
     /// Note: using `append1`, `append2` or `append3` might be faster, especially for large arrays.
@@ -381,6 +414,14 @@ impl Message {
         assert!(unsafe { ffi::dbus_message_set_destination(self.msg, c_dest) } != 0);
     }
 
+    /// Sets the sender of this Message.
+    ///
+    /// This is normally done by the D-Bus server, so you only need this if you are implementing one.
+    pub fn set_sender(&mut self, sender: Option<BusName>) {
+        let c_sender = sender.as_ref().map(|d| d.as_cstr().as_ptr()).unwrap_or(ptr::null());
+        assert!(unsafe { ffi::dbus_message_set_sender(self.msg, c_sender) } != 0);
+    }
+
     /// Gets the interface this Message is being sent to.
     pub fn interface(&self) -> Option<Interface> {
         self.msg_internal_str(unsafe { ffi::dbus_message_get_interface(self.msg) })
@@ -401,10 +442,37 @@ impl Message {
         self.set_error_from_msg().map(|_| self)
     }
 
+    /// Serializes the message into the D-Bus wire format.
+    ///
+    /// The resulting bytes can be turned back into a message with `demarshal`.
+    pub fn marshal(&self) -> Result<Vec<u8>, Error> {
+        let mut p = ptr::null_mut();
+        let mut len = 0;
+        if unsafe { ffi::dbus_message_marshal(self.msg, &mut p, &mut len) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Failed to marshal message"));
+        }
+        let r = unsafe { std::slice::from_raw_parts(p as *const u8, len as usize) }.to_vec();
+        unsafe { ffi::dbus_free(p as *mut libc::c_void) };
+        Ok(r)
+    }
+
+    /// Creates a message from bytes in the D-Bus wire format, e g as returned by `marshal`.
+    pub fn demarshal(data: &[u8]) -> Result<Message, Error> {
+        let mut e = Error::empty();
+        let p = unsafe { ffi::dbus_message_demarshal(data.as_ptr() as *const libc::c_char, data.len() as libc::c_int, e.get_mut()) };
+        if p.is_null() { Err(e) } else { Ok(Message::from_ptr(p, false)) }
+    }
+
     pub (crate) fn set_error_from_msg(&self) -> Result<(), Error> {
         let mut e = Error::empty();
-        if unsafe { ffi::dbus_set_error_from_message(e.get_mut(), self.msg) } != 0 { Err(e) }
-        else { Ok(()) }
+        if unsafe { ffi::dbus_set_error_from_message(e.get_mut(), self.msg) } == 0 { return Ok(()) }
+        // The first argument, if it is a string, is the error message. Keep the rest.
+        let mut i = self.iter_init();
+        if i.arg_type() == crate::arg::ArgType::String { i.next(); }
+        let mut args = vec!();
+        while let Some(a) = crate::arg::messageitem::MessageItem::get(&mut i) { args.push(a); i.next(); }
+        e.set_args(args);
+        Err(e)
     }
 
     pub (crate) fn ptr(&self) -> *mut ffi::DBusMessage { self.msg }
@@ -413,7 +481,7 @@ impl Message {
         if add_ref {
             unsafe { ffi::dbus_message_ref(ptr) };
         }
-        Message { msg: ptr }
+        Message { msg: ptr, received: None }
     }
 
 }
@@ -450,8 +518,9 @@ impl fmt::Debug for Message {
     }
 }
 
-// For purpose of testing the library only.
-#[cfg(test)]
+// For purpose of testing the library, and for localbus, which acts as the D-Bus server.
+pub (crate) fn message_set_received(m: &mut Message, t: Instant) { m.received = Some(t); }
+
 pub (crate) fn message_set_serial(m: &mut Message, s: u32) {
     unsafe { ffi::dbus_message_set_serial(m.msg, s) };
 }
@@ -470,5 +539,36 @@ mod test {
         assert!(!m.get_no_reply());
         m.set_no_reply(true);
         assert!(m.get_no_reply());
+
+        assert!(!m.get_allow_interactive_authorization());
+        m.set_allow_interactive_authorization(true);
+        assert!(m.get_allow_interactive_authorization());
+    }
+
+    #[test]
+    fn marshal_roundtrip() {
+        let mut m = Message::new_method_call("org.test.rust", "/marshal", "org.test.rust", "Test").unwrap().append2("Hello", 5u32);
+        super::message_set_serial(&mut m, 7);
+        let data = m.marshal().unwrap();
+        let m2 = Message::demarshal(&data).unwrap();
+        assert_eq!(&*m2.path().unwrap(), "/marshal");
+        assert_eq!(m2.get_serial(), Some(7));
+        assert_eq!(m2.read2::<&str, u32>().unwrap(), ("Hello", 5));
+        assert!(Message::demarshal(&data[..10]).is_err());
+    }
+
+    #[test]
+    fn receive_timestamp() {
+        use crate::channel::{Channel, BusType};
+        use std::time::{Duration, Instant};
+        assert!(Message::new_signal("/", "com.example.dbusrs", "Test").unwrap().timestamp().is_none());
+        let before = Instant::now();
+        let c = Channel::get_private(BusType::Session).unwrap();
+        let m = c.blocking_pop_message(Duration::from_secs(5)).unwrap().unwrap();
+        let t = m.timestamp().unwrap();
+        assert!(t >= before && t <= Instant::now());
+        let ping = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus.Peer", "Ping").unwrap();
+        let r = c.send_with_reply_and_block(ping, Duration::from_secs(5)).unwrap();
+        assert!(r.timestamp().unwrap() >= t);
     }
 }
@@ -0,0 +1,143 @@
+// Comparing messages in tests, with readable output of what differs.
+
+use super::Message;
+use crate::arg::messageitem::MessageItem;
+use std::fmt;
+
+/// The differences between two messages, from `Message::diff`. Displays as one line per difference.
+///
+/// The serial number is not compared, since the connection sets it when the message is sent.
+/// The reply serial is, as it tells which call a reply belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MessageDiff(Vec<String>);
+
+impl MessageDiff {
+    /// Returns true if no differences were found.
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// The differences, e g `member: "Foo" != "Bar"` or `arg 1[2].0: Int32(5) != Int32(6)`.
+    pub fn iter(&self) -> impl Iterator<Item=&str> { self.0.iter().map(|s| &**s) }
+
+    fn item(&mut self, at: &str, a: &MessageItem, b: &MessageItem) {
+        match (a, b) {
+            (MessageItem::Struct(x), MessageItem::Struct(y)) if x.len() == y.len() => {
+                for (i, (xx, yy)) in x.iter().zip(y).enumerate() { self.item(&format!("{}.{}", at, i), xx, yy) }
+            }
+            (MessageItem::Array(x), MessageItem::Array(y)) if x.signature() == y.signature() => {
+                if x.len() != y.len() { self.0.push(format!("{}: {} elements != {} elements", at, x.len(), y.len())) }
+                for (i, (xx, yy)) in x.iter().zip(y.iter()).enumerate() { self.item(&format!("{}[{}]", at, i), xx, yy) }
+            }
+            (MessageItem::Dict(x), MessageItem::Dict(y)) if x.signature() == y.signature() => {
+                for (k, xv) in x.iter() {
+                    match y.iter().find(|(kk, _)| kk == k) {
+                        Some((_, yv)) => self.item(&format!("{}[{:?}]", at, k), xv, yv),
+                        None => self.0.push(format!("{}[{:?}]: {:?} != missing", at, k, xv)),
+                    }
+                }
+                for (k, yv) in y.iter().filter(|(k, _)| !x.iter().any(|(kk, _)| kk == k)) {
+                    self.0.push(format!("{}[{:?}]: missing != {:?}", at, k, yv))
+                }
+            }
+            (MessageItem::Variant(x), MessageItem::Variant(y)) => self.item(at, x, y),
+            _ => if a != b { self.0.push(format!("{}: {:?} != {:?}", at, a, b)) },
+        }
+    }
+}
+
+impl fmt::Display for MessageDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for s in &self.0 { writeln!(f, "{}", s)?; }
+        Ok(())
+    }
+}
+
+impl Message {
+    /// Compares the headers and arguments of two messages.
+    ///
+    /// Differing arguments are compared field by field and element by element, so that
+    /// the result points to what differs, rather than just saying that the messages do.
+    /// See also `assert_msg_eq!`.
+    pub fn diff(&self, other: &Message) -> MessageDiff {
+        let mut d = MessageDiff::default();
+        macro_rules! header { ($name: expr, $a: expr, $b: expr) => {
+            let (a, b) = ($a, $b);
+            if a != b { d.0.push(format!("{}: {:?} != {:?}", $name, a, b)) }
+        }}
+        header!("type", self.msg_type(), other.msg_type());
+        header!("path", self.path().map(|x| x.to_string()), other.path().map(|x| x.to_string()));
+        header!("interface", self.interface().map(|x| x.to_string()), other.interface().map(|x| x.to_string()));
+        header!("member", self.member().map(|x| x.to_string()), other.member().map(|x| x.to_string()));
+        header!("sender", self.sender().map(|x| x.to_string()), other.sender().map(|x| x.to_string()));
+        header!("destination", self.destination().map(|x| x.to_string()), other.destination().map(|x| x.to_string()));
+        let error_name = |m: &Message| crate::c_str_to_slice(&unsafe { crate::ffi::dbus_message_get_error_name(m.msg) }).map(|x| x.to_string());
+        header!("error name", error_name(self), error_name(other));
+        header!("reply serial", self.get_reply_serial(), other.get_reply_serial());
+        let (a, b) = (self.get_items(), other.get_items());
+        let sig = |v: &[MessageItem]| v.iter().map(|x| x.signature().to_string()).collect::<String>();
+        header!("signature", sig(&a), sig(&b));
+        for (i, (x, y)) in a.iter().zip(&b).enumerate() { d.item(&format!("arg {}", i), x, y) }
+        d
+    }
+}
+
+/// Asserts that two messages have the same headers and arguments, see `Message::diff`.
+///
+/// On failure, the panic message lists what differs.
+///
+/// # Example
+/// ```rust
+/// # #[macro_use] extern crate dbus;
+/// # fn main() {
+/// use dbus::Message;
+/// let a = Message::new_signal("/a", "com.example", "Changed").unwrap().append1(vec!(1, 2, 3));
+/// let b = Message::new_signal("/a", "com.example", "Changed").unwrap().append1(vec!(1, 2, 3));
+/// assert_msg_eq!(a, b);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_msg_eq {
+    ($left: expr, $right: expr $(,)?) => {{
+        let d = $crate::Message::diff(&$left, &$right);
+        if !d.is_empty() { panic!("assertion failed: messages differ\n{}", d) }
+    }};
+}
+
+#[test]
+fn test_diff() {
+    use crate::arg::{Variant, Dict};
+    let m = |member: &str, v: Vec<(i32, &str)>, d: &[(&str, u8)]| {
+        let d = Dict::new(d.iter().map(|(k, v)| (*k, Variant(*v))));
+        Message::new_signal("/diff", "com.example.Diff", member).unwrap().append2(v, d)
+    };
+    let a = m("Changed", vec!((1, "a"), (2, "b")), &[("x", 1), ("y", 2)]);
+    assert!(a.diff(&m("Changed", vec!((1, "a"), (2, "b")), &[("x", 1), ("y", 2)])).is_empty());
+    assert_msg_eq!(a, m("Changed", vec!((1, "a"), (2, "b")), &[("x", 1), ("y", 2)]));
+
+    let d = a.diff(&m("Removed", vec!((1, "a"), (2, "c"), (3, "d")), &[("x", 5), ("z", 2)]));
+    assert_eq!(d.iter().collect::<Vec<_>>(), vec!(
+        r#"member: Some("Changed") != Some("Removed")"#,
+        "arg 0: 2 elements != 3 elements",
+        r#"arg 0[1].1: Str("b") != Str("c")"#,
+        r#"arg 1[Str("x")]: Byte(1) != Byte(5)"#,
+        r#"arg 1[Str("y")]: Variant(Byte(2)) != missing"#,
+        r#"arg 1[Str("z")]: missing != Variant(Byte(2))"#,
+    ));
+    assert!(d.to_string().ends_with("missing != Variant(Byte(2))\n"));
+
+    let b = Message::new_signal("/diff", "com.example.Diff", "Changed").unwrap().append1(5u32);
+    assert_eq!(a.diff(&b).iter().next(), Some(r#"signature: "a(is)a{sv}" != "u""#));
+    assert!(std::panic::catch_unwind(|| assert_msg_eq!(a, b)).is_err());
+
+    let call = |serial| {
+        let mut c = Message::new_method_call("com.example.Diff", "/diff", "com.example.Diff", "Get").unwrap();
+        super::message_set_serial(&mut c, serial);
+        c
+    };
+    let (call, call2) = (call(5), call(6));
+    let e = |c: &Message, name: &str| c.error(&name.into(), &std::ffi::CString::new("").unwrap());
+    assert_msg_eq!(e(&call, "com.example.Error.A"), e(&call, "com.example.Error.A"));
+    assert_eq!(e(&call, "com.example.Error.A").diff(&e(&call, "com.example.Error.B")).iter().collect::<Vec<_>>(),
+        vec!(r#"error name: Some("com.example.Error.A") != Some("com.example.Error.B")"#));
+    assert_eq!(call.method_return().diff(&call2.method_return()).iter().collect::<Vec<_>>(),
+        vec!("reply serial: Some(5) != Some(6)"));
+}
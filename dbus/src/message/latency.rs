@@ -0,0 +1,180 @@
+use super::{Message, MessageType, Direction};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::fmt;
+
+/// The method a latency is recorded for: destination, interface and member of the method call.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CallKey {
+    /// The destination of the method call, as given by the caller (often a well-known name).
+    pub destination: String,
+    /// The interface of the method call, or an empty string if it had none.
+    pub interface: String,
+    /// The name of the method.
+    pub member: String,
+}
+
+impl fmt::Display for CallKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{} {}.{}", self.destination, self.interface, self.member) }
+}
+
+/// Round-trip times of the method calls to one method, see `LatencyTracker::summary`.
+///
+/// The percentiles are computed from the most recent calls only, see `LatencyTracker::new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// The number of calls that got a reply, since the tracker was created or cleared.
+    pub count: u64,
+    /// The number of those replies that were errors (including timeouts).
+    pub errors: u64,
+    /// The shortest round-trip time.
+    pub min: Duration,
+    /// The longest round-trip time.
+    pub max: Duration,
+    /// The median round-trip time.
+    pub p50: Duration,
+    /// The 90th percentile of the round-trip times.
+    pub p90: Duration,
+    /// The 99th percentile of the round-trip times.
+    pub p99: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    count: u64,
+    errors: u64,
+    min: Option<Duration>,
+    max: Duration,
+    recent: VecDeque<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: HashMap<u32, (CallKey, Instant)>,
+    calls: BTreeMap<CallKey, Samples>,
+}
+
+// Method calls that never get a reply should not make the table grow forever.
+const MAX_IN_FLIGHT: usize = 4096;
+
+/// Keeps track of how long method calls take to be answered, per destination and method.
+///
+/// Useful for finding out which services are slowing an application down. Attach it to a channel
+/// with `Channel::set_latency_tracker`, and call `summary` to see the percentiles. The round-trip time
+/// is measured from sending the method call until its reply is read from the connection.
+///
+/// Since replies are matched to method calls by serial number, a tracker should only be attached to one channel.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::sync::Arc;
+/// use dbus::channel::{Channel, BusType};
+/// use dbus::message::LatencyTracker;
+///
+/// let tracker = Arc::new(LatencyTracker::new(1000));
+/// let mut channel = Channel::get_private(BusType::Session).unwrap();
+/// channel.set_latency_tracker(Some(tracker.clone()));
+/// let c = dbus::blocking::Connection::from(channel);
+/// // ...
+/// for (call, s) in tracker.summary() { println!("{}: {:?} (p99 {:?})", call, s.p50, s.p99); }
+/// ```
+#[derive(Debug)]
+pub struct LatencyTracker {
+    samples: usize,
+    state: Mutex<State>,
+}
+
+impl LatencyTracker {
+    /// Creates a new tracker that computes percentiles from the last `samples` calls to each method.
+    pub fn new(samples: usize) -> Self { LatencyTracker { samples: samples.max(1), state: Default::default() } }
+
+    /// Records a sent method call or a received reply. Other messages are ignored.
+    pub fn record(&self, direction: Direction, msg: &Message) {
+        match (direction, msg.msg_type()) {
+            (Direction::Sent, MessageType::MethodCall) if !msg.get_no_reply() => {
+                let serial = match msg.get_serial() { Some(s) => s, None => return };
+                let key = CallKey {
+                    destination: msg.destination().map(|d| d.to_string()).unwrap_or_default(),
+                    interface: msg.interface().map(|i| i.to_string()).unwrap_or_default(),
+                    member: msg.member().map(|m| m.to_string()).unwrap_or_default(),
+                };
+                let mut s = self.state.lock().unwrap();
+                if s.in_flight.len() >= MAX_IN_FLIGHT {
+                    let oldest = s.in_flight.iter().min_by_key(|(_, (_, t))| *t).map(|(serial, _)| *serial);
+                    if let Some(oldest) = oldest { s.in_flight.remove(&oldest); }
+                }
+                s.in_flight.insert(serial, (key, Instant::now()));
+            },
+            (Direction::Received, MessageType::MethodReturn) | (Direction::Received, MessageType::Error) => {
+                let serial = match msg.get_reply_serial() { Some(s) => s, None => return };
+                let mut s = self.state.lock().unwrap();
+                let (key, sent) = match s.in_flight.remove(&serial) { Some(x) => x, None => return };
+                let rtt = msg.timestamp().unwrap_or_else(Instant::now).saturating_duration_since(sent);
+                let e = s.calls.entry(key).or_default();
+                e.count += 1;
+                if msg.msg_type() == MessageType::Error { e.errors += 1; }
+                e.min = Some(e.min.map_or(rtt, |m| m.min(rtt)));
+                e.max = e.max.max(rtt);
+                if e.recent.len() >= self.samples { e.recent.pop_front(); }
+                e.recent.push_back(rtt);
+            },
+            _ => {},
+        }
+    }
+
+    /// Returns the round-trip times of all methods that have been called, sorted by destination, interface and method.
+    pub fn summary(&self) -> Vec<(CallKey, LatencySummary)> {
+        self.state.lock().unwrap().calls.iter().map(|(k, v)| (k.clone(), summarize(v))).collect()
+    }
+
+    /// Returns the round-trip times of one method, or None if it has not been called (and answered).
+    pub fn get(&self, destination: &str, interface: &str, member: &str) -> Option<LatencySummary> {
+        let key = CallKey { destination: destination.into(), interface: interface.into(), member: member.into() };
+        self.state.lock().unwrap().calls.get(&key).map(summarize)
+    }
+
+    /// Forgets all recorded round-trip times.
+    pub fn clear(&self) { self.state.lock().unwrap().calls.clear() }
+}
+
+fn summarize(s: &Samples) -> LatencySummary {
+    let mut sorted: Vec<_> = s.recent.iter().copied().collect();
+    sorted.sort();
+    // Nearest-rank percentile
+    let p = |n: usize| sorted[(sorted.len() * n).saturating_sub(1) / 100];
+    LatencySummary { count: s.count, errors: s.errors, min: s.min.unwrap_or_default(), max: s.max, p50: p(50), p90: p(90), p99: p(99) }
+}
+
+#[test]
+fn test_latency_tracker() {
+    use crate::blocking::Connection;
+    use crate::channel::{Channel, BusType};
+    use std::sync::Arc;
+
+    let tracker = Arc::new(LatencyTracker::new(10));
+    let mut ch = Channel::get_private(BusType::Session).unwrap();
+    ch.set_latency_tracker(Some(tracker.clone()));
+    let c = Connection::from(ch);
+    let p = c.with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(5));
+    for _ in 0..20 {
+        let _: (bool,) = p.method_call("org.freedesktop.DBus", "NameHasOwner", ("com.example.dbusrs.nonexistent",)).unwrap();
+    }
+    let p = c.with_proxy("com.example.dbusrs.nonexistent", "/", Duration::from_secs(5));
+    assert!(p.method_call::<(), _, _, _>("com.example.dbusrs.Latency", "Hello", ()).is_err());
+
+    let s = tracker.get("org.freedesktop.DBus", "org.freedesktop.DBus", "NameHasOwner").unwrap();
+    assert_eq!((s.count, s.errors), (20, 0));
+    assert!(s.min <= s.p50 && s.p50 <= s.p90 && s.p90 <= s.p99 && s.p99 <= s.max);
+    assert!(s.max < Duration::from_secs(5));
+    let e = tracker.get("com.example.dbusrs.nonexistent", "com.example.dbusrs.Latency", "Hello").unwrap();
+    assert_eq!((e.count, e.errors), (1, 1));
+    assert_eq!(e.p50, e.max);
+
+    let all = tracker.summary();
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[0].0.to_string(), "com.example.dbusrs.nonexistent com.example.dbusrs.Latency.Hello");
+    tracker.clear();
+    assert!(tracker.summary().is_empty());
+}
@@ -0,0 +1,171 @@
+use super::Message;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant};
+use std::fmt::Write;
+
+/// Whether a logged message was sent or received.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The message was sent by us.
+    Sent,
+    /// The message was received from the other side.
+    Received,
+}
+
+/// One entry in a MessageLog.
+#[derive(Debug, Clone)]
+pub struct LoggedMessage {
+    /// Time since the log was created.
+    pub elapsed: Duration,
+    /// Whether the message was sent or received.
+    pub direction: Direction,
+    /// The message in human readable form (the same as its Debug output).
+    pub text: String,
+    /// The message in D-Bus wire format, if the log was set up to record raw messages.
+    pub raw: Option<Vec<u8>>,
+}
+
+/// An in-memory ring buffer of the last sent and received messages.
+///
+/// Useful for diagnosing intermittent protocol issues after the fact. Attach it to a connection
+/// with `Channel::set_message_log`, then call `dump` (or `dump_on_panic`) to see what happened.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::sync::Arc;
+/// use dbus::channel::{Channel, BusType};
+/// use dbus::message::MessageLog;
+///
+/// let log = Arc::new(MessageLog::new(100));
+/// log.dump_on_panic();
+/// let mut channel = Channel::get_private(BusType::Session).unwrap();
+/// channel.set_message_log(Some(log.clone()));
+/// let c = dbus::blocking::Connection::from(channel);
+/// // ...
+/// println!("{}", log.dump());
+/// ```
+#[derive(Debug)]
+pub struct MessageLog {
+    capacity: usize,
+    raw: bool,
+    start: Instant,
+    entries: Mutex<VecDeque<LoggedMessage>>,
+}
+
+impl MessageLog {
+    /// Creates a new log that keeps the last `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        MessageLog { capacity, raw: false, start: Instant::now(), entries: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    /// Also record the messages in D-Bus wire format. This is off by default.
+    pub fn with_raw(mut self, raw: bool) -> Self { self.raw = raw; self }
+
+    /// The maximum number of messages kept.
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    /// Adds a message to the log, throwing away the oldest one if the log is full.
+    pub fn record(&self, direction: Direction, msg: &Message) {
+        if self.capacity == 0 { return }
+        let entry = LoggedMessage {
+            elapsed: self.start.elapsed(),
+            direction,
+            text: format!("{:?}", msg),
+            raw: if self.raw { msg.marshal().ok() } else { None },
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity { entries.pop_front(); }
+        entries.push_back(entry);
+    }
+
+    /// Returns the logged messages, oldest first.
+    pub fn entries(&self) -> Vec<LoggedMessage> { self.entries.lock().unwrap().iter().cloned().collect() }
+
+    /// Removes all logged messages.
+    pub fn clear(&self) { self.entries.lock().unwrap().clear() }
+
+    /// Returns the logged messages as text, one line per message, oldest first.
+    pub fn dump(&self) -> String { format_entries(&self.entries.lock().unwrap()) }
+
+    // The text printed by the panic hook, or None if the log stays locked, e g by the panicking thread.
+    fn panic_dump(&self) -> Option<String> {
+        // Others hold the lock only briefly, but waiting for this thread to release it would never end.
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let entries = loop {
+            match self.entries.try_lock() {
+                Ok(g) => break g,
+                // Don't panic inside the panic hook because of another panic.
+                Err(TryLockError::Poisoned(p)) => break p.into_inner(),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => std::thread::yield_now(),
+                Err(TryLockError::WouldBlock) => return None,
+            }
+        };
+        Some(format!("Last {} D-Bus messages:\n{}", entries.len(), format_entries(&entries)))
+    }
+
+    /// Installs a panic hook that prints the log to stderr, then calls the previous panic hook.
+    pub fn dump_on_panic(self: &Arc<Self>) {
+        let log = self.clone();
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Some(s) = log.panic_dump() { eprintln!("{}", s) }
+            prev(info)
+        }));
+    }
+}
+
+fn format_entries(entries: &VecDeque<LoggedMessage>) -> String {
+    let mut r = String::new();
+    for e in entries.iter() {
+        let d = match e.direction { Direction::Sent => "->", Direction::Received => "<-" };
+        let _ = write!(r, "[{:>4}.{:03}] {} {}", e.elapsed.as_secs(), e.elapsed.subsec_millis(), d, e.text);
+        if let Some(raw) = &e.raw {
+            r.push_str(" Raw: ");
+            for b in raw { let _ = write!(r, "{:02x}", b); }
+        }
+        r.push('\n');
+    }
+    r
+}
+
+#[test]
+fn test_message_log() {
+    use crate::blocking::Connection;
+    use crate::channel::{Channel, BusType};
+
+    let log = Arc::new(MessageLog::new(2).with_raw(true));
+    let mut ch = Channel::get_private(BusType::Session).unwrap();
+    ch.set_message_log(Some(log.clone()));
+    let c = Connection::from(ch);
+    let p = c.with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(5));
+    let (has_owner,): (bool,) = p.method_call("org.freedesktop.DBus", "NameHasOwner", ("com.example.dbusrs.nonexistent",)).unwrap();
+    assert!(!has_owner);
+
+    let e = log.entries();
+    assert_eq!(e.len(), 2);
+    assert_eq!(e[0].direction, Direction::Sent);
+    assert!(e[0].text.contains("NameHasOwner"));
+    assert_eq!(e[1].direction, Direction::Received);
+    let reply = Message::demarshal(e[1].raw.as_ref().unwrap()).unwrap();
+    assert_eq!(reply.read1::<bool>().unwrap(), false);
+    assert_eq!(log.dump().lines().count(), 2);
+
+    let _: (bool,) = p.method_call("org.freedesktop.DBus", "NameHasOwner", ("com.example.dbusrs.nonexistent",)).unwrap();
+    assert_eq!(log.entries().len(), 2);
+    // The panic hook waits for a short while if someone else is recording.
+    std::thread::scope(|s| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let log = &log;
+        s.spawn(move || {
+            let _g = log.entries.lock().unwrap();
+            tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        });
+        rx.recv().unwrap();
+        assert!(log.panic_dump().unwrap().starts_with("Last 2 D-Bus messages:\n"));
+    });
+    log.clear();
+    assert!(log.dump().is_empty());
+}
@@ -1,4 +1,4 @@
-use crate::{Message, MessageType};
+use crate::{Message, MessageType, Error};
 use crate::strings::{BusName, Path, Interface, Member};
 
 #[derive(Clone, Debug, Default)]
@@ -95,6 +95,54 @@ impl<'a> MatchRule<'a> {
     }
 
 
+    /// Parses a string in the format used in the call to "add_match", i e the format returned by `match_str`.
+    ///
+    /// Only the keys that have a corresponding field in this struct are supported, other keys (e g "arg0")
+    /// are an error.
+    pub fn parse(s: &str) -> Result<MatchRule<'static>, Error> {
+        let invalid = |m: &str| Error::new_custom("org.freedesktop.DBus.Error.MatchRuleInvalid", &format!("{} in match rule {:?}", m, s));
+        let mut r = MatchRule::new();
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let eq = rest.find('=').ok_or_else(|| invalid("Expected '='"))?;
+            let key = rest[..eq].trim();
+            rest = rest[eq+1..].trim_start();
+            let value = if rest.starts_with('\'') {
+                let end = rest[1..].find('\'').ok_or_else(|| invalid("Unterminated quote"))?;
+                let v = &rest[1..end+1];
+                rest = rest[end+2..].trim_start();
+                v
+            } else {
+                let end = rest.find(',').unwrap_or(rest.len());
+                let v = rest[..end].trim_end();
+                rest = &rest[end..];
+                v
+            };
+            if rest.starts_with(',') { rest = rest[1..].trim_start() }
+            else if !rest.is_empty() { return Err(invalid("Expected ','")) }
+
+            let value = value.to_string();
+            match key {
+                "type" => r.msg_type = Some(match &*value {
+                    "signal" => MessageType::Signal,
+                    "method_call" => MessageType::MethodCall,
+                    "method_return" => MessageType::MethodReturn,
+                    "error" => MessageType::Error,
+                    _ => return Err(invalid("Invalid message type")),
+                }),
+                "sender" => r.sender = Some(BusName::new(value).map_err(|e| invalid(&e))?),
+                "path" | "path_namespace" => {
+                    r.path = Some(Path::new(value).map_err(|e| invalid(&e))?);
+                    r.path_is_namespace = key == "path_namespace";
+                },
+                "interface" => r.interface = Some(Interface::new(value).map_err(|e| invalid(&e))?),
+                "member" => r.member = Some(Member::new(value).map_err(|e| invalid(&e))?),
+                _ => return Err(invalid(&format!("Unsupported key {:?}", key))),
+            }
+        }
+        Ok(r)
+    }
+
     /// Returns a clone with no borrowed references
     pub fn static_clone(&self) -> MatchRule<'static> {
         MatchRule {
@@ -109,3 +157,19 @@ impl<'a> MatchRule<'a> {
         }
     }
 } 
+
+#[test]
+fn test_parse() {
+    let mut mr = MatchRule::new_signal("com.example.dbusrs.Parse", "Parsed");
+    mr.sender = Some(":1.54".into());
+    mr.path = Some("/parse".into());
+    mr.path_is_namespace = true;
+    let s = mr.match_str();
+    assert_eq!(MatchRule::parse(&s).unwrap().match_str(), s);
+    let mr2 = MatchRule::parse(" type = signal, member='Parsed',interface=com.example.dbusrs.Parse").unwrap();
+    assert_eq!(mr2.match_str(), "type='signal',interface='com.example.dbusrs.Parse',member='Parsed'");
+    assert!(MatchRule::parse("").unwrap().match_str().is_empty());
+    assert!(MatchRule::parse("arg0='x'").is_err());
+    assert!(MatchRule::parse("member='Parsed").is_err());
+    assert!(MatchRule::parse("type='blob'").is_err());
+}
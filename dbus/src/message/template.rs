@@ -0,0 +1,68 @@
+// Messages that are sent many times with mostly the same contents.
+
+use super::Message;
+use crate::ffi;
+use crate::arg::{AppendAll, IterAppend};
+
+/// A message with fixed headers and leading arguments, to be sent many times with different trailing arguments.
+///
+/// Every call to `make` copies the already marshalled template, and appends only the arguments
+/// that change. This is cheaper than building the same signal from scratch, e g for progress
+/// updates emitted thousands of times per second. The copies get a new serial when they are sent.
+///
+/// # Example
+/// ```rust
+/// use dbus::message::MessageTemplate;
+/// let t = MessageTemplate::signal("/job/1", "com.example.Job", "Progress").unwrap().fixed(("job-1",));
+/// for percent in 0..=100u8 {
+///     let m = t.make((percent,));
+///     // c.send(m) ...
+///     # assert_eq!(m.read2::<&str, u8>().unwrap(), ("job-1", percent));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MessageTemplate(Message);
+
+impl MessageTemplate {
+    /// Creates a template from a message. Its headers and arguments are the same in every copy.
+    pub fn new(m: Message) -> Self { MessageTemplate(m) }
+
+    /// Creates a template for a signal, without arguments.
+    pub fn signal<P, I, M>(path: P, iface: I, member: M) -> Result<Self, String>
+    where P: Into<Vec<u8>>, I: Into<Vec<u8>>, M: Into<Vec<u8>> {
+        Message::new_signal(path, iface, member).map(MessageTemplate)
+    }
+
+    /// Builder function that appends arguments that are the same in every copy.
+    pub fn fixed<A: AppendAll>(mut self, args: A) -> Self {
+        args.append(&mut IterAppend::new(&mut self.0));
+        self
+    }
+
+    /// Returns the message the copies are made from.
+    pub fn message(&self) -> &Message { &self.0 }
+
+    /// Makes a copy of the template, with "args" appended after the fixed arguments.
+    pub fn make<A: AppendAll>(&self, args: A) -> Message {
+        let p = unsafe { ffi::dbus_message_copy(self.0.ptr()) };
+        if p.is_null() { panic!("D-Bus error: dbus_message_copy failed") }
+        let mut m = Message::from_ptr(p, false);
+        args.append(&mut IterAppend::new(&mut m));
+        m
+    }
+}
+
+#[test]
+fn test_template() {
+    let t = MessageTemplate::signal("/job/1", "com.example.Job", "Progress").unwrap().fixed(("job-1", 7u32));
+    let mut m1 = t.make((10u8, "copying"));
+    super::message_set_serial(&mut m1, 5);
+    let m2 = t.make((20u8, "done"));
+    assert_eq!(m1.read3::<&str, u32, u8>().unwrap(), ("job-1", 7, 10));
+    assert_eq!(m2.read3::<&str, u32, u8>().unwrap(), ("job-1", 7, 20));
+    assert_eq!(m2.iter_init().count(), 4);
+    assert_eq!(m2.get_serial(), None);
+    assert_eq!(&*m2.member().unwrap(), "Progress");
+    assert_eq!(&*m2.path().unwrap(), "/job/1");
+    assert_eq!(t.message().iter_init().count(), 2);
+}
@@ -0,0 +1,147 @@
+//! Hooks for collecting metrics, e g to export them to Prometheus or StatsD.
+//!
+//! Implement the `Metrics` trait on top of your metrics library of choice, and hand it to
+//! `Channel::set_metrics` (for message traffic) and `Tree::metrics` (for method dispatch).
+//! The names of the metrics are the constants in this module; their labels are listed with each constant.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use dbus::metrics::{Metrics, MESSAGES_RECEIVED};
+//! use dbus::channel::{Channel, BusType};
+//! use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+//!
+//! #[derive(Default)]
+//! struct Received(AtomicU64);
+//!
+//! impl Metrics for Received {
+//!     fn counter(&self, name: &'static str, _: &[(&'static str, &str)], value: u64) {
+//!         if name == MESSAGES_RECEIVED { self.0.fetch_add(value, Ordering::Relaxed); }
+//!     }
+//! }
+//!
+//! let m = Arc::new(Received::default());
+//! let mut c = Channel::get_private(BusType::Session).unwrap();
+//! c.set_metrics(Some(m.clone()));
+//! ```
+
+use crate::{Message, MessageType, ErrorKind};
+use crate::tree::MethodErr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::fmt;
+
+/// Counter: messages read from the connection. Label: "type".
+pub const MESSAGES_RECEIVED: &str = "dbus_messages_received_total";
+/// Counter: messages sent on the connection. Label: "type".
+pub const MESSAGES_SENT: &str = "dbus_messages_sent_total";
+/// Counter: error replies received, i e failed method calls. Label: "kind".
+pub const ERRORS_RECEIVED: &str = "dbus_errors_received_total";
+/// Counter: error replies sent by a tree. Labels: "interface", "member", "kind".
+pub const ERRORS_SENT: &str = "dbus_errors_sent_total";
+/// Histogram: the time, in seconds, a tree took to handle a method call. Labels: "interface", "member".
+///
+/// For `MTFuture` trees, this includes the time spent waiting for the future.
+pub const DISPATCH_SECONDS: &str = "dbus_dispatch_duration_seconds";
+/// Gauge: the number of bytes waiting to be written to the connection, after a message has been sent. No labels.
+pub const OUTGOING_QUEUE_BYTES: &str = "dbus_outgoing_queue_bytes";
+/// Gauge: the number of method calls waiting for a reply (see `Channel::pending_replies`). No labels.
+///
+/// Reported when `Channel::send_with_reply` is called, and when its `PendingCall` is done.
+pub const PENDING_REPLIES: &str = "dbus_pending_replies";
+
+/// Callbacks for the metrics collected by channels and trees.
+///
+/// All methods do nothing by default, so implement only the ones you need. They are called while
+/// sending or receiving messages, so they should be fast and must not block.
+pub trait Metrics: Send + Sync {
+    /// Increases the counter "name" by "value".
+    fn counter(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64) { let _ = (name, labels, value); }
+    /// Sets the gauge "name" to "value".
+    fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) { let _ = (name, labels, value); }
+    /// Records "value" in the histogram "name".
+    fn histogram(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) { let _ = (name, labels, value); }
+}
+
+/// Returns the value of the "type" label for a message type.
+pub fn type_label(t: MessageType) -> &'static str {
+    match t {
+        MessageType::MethodCall => "method_call",
+        MessageType::MethodReturn => "method_return",
+        MessageType::Error => "error",
+        MessageType::Signal => "signal",
+    }
+}
+
+#[derive(Clone)]
+pub (crate) struct SharedMetrics(pub (crate) Arc<dyn Metrics>);
+
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "Metrics") }
+}
+
+impl SharedMetrics {
+    pub (crate) fn received(&self, msg: &Message) {
+        self.0.counter(MESSAGES_RECEIVED, &[("type", type_label(msg.msg_type()))], 1);
+        if msg.msg_type() == MessageType::Error {
+            let kind = msg.set_error_from_msg().err().map(|e| e.kind()).unwrap_or(ErrorKind::Other);
+            self.0.counter(ERRORS_RECEIVED, &[("kind", &format!("{:?}", kind))], 1);
+        }
+    }
+
+    pub (crate) fn dispatched(&self, msg: &Message, err: Option<&MethodErr>, elapsed: Duration) {
+        let (iface, member) = (msg.interface(), msg.member());
+        let (iface, member) = (iface.as_deref().unwrap_or(""), member.as_deref().unwrap_or(""));
+        self.0.histogram(DISPATCH_SECONDS, &[("interface", iface), ("member", member)], elapsed.as_secs_f64());
+        if let Some(e) = err {
+            let kind = format!("{:?}", ErrorKind::from_name(e.errorname()));
+            self.0.counter(ERRORS_SENT, &[("interface", iface), ("member", member), ("kind", &kind)], 1);
+        }
+    }
+
+    pub (crate) fn sent(&self, msg: &Message, queued: usize) {
+        self.0.counter(MESSAGES_SENT, &[("type", type_label(msg.msg_type()))], 1);
+        self.0.gauge(OUTGOING_QUEUE_BYTES, &[], queued as f64);
+    }
+}
+
+#[test]
+fn test_metrics() {
+    use crate::channel::{Channel, BusType};
+    use crate::tree::Factory;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(&'static str, String, f64)>>);
+    impl Recorder {
+        fn record(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+            let l = labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(",");
+            self.0.lock().unwrap().push((name, l, value));
+        }
+        fn has(&self, name: &str, labels: &str) -> bool { self.0.lock().unwrap().iter().any(|(n, l, _)| *n == name && l == labels) }
+    }
+    impl Metrics for Recorder {
+        fn counter(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64) { self.record(name, labels, value as f64) }
+        fn histogram(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) { self.record(name, labels, value) }
+    }
+
+    let r = Arc::new(Recorder::default());
+    let mut c = Channel::get_private(BusType::Session).unwrap();
+    c.set_metrics(Some(r.clone()));
+    let ping = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus.Peer", "Ping").unwrap();
+    c.send_with_reply_and_block(ping, Duration::from_secs(5)).unwrap();
+    assert!(r.has(MESSAGES_SENT, "type=method_call"));
+    assert!(r.has(MESSAGES_RECEIVED, "type=method_return"));
+    let m = Message::new_method_call("com.example.dbusrs.Nonexistent", "/", "com.example.dbusrs", "Hello").unwrap();
+    assert!(c.send_with_reply_and_block(m, Duration::from_secs(5)).is_err());
+    assert!(r.has(ERRORS_RECEIVED, "kind=ServiceUnknown"));
+
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).metrics(r.clone()).add(f.object_path("/metrics", ()).add(f.interface("com.example.dbusrs.Metrics", ())
+        .add_m(f.method("Fail", (), |m| m.reply_err(MethodErr::failed(&"Oops"))))));
+    let mut m = Message::new_method_call("com.example.dbusrs", "/metrics", "com.example.dbusrs.Metrics", "Fail").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    t.handle(&m).unwrap();
+    assert!(r.has(DISPATCH_SECONDS, "interface=com.example.dbusrs.Metrics,member=Fail"));
+    assert!(r.has(ERRORS_SENT, "interface=com.example.dbusrs.Metrics,member=Fail,kind=Failed"));
+}
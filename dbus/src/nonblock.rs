@@ -7,13 +7,15 @@
 
 use crate::{Error, Message};
 use crate::channel::{MatchingReceiver, Channel, Sender, Token};
-use crate::strings::{BusName, Path, Interface, Member};
+use crate::strings::{BusName, UniqueName, WellKnownName, Path, Interface, Member};
 use crate::arg::{AppendAll, ReadAll, IterAppend};
-use crate::message::MatchRule;
+use crate::message::{MatchRule, SignalArgs};
 
 use std::sync::{Arc, Mutex};
 use std::{future, task, pin, mem};
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
 use crate::filters::{Filters, Replies};
 
 mod generated_org_freedesktop_notifications;
@@ -111,6 +113,10 @@ impl MatchingReceiver for $c {
 impl NonblockReply for $c {
     type F = $rcb;
     fn send_with_reply(&self, msg: Message, f: Self::F) -> Result<Token, ()> {
+        if self.is_congested() {
+            trace_event!("Method call not sent, too many pending replies");
+            return Err(())
+        }
         self.channel.send(msg).map(|x| {
             let t = Token(x as usize);
             self.replies_mut().insert(t, f);
@@ -118,20 +124,27 @@ impl NonblockReply for $c {
         })
     }
     fn cancel_reply(&self, id: Token) -> Option<Self::F> { self.replies_mut().remove(&id) }
+    fn is_congested(&self) -> bool {
+        let waiting = self.replies_mut().len();
+        self.channel.is_congested_with(waiting)
+    }
     fn make_f<G: FnOnce(Message, &Self) + Send + 'static>(g: G) -> Self::F { Box::new(g) }
 }
 
 
 impl Process for $c {
     fn process_one(&self, msg: Message) {
+        trace_span!("dispatch", serial = ?msg.get_serial());
         if let Some(serial) = msg.get_reply_serial() {
             if let Some(f) = self.replies_mut().remove(&Token(serial as usize)) {
+                trace_event!(reply_serial = serial, "Calling reply callback");
                 f(msg, self);
                 return;
             }
         }
         let ff = self.filters_mut().remove_matching(&msg);
         if let Some(mut ff) = ff {
+            trace_event!(token = (ff.0).0, match_rule = %ff.1.match_str(), "Calling message callback");
             if ff.2(msg, self) {
                 self.filters_mut().insert(ff);
             }
@@ -139,6 +152,8 @@ impl Process for $c {
             let _ = self.send(reply);
         }
     }
+
+    fn make_filter<G: FnMut(Message, &Self) -> bool + Send + Sync + 'static>(g: G) -> $cb { Box::new(g) }
 }
 
 impl $c {
@@ -147,6 +162,14 @@ impl $c {
     /// It's usually something like ":1.54"
     pub fn unique_name(&self) -> BusName { self.channel.unique_name().unwrap().into() }
 
+    /// Get the connection's unique name, as a UniqueName.
+    pub fn unique_bus_name(&self) -> UniqueName<'_> { self.channel.unique_name().unwrap().into() }
+
+    /// Returns a cloneable handle for sending messages on this connection from other threads.
+    ///
+    /// See `channel::MsgSender` for details.
+    pub fn msg_sender(&self) -> crate::channel::MsgSender { self.channel.msg_sender() }
+
     /// Request a name on the D-Bus.
     ///
     /// For detailed information on the flags and return values, see the libdbus documentation.
@@ -177,6 +200,52 @@ impl $c {
             crate::Error::new_failed("Invalid reply from DBus server")
         )
     }
+
+    /// Same as `request_name`, but only accepts well-known names.
+    pub async fn request_well_known_name<'a, N: Into<WellKnownName<'a>>>(&self, name: N, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+    -> Result<stdintf::org_freedesktop_dbus::RequestNameReply, Error> {
+        let name: WellKnownName = name.into();
+        self.request_name(name, allow_replacement, replace_existing, do_not_queue).await
+    }
+
+    /// Same as `release_name`, but only accepts well-known names.
+    pub async fn release_well_known_name<'a, N: Into<WellKnownName<'a>>>(&self, name: N) -> Result<stdintf::org_freedesktop_dbus::ReleaseNameReply, Error> {
+        let name: WellKnownName = name.into();
+        self.release_name(name).await
+    }
+
+    /// Calls org.freedesktop.DBus.Peer.Ping on "dest", and returns how long it took to get a reply.
+    ///
+    /// Useful as a health check. In case of failure, `Error::kind` tells why. There is no timeout here,
+    /// for the same reason as in `wait_for_signal`.
+    pub async fn ping<'a, D: Into<BusName<'a>>>(&self, dest: D) -> Result<std::time::Duration, Error> {
+        let start = std::time::Instant::now();
+        Proxy::new(dest, "/", self).method_call::<(), _, _, _>("org.freedesktop.DBus.Peer", "Ping", ()).await?;
+        Ok(start.elapsed())
+    }
+
+    /// Waits for the first message matching the match rule, and returns it.
+    ///
+    /// The match is added before waiting, and removed again when the returned future resolves
+    /// or is dropped. There is no timeout here, because this crate does not depend on a specific
+    /// reactor; use e g `tokio::time::timeout` to wrap the returned future.
+    pub async fn wait_for_signal(&self, match_rule: MatchRule<'static>) -> Result<Message, Error> {
+        let mstr = match_rule.match_str();
+        let mr = Arc::new(Mutex::new(MRInner::Neither));
+        let mr2 = mr.clone();
+        // Start receiving before adding the match, so that no matching message can slip through.
+        let token = self.start_receive(match_rule, Box::new(move |msg, _| {
+            let old = mem::replace(&mut *mr2.lock().unwrap(), MRInner::Ready(Ok(msg)));
+            if let MRInner::Pending(waker) = old { waker.wake() }
+            false
+        }));
+        let _guard = SignalMatch { connection: self, token, mstr: mstr.clone() };
+        let proxy = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", self);
+        use stdintf::org_freedesktop_dbus::DBus;
+        trace_event!(match_rule = %mstr, "Adding match");
+        proxy.add_match(&mstr).await?;
+        MethodReply(mr, Some(Box::new(Ok))).await
+    }
 }
 
 
@@ -202,6 +271,68 @@ impl SyncConnection {
     fn replies_mut(&self) -> std::sync::MutexGuard<Replies<SyncRepliesCb>> { self.replies.lock().unwrap() }
 }
 
+/// A stream of incoming signals, created by Proxy::receive.
+///
+/// Use the `next_signal` method to wait for the next signal. With the "futures" feature enabled,
+/// this also implements `futures_core::Stream`. The stream never ends by itself.
+pub struct SignalStream<'a, C: MatchingReceiver + Sender, S> {
+    _m: SignalMatch<'a, C>,
+    inner: Arc<Mutex<(VecDeque<Message>, Option<task::Waker>)>>,
+    _s: PhantomData<fn() -> S>,
+}
+
+impl<'a, C: MatchingReceiver + Sender, S: ReadAll> SignalStream<'a, C, S> {
+    /// Attempts to get the next signal, registering the current task for wakeup if there is none yet.
+    pub fn poll_next(&mut self, ctx: &mut task::Context) -> task::Poll<Option<(S, Message)>> {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some(msg) = inner.0.pop_front() {
+            if let Ok(s) = msg.read_all() { return task::Poll::Ready(Some((s, msg))) }
+        }
+        inner.1 = Some(ctx.waker().clone());
+        task::Poll::Pending
+    }
+
+    /// Waits for the next signal.
+    pub fn next_signal(&mut self) -> NextSignal<'_, 'a, C, S> { NextSignal(self) }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, C: MatchingReceiver + Sender, S: ReadAll> futures_core::Stream for SignalStream<'a, C, S> {
+    type Item = (S, Message);
+    fn poll_next(self: pin::Pin<&mut Self>, ctx: &mut task::Context) -> task::Poll<Option<(S, Message)>> {
+        SignalStream::poll_next(self.get_mut(), ctx)
+    }
+}
+
+/// Future returned by SignalStream::next_signal.
+pub struct NextSignal<'b, 'a, C: MatchingReceiver + Sender, S>(&'b mut SignalStream<'a, C, S>);
+
+impl<'b, 'a, C: MatchingReceiver + Sender, S: ReadAll> future::Future for NextSignal<'b, 'a, C, S> {
+    type Output = Option<(S, Message)>;
+    fn poll(mut self: pin::Pin<&mut Self>, ctx: &mut task::Context) -> task::Poll<Option<(S, Message)>> {
+        self.0.poll_next(ctx)
+    }
+}
+
+/// Removes the match and callback of wait_for_signal or SignalStream, also if the future is dropped early.
+struct SignalMatch<'a, C: MatchingReceiver + Sender> {
+    connection: &'a C,
+    token: Token,
+    mstr: String,
+}
+
+impl<C: MatchingReceiver + Sender> Drop for SignalMatch<'_, C> {
+    fn drop(&mut self) {
+        self.connection.stop_receive(self.token);
+        trace_event!(match_rule = %self.mstr, "Removing match");
+        // We can't wait for the reply here, so just send the message and don't ask for one.
+        let mut msg = Message::method_call(&"org.freedesktop.DBus".into(), &"/org/freedesktop/DBus".into(),
+            &"org.freedesktop.DBus".into(), &"RemoveMatch".into()).append1(&self.mstr);
+        msg.set_no_reply(true);
+        let _ = self.connection.send(msg);
+    }
+}
+
 /// Internal helper trait for async method replies.
 pub trait NonblockReply {
     /// Callback type
@@ -210,6 +341,9 @@ pub trait NonblockReply {
     fn send_with_reply(&self, msg: Message, f: Self::F) -> Result<Token, ()>;
     /// Cancels a pending reply.
     fn cancel_reply(&self, id: Token) -> Option<Self::F>;
+    /// Returns true if no more method calls can be made until some replies have arrived,
+    /// see `Channel::set_max_pending_replies`.
+    fn is_congested(&self) -> bool { false }
     /// Internal helper function that creates a callback.
     fn make_f<G: FnOnce(Message, &Self) + Send + 'static>(g: G) -> Self::F where Self: Sized;
 }
@@ -231,6 +365,10 @@ pub trait Process: Sender + AsRef<Channel> {
 
     /// Dispatches a message.
     fn process_one(&self, msg: Message);
+
+    /// Internal helper function that creates a message callback.
+    fn make_filter<G: FnMut(Message, &Self) -> bool + Send + Sync + 'static>(g: G) -> <Self as MatchingReceiver>::F
+    where Self: MatchingReceiver + Sized;
 }
 
 /// A struct that wraps a connection, destination and path.
@@ -246,13 +384,21 @@ pub struct Proxy<'a, C> {
     pub path: Path<'a>,
     /// Some way to send and/or receive messages, non-blocking.
     pub connection: C,
+    allow_interactive_authorization: bool,
 }
 
 impl<'a, C> Proxy<'a, C> {
     /// Creates a new proxy struct.
     pub fn new<D: Into<BusName<'a>>, P: Into<Path<'a>>>(dest: D, path: P, connection: C) -> Self {
-        Proxy { destination: dest.into(), path: path.into(), connection }
+        Proxy { destination: dest.into(), path: path.into(), connection, allow_interactive_authorization: false }
     }
+
+    /// Sets whether method calls allow the remote side to prompt the user for authorization,
+    /// see `Message::set_allow_interactive_authorization`. Defaults to false.
+    pub fn with_interactive_authorization(mut self, allow: bool) -> Self { self.allow_interactive_authorization = allow; self }
+
+    /// Whether method calls allow the remote side to prompt the user for authorization.
+    pub fn allow_interactive_authorization(&self) -> bool { self.allow_interactive_authorization }
 }
 
 impl<'a, T, C> Proxy<'a, C>
@@ -265,6 +411,7 @@ where
     pub fn method_call<'i, 'm, R: ReadAll + 'static, A: AppendAll, I: Into<Interface<'i>>, M: Into<Member<'m>>>(&self, i: I, m: M, args: A)
     -> MethodReply<R> {
         let mut msg = Message::method_call(&self.destination, &self.path, &i.into(), &m.into());
+        msg.set_allow_interactive_authorization(self.allow_interactive_authorization);
         args.append(&mut IterAppend::new(&mut msg));
 
         let mr = Arc::new(Mutex::new(MRInner::Neither));
@@ -274,11 +421,38 @@ where
             let old = mem::replace(&mut *inner, MRInner::Ready(Ok(msg)));
             if let MRInner::Pending(waker) = old { waker.wake() }
         });
-        if let Err(_) = self.connection.send_with_reply(msg, f) {
+        if self.connection.is_congested() {
+            *mr.lock().unwrap() = MRInner::Ready(Err(Error::new_congested()));
+        } else if let Err(_) = self.connection.send_with_reply(msg, f) {
             *mr.lock().unwrap() = MRInner::Ready(Err(Error::new_failed("Failed to send message")));
         }
         MethodReply(mr, Some(Box::new(|msg: Message| { msg.read_all() })))
     }
+
+    /// Starts receiving signals of type S from this destination and path.
+    ///
+    /// The returned future resolves when the match has been added, so signals sent after that
+    /// are guaranteed to end up in the SignalStream. The match is removed when the SignalStream is dropped.
+    pub async fn receive<'b, S: SignalArgs + ReadAll>(&'b self) -> Result<SignalStream<'b, T, S>, Error>
+    where T: Process + MatchingReceiver + 'b
+    {
+        let mr = S::match_rule(Some(&self.destination), Some(&self.path)).static_clone();
+        let mstr = mr.match_str();
+        let inner: Arc<Mutex<(VecDeque<Message>, Option<task::Waker>)>> = Default::default();
+        let inner2 = inner.clone();
+        let token = self.connection.start_receive(mr, T::make_filter(move |msg, _| {
+            let mut i = inner2.lock().unwrap();
+            i.0.push_back(msg);
+            if let Some(waker) = i.1.take() { waker.wake() }
+            true
+        }));
+        let m = SignalMatch { connection: &*self.connection, token, mstr };
+        let proxy = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", &*self.connection);
+        use stdintf::org_freedesktop_dbus::DBus;
+        trace_event!(match_rule = %m.mstr, "Adding match");
+        proxy.add_match(&m.mstr).await?;
+        Ok(SignalStream { _m: m, inner, _s: PhantomData })
+    }
 }
 
 enum MRInner {
@@ -316,6 +490,11 @@ impl<T: 'static> MethodReply<T> {
             Box::new(|r| first(r).and_then(f))
         }))
     }
+
+    // A reply that fails right away, without anything being sent.
+    pub (crate) fn from_err(e: Error) -> Self {
+        MethodReply(Arc::new(Mutex::new(MRInner::Ready(Err(e)))), Some(Box::new(|_| unreachable!())))
+    }
 }
 
 
@@ -330,3 +509,26 @@ fn test_conn_send_sync() {
     let c = Connection::from(Channel::get_private(crate::channel::BusType::Session).unwrap());
     is_send(&c);
 }
+
+#[test]
+fn test_congested() {
+    use std::future::Future;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    fn raw() -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+    let waker = unsafe { Waker::from_raw(raw()) };
+
+    let mut ch = Channel::get_private(crate::channel::BusType::Session).unwrap();
+    ch.set_max_pending_replies(Some(1));
+    let c = LocalConnection::from(ch);
+    // Nobody answers calls to ourselves.
+    let p = Proxy::new(c.unique_name().into_static(), "/congested", &c);
+    let mut first = p.method_call::<(), _, _, _>("com.example.dbusrs.Congested", "Hang", ());
+    assert!(pin::Pin::new(&mut first).poll(&mut Context::from_waker(&waker)).is_pending());
+    assert!(c.is_congested());
+    let mut second = p.method_call::<(), _, _, _>("com.example.dbusrs.Congested", "Hang", ());
+    match pin::Pin::new(&mut second).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(Err(e)) => assert_eq!(e.kind(), crate::ErrorKind::Congested),
+        _ => panic!("Expected a Congested error"),
+    }
+}
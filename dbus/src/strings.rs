@@ -7,6 +7,7 @@
 use std::{str, fmt, ops, default, hash};
 use std::ffi::{CStr, CString};
 use std::borrow::{Borrow, Cow};
+use std::convert::TryFrom;
 use std::os::raw::c_char;
 
 #[cfg(not(feature = "no-string-validation"))]
@@ -15,7 +16,9 @@ use crate::Error;
 use crate::ffi;
 
 macro_rules! cstring_wrapper {
-    ($t: ident, $s: ident) => {
+    ($t: ident, $s: ident) => { cstring_wrapper!(@impl $t, ffi::$s); };
+    ($t: ident, fn $s: ident) => { cstring_wrapper!(@impl $t, $s); };
+    (@impl $t: ident, $s: path) => {
 
 impl<'m> $t<'m> {
     #[cfg(feature = "no-string-validation")]
@@ -24,7 +27,7 @@ impl<'m> $t<'m> {
     #[cfg(not(feature = "no-string-validation"))]
     fn check_valid(c: *const c_char) -> Result<(), String> {
         let mut e = Error::empty();
-        let b = unsafe { ffi::$s(c, e.get_mut()) };
+        let b = unsafe { $s(c, e.get_mut()) };
         if b != 0 { Ok(()) } else { Err(e.message().unwrap().into()) }
     }
 
@@ -165,6 +168,46 @@ impl<'a> default::Default for Path<'a> {
     fn default() -> Path<'a> { Path(Cow::Borrowed(unsafe { CStr::from_ptr(b"/\0".as_ptr() as *const c_char)})) }
 }
 
+impl<'a> Path<'a> {
+    /// Checks whether s can be used as a single component of a path, i e it is non-empty
+    /// and only contains the characters "[A-Z][a-z][0-9]_".
+    pub fn is_valid_component(s: &str) -> bool {
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+    }
+
+    /// Iterates over the components of this path, e g "/org/example" yields "org" and "example".
+    ///
+    /// The root path has no components.
+    pub fn components(&self) -> impl Iterator<Item = &str> { self.split('/').filter(|s| !s.is_empty()) }
+
+    /// Returns the path one level up, or None if this is the root path.
+    pub fn parent(&self) -> Option<Path<'static>> {
+        let s: &str = self;
+        if s == "/" { return None }
+        let idx = s.rfind('/').unwrap_or(0);
+        Some(if idx == 0 { Path::default().into_static() } else { Path::new(&s[..idx]).unwrap() })
+    }
+
+    /// Returns a new path with the component appended, e g "/org/example" joined with "device0"
+    /// is "/org/example/device0".
+    ///
+    /// Fails if the component is not valid, see `is_valid_component`.
+    pub fn join(&self, component: &str) -> Result<Path<'static>, String> {
+        if !Path::is_valid_component(component) { return Err(format!("Object path component was not valid: '{}'", component)) }
+        let s: &str = self;
+        let sep = if s == "/" { "" } else { "/" };
+        Path::new(format!("{}{}{}", s, sep, component))
+    }
+
+    /// Returns true if this path is base, or below it.
+    ///
+    /// This compares whole components, so "/org/example2" is not a descendant of "/org/example".
+    pub fn is_descendant_of(&self, base: &Path) -> bool {
+        let (s, b): (&str, &str) = (self, base);
+        b == "/" || s == b || (s.starts_with(b) && s.as_bytes()[b.len()] == b'/')
+    }
+}
+
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus member, i e, a signal or method name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -186,6 +229,63 @@ pub struct BusName<'a>(Cow<'a, CStr>);
 
 cstring_wrapper!(BusName, dbus_validate_bus_name);
 
+#[cfg(not(feature = "no-string-validation"))]
+unsafe fn validate_name_kind(c: *const c_char, e: *mut ffi::DBusError, unique: bool) -> u32 {
+    if ffi::dbus_validate_bus_name(c, e) == 0 { return 0 }
+    if (*c == b':' as c_char) == unique { return 1 }
+    let kind = if unique { "Unique name" } else { "Well-known name" };
+    let msg = CString::new(format!("{} was not valid: '{}'", kind, CStr::from_ptr(c).to_string_lossy())).unwrap();
+    ffi::dbus_set_error(e, b"org.freedesktop.DBus.Error.InvalidArgs\0".as_ptr() as *const c_char, b"%s\0".as_ptr() as *const c_char, msg.as_ptr());
+    0
+}
+
+#[cfg(not(feature = "no-string-validation"))]
+unsafe fn validate_unique_name(c: *const c_char, e: *mut ffi::DBusError) -> u32 { validate_name_kind(c, e, true) }
+
+#[cfg(not(feature = "no-string-validation"))]
+unsafe fn validate_well_known_name(c: *const c_char, e: *mut ffi::DBusError) -> u32 { validate_name_kind(c, e, false) }
+
+/// A wrapper around a string that is guaranteed to be
+/// a valid D-Bus unique name, i e a bus name starting with a colon, like ":1.42".
+///
+/// Every connection to a bus gets a unique name assigned by the bus when connecting.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct UniqueName<'a>(Cow<'a, CStr>);
+
+cstring_wrapper!(UniqueName, fn validate_unique_name);
+
+/// A wrapper around a string that is guaranteed to be
+/// a valid D-Bus well-known name, i e a bus name not starting with a colon, like "com.example.Service".
+///
+/// Well-known names are the ones that can be requested and released by a connection.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct WellKnownName<'a>(Cow<'a, CStr>);
+
+cstring_wrapper!(WellKnownName, fn validate_well_known_name);
+
+impl<'a> From<UniqueName<'a>> for BusName<'a> { fn from(n: UniqueName<'a>) -> BusName<'a> { BusName(n.0) } }
+
+impl<'a> From<WellKnownName<'a>> for BusName<'a> { fn from(n: WellKnownName<'a>) -> BusName<'a> { BusName(n.0) } }
+
+impl<'a> TryFrom<BusName<'a>> for UniqueName<'a> {
+    type Error = String;
+    fn try_from(n: BusName<'a>) -> Result<UniqueName<'a>, String> {
+        if n.starts_with(':') { Ok(UniqueName(n.0)) } else { Err(format!("Unique name was not valid: '{}'", &*n)) }
+    }
+}
+
+impl<'a> TryFrom<BusName<'a>> for WellKnownName<'a> {
+    type Error = String;
+    fn try_from(n: BusName<'a>) -> Result<WellKnownName<'a>, String> {
+        if !n.starts_with(':') { Ok(WellKnownName(n.0)) } else { Err(format!("Well-known name was not valid: '{}'", &*n)) }
+    }
+}
+
+impl<'a> BusName<'a> {
+    /// Returns true if this is a unique name, i e it starts with a colon.
+    pub fn is_unique(&self) -> bool { self.starts_with(':') }
+}
+
 /// A wrapper around a string that is guaranteed to be
 /// a valid D-Bus bus name.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -219,6 +319,51 @@ fn reborrow_path() {
     assert_eq!(p1, p2);
 }
 
+#[test]
+fn path_components() {
+    let p = Path::from("/org/example/device0");
+    assert_eq!(p.components().collect::<Vec<_>>(), vec!("org", "example", "device0"));
+    assert_eq!(Path::default().components().count(), 0);
+
+    assert_eq!(p.parent(), Some(Path::from("/org/example")));
+    assert_eq!(Path::from("/org").parent(), Some(Path::from("/")));
+    assert_eq!(Path::default().parent(), None);
+
+    assert_eq!(Path::from("/org/example").join("device0"), Ok(p.clone()));
+    assert_eq!(Path::default().join("org"), Ok(Path::from("/org")));
+    assert!(p.join("").is_err());
+    assert!(p.join("a/b").is_err());
+    assert!(p.join("dev-1").is_err());
+    assert!(Path::is_valid_component("Device_1"));
+
+    assert!(p.is_descendant_of(&"/org/example".into()));
+    assert!(p.is_descendant_of(&p));
+    assert!(p.is_descendant_of(&Path::default()));
+    assert!(!Path::from("/org/example2").is_descendant_of(&"/org/example".into()));
+    assert!(!Path::from("/org").is_descendant_of(&"/org/example".into()));
+}
+
+#[test]
+fn bus_name_kinds() {
+    let u = UniqueName::new(":1.42").unwrap();
+    let w = WellKnownName::new("com.example.dbusrs").unwrap();
+    #[cfg(not(feature = "no-string-validation"))]
+    {
+        assert_eq!(UniqueName::new("com.example.dbusrs"), Err("Unique name was not valid: 'com.example.dbusrs'".into()));
+        assert!(WellKnownName::new(":1.42").is_err());
+        assert!(WellKnownName::new("com..example").is_err());
+    }
+    assert_eq!(u.to_string(), ":1.42");
+    let b: BusName = u.clone().into();
+    assert!(b.is_unique());
+    assert_eq!(UniqueName::try_from(b.clone()), Ok(u));
+    assert!(WellKnownName::try_from(b).is_err());
+    let b: BusName = w.clone().into();
+    assert!(!b.is_unique());
+    assert_eq!(WellKnownName::try_from(b.clone()), Ok(w));
+    assert!(UniqueName::try_from(b).is_err());
+}
+
 #[test]
 fn make_sig() {
     assert_eq!(&*Signature::make::<(&str, u8)>(), "(sy)");
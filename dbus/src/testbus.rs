@@ -0,0 +1,150 @@
+//! A private D-Bus server for integration tests.
+//!
+//! `TestBus` launches its own dbus-daemon, listening on a socket in a temporary directory,
+//! so that tests neither depend on nor disturb the user's session bus. The daemon is
+//! shut down and the directory removed when the TestBus is dropped.
+//!
+//! The dbus-daemon binary is looked up in PATH, unless the `DBUS_DAEMON` environment
+//! variable is set to the binary to use.
+//!
+//! This module is only available with the `testbus` feature, typically enabled for dev-dependencies only.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use dbus::testbus::TestBus;
+//! use dbus::blocking::Connection;
+//!
+//! let bus = TestBus::new()?;
+//! let c: Connection = bus.connect()?;
+//! let proxy = c.with_proxy("org.freedesktop.DBus", "/", std::time::Duration::from_secs(5));
+//! let (names,): (Vec<String>,) = proxy.method_call("org.freedesktop.DBus", "ListNames", ())?;
+//! assert!(names.contains(&c.unique_name().to_string()));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::Error;
+use crate::channel::Channel;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// How long to wait for dbus-daemon to print its address.
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path=SOCKET</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+/// A dbus-daemon private to the process, see the module documentation.
+#[derive(Debug)]
+pub struct TestBus {
+    child: Child,
+    dir: PathBuf,
+    address: String,
+}
+
+impl TestBus {
+    /// Launches a new dbus-daemon, and waits until it accepts connections.
+    ///
+    /// Fails with a TimedOut error if the daemon is not ready within ten seconds.
+    pub fn new() -> io::Result<TestBus> {
+        let dir = std::env::temp_dir().join(format!("dbus-rs-testbus-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::SeqCst)));
+        std::fs::create_dir_all(&dir)?;
+        match Self::launch(&dir) {
+            Ok((child, address)) => Ok(TestBus { child, dir, address }),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn launch(dir: &Path) -> io::Result<(Child, String)> {
+        let socket = dir.join("bus");
+        let config = dir.join("bus.conf");
+        std::fs::write(&config, CONFIG.replace("SOCKET", &socket.to_string_lossy()))?;
+        let daemon = std::env::var_os("DBUS_DAEMON").unwrap_or_else(|| "dbus-daemon".into());
+        let mut child = Command::new(daemon).arg("--nofork").arg("--print-address").arg("--config-file").arg(&config)
+            .stdin(Stdio::null()).stdout(Stdio::piped()).spawn()?;
+        // The address is printed once the daemon is ready to accept connections.
+        // Read it in another thread, so that a daemon that hangs does not make us hang too.
+        let stdout = child.stdout.take().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut address = String::new();
+            let _ = tx.send(BufReader::new(stdout).read_line(&mut address).map(|_| address));
+        });
+        let r = match rx.recv_timeout(LAUNCH_TIMEOUT) {
+            Ok(Ok(a)) if !a.trim().is_empty() => return Ok((child, a.trim().to_string())),
+            Ok(Ok(_)) => io::Error::new(io::ErrorKind::UnexpectedEof, "dbus-daemon exited without printing its address"),
+            Ok(Err(e)) => e,
+            Err(_) => io::Error::new(io::ErrorKind::TimedOut, "dbus-daemon did not print its address in time"),
+        };
+        // Killing the daemon also ends the reading thread.
+        let _ = child.kill();
+        let _ = child.wait();
+        Err(r)
+    }
+
+    /// The address of the bus, e g to pass to `Channel::open_private`.
+    pub fn address(&self) -> &str { &self.address }
+
+    /// Opens a new channel to the bus, and registers it (i e it gets a unique name).
+    pub fn channel(&self) -> Result<Channel, Error> {
+        let mut c = Channel::open_private(&self.address)?;
+        c.register()?;
+        Ok(c)
+    }
+
+    /// Opens a new connection to the bus. This works for all connection types that can be
+    /// created from a Channel, e g `blocking::Connection` and `nonblock::SyncConnection`.
+    pub fn connect<C: From<Channel>>(&self) -> Result<C, Error> { Ok(C::from(self.channel()?)) }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_testbus() {
+    use crate::blocking::Connection;
+    use std::time::Duration;
+
+    let bus = TestBus::new().unwrap();
+    assert!(bus.address().starts_with("unix:path="));
+    let c1: Connection = bus.connect().unwrap();
+    let c2: Connection = bus.connect().unwrap();
+    c1.request_name("com.example.dbusrs.TestBus", false, true, false).unwrap();
+    let p = c2.with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(5));
+    let (owner,): (String,) = p.method_call("org.freedesktop.DBus", "GetNameOwner", ("com.example.dbusrs.TestBus",)).unwrap();
+    assert_eq!(owner, &*c1.unique_name());
+    // Names on this bus are not visible on any other bus.
+    let session = Connection::new_session().unwrap();
+    let p = session.with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(5));
+    let (has_owner,): (bool,) = p.method_call("org.freedesktop.DBus", "NameHasOwner", ("com.example.dbusrs.TestBus",)).unwrap();
+    assert!(!has_owner);
+
+    let (dir, address) = (bus.dir.clone(), bus.address().to_string());
+    drop(bus);
+    assert!(!dir.exists());
+    assert!(Channel::open_private(&address).is_err());
+}
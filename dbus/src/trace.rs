@@ -0,0 +1,15 @@
+// Internal macros for the optional "tracing" feature. Without the feature, they expand to nothing,
+// so the arguments are not even evaluated.
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_event { ($($t:tt)*) => { tracing::debug!($($t)*) } }
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event { ($($t:tt)*) => { } }
+
+// Enters a span until the end of the current scope.
+#[cfg(feature = "tracing")]
+macro_rules! trace_span { ($($t:tt)*) => { let _span = tracing::debug_span!($($t)*).entered(); } }
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span { ($($t:tt)*) => { } }
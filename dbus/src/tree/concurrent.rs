@@ -0,0 +1,218 @@
+// Handling of incoming method calls to an MTFuture tree, several at a time.
+
+use super::{Tree, MTFuture, DataType, MethodReplies, DispatchOrder};
+use crate::{Message, MessageType, message, channel};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+use std::mem;
+
+type ReplyFuture = Pin<Box<dyn Future<Output = MethodReplies>>>;
+
+struct Inner {
+    queue: VecDeque<Message>,
+    running: Vec<(Option<String>, ReplyFuture)>,
+    waker: Option<Waker>,
+}
+
+struct Dispatcher<D: DataType, S> {
+    tree: Tree<MTFuture<D>, D>,
+    replies: Weak<S>,
+    max_running: usize,
+    order: DispatchOrder,
+    inner: RefCell<Inner>,
+}
+
+/// Handles method calls with a Tree of MTFuture methods, where replies can be deferred.
+///
+/// Up to `max_running` method calls are handled at the same time, and replies are sent as soon
+/// as they are ready. Calls to the same object path (or from the same sender, depending on the
+/// DispatchOrder) are handled one at a time, in the order they arrived. Calls without a sender are
+/// not ordered. Method calls handed over with `dispatch` are queued until they are allowed to start.
+/// The handling itself is done by the future returned from `run`, which needs to be spawned on a
+/// single threaded executor (e g with `tokio::task::spawn_local`). Handles are cheap to clone.
+pub struct AsyncDispatcher<D: DataType, S>(Rc<Dispatcher<D, S>>);
+
+impl<D: DataType, S> Clone for AsyncDispatcher<D, S> {
+    fn clone(&self) -> Self { AsyncDispatcher(self.0.clone()) }
+}
+
+impl<D: DataType + 'static, S: channel::Sender + 'static> AsyncDispatcher<D, S> {
+    /// Creates a new dispatcher.
+    ///
+    /// Replies are sent through "replies", which is typically a connection. It is weak to avoid
+    /// reference cycles in case the dispatcher is owned by the connection itself.
+    ///
+    /// With `max_running` set to 1, replies are sent in the order the calls arrived.
+    pub fn new(tree: Tree<MTFuture<D>, D>, replies: Weak<S>, max_running: usize, order: DispatchOrder) -> Self {
+        assert!(max_running > 0, "AsyncDispatcher needs to handle at least one call at a time");
+        let inner = RefCell::new(Inner { queue: VecDeque::new(), running: vec!(), waker: None });
+        AsyncDispatcher(Rc::new(Dispatcher { tree, replies, max_running, order, inner }))
+    }
+
+    /// Queues a method call for handling.
+    ///
+    /// Returns false if the message is not a method call.
+    pub fn dispatch(&self, msg: Message) -> bool {
+        if msg.msg_type() != MessageType::MethodCall { return false }
+        let mut inner = self.0.inner.borrow_mut();
+        inner.queue.push_back(msg);
+        if let Some(w) = inner.waker.take() { w.wake() }
+        true
+    }
+
+    /// The number of method calls currently being handled.
+    pub fn running(&self) -> usize { self.0.inner.borrow().running.len() }
+
+    /// The number of method calls waiting to be handled.
+    pub fn queued(&self) -> usize { self.0.inner.borrow().queue.len() }
+
+    /// Returns a future that handles the method calls.
+    ///
+    /// The future finishes when all other handles to the dispatcher have been dropped,
+    /// and all method calls have been handled.
+    pub fn run(&self) -> impl Future<Output = ()> { Run(self.clone()) }
+
+    // Takes the first queued call that is allowed to start.
+    fn next_call(&self) -> Option<Message> {
+        let mut inner = self.0.inner.borrow_mut();
+        if inner.running.len() >= self.0.max_running { return None }
+        let inner = &mut *inner;
+        let idx = inner.queue.iter().position(|m| match self.order_key(m) {
+            Some(k) => !inner.running.iter().any(|(s, _)| s.as_ref() == Some(&k)),
+            None => true,
+        })?;
+        inner.queue.remove(idx)
+    }
+
+    // Calls with the same key must not overlap.
+    fn order_key(&self, msg: &Message) -> Option<String> {
+        match self.0.order {
+            DispatchOrder::PerPath => msg.path().map(|p| p.to_string()),
+            DispatchOrder::PerSender => msg.sender().map(|s| s.to_string()),
+        }
+    }
+
+    fn send(&self, replies: MethodReplies) {
+        if let Some(s) = self.0.replies.upgrade() {
+            // Ignore send errors, the remote might have disconnected during our processing.
+            for m in replies { let _ = s.send(m); }
+        }
+    }
+
+    fn poll_calls(&self, ctx: &mut Context) -> bool {
+        let mut done = false;
+        // Don't keep inner borrowed while calling the handlers, in case they call dispatch.
+        while let Some(msg) = self.next_call() {
+            let key = self.order_key(&msg);
+            if let Some(f) = self.0.tree.handle_async(msg) { self.0.inner.borrow_mut().running.push((key, f)) }
+        }
+        let mut running = mem::take(&mut self.0.inner.borrow_mut().running);
+        let mut i = 0;
+        while i < running.len() {
+            match running[i].1.as_mut().poll(ctx) {
+                Poll::Ready(r) => { drop(running.remove(i)); self.send(r); done = true; },
+                Poll::Pending => i += 1,
+            }
+        }
+        let mut inner = self.0.inner.borrow_mut();
+        running.append(&mut inner.running);
+        inner.running = running;
+        done
+    }
+}
+
+struct Run<D: DataType, S>(AsyncDispatcher<D, S>);
+
+impl<D: DataType + 'static, S: channel::Sender + 'static> Future for Run<D, S> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+        let d = &self.0;
+        // A finished call might allow a queued one to start, so keep going until nothing finishes.
+        while d.poll_calls(ctx) {}
+        let mut inner = d.0.inner.borrow_mut();
+        if Rc::strong_count(&d.0) == 1 && inner.queue.is_empty() && inner.running.is_empty() { return Poll::Ready(()) }
+        inner.waker = Some(ctx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<D: DataType + 'static> Tree<MTFuture<D>, D> {
+    /// Connects a Connection with a Tree so that incoming method calls are handled, several at a time.
+    ///
+    /// Returns the future that does the handling, which needs to be spawned. See AsyncDispatcher for details.
+    pub fn start_receive_async<C>(self, connection: &Rc<C>, max_running: usize, order: DispatchOrder) -> impl Future<Output = ()>
+    where
+        C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool>> + channel::Sender + 'static
+    {
+        let d = AsyncDispatcher::new(self, Rc::downgrade(connection), max_running, order);
+        let run = d.run();
+        let mut rule = message::MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        connection.start_receive(rule, Box::new(move |msg, _| { d.dispatch(msg); true }));
+        run
+    }
+}
+
+#[test]
+fn test_reply_order() {
+    use super::Factory;
+    use std::collections::HashSet;
+    use std::task::{RawWaker, RawWakerVTable};
+
+    // Resolves once the id has been released.
+    struct Gate(Rc<RefCell<HashSet<u32>>>, u32);
+    impl Future for Gate {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            if self.0.borrow().contains(&self.1) { Poll::Ready(()) } else { Poll::Pending }
+        }
+    }
+
+    fn raw() -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+    let waker = unsafe { Waker::from_raw(raw()) };
+
+    let run_test = |max_running, calls: &[(u32, Option<&'static str>)], releases: &[&[u32]]| -> Vec<u32> {
+        let released = Rc::new(RefCell::new(HashSet::new()));
+        let released2 = released.clone();
+        let f = Factory::new_future::<()>();
+        let t = f.tree(()).add(f.object_path("/gate", ()).add(f.interface("com.example.gate", ())
+            .add_m(f.method("Wait", (), move |m| {
+                let id: u32 = m.msg.read1().unwrap();
+                let (gate, mret) = (Gate(released2.clone(), id), m.msg.method_return());
+                async move { gate.await; Ok(mret.append1(id).into()) }
+            }))
+        ));
+        let replies = Rc::new(RefCell::new(vec!()));
+        let d = AsyncDispatcher::new(t, Rc::downgrade(&replies), max_running, DispatchOrder::PerSender);
+        let mut run = Box::pin(d.run());
+        for &(id, sender) in calls {
+            let mut m = Message::new_method_call("com.example.gate", "/gate", "com.example.gate", "Wait").unwrap().append1(id);
+            m.set_sender(sender.map(|s| s.into()));
+            message::message_set_serial(&mut m, id);
+            assert!(d.dispatch(m));
+        }
+        let mut ctx = Context::from_waker(&waker);
+        for r in releases {
+            released.borrow_mut().extend(r.iter());
+            assert!(run.as_mut().poll(&mut ctx).is_pending());
+        }
+        assert_eq!((d.running(), d.queued()), (0, 0));
+        drop(d);
+        assert!(run.as_mut().poll(&mut ctx).is_ready());
+        let r = replies.borrow().iter().map(|m: &Message| m.read1().unwrap()).collect();
+        r
+    };
+
+    let calls = [(1, Some(":1.1")), (2, Some(":1.1")), (3, Some(":1.2")), (4, Some(":1.3"))];
+    assert_eq!(run_test(1, &calls, &[&[], &[3, 4], &[2], &[1]]), vec!(1, 2, 3, 4));
+    assert_eq!(run_test(2, &calls, &[&[], &[3], &[4, 2, 1]]), vec!(3, 1, 4, 2));
+    assert_eq!(run_test(10, &calls, &[&[2], &[4], &[1, 3]]), vec!(4, 1, 3, 2));
+    // Calls without a sender are not ordered
+    let calls = [(1, None), (2, None), (3, Some(":1.2"))];
+    assert_eq!(run_test(10, &calls, &[&[2], &[3, 1]]), vec!(2, 1, 3));
+}
@@ -0,0 +1,114 @@
+// Only handling method calls that are meant for this connection.
+
+use crate::{Message, MessageType};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+/// The bus names a Tree answers method calls for.
+///
+/// Normally every method call that reaches a connection is meant for it, but a connection that also
+/// monitors or forwards traffic sees calls meant for others. Attach this to a Tree with `Tree::destinations`,
+/// and method calls with a destination that is not in the set are passed through instead of handled.
+/// Method calls without a destination (e g on peer-to-peer connections) are always handled.
+///
+/// The set is shared between clones. Names can be added and removed by hand; in addition the Tree
+/// updates the set when it sees a NameAcquired or NameLost signal from the bus, sent to a unique name
+/// in the set. So if all incoming messages (not just method calls) are passed to `Tree::handle`, e g
+/// through `TreeServer` or `Tree::start_receive`, well-known names are tracked automatically.
+#[derive(Clone, Debug, Default)]
+pub struct Destinations(Arc<Mutex<BTreeSet<String>>>);
+
+impl Destinations {
+    /// Creates a new, empty set. Add at least the unique name of the connection.
+    pub fn new() -> Self { Default::default() }
+
+    /// Creates a new set, containing the unique name of "c".
+    pub fn for_channel(c: &crate::channel::Channel) -> Self {
+        let d = Self::new();
+        if let Some(n) = c.unique_name() { d.insert(n) }
+        d
+    }
+
+    /// Builder function that adds a name.
+    pub fn name<N: Into<String>>(self, n: N) -> Self { self.insert(n); self }
+
+    /// Adds a name, e g after a successful call to RequestName.
+    pub fn insert<N: Into<String>>(&self, n: N) { self.0.lock().unwrap().insert(n.into()); }
+
+    /// Removes a name. Returns false if the name was not in the set.
+    pub fn remove(&self, n: &str) -> bool { self.0.lock().unwrap().remove(n) }
+
+    /// Returns true if "n" is in the set.
+    pub fn contains(&self, n: &str) -> bool { self.0.lock().unwrap().contains(n) }
+
+    /// Returns true if "m" should be handled, i e it has no destination, or one that is in the set.
+    pub fn accepts(&self, m: &Message) -> bool {
+        match m.destination() { Some(d) => self.contains(&d), None => true }
+    }
+
+    /// Updates the set if "m" is a NameAcquired or NameLost signal from the bus. Returns true if it was.
+    ///
+    /// The signal must be sent to a unique name in the set, so that signals meant for other
+    /// connections (e g when monitoring) are ignored.
+    pub fn update(&self, m: &Message) -> bool {
+        if m.msg_type() != MessageType::Signal || m.sender().as_deref() != Some("org.freedesktop.DBus") ||
+            m.interface().as_deref() != Some("org.freedesktop.DBus") { return false }
+        if !m.destination().map(|d| d.starts_with(':') && self.contains(&d)).unwrap_or(false) { return false }
+        let n = match m.read1::<&str>() { Ok(n) => n, Err(_) => return false };
+        match m.member().as_deref() {
+            Some("NameAcquired") => self.insert(n),
+            Some("NameLost") => { self.remove(n); },
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[test]
+fn test_destinations() {
+    use super::Factory;
+    let f = Factory::new_fn::<()>();
+    let d = Destinations::new().name(":1.5");
+    let t = f.tree(()).destinations(d.clone()).add(f.object_path("/dest", ()).add(f.interface("com.example.dest", ())
+        .add_m(f.method("Hi", (), |m| Ok(m.msg.method_return().into())))));
+    let call = |dest: Option<&str>| {
+        let mut m = Message::new_method_call("com.example.dest", "/dest", "com.example.dest", "Hi").unwrap();
+        m.set_destination(dest.map(|d| d.into()));
+        crate::message::message_set_serial(&mut m, 1);
+        t.handle(&m).is_some()
+    };
+    assert!(call(Some(":1.5")));
+    assert!(call(None));
+    assert!(!call(Some(":1.6")));
+    assert!(!call(Some("com.example.dest")));
+
+    let mut s = Message::new_signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameAcquired").unwrap().append1("com.example.dest");
+    s.set_sender(Some("org.freedesktop.DBus".into()));
+    // Meant for someone else
+    s.set_destination(Some(":1.6".into()));
+    assert!(t.handle(&s).is_none());
+    assert!(!call(Some("com.example.dest")));
+    s.set_destination(Some(":1.5".into()));
+    assert!(t.handle(&s).is_none());
+    assert!(call(Some("com.example.dest")));
+
+    let mut s = Message::new_signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameLost").unwrap().append1("com.example.dest");
+    s.set_destination(Some(":1.5".into()));
+    assert!(!d.update(&s));
+    s.set_sender(Some("org.freedesktop.DBus".into()));
+    assert!(d.update(&s));
+    assert!(!call(Some("com.example.dest")));
+    assert!(d.contains(":1.5"));
+
+    // Through start_receive, the names are tracked too
+    let mut c = crate::blocking::LocalConnection::new_session().unwrap();
+    let d = Destinations::new().name(c.unique_name().to_string());
+    f.tree(()).destinations(d.clone()).start_receive(&c);
+    let n = "com.example.dbusrs.destinations";
+    c.request_name(n, false, false, true).unwrap();
+    for _ in 0..50 {
+        if d.contains(n) { break }
+        c.process(std::time::Duration::from_millis(100)).unwrap();
+    }
+    assert!(d.contains(n));
+}
@@ -1,10 +1,11 @@
-use super::{MethodType, DataType, MTFn, MTFnMut, MTSync, MethodResult, MethodInfo};
-use super::{Tree, ObjectPath, Interface, Property, Signal, Method};
+use super::{MethodType, DataType, MTFn, MTFnMut, MTSync, MTFuture, MethodFuture, MethodResult, MethodInfo};
+use super::{Tree, ObjectPath, Interface, Mixin, Property, Signal, Method};
 use super::objectpath::IfaceCache;
 use std::sync::Arc;
 use crate::strings::{Interface as IfaceName, Member};
 use crate::{Path, arg};
 use std::cell::RefCell;
+use std::future::Future;
 
 /// The factory is used to create object paths, interfaces, methods etc.
 ///
@@ -13,10 +14,15 @@ use std::cell::RefCell;
 ///  **MTFn** - all methods are `Fn()`.
 ///
 ///  **MTFnMut** - all methods are `FnMut()`. This means they can mutate their environment,
-///  which has the side effect that if you call it recursively, it will RefCell panic.
+///  so simple services can keep their state in captured variables. The tree takes care of the
+///  borrowing; a handler that ends up being called recursively returns an error instead.
 ///
 ///  **MTSync** - all methods are `Fn() + Send + Sync + 'static`. This means that the methods
-///  can be called from different threads in parallel.
+///  can be called from different threads in parallel, e g through `Tree::start_receive_parallel`,
+///  or by sharing the tree between several connections with `Tree::start_receive_shared`.
+///
+///  **MTFuture** - all methods are `Fn()` returning a future, so they can await other D-Bus calls
+///  or IO before replying. Such a tree must be handled by `Tree::handle_async` or an `AsyncDispatcher`.
 ///
 #[derive(Debug, Clone)]
 pub struct Factory<M: MethodType<D>, D: DataType=()>(Arc<IfaceCache<M, D>>);
@@ -34,6 +40,9 @@ impl Factory<MTFn<()>, ()> {
 
     /// Creates a new factory for multi-thread use.
     pub fn new_sync<D: DataType>() -> Factory<MTSync<D>, D> { Factory(IfaceCache::new()) }
+
+    /// Creates a new factory for single-thread use, where methods return futures.
+    pub fn new_future<D: DataType>() -> Factory<MTFuture<D>, D> { Factory(IfaceCache::new()) }
    
 }
 
@@ -43,6 +52,12 @@ impl<D: DataType> Factory<MTFn<D>, D> {
         where H: 'static + Fn(&MethodInfo<MTFn<D>, D>) -> MethodResult, T: Into<Member<'static>> {
         super::leaves::new_method(t.into(), data, Box::new(handler) as Box<_>)
     }
+
+    /// Creates a new method, or returns an error if the name is not a valid member name.
+    pub fn try_method<H, T>(&self, t: T, data: D::Method, handler: H) -> Result<Method<MTFn<D>, D>, String>
+        where H: 'static + Fn(&MethodInfo<MTFn<D>, D>) -> MethodResult, T: Into<Vec<u8>> {
+        Ok(self.method(Member::new(t)?, data, handler))
+    }
 }
 
 impl<D: DataType> Factory<MTFnMut<D>, D> {
@@ -51,6 +66,28 @@ impl<D: DataType> Factory<MTFnMut<D>, D> {
         where H: 'static + FnMut(&MethodInfo<MTFnMut<D>, D>) -> MethodResult, T: Into<Member<'static>> {
         super::leaves::new_method(t.into(), data, Box::new(RefCell::new(handler)) as Box<_>)
     }
+
+    /// Creates a new method, or returns an error if the name is not a valid member name.
+    pub fn try_method<H, T>(&self, t: T, data: D::Method, handler: H) -> Result<Method<MTFnMut<D>, D>, String>
+        where H: 'static + FnMut(&MethodInfo<MTFnMut<D>, D>) -> MethodResult, T: Into<Vec<u8>> {
+        Ok(self.method(Member::new(t)?, data, handler))
+    }
+}
+
+impl<D: DataType> Factory<MTFuture<D>, D> {
+    /// Creates a new method, which returns a future that resolves into the method result.
+    pub fn method<H, F, T>(&self, t: T, data: D::Method, handler: H) -> Method<MTFuture<D>, D>
+        where H: 'static + Fn(&MethodInfo<MTFuture<D>, D>) -> F, F: 'static + Future<Output = MethodResult>,
+        T: Into<Member<'static>> {
+        super::leaves::new_method(t.into(), data, Box::new(move |minfo: &MethodInfo<_, _>| Box::pin(handler(minfo)) as MethodFuture) as Box<_>)
+    }
+
+    /// Creates a new method, or returns an error if the name is not a valid member name.
+    pub fn try_method<H, F, T>(&self, t: T, data: D::Method, handler: H) -> Result<Method<MTFuture<D>, D>, String>
+        where H: 'static + Fn(&MethodInfo<MTFuture<D>, D>) -> F, F: 'static + Future<Output = MethodResult>,
+        T: Into<Vec<u8>> {
+        Ok(self.method(Member::new(t)?, data, handler))
+    }
 }
 
 impl<D: DataType> Factory<MTSync<D>, D> {
@@ -59,6 +96,12 @@ impl<D: DataType> Factory<MTSync<D>, D> {
         where H: Fn(&MethodInfo<MTSync<D>, D>) -> MethodResult + Send + Sync + 'static, T: Into<Member<'static>> {
         super::leaves::new_method(t.into(), data, Box::new(handler) as Box<_>)
     }
+
+    /// Creates a new method, or returns an error if the name is not a valid member name.
+    pub fn try_method<H, T>(&self, t: T, data: D::Method, handler: H) -> Result<Method<MTSync<D>, D>, String>
+        where H: Fn(&MethodInfo<MTSync<D>, D>) -> MethodResult + Send + Sync + 'static, T: Into<Vec<u8>> {
+        Ok(self.method(Member::new(t)?, data, handler))
+    }
 }
 
 
@@ -77,16 +120,40 @@ impl<M: MethodType<D>, D: DataType> Factory<M, D> {
         super::leaves::new_signal(name.into(), data)
     }
 
+    /// Creates a new signal, or returns an error if the name is not a valid member name.
+    pub fn try_signal<T: Into<Vec<u8>>>(&self, name: T, data: D::Signal) -> Result<Signal<D>, String> {
+        Ok(self.signal(Member::new(name)?, data))
+    }
+
+    /// Creates a new, empty mixin, i e a set of methods, properties and signals
+    /// that can be added to several interfaces.
+    pub fn mixin(&self) -> Mixin<M, D> {
+        super::objectpath::new_mixin()
+    }
+
     /// Creates a new interface.
     pub fn interface<T: Into<IfaceName<'static>>>(&self, name: T, data: D::Interface) -> Interface<M, D> {
         super::objectpath::new_interface(name.into(), data)
     }
 
+    /// Creates a new interface, or returns an error if the name is not a valid interface name.
+    ///
+    /// Use this instead of `interface` when the name comes from somewhere else than your source code,
+    /// as converting an invalid string into an interface name panics.
+    pub fn try_interface<T: Into<Vec<u8>>>(&self, name: T, data: D::Interface) -> Result<Interface<M, D>, String> {
+        Ok(self.interface(IfaceName::new(name)?, data))
+    }
+
     /// Creates a new object path.
     pub fn object_path<T: Into<Path<'static>>>(&self, name: T, data: D::ObjectPath) -> ObjectPath<M, D> {
         super::objectpath::new_objectpath(name.into(), data, self.0.clone())
     }
 
+    /// Creates a new object path, or returns an error if the name is not a valid object path.
+    pub fn try_object_path<T: Into<Vec<u8>>>(&self, name: T, data: D::ObjectPath) -> Result<ObjectPath<M, D>, String> {
+        Ok(self.object_path(Path::new(name)?, data))
+    }
+
     /// Creates a new tree.
     pub fn tree(&self, data: D::Tree) -> Tree<M, D> {
         super::objectpath::new_tree(data)
@@ -108,7 +175,7 @@ fn create_fnmut() {
     let mut move_me = 5u32;
     let m = f.method("test", (), move |m| {
         move_me += 1; 
-        Ok(vec!(m.msg.method_return().append1(&move_me)))
+        Ok(m.msg.method_return().append1(&move_me).into())
     });
     assert_eq!(&**m.get_name(), "test");
 }
@@ -135,3 +202,16 @@ fn fn_customdata() {
     let o = f.object_path("/test/test", Arc::new(7));
     assert_eq!(**o.get_data(), 7);
 }
+
+#[test]
+fn try_names() {
+    let f = Factory::new_fn::<()>();
+    assert!(f.try_interface("com.example.my-iface", ()).is_err());
+    assert!(f.try_interface("com.example.my_iface", ()).is_ok());
+    assert!(f.try_object_path("/example/", ()).is_err());
+    assert!(f.try_object_path("/example/path", ()).is_ok());
+    assert!(f.try_signal("Bad.Signal", ()).is_err());
+    assert!(f.try_method("", (), |_| Err(super::MethodErr::failed("Not called"))).is_err());
+    let m = f.try_method(String::from("Good"), (), |_| Err(super::MethodErr::failed("Not called"))).unwrap();
+    assert_eq!(&**m.get_name(), "Good");
+}
@@ -0,0 +1,176 @@
+// Exiting a service when nobody has used it for a while.
+
+use crate::Error;
+use crate::blocking::BlockingSender;
+use crate::blocking::stdintf::org_freedesktop_dbus;
+use crate::strings::WellKnownName;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::fmt;
+
+type IdleFn = Box<dyn FnOnce() + Send + 'static>;
+
+struct IdleState {
+    timeout: Duration,
+    last: Instant,
+    in_flight: usize,
+    name: Option<WellKnownName<'static>>,
+    on_idle: Option<IdleFn>,
+    done: bool,
+}
+
+/// Keeps track of the activity of a Tree, so that a service can exit after being idle for a while.
+///
+/// This is the usual pattern for services started by D-Bus activation: once nobody has called a method
+/// for some time, the service releases its well-known name (so that the next call to that name starts a
+/// new instance, instead of going to one that is about to exit) and shuts down.
+///
+/// Attach to a Tree with `Tree::idle_exit`, which counts every method call to one of its objects as activity. A method call
+/// counts as in flight until its replies are ready, which for `MTFuture` trees includes waiting for the future.
+/// Then call `check` regularly from the main loop, e g after every call to `process`.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::{tree, blocking::LocalConnection};
+/// use std::time::Duration;
+/// let mut c = LocalConnection::new_session().unwrap();
+/// c.request_name("com.example.Activated", false, true, false).unwrap();
+/// let idle = tree::IdleExit::new(Duration::from_secs(30)).release_name("com.example.Activated");
+/// let f = tree::Factory::new_fn::<()>();
+/// let t = f.tree(()).idle_exit(idle.clone()).add(f.object_path("/", ()).introspectable());
+/// t.start_receive(&c);
+/// while !idle.check(&c).unwrap() { c.process(idle.time_left()).unwrap(); }
+/// // Handle method calls that arrived before the name was released.
+/// while c.process(Duration::from_millis(0)).unwrap() {}
+/// ```
+#[derive(Clone)]
+pub struct IdleExit(Arc<Mutex<IdleState>>);
+
+impl fmt::Debug for IdleExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.0.lock().unwrap();
+        f.debug_struct("IdleExit").field("timeout", &s.timeout).field("in_flight", &s.in_flight)
+            .field("name", &s.name).field("done", &s.done).finish()
+    }
+}
+
+/// Counts as an in-flight method call until dropped. Returned from `IdleExit::activity`.
+#[derive(Debug)]
+pub struct Activity(IdleExit);
+
+impl Drop for Activity {
+    fn drop(&mut self) {
+        let mut s = (self.0).0.lock().unwrap();
+        s.in_flight -= 1;
+        s.last = Instant::now();
+    }
+}
+
+impl IdleExit {
+    /// Creates a new tracker, that considers the service idle after "timeout" without any method calls.
+    pub fn new(timeout: Duration) -> Self {
+        IdleExit(Arc::new(Mutex::new(IdleState { timeout, last: Instant::now(), in_flight: 0, name: None, on_idle: None, done: false })))
+    }
+
+    /// Builder function that sets a well-known name to release before shutting down.
+    pub fn release_name<N: Into<WellKnownName<'static>>>(self, name: N) -> Self {
+        self.0.lock().unwrap().name = Some(name.into()); self
+    }
+
+    /// Builder function that sets a callback to call (once) when shutting down.
+    pub fn on_idle<F: FnOnce() + Send + 'static>(self, f: F) -> Self {
+        self.0.lock().unwrap().on_idle = Some(Box::new(f)); self
+    }
+
+    /// Marks the start of a method call, which lasts until the returned value is dropped.
+    ///
+    /// Trees call this themselves; use it for other work that should keep the service alive.
+    pub fn activity(&self) -> Activity {
+        self.0.lock().unwrap().in_flight += 1;
+        Activity(self.clone())
+    }
+
+    /// Restarts the idle period without starting a method call.
+    pub fn touch(&self) { self.0.lock().unwrap().last = Instant::now(); }
+
+    /// Returns how long the service can stay idle before `check` shuts it down.
+    ///
+    /// This is a suitable timeout for `process`. It is the full timeout while a method call is in flight.
+    pub fn time_left(&self) -> Duration {
+        let s = self.0.lock().unwrap();
+        if s.in_flight > 0 { s.timeout } else { s.timeout.checked_sub(s.last.elapsed()).unwrap_or_default() }
+    }
+
+    /// Returns true if the service has been idle for long enough (and no method call is in flight).
+    pub fn is_idle(&self) -> bool {
+        let s = self.0.lock().unwrap();
+        s.done || (s.in_flight == 0 && s.last.elapsed() >= s.timeout)
+    }
+
+    /// Shuts down if the service has been idle for long enough.
+    ///
+    /// That means releasing the well-known name (if set) through "conn" and then calling the on_idle callback
+    /// (if set). Returns true if the service has shut down, now or in an earlier call, in which case
+    /// the main loop should stop.
+    pub fn check<C: BlockingSender>(&self, conn: &C) -> Result<bool, Error> {
+        if !self.is_idle() { return Ok(false) }
+        let (name, f) = {
+            let mut s = self.0.lock().unwrap();
+            if s.done { return Ok(true) }
+            s.done = true;
+            (s.name.take(), s.on_idle.take())
+        };
+        trace_event!(name = ?name, "Idle, shutting down");
+        if let Some(name) = name { org_freedesktop_dbus::release_name(conn, &name)?; }
+        if let Some(f) = f { f() }
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_idle_exit() {
+    use super::Factory;
+    use crate::{Message, blocking::Connection};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let called = Arc::new(AtomicBool::new(false));
+    let called2 = called.clone();
+    let idle = IdleExit::new(Duration::from_millis(100)).on_idle(move || called2.store(true, Ordering::SeqCst));
+    let a = idle.activity();
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(!idle.is_idle());
+    assert_eq!(idle.time_left(), Duration::from_millis(100));
+    drop(a);
+    assert!(!idle.is_idle());
+    assert!(idle.time_left() > Duration::from_millis(50));
+
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).idle_exit(idle.clone()).add(f.object_path("/idle", ()).introspectable());
+    std::thread::sleep(Duration::from_millis(150));
+    assert!(idle.is_idle());
+    // Only method calls to objects in the tree count.
+    let mut m = Message::new_signal("/idle", "com.example.dbusrs", "Ping").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    assert!(t.handle(&m).is_none());
+    let mut m = Message::new_method_call("com.example.dbusrs", "/unknown", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    assert!(t.handle(&m).is_none());
+    assert!(idle.is_idle());
+    let mut m = Message::new_method_call("com.example.dbusrs", "/idle", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    t.handle(&m).unwrap();
+    assert!(!idle.is_idle());
+
+    let c = Connection::new_session().unwrap();
+    let name = "com.example.dbusrs.idle";
+    c.request_name(name, false, true, false).unwrap();
+    let idle = idle.release_name(name);
+    assert!(!idle.check(&c).unwrap());
+    assert!(!called.load(Ordering::SeqCst));
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(idle.time_left(), Duration::from_millis(0));
+    assert!(idle.check(&c).unwrap());
+    assert!(called.load(Ordering::SeqCst));
+    assert_eq!(c.release_name(name).unwrap(), org_freedesktop_dbus::ReleaseNameReply::NonExistent);
+    assert!(idle.check(&c).unwrap());
+}
@@ -1,10 +1,11 @@
 // Methods, signals, properties, and interfaces.
-use super::utils::{Argument, Annotations, Introspect, introspect_args};
-use super::{MethodType, MethodInfo, MethodResult, MethodErr, DataType, PropInfo, MTFn, MTFnMut, MTSync};
-use crate::strings::{Interface as IfaceName, Member, Signature, Path};
-use crate::{arg, Message};
+use super::utils::{Argument, Annotation, Annotations, Introspect, introspect_args, Names};
+use super::{MethodType, MethodInfo, MethodResult, MethodErr, DataType, PropInfo, MTFn, MTFnMut, MTSync, MTFuture};
+use crate::strings::{Interface as IfaceName, Member, Signature, Path, BusName};
+use crate::{arg, channel, Message};
 use std::fmt;
 use std::cell::RefCell;
+use std::sync::Arc;
 use crate::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
 
 
@@ -28,7 +29,7 @@ impl<M: MethodType<D>, D: DataType> fmt::Debug for DebugSetProp<M, D> {
 pub struct Method<M: MethodType<D>, D: DataType> {
     cb: DebugMethod<M, D>,
     data: D::Method,
-    name: Member<'static>,
+    name: Arc<Member<'static>>,
     i_args: Vec<Argument>,
     o_args: Vec<Argument>,
     anns: Annotations,
@@ -58,8 +59,14 @@ impl<M: MethodType<D>, D: DataType> Method<M, D> {
     pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
         self.anns.insert(name, value); self
     }
+    /// Builder method that adds a well-known annotation to the method.
+    pub fn annotation(self, a: Annotation) -> Self { let (n, v) = a.into_pair(); self.annotate(n, v) }
     /// Builder method that adds an annotation that this entity is deprecated.
-    pub fn deprecated(self) -> Self { self.annotate("org.freedesktop.DBus.Deprecated", "true") }
+    pub fn deprecated(self) -> Self { self.annotation(Annotation::Deprecated) }
+    /// Builder method that adds an annotation that this method does not reply.
+    ///
+    /// This only informs callers; the method should still return an empty list of messages.
+    pub fn no_reply(self) -> Self { self.annotation(Annotation::NoReply) }
 
     /// Call the Method
     pub fn call(&self, minfo: &MethodInfo<M, D>) -> MethodResult { M::call_method(&self.cb.0, minfo) }
@@ -70,6 +77,15 @@ impl<M: MethodType<D>, D: DataType> Method<M, D> {
     /// Get associated data
     pub fn get_data(&self) -> &D::Method { &self.data }
 
+    pub (super) fn name_arc(&self) -> &Arc<Member<'static>> { &self.name }
+
+    // Makes the method use the tree's copy of its name, unless the method is shared. See `Names`.
+    pub (super) fn intern_name(m: &mut Arc<Self>, names: &mut Names) -> Arc<Member<'static>> {
+        let n = names.member(&m.name);
+        if let Some(m) = Arc::get_mut(m) { m.name = n.clone() }
+        n
+    }
+
 }
 
 impl<M: MethodType<D>, D: DataType> Introspect for Method<M, D> {
@@ -84,7 +100,7 @@ impl<M: MethodType<D>, D: DataType> Introspect for Method<M, D> {
 }
 
 pub fn new_method<M: MethodType<D>, D: DataType>(n: Member<'static>, data: D::Method, cb: Box<M::Method>) -> Method<M, D> {
-    Method { name: n, i_args: vec!(), o_args: vec!(), anns: Annotations::new(), cb: DebugMethod(cb), data: data }
+    Method { name: Arc::new(n), i_args: vec!(), o_args: vec!(), anns: Annotations::new(), cb: DebugMethod(cb), data: data }
 }
 
 
@@ -92,7 +108,7 @@ pub fn new_method<M: MethodType<D>, D: DataType>(n: Member<'static>, data: D::Me
 #[derive(Debug)]
 /// A D-Bus Signal.
 pub struct Signal<D: DataType> {
-    name: Member<'static>,
+    name: Arc<Member<'static>>,
     data: D::Signal,
     arguments: Vec<Argument>,
     anns: Annotations,
@@ -115,8 +131,10 @@ impl<D: DataType> Signal<D> {
     pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
         self.anns.insert(name, value); self
     }
+    /// Builder method that adds a well-known annotation to the signal.
+    pub fn annotation(self, a: Annotation) -> Self { let (n, v) = a.into_pair(); self.annotate(n, v) }
     /// Add an annotation that this entity is deprecated.
-    pub fn deprecated(self) -> Self { self.annotate("org.freedesktop.DBus.Deprecated", "true") }
+    pub fn deprecated(self) -> Self { self.annotation(Annotation::Deprecated) }
 
     /// Get signal name
     pub fn get_name(&self) -> &Member<'static> { &self.name }
@@ -124,6 +142,15 @@ impl<D: DataType> Signal<D> {
     /// Get associated data
     pub fn get_data(&self) -> &D::Signal { &self.data }
 
+    pub (super) fn name_arc(&self) -> &Arc<Member<'static>> { &self.name }
+
+    // Makes the signal use the tree's copy of its name, unless the signal is shared. See `Names`.
+    pub (super) fn intern_name(m: &mut Arc<Self>, names: &mut Names) -> Arc<Member<'static>> {
+        let n = names.member(&m.name);
+        if let Some(m) = Arc::get_mut(m) { m.name = n.clone() }
+        n
+    }
+
     /// Returns a message which emits the signal when sent.
     ///
     /// Same as "msg" but also takes a list of arguments to send.
@@ -141,6 +168,24 @@ impl<D: DataType> Signal<D> {
         Message::signal(p, i, &self.name)
     }
 
+    /// Returns a message which emits the signal to a single peer when sent.
+    ///
+    /// Same as "emit", but the signal is only delivered to "dest" instead of being broadcast
+    /// to everyone with a matching match rule.
+    pub fn emit_to<A: arg::Append>(&self, p: &Path<'static>, i: &IfaceName<'static>, dest: &BusName, items: &[A]) -> Message {
+        let mut m = self.emit(p, i, items);
+        m.set_destination(Some(dest.clone()));
+        m
+    }
+
+    /// Emits the signal by sending it through "s", which is typically a connection.
+    ///
+    /// Same as "emit", but also sends the message. Returns the serial of the sent message.
+    #[allow(clippy::result_unit_err)] // Same error type as channel::Sender
+    pub fn send<S: channel::Sender + ?Sized, A: arg::Append>(&self, s: &S, p: &Path<'static>, i: &IfaceName<'static>, items: &[A]) -> Result<u32, ()> {
+        s.send(self.emit(p, i, items))
+    }
+
 }
 
 impl<D: DataType> Introspect for Signal<D> {
@@ -154,7 +199,7 @@ impl<D: DataType> Introspect for Signal<D> {
 }
 
 pub fn new_signal<D: DataType>(n: Member<'static>, data: D::Signal) -> Signal<D> {
-    Signal { name: n, arguments: vec!(), anns: Annotations::new(), data: data }
+    Signal { name: Arc::new(n), arguments: vec!(), anns: Annotations::new(), data: data }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Debug)]
@@ -210,6 +255,25 @@ pub fn prop_append_dict<'v, M: MethodType<D> + 'v, D: DataType + 'v, I: Iterator
     result
 }
 
+// Like prop_append_dict, but leaves out the properties whose getter fails, instead of failing.
+// Each value is fetched into a scratch message first, since a failing getter might already have appended something.
+pub fn prop_append_dict_lossy<'v, M: MethodType<D> + 'v, D: DataType + 'v, I: Iterator<Item=&'v Property<M, D>>>
+    (iter: &mut arg::IterAppend, props: I, minfo: &MethodInfo<M, D>) {
+
+    let mut values = vec!();
+    for p in props.filter(|p| p.can_get().is_ok()) {
+        let mut m = Message::new_signal("/", "org.freedesktop.DBus.Properties", "Get").unwrap();
+        let r = p.get_as_variant(&mut arg::IterAppend::new(&mut m), &minfo.to_prop_info(minfo.iface, p));
+        match r.map(|_| m.read1::<arg::Variant<Box<dyn arg::RefArg>>>()) {
+            Ok(Ok(v)) => values.push((p.get_name(), v)),
+            _ => { trace_event!(property = p.get_name(), "Getter failed, leaving the property out"); },
+        }
+    }
+    iter.append_dict(&Signature::make::<&str>(), &Signature::make::<arg::Variant<bool>>(), |subiter| {
+        for (name, v) in values { subiter.append_dict_entry(|e| { e.append(name); e.append(v); }) }
+    });
+}
+
 
 #[derive(Debug)]
 /// A D-Bus Property.
@@ -222,6 +286,8 @@ pub struct Property<M: MethodType<D>, D: DataType> {
     rw: Access,
     get_cb: Option<DebugGetProp<M, D>>,
     set_cb: Option<DebugSetProp<M, D>>,
+    changed_cb: Option<DebugSetProp<M, D>>,
+    affects: Vec<String>,
     anns: Annotations,
 }
 
@@ -266,9 +332,21 @@ impl<M: MethodType<D>, D: DataType> Property<M, D> {
     pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
         self.anns.insert(name, value); self
     }
+    /// Builder method that adds a well-known annotation to the property.
+    pub fn annotation(self, a: Annotation) -> Self { let (n, v) = a.into_pair(); self.annotate(n, v) }
 
     /// Builder method that adds an annotation that this entity is deprecated.
-    pub fn deprecated(self) -> Self { self.annotate("org.freedesktop.DBus.Deprecated", "true") }
+    pub fn deprecated(self) -> Self { self.annotation(Annotation::Deprecated) }
+
+    /// Builder method that declares other properties on the same interface, which
+    /// change when this property is set.
+    ///
+    /// This is for computed properties, i e properties whose getter computes the value
+    /// from other state. When this property is set, the properties given here are included
+    /// in the PropertiesChanged signal, according to their own "emits_changed" setting.
+    pub fn affects<I: IntoIterator<Item=S>, S: Into<String>>(mut self, names: I) -> Self {
+        self.affects.extend(names.into_iter().map(|s| s.into())); self
+    }
 
     /// Get property name
     pub fn get_name(&self) -> &str { &self.name }
@@ -304,8 +382,10 @@ impl<M: MethodType<D>, D: DataType> Property<M, D> {
         }
         if let Some(mut i) = i {
             let mut subiter = i.recurse(arg::Variant::<bool>::ARG_TYPE).ok_or_else(|| MethodErr::invalid_arg(&2))?;
-            if *subiter.signature() != *self.sig {
-               return Err(MethodErr::failed(&format!("Property {} cannot change type", &self.name)))
+            // A property of type "v" can hold a value of any type.
+            let sig = subiter.signature();
+            if *sig != *self.sig && &*self.sig != "v" {
+               return Err(MethodErr::wrong_prop_type(&self.name, &self.sig, &sig))
             }
         }
         Ok(())
@@ -318,13 +398,22 @@ impl<M: MethodType<D>, D: DataType> Property<M, D> {
     pub fn set_as_variant(&self, i: &mut arg::Iter, pinfo: &PropInfo<M, D>) -> Result<Option<Message>, MethodErr> {
         use crate::arg::Arg;
         let mut subiter = i.recurse(arg::Variant::<bool>::ARG_TYPE).ok_or_else(|| MethodErr::invalid_arg(&2))?;
+        let mut changediter = subiter;
         M::call_setprop(&*self.set_cb.as_ref().unwrap().0, &mut subiter, pinfo)?;
+        if let Some(ref cb) = self.changed_cb {
+            // The value has been committed at this point, so the Set call has succeeded anyway.
+            if let Err(_e) = M::call_setprop(&*cb.0, &mut changediter, pinfo) {
+                trace_event!(property = %self.name, error = ?_e, "on_changed failed");
+            }
+        }
         self.get_emits_changed_signal(pinfo)
     }
 
     /// Gets the signal (if any) associated with the Property.
     fn get_signal(&self, p: &PropInfo<M, D>) -> Message {
-        Message::signal(p.path.get_name(), &"org.freedesktop.DBus.Properties".into(), &"PropertiesChanged".into())
+        let (i, m) = unsafe { (IfaceName::from_slice_unchecked(b"org.freedesktop.DBus.Properties\0"),
+            Member::from_slice_unchecked(b"PropertiesChanged\0")) };
+        Message::signal(p.path.get_name(), &i, &m)
             .append1(&**p.iface.get_name())
     }
 
@@ -358,23 +447,20 @@ impl<M: MethodType<D>, D: DataType> Property<M, D> {
 
     fn get_emits_changed_signal(&self, m: &PropInfo<M, D>) -> Result<Option<Message>, MethodErr> {
         if !self.auto_emit { return Ok(None) }
-        match self.emits {
-            EmitsChangedSignal::False => Ok(None),
-            EmitsChangedSignal::Const => Err(MethodErr::ro_property(&self.name)),
-            EmitsChangedSignal::True => Ok(Some({
-                let mut s = self.get_signal(m);
-                {
-                    let mut iter = arg::IterAppend::new(&mut s);
-                    prop_append_dict(&mut iter, Some(self).into_iter(), &m.to_method_info())?;
-                    iter.append(arg::Array::<&str, _>::new(vec!()));
-                }
-                s
-            })),
-            EmitsChangedSignal::Invalidates => Ok(Some(self.get_signal(m).append2(
-                arg::Dict::<&str, arg::Variant<bool>, _>::new(vec!()),
-                arg::Array::new(Some(&*self.name).into_iter())
-            ))),
+        if self.emits == EmitsChangedSignal::Const { return Err(MethodErr::ro_property(&self.name)) }
+        let affected = m.iface.iter_p().filter(|p| self.affects.contains(&p.name)).map(|p| &**p);
+        let props: Vec<&Property<M, D>> = Some(self).into_iter().chain(affected).collect();
+        let changed = props.iter().filter(|p| p.emits == EmitsChangedSignal::True).cloned();
+        let invalidated: Vec<&str> = props.iter().filter(|p| p.emits == EmitsChangedSignal::Invalidates).map(|p| &*p.name).collect();
+        if changed.clone().next().is_none() && invalidated.is_empty() { return Ok(None) }
+
+        let mut s = self.get_signal(m);
+        {
+            let mut iter = arg::IterAppend::new(&mut s);
+            prop_append_dict(&mut iter, changed, &m.to_method_info())?;
+            iter.append(arg::Array::<&str, _>::new(invalidated));
         }
+        Ok(Some(s))
     }
 }
 
@@ -396,27 +482,76 @@ impl<'a, D: DataType> Property<MTFn<D>, D> {
         self.set_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
         self
     }
+
+    /// Sets a callback which is called after the property has been successfully set,
+    /// with the new value. The property has been set already, so an error from it does not fail the Set call.
+    pub fn on_changed<H>(mut self, handler: H) -> Property<MTFn<D>, D>
+        where H: 'static + Fn(&mut arg::Iter, &PropInfo<MTFn<D>, D>) -> Result<(), MethodErr> {
+        self.changed_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
+        self
+    }
 }
 
 
 impl<'a, D: DataType> Property<MTFnMut<D>, D> {
     /// Sets the callback for getting a property.
     ///
-    /// For single-thread use.
+    /// For single-thread use. The callback can mutate its environment.
     pub fn on_get<H>(mut self, handler: H) -> Property<MTFnMut<D>, D>
-        where H: 'static + Fn(&mut arg::IterAppend, &PropInfo<MTFnMut<D>, D>) -> Result<(), MethodErr> {
+        where H: 'static + FnMut(&mut arg::IterAppend, &PropInfo<MTFnMut<D>, D>) -> Result<(), MethodErr> {
         self.get_cb = Some(DebugGetProp(Box::new(RefCell::new(handler)) as Box<_>));
         self
     }
 
     /// Sets the callback for setting a property.
     ///
-    /// For single-thread use.
+    /// For single-thread use. The callback can mutate its environment.
     pub fn on_set<H>(mut self, handler: H) -> Property<MTFnMut<D>, D>
-        where H: 'static + Fn(&mut arg::Iter, &PropInfo<MTFnMut<D>, D>) -> Result<(), MethodErr> {
+        where H: 'static + FnMut(&mut arg::Iter, &PropInfo<MTFnMut<D>, D>) -> Result<(), MethodErr> {
         self.set_cb = Some(DebugSetProp(Box::new(RefCell::new(handler)) as Box<_>));
         self
     }
+
+    /// Sets a callback which is called after the property has been successfully set,
+    /// with the new value. The property has been set already, so an error from it does not fail the Set call.
+    pub fn on_changed<H>(mut self, handler: H) -> Property<MTFnMut<D>, D>
+        where H: 'static + FnMut(&mut arg::Iter, &PropInfo<MTFnMut<D>, D>) -> Result<(), MethodErr> {
+        self.changed_cb = Some(DebugSetProp(Box::new(RefCell::new(handler)) as Box<_>));
+        self
+    }
+}
+
+impl<D: DataType> Method<MTFuture<D>, D> {
+    // The future of the method, for Tree::handle_async.
+    pub (super) fn call_future(&self, minfo: &MethodInfo<MTFuture<D>, D>) -> super::MethodFuture { (self.cb.0)(minfo) }
+}
+
+impl<D: DataType> Property<MTFuture<D>, D> {
+    /// Sets the callback for getting a property.
+    ///
+    /// For single-thread use. Property callbacks are not async, only methods are.
+    pub fn on_get<H>(mut self, handler: H) -> Property<MTFuture<D>, D>
+        where H: 'static + Fn(&mut arg::IterAppend, &PropInfo<MTFuture<D>, D>) -> Result<(), MethodErr> {
+        self.get_cb = Some(DebugGetProp(Box::new(handler) as Box<_>));
+        self
+    }
+
+    /// Sets the callback for setting a property.
+    ///
+    /// For single-thread use. Property callbacks are not async, only methods are.
+    pub fn on_set<H>(mut self, handler: H) -> Property<MTFuture<D>, D>
+        where H: 'static + Fn(&mut arg::Iter, &PropInfo<MTFuture<D>, D>) -> Result<(), MethodErr> {
+        self.set_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
+        self
+    }
+
+    /// Sets a callback which is called after the property has been successfully set,
+    /// with the new value. The property has been set already, so an error from it does not fail the Set call.
+    pub fn on_changed<H>(mut self, handler: H) -> Property<MTFuture<D>, D>
+        where H: 'static + Fn(&mut arg::Iter, &PropInfo<MTFuture<D>, D>) -> Result<(), MethodErr> {
+        self.changed_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
+        self
+    }
 }
 
 impl<D: DataType> Property<MTSync<D>, D> {
@@ -437,9 +572,46 @@ impl<D: DataType> Property<MTSync<D>, D> {
         self.set_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
         self
     }
+
+    /// Sets a callback which is called after the property has been successfully set,
+    /// with the new value. The property has been set already, so an error from it does not fail the Set call.
+    pub fn on_changed<H>(mut self, handler: H) -> Property<MTSync<D>, D>
+        where H: Fn(&mut arg::Iter, &PropInfo<MTSync<D>, D>) -> Result<(), MethodErr> + Send + Sync + 'static {
+        self.changed_cb = Some(DebugSetProp(Box::new(handler) as Box<_>));
+        self
+    }
 }
 
 
+impl<M: MethodType<D>, D: DataType> Property<M, D> {
+    /// Sets the callback for getting a property - usually you'll use "on_get" instead.
+    ///
+    /// This is useful for being able to create properties in code which is generic over methodtype.
+    pub fn on_get_sync<H>(mut self, handler: H) -> Self
+        where H: Fn(&mut arg::IterAppend, &PropInfo<M, D>) -> Result<(), MethodErr> + Send + Sync + 'static {
+        self.get_cb = Some(DebugGetProp(M::make_getprop(handler)));
+        self
+    }
+
+    /// Sets the callback for setting a property - usually you'll use "on_set" instead.
+    ///
+    /// This is useful for being able to create properties in code which is generic over methodtype.
+    pub fn on_set_sync<H>(mut self, handler: H) -> Self
+        where H: Fn(&mut arg::Iter, &PropInfo<M, D>) -> Result<(), MethodErr> + Send + Sync + 'static {
+        self.set_cb = Some(DebugSetProp(M::make_setprop(handler)));
+        self
+    }
+
+    /// Sets the callback called after the property has been set - usually you'll use "on_changed" instead.
+    ///
+    /// This is useful for being able to create properties in code which is generic over methodtype.
+    pub fn on_changed_sync<H>(mut self, handler: H) -> Self
+        where H: Fn(&mut arg::Iter, &PropInfo<M, D>) -> Result<(), MethodErr> + Send + Sync + 'static {
+        self.changed_cb = Some(DebugSetProp(M::make_setprop(handler)));
+        self
+    }
+}
+
 impl<M: MethodType<D>, D: DataType> Property<M, D> where D::Property: arg::Append + Clone {
     /// Adds a "standard" get handler.
     pub fn default_get(mut self) -> Self {
@@ -479,7 +651,7 @@ pub fn new_property<M: MethodType<D>, D: DataType>
     (n: String, sig: Signature<'static>, data: D::Property) -> Property<M, D> {
     Property {
         name: n, emits: EmitsChangedSignal::True, auto_emit: true, rw: Access::Read,
-        sig: sig, anns: Annotations::new(), set_cb: None, get_cb: None, data: data
+        sig: sig, anns: Annotations::new(), set_cb: None, get_cb: None, changed_cb: None, affects: vec!(), data: data
     }
 }
 
@@ -552,7 +724,7 @@ fn test_set_prop() {
     use std::rc::Rc;
  
     let changes = Rc::new(Cell::new(0i32));
-    let (changes1, changes2) = (changes.clone(), changes.clone());
+    let (changes1, changes2, changes3) = (changes.clone(), changes.clone(), changes.clone());
     let setme = Rc::new(RefCell::new("I have not been set yet!".to_owned()));
     let (setme1, setme2) = (setme.clone(), setme.clone());
 
@@ -569,6 +741,10 @@ fn test_set_prop() {
                     changes2.set(changes2.get() + 1);
                     Ok(())
                 }))
+            .add_p(f.property::<arg::Variant<i32>,_>("anything", ())
+                .access(Access::ReadWrite)
+                .on_get(|i, _| { i.append(arg::Variant(0i32)); Ok(()) })
+                .on_set(move |i, _| { assert_eq!(i.get::<i32>(), Some(8)); changes3.set(changes3.get() + 1); Ok(()) }))
         )
     );
     
@@ -584,7 +760,18 @@ fn test_set_prop() {
         .append3("com.example.dbus.rs", "setme", arg::Variant(8i32));
     crate::message::message_set_serial(&mut msg, 30);
     let mut r = tree.handle(&msg).unwrap();
-    assert!(r.get_mut(0).unwrap().as_result().is_err());
+    let e = r.get_mut(0).unwrap().as_result().unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+    assert_eq!(e.message(), Some("Property setme has type s, cannot set it to a value of type i"));
+    assert_eq!(changes.get(), 0);
+
+    // Any type goes for a variant
+    let mut msg = Message::new_method_call("com.example.dbus.rs", "/example", "org.freedesktop.DBus.Properties", "Set").unwrap()
+        .append3("com.example.dbus.rs", "anything", arg::Variant(8i32));
+    crate::message::message_set_serial(&mut msg, 30);
+    let mut r = tree.handle(&msg).unwrap();
+    assert!(r.get_mut(0).unwrap().as_result().is_ok());
+    assert_eq!(changes.get(), 1);
 
     // Correct!
     let mut msg = Message::new_method_call("com.example.dbus.rs", "/example", "org.freedesktop.DBus.Properties", "Set").unwrap()
@@ -592,7 +779,7 @@ fn test_set_prop() {
     crate::message::message_set_serial(&mut msg, 30);
     let r = tree.handle(&msg).unwrap();
 
-    assert_eq!(changes.get(), 1);
+    assert_eq!(changes.get(), 2);
     assert_eq!(&**setme.borrow(), "Correct");
 
     println!("{:?}", r);
@@ -651,3 +838,110 @@ fn test_sync_prop() {
    }
    assert_eq!(count.load(Ordering::SeqCst), 5);
 }
+
+#[test]
+fn test_fnmut_state() {
+    use crate::tree::Factory;
+
+    let f = Factory::new_fnmut::<()>();
+    let mut calls = 0u32;
+    let mut reads = 0u32;
+    let tree = f.tree(()).add(f.object_path("/example", ()).introspectable()
+        .add(f.interface("com.example.dbus.rs", ())
+            .add_m(f.method("Count", (), move |m| {
+                calls += 1;
+                Ok(m.msg.method_return().append1(calls).into())
+            }))
+            .add_m(f.method("Recurse", (), |m| {
+                let r = m.tree.handle(m.msg).unwrap();
+                Ok(r.into_iter().collect())
+            }))
+            .add_p(f.property::<u32,_>("Reads", ())
+                .on_get(move |i, _| { reads += 1; i.append(reads); Ok(()) }))
+        )
+    );
+
+    for i in 1..4u32 {
+        let mut msg = Message::new_method_call("com.example.dbus.rs", "/example", "com.example.dbus.rs", "Count").unwrap();
+        crate::message::message_set_serial(&mut msg, i);
+        let r = tree.handle(&msg).unwrap();
+        assert_eq!(r[0].read1::<u32>().unwrap(), i);
+
+        let mut msg = Message::new_method_call("com.example.dbus.rs", "/example", "org.freedesktop.DBus.Properties", "Get").unwrap()
+            .append2("com.example.dbus.rs", "Reads");
+        crate::message::message_set_serial(&mut msg, 10 + i);
+        let r = tree.handle(&msg).unwrap();
+        assert_eq!(r[0].read1::<arg::Variant<u32>>().unwrap().0, i);
+    }
+
+    let mut msg = Message::new_method_call("com.example.dbus.rs", "/example", "com.example.dbus.rs", "Recurse").unwrap();
+    crate::message::message_set_serial(&mut msg, 20);
+    let mut r = tree.handle(&msg).unwrap();
+    assert_eq!(r.len(), 1);
+    assert_eq!(r[0].as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.Failed"));
+}
+
+#[test]
+fn test_computed_prop() {
+    use crate::tree::{Factory, Access, EmitsChangedSignal};
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    #[derive(Debug, Default)]
+    struct Names;
+    impl DataType for Names {
+        type Tree = ();
+        type ObjectPath = RefCell<(String, String)>;
+        type Interface = ();
+        type Property = ();
+        type Method = ();
+        type Signal = ();
+    }
+
+    let changes = Rc::new(Cell::new(0));
+    let changes2 = changes.clone();
+    let f = Factory::new_fn::<Names>();
+    let tree = f.tree(()).add(f.object_path("/person", RefCell::new(("John".into(), "Doe".into())))
+        .add(f.interface("com.example.Person", ())
+            .add_p(f.property::<&str,_>("First", ()).access(Access::ReadWrite)
+                .on_get(|i, p| { i.append(&*p.path.get_data().borrow().0); Ok(()) })
+                .on_set(|i, p| { p.path.get_data().borrow_mut().0 = i.read()?; Ok(()) })
+                .on_changed(move |i, _| {
+                    let s: &str = i.read()?;
+                    if s.is_empty() { return Err(MethodErr::failed("Empty name")) }
+                    changes2.set(changes2.get() + s.len());
+                    Ok(())
+                })
+                .emits_changed(EmitsChangedSignal::False)
+                .affects(vec!("Full", "Initials")))
+            .add_p(f.property::<&str,_>("Full", ())
+                .on_get(|i, p| { let d = p.path.get_data().borrow(); i.append(format!("{} {}", d.0, d.1)); Ok(()) }))
+            .add_p(f.property::<&str,_>("Initials", ()).emits_changed(EmitsChangedSignal::Invalidates)
+                .on_get(|_, _| Err(MethodErr::failed("Not called"))))
+        )
+    );
+
+    let mut msg = Message::new_method_call("com.example.person", "/person", "org.freedesktop.DBus.Properties", "Set").unwrap()
+        .append3("com.example.Person", "First", arg::Variant("Jane"));
+    crate::message::message_set_serial(&mut msg, 1);
+    let r = tree.handle(&msg).unwrap();
+    assert_eq!(changes.get(), 4);
+    assert_eq!(r.len(), 2);
+    let (s, d, inv): (&str, arg::Dict<&str, arg::Variant<&str>, _>, Vec<&str>) = r[0].read3().unwrap();
+    assert_eq!(s, "com.example.Person");
+    let d: BTreeMap<_, _> = d.collect();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d.get("Full"), Some(&arg::Variant("Jane Doe")));
+    assert_eq!(inv, vec!("Initials"));
+
+    // The hook fails, but the property was set, so the call did not.
+    let mut msg = Message::new_method_call("com.example.person", "/person", "org.freedesktop.DBus.Properties", "Set").unwrap()
+        .append3("com.example.Person", "First", arg::Variant(""));
+    crate::message::message_set_serial(&mut msg, 2);
+    let mut r = tree.handle(&msg).unwrap();
+    assert!(r[1].as_result().is_ok());
+    assert_eq!(changes.get(), 4);
+    let (_, d, _): (&str, arg::Dict<&str, arg::Variant<&str>, _>, Vec<&str>) = r[0].read3().unwrap();
+    assert_eq!(d.collect::<BTreeMap<_, _>>().get("Full"), Some(&arg::Variant(" Doe")));
+}
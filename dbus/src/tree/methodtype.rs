@@ -3,87 +3,276 @@
 use std::fmt;
 use crate::Message;
 use crate::ffidisp::stdintf;
-use crate::arg::{Iter, IterAppend, TypeMismatchError};
+use crate::arg::{Iter, IterAppend, AppendAll, TypeMismatchError};
+use crate::arg::messageitem::MessageItem;
+use std::cmp::Ordering;
 use std::marker::PhantomData;
 use super::{Method, Interface, Property, ObjectPath, Tree};
-use crate::strings::{ErrorName};
+use crate::strings::{ErrorName, BusName, Interface as IfaceName, Member, Signature};
 use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
 use std::ffi::CString;
 use crate::Error as dbusError;
 
+const INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs\0";
+const FAILED: &str = "org.freedesktop.DBus.Error.Failed\0";
+const UNKNOWN_OBJECT: &str = "org.freedesktop.DBus.Error.UnknownObject\0";
+const UNKNOWN_INTERFACE: &str = "org.freedesktop.DBus.Error.UnknownInterface\0";
+const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod\0";
+const UNKNOWN_PROPERTY: &str = "org.freedesktop.DBus.Error.UnknownProperty\0";
+const PROPERTY_READ_ONLY: &str = "org.freedesktop.DBus.Error.PropertyReadOnly\0";
+const ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied\0";
+
+// The common error names are borrowed from static strings, so that creating one
+// of these errors does not need to allocate (or validate) the name.
+fn static_errorname(s: &'static str) -> ErrorName<'static> { unsafe { ErrorName::from_slice_unchecked(s.as_bytes()) } }
+
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
-/// A D-Bus Method Error, containing an error name and a description.
-pub struct MethodErr(ErrorName<'static>, String);
+/// A D-Bus Method Error, containing an error name, a description and optionally additional arguments.
+pub struct MethodErr(ErrorName<'static>, String, ErrorArgs);
+
+// MessageItem is not Ord (because of floats), so doubles are compared with total_cmp here.
+#[derive(Clone, Debug, Default)]
+struct ErrorArgs(Vec<MessageItem>);
+
+fn cmp_item(a: &MessageItem, b: &MessageItem) -> Ordering {
+    match (a, b) {
+        (MessageItem::Double(x), MessageItem::Double(y)) => x.total_cmp(y),
+        (MessageItem::Variant(x), MessageItem::Variant(y)) => cmp_item(x, y),
+        (MessageItem::Struct(x), MessageItem::Struct(y)) => cmp_items(x, y),
+        (MessageItem::Array(x), MessageItem::Array(y)) => x.signature().cmp(y.signature()).then_with(|| cmp_items(x, y)),
+        (MessageItem::Dict(x), MessageItem::Dict(y)) => x.signature().cmp(y.signature()).then_with(|| {
+            x.iter().zip(y.iter()).map(|((xk, xv), (yk, yv))| cmp_item(xk, yk).then_with(|| cmp_item(xv, yv)))
+                .find(|o| *o != Ordering::Equal).unwrap_or_else(|| x.len().cmp(&y.len()))
+        }),
+        // Different kinds of items compare by kind, and the remaining ones by value.
+        _ => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn cmp_items(a: &[MessageItem], b: &[MessageItem]) -> Ordering {
+    a.iter().zip(b).map(|(x, y)| cmp_item(x, y)).find(|o| *o != Ordering::Equal).unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+impl PartialEq for ErrorArgs {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for ErrorArgs {}
+
+impl PartialOrd for ErrorArgs {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for ErrorArgs {
+    fn cmp(&self, other: &Self) -> Ordering { cmp_items(&self.0, &other.0) }
+}
 
 impl MethodErr {
     /// Create an Invalid Args MethodErr.
     pub fn invalid_arg<T: fmt::Debug + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.InvalidArgs", format!("Invalid argument {:?}", a)).into()
+        (static_errorname(INVALID_ARGS), format!("Invalid argument {:?}", a)).into()
     }
     /// Create a MethodErr that there are not enough arguments given.
     pub fn no_arg() -> MethodErr {
-        ("org.freedesktop.DBus.Error.InvalidArgs", "Not enough arguments").into()
+        (static_errorname(INVALID_ARGS), "Not enough arguments").into()
     }
     /// Create a MethodErr that the method failed in the way specified.
     pub fn failed<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.Failed", a.to_string()).into()
+        (static_errorname(FAILED), a.to_string()).into()
     }
 
     /// Create a MethodErr that the Object path was unknown.
     pub fn no_path<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.UnknownObject", format!("Unknown object path {}", a)).into()
+        (static_errorname(UNKNOWN_OBJECT), format!("Unknown object path {}", a)).into()
     }
 
     /// Create a MethodErr that the Interface was unknown.
     pub fn no_interface<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.UnknownInterface", format!("Unknown interface {}", a)).into()
+        (static_errorname(UNKNOWN_INTERFACE), format!("Unknown interface {}", a)).into()
     }
     /// Create a MethodErr that the Method was unknown.
     pub fn no_method<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.UnknownMethod", format!("Unknown method {}", a)).into()
+        (static_errorname(UNKNOWN_METHOD), format!("Unknown method {}", a)).into()
     }
     /// Create a MethodErr that the Property was unknown.
     pub fn no_property<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.UnknownProperty", format!("Unknown property {}", a)).into()
+        (static_errorname(UNKNOWN_PROPERTY), format!("Unknown property {}", a)).into()
+    }
+    /// Create a MethodErr that a Property was set to a value of the wrong type.
+    pub fn wrong_prop_type<T: fmt::Display + ?Sized>(a: &T, expected: &Signature, got: &Signature) -> MethodErr {
+        (static_errorname(INVALID_ARGS), format!("Property {} has type {}, cannot set it to a value of type {}", a, expected, got)).into()
     }
     /// Create a MethodErr that the Property was read-only.
     pub fn ro_property<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
-        ("org.freedesktop.DBus.Error.PropertyReadOnly", format!("Property {} is read only", a)).into()
+        (static_errorname(PROPERTY_READ_ONLY), format!("Property {} is read only", a)).into()
     }
+    /// Create a MethodErr that the caller is not allowed to do this.
+    pub fn access_denied<T: fmt::Display + ?Sized>(a: &T) -> MethodErr {
+        (static_errorname(ACCESS_DENIED), format!("Access denied to {}", a)).into()
+    }
+
+    // An MTFnMut handler is already running further up the stack.
+    fn recursive() -> MethodErr { MethodErr::failed("Handler called recursively") }
 
     /// Error name accessor
     pub fn errorname(&self) -> &ErrorName<'static> { &self.0 }
     /// Description accessor
     pub fn description(&self) -> &str { &self.1 }
+    /// Additional arguments accessor
+    pub fn args(&self) -> &[MessageItem] { &(self.2).0 }
+
+    /// Adds an argument to the error reply, after the description.
+    ///
+    /// This can be used to give clients machine-readable details, such as an error code.
+    /// Clients can get them through `dbus::Error::args` or `dbus::Error::read_args`.
+    pub fn with_arg<A: Into<MessageItem>>(mut self, a: A) -> Self { (self.2).0.push(a.into()); self }
 
     /// Creates an error reply from a method call message.
     ///
     /// Note: You normally don't need to use this function,
     /// as it is called internally from Tree::handle.
     pub fn to_message(&self, msg: &Message) -> Message {
-        msg.error(&self.0, &CString::new(&*self.1).unwrap())
+        let mut m = msg.error(&self.0, &CString::new(&*self.1).unwrap());
+        m.append_items(self.args());
+        m
     }
 }
 
 impl From<TypeMismatchError> for MethodErr {
-    fn from(t: TypeMismatchError) -> MethodErr { ("org.freedesktop.DBus.Error.Failed", format!("{}", t)).into() }
+    fn from(t: TypeMismatchError) -> MethodErr { (static_errorname(FAILED), format!("{}", t)).into() }
 }
 
 impl<T: Into<ErrorName<'static>>, M: Into<String>> From<(T, M)> for MethodErr {
-    fn from((t, m): (T, M)) -> MethodErr { MethodErr(t.into(), m.into()) }
+    fn from((t, m): (T, M)) -> MethodErr { MethodErr(t.into(), m.into(), Default::default()) }
 }
 
 impl From<dbusError> for MethodErr {
     fn from(t: dbusError) -> MethodErr {
         let n = t.name().unwrap_or("org.freedesktop.DBus.Error.Failed");
         let m = t.message().unwrap_or("Unknown error");
-        MethodErr(String::from(n).into(), m.into())
+        MethodErr(String::from(n).into(), m.into(), ErrorArgs(t.args().to_vec()))
     }
 }
 
 
 /// Result containing the Messages returned from the Method, or a MethodErr.
-pub type MethodResult = Result<Vec<Message>, MethodErr>;
+pub type MethodResult = Result<MethodReplies, MethodErr>;
+
+#[derive(Debug)]
+enum RepliesInner {
+    One([Message; 1]),
+    Two([Message; 2]),
+    Many(Vec<Message>),
+}
+
+/// The Messages returned from a Method.
+///
+/// One or two messages (typically a method return, and maybe a signal) are stored inline,
+/// so that the common case does not need a heap allocation. More than that are stored in a Vec.
+/// Derefs to a slice of Messages.
+#[derive(Debug)]
+pub struct MethodReplies(RepliesInner);
+
+impl MethodReplies {
+    /// Creates an empty list of replies.
+    pub fn new() -> Self { MethodReplies(RepliesInner::Many(Vec::new())) }
+
+    /// Appends a message to the list of replies.
+    pub fn push(&mut self, m: Message) {
+        let old = std::mem::replace(&mut self.0, RepliesInner::Many(Vec::new()));
+        self.0 = match old {
+            RepliesInner::One([a]) => RepliesInner::Two([a, m]),
+            RepliesInner::Two([a, b]) => RepliesInner::Many(vec!(a, b, m)),
+            RepliesInner::Many(ref v) if v.is_empty() => RepliesInner::One([m]),
+            RepliesInner::Many(mut v) => { v.push(m); RepliesInner::Many(v) },
+        };
+    }
+}
+
+impl Default for MethodReplies {
+    fn default() -> Self { MethodReplies::new() }
+}
+
+impl std::ops::Deref for MethodReplies {
+    type Target = [Message];
+    fn deref(&self) -> &[Message] {
+        match self.0 {
+            RepliesInner::One(ref a) => a,
+            RepliesInner::Two(ref a) => a,
+            RepliesInner::Many(ref v) => v,
+        }
+    }
+}
+
+impl std::ops::DerefMut for MethodReplies {
+    fn deref_mut(&mut self) -> &mut [Message] {
+        match self.0 {
+            RepliesInner::One(ref mut a) => a,
+            RepliesInner::Two(ref mut a) => a,
+            RepliesInner::Many(ref mut v) => v,
+        }
+    }
+}
+
+impl From<Message> for MethodReplies {
+    fn from(m: Message) -> Self { MethodReplies(RepliesInner::One([m])) }
+}
+
+impl From<Vec<Message>> for MethodReplies {
+    fn from(v: Vec<Message>) -> Self { MethodReplies(RepliesInner::Many(v)) }
+}
+
+impl From<MethodReplies> for Vec<Message> {
+    fn from(r: MethodReplies) -> Self {
+        match r.0 {
+            RepliesInner::Many(v) => v,
+            _ => r.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<Message> for MethodReplies {
+    fn extend<I: IntoIterator<Item=Message>>(&mut self, iter: I) { for m in iter { self.push(m) } }
+}
+
+impl std::iter::FromIterator<Message> for MethodReplies {
+    fn from_iter<I: IntoIterator<Item=Message>>(iter: I) -> Self {
+        let mut r = MethodReplies::new();
+        r.extend(iter);
+        r
+    }
+}
+
+/// Iterator over the Messages in a MethodReplies.
+#[derive(Debug)]
+pub struct MethodRepliesIter(Option<Message>, Option<Message>, std::vec::IntoIter<Message>);
+
+impl Iterator for MethodRepliesIter {
+    type Item = Message;
+    fn next(&mut self) -> Option<Message> {
+        self.0.take().or_else(|| self.1.take()).or_else(|| self.2.next())
+    }
+}
+
+impl IntoIterator for MethodReplies {
+    type Item = Message;
+    type IntoIter = MethodRepliesIter;
+    fn into_iter(self) -> MethodRepliesIter {
+        match self.0 {
+            RepliesInner::One([a]) => MethodRepliesIter(Some(a), None, Vec::new().into_iter()),
+            RepliesInner::Two([a, b]) => MethodRepliesIter(Some(a), Some(b), Vec::new().into_iter()),
+            RepliesInner::Many(v) => MethodRepliesIter(None, None, v.into_iter()),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MethodReplies {
+    type Item = &'a Message;
+    type IntoIter = std::slice::Iter<'a, Message>;
+    fn into_iter(self) -> Self::IntoIter { self.iter() }
+}
 
 /// Associated data for different objects in a tree.
 ///
@@ -113,7 +302,7 @@ impl DataType for () {
     type Signal = ();
 }
 
-/// A helper trait used internally to make the tree generic over MTFn, MTFnMut and MTSync.
+/// A helper trait used internally to make the tree generic over MTFn, MTFnMut, MTSync and MTFuture.
 ///
 /// You should not need to call these methods directly, it's primarily for internal use.
 pub trait MethodType<D: DataType>: Sized + Default {
@@ -135,6 +324,9 @@ pub trait MethodType<D: DataType>: Sized + Default {
     fn make_getprop<H>(h: H) -> Box<Self::GetProp>
     where H: Fn(&mut IterAppend, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static;
     /// For internal use.
+    fn make_setprop<H>(h: H) -> Box<Self::SetProp>
+    where H: Fn(&mut Iter, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static;
+    /// For internal use.
     fn make_method<H>(h: H) -> Box<Self::Method>
     where H: Fn(&MethodInfo<Self,D>) -> MethodResult + Send + Sync + 'static;
 }
@@ -158,6 +350,8 @@ impl<D: DataType> MethodType<D> for MTFn<D> {
 
     fn make_getprop<H>(h: H) -> Box<Self::GetProp>
     where H: Fn(&mut IterAppend, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(h) }
+    fn make_setprop<H>(h: H) -> Box<Self::SetProp>
+    where H: Fn(&mut Iter, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(h) }
     fn make_method<H>(h: H) -> Box<Self::Method>
     where H: Fn(&MethodInfo<Self,D>) -> MethodResult + Send + Sync + 'static { Box::new(h) }
 }
@@ -172,14 +366,16 @@ impl<D: DataType> MethodType<D> for MTFnMut<D> {
     type Method = RefCell<dyn FnMut(&MethodInfo<Self, D>) -> MethodResult>;
 
     fn call_getprop(p: &Self::GetProp, i: &mut IterAppend, pinfo: &PropInfo<Self, D>)
-        -> Result<(), MethodErr> { (&mut *p.borrow_mut())(i, pinfo) }
+        -> Result<(), MethodErr> { (*p.try_borrow_mut().map_err(|_| MethodErr::recursive())?)(i, pinfo) }
     fn call_setprop(p: &Self::SetProp, i: &mut Iter, pinfo: &PropInfo<Self, D>)
-        -> Result<(), MethodErr> { (&mut *p.borrow_mut())(i, pinfo) }
+        -> Result<(), MethodErr> { (*p.try_borrow_mut().map_err(|_| MethodErr::recursive())?)(i, pinfo) }
     fn call_method(p: &Self::Method, minfo: &MethodInfo<Self, D>)
-        -> MethodResult { (&mut *p.borrow_mut())(minfo) }
+        -> MethodResult { (*p.try_borrow_mut().map_err(|_| MethodErr::recursive())?)(minfo) }
 
     fn make_getprop<H>(h: H) -> Box<Self::GetProp>
     where H: Fn(&mut IterAppend, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(RefCell::new(h)) }
+    fn make_setprop<H>(h: H) -> Box<Self::SetProp>
+    where H: Fn(&mut Iter, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(RefCell::new(h)) }
     fn make_method<H>(h: H) -> Box<Self::Method>
     where H: Fn(&MethodInfo<Self,D>) -> MethodResult + Send + Sync + 'static { Box::new(RefCell::new(h)) }
 
@@ -203,10 +399,44 @@ impl<D: DataType> MethodType<D> for MTSync<D> {
 
     fn make_getprop<H>(h: H) -> Box<Self::GetProp>
     where H: Fn(&mut IterAppend, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static  { Box::new(h) }
+    fn make_setprop<H>(h: H) -> Box<Self::SetProp>
+    where H: Fn(&mut Iter, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(h) }
     fn make_method<H>(h: H) -> Box<Self::Method>
     where H: Fn(&MethodInfo<Self,D>) -> MethodResult + Send + Sync + 'static { Box::new(h) }
 }
 
+/// The future returned from a method handler of an MTFuture tree.
+pub type MethodFuture = Pin<Box<dyn Future<Output = MethodResult>>>;
+
+/// An abstract type to represent methods that return a future (property callbacks are still Fn functions).
+///
+/// Trees using this method type must be handled by `Tree::handle_async`. Handled in any other way,
+/// e g by `Tree::handle` or `Tree::start_receive`, every method call gets an error reply.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct MTFuture<D=()>(PhantomData<*const D>);
+
+impl<D: DataType> MethodType<D> for MTFuture<D> {
+    type GetProp = dyn Fn(&mut IterAppend, &PropInfo<Self, D>) -> Result<(), MethodErr>;
+    type SetProp = dyn Fn(&mut Iter, &PropInfo<Self, D>) -> Result<(), MethodErr>;
+    type Method = dyn Fn(&MethodInfo<Self, D>) -> MethodFuture;
+
+    fn call_getprop(p: &Self::GetProp, i: &mut IterAppend, pinfo: &PropInfo<Self, D>)
+        -> Result<(), MethodErr> { p(i, pinfo) }
+    fn call_setprop(p: &Self::SetProp, i: &mut Iter, pinfo: &PropInfo<Self, D>)
+        -> Result<(), MethodErr> { p(i, pinfo) }
+    fn call_method(_: &Self::Method, _: &MethodInfo<Self, D>) -> MethodResult {
+        Err(MethodErr::failed(&"This method returns a future, and must be called through Tree::handle_async"))
+    }
+
+    fn make_getprop<H>(h: H) -> Box<Self::GetProp>
+    where H: Fn(&mut IterAppend, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(h) }
+    fn make_setprop<H>(h: H) -> Box<Self::SetProp>
+    where H: Fn(&mut Iter, &PropInfo<Self,D>) -> Result<(), MethodErr> + Send + Sync + 'static { Box::new(h) }
+    fn make_method<H>(h: H) -> Box<Self::Method>
+    where H: Fn(&MethodInfo<Self,D>) -> MethodResult + Send + Sync + 'static {
+        Box::new(move |minfo| { let r = h(minfo); Box::pin(async move { r }) })
+    }
+}
 
 
 #[derive(Debug, Copy, Clone)]
@@ -229,6 +459,93 @@ impl<'a, M: 'a + MethodType<D>, D: 'a + DataType> MethodInfo<'a, M, D> {
     pub fn to_prop_info(&self, iface: &'a Interface<M, D>, prop: &'a Property<M, D>) -> PropInfo<'a, M, D> {
         PropInfo { msg: self.msg, method: self.method, iface: iface, prop: prop, path: self.path, tree: self.tree }
     }
+
+    /// The data of the interface the method belongs to.
+    ///
+    /// This is a good place for state that is shared by the methods and properties of an interface,
+    /// but differs between object paths, e g when one interface is implemented for several devices.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dbus::tree::{Factory, DataType};
+    /// use std::cell::Cell;
+    ///
+    /// #[derive(Debug, Default)]
+    /// struct Counters;
+    /// impl DataType for Counters {
+    ///     type Tree = ();
+    ///     type ObjectPath = ();
+    ///     type Interface = Cell<u32>;
+    ///     type Property = ();
+    ///     type Method = ();
+    ///     type Signal = ();
+    /// }
+    ///
+    /// let f = Factory::new_fn::<Counters>();
+    /// let counter = |start| f.interface("com.example.Counter", Cell::new(start))
+    ///     .add_m(f.method("Increment", (), |m| {
+    ///         let c = m.iface_data();
+    ///         c.set(c.get() + 1);
+    ///         m.reply((c.get(),))
+    ///     }))
+    ///     .add_p(f.property::<u32, _>("Value", ()).on_get(|i, p| { i.append(p.iface_data().get()); Ok(()) }));
+    /// let tree = f.tree(())
+    ///     .add(f.object_path("/counter/a", ()).add(counter(0)))
+    ///     .add(f.object_path("/counter/b", ()).add(counter(100)));
+    /// ```
+    pub fn iface_data(&self) -> &'a D::Interface { self.iface.get_data() }
+
+    /// Replies to the method call with the given arguments, e g `m.reply((5u32, "five"))`.
+    pub fn reply<A: AppendAll>(&self, args: A) -> MethodResult {
+        let mut r = self.msg.method_return();
+        args.append(&mut IterAppend::new(&mut r));
+        Ok(r.into())
+    }
+
+    /// Replies to the method call with an error.
+    pub fn reply_err<E: Into<MethodErr>>(&self, e: E) -> MethodResult { Err(e.into()) }
+
+    /// Does not reply to the method call.
+    ///
+    /// This is for when the caller does not expect a reply, or when you are going to reply later
+    /// by sending a method return (made with `msg.method_return()`) yourself.
+    pub fn no_reply(&self) -> MethodResult { Ok(MethodReplies::new()) }
+
+    /// The unique name of the caller.
+    pub fn sender(&self) -> Option<BusName<'a>> { self.msg.sender() }
+
+    /// The time the method call was received, see `Message::timestamp`.
+    ///
+    /// E g, `m.timestamp().map(|t| t.elapsed())` is how long the call has waited so far.
+    pub fn timestamp(&self) -> Option<std::time::Instant> { self.msg.timestamp() }
+
+    /// Returns true if the caller is prepared to wait while the user is asked for authorization,
+    /// e g by polkit. Otherwise, such a method should fail with an InteractiveAuthorizationRequired error.
+    pub fn allow_interactive_authorization(&self) -> bool { self.msg.get_allow_interactive_authorization() }
+
+    /// Creates a signal from the object path this method was called on.
+    ///
+    /// To emit the signal together with the method return, push it to the replies, e g:
+    /// `let mut r = m.reply(())?; r.push(m.signal("com.example.Foo", "Changed", (5u32,))); Ok(r)`
+    pub fn signal<I, N, A>(&self, iface: I, member: N, args: A) -> Message
+    where I: Into<IfaceName<'static>>, N: Into<Member<'static>>, A: AppendAll {
+        let mut r = Message::signal(self.path.get_name(), &iface.into(), &member.into());
+        args.append(&mut IterAppend::new(&mut r));
+        r
+    }
+
+    /// Creates a signal from the object path this method was called on, which is only
+    /// delivered to the caller of the method.
+    ///
+    /// This is useful for e g notifying the caller when a job it started has finished, without
+    /// broadcasting this to everyone else. On peer-to-peer connections there is no sender, and
+    /// the signal has no destination.
+    pub fn signal_to_sender<I, N, A>(&self, iface: I, member: N, args: A) -> Message
+    where I: Into<IfaceName<'static>>, N: Into<Member<'static>>, A: AppendAll {
+        let mut r = self.signal(iface, member, args);
+        r.set_destination(self.sender());
+        r
+    }
 }
 
 
@@ -250,7 +567,7 @@ where
         let arg0 = d.introspect()?;
         let rm = minfo.msg.method_return();
         let rm = rm.append1(arg0);
-        Ok(vec!(rm))
+        Ok(rm.into())
     };
     let m = factory.method_sync("Introspect", Default::default(), h);
     let m = m.out_arg(("xml_data", "s"));
@@ -279,4 +596,200 @@ impl<'a, M: 'a + MethodType<D>, D: 'a + DataType> PropInfo<'a, M, D> {
     pub fn to_method_info(&self) -> MethodInfo<'a, M, D> {
         MethodInfo { msg: self.msg, method: self.method, iface: self.iface, path: self.path, tree: self.tree }
     }
+
+    /// The unique name of the caller that gets or sets the property.
+    ///
+    /// Together with `sender_uid` and friends, this lets a property return different values
+    /// to different callers, or refuse to be set by some of them.
+    pub fn sender(&self) -> Option<BusName<'a>> { self.msg.sender() }
+
+    /// The data of the interface the property belongs to, see `MethodInfo::iface_data`.
+    pub fn iface_data(&self) -> &'a D::Interface { self.iface.get_data() }
+}
+
+#[test]
+fn test_static_errorname() {
+    let e = MethodErr::no_property(&"Foo");
+    assert_eq!(&**e.errorname(), "org.freedesktop.DBus.Error.UnknownProperty");
+    assert_eq!(e.description(), "Unknown property Foo");
+    let mut m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "ListNames").unwrap();
+    crate::message::message_set_serial(&mut m, 4);
+    let mut r = MethodErr::failed(&"Oops").to_message(&m);
+    assert_eq!(r.as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.Failed"));
+}
+
+#[test]
+fn test_method_replies() {
+    let m = || Message::new_signal("/test", "com.example.test", "Test").unwrap();
+    let mut r = MethodReplies::new();
+    assert_eq!(r.len(), 0);
+    r.push(m());
+    assert_eq!(r.len(), 1);
+    r.extend(vec!(m(), m()));
+    assert_eq!(r.len(), 3);
+    assert!(r.iter().all(|z| &*z.member().unwrap() == "Test"));
+    assert_eq!(r.into_iter().count(), 3);
+
+    let r: MethodReplies = vec!(m(), m()).into_iter().collect();
+    assert_eq!(r.len(), 2);
+    let v: Vec<Message> = r.into();
+    assert_eq!(v.len(), 2);
+}
+
+#[test]
+fn test_method_info_helpers() {
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/counter", ()).add(f.interface("com.example.Counter", ())
+        .add_m(f.method("Add", (), |m| {
+            let (a, b): (u32, u32) = m.msg.read2()?;
+            if b == 0 { return m.reply_err(MethodErr::invalid_arg(&b)) }
+            let mut r = m.reply((a + b, m.sender().is_none()))?;
+            r.push(m.signal("com.example.Counter", "Added", (b,)));
+            Ok(r)
+        }))
+        .add_m(f.method("Quiet", (), |m| m.no_reply()))
+    ));
+
+    let mut msg = Message::new_method_call("com.example.counter", "/counter", "com.example.Counter", "Add").unwrap().append2(5u32, 3u32);
+    crate::message::message_set_serial(&mut msg, 1);
+    let r = t.handle(&msg).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(r[0].read2::<u32, bool>().unwrap(), (8, true));
+    assert_eq!(&*r[1].path().unwrap(), "/counter");
+    assert_eq!(&*r[1].member().unwrap(), "Added");
+    assert_eq!(r[1].read1::<u32>().unwrap(), 3);
+
+    let mut msg = Message::new_method_call("com.example.counter", "/counter", "com.example.Counter", "Add").unwrap().append2(5u32, 0u32);
+    crate::message::message_set_serial(&mut msg, 2);
+    let mut r = t.handle(&msg).unwrap();
+    assert_eq!(r[0].as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+
+    let mut msg = Message::new_method_call("com.example.counter", "/counter", "com.example.Counter", "Quiet").unwrap();
+    crate::message::message_set_serial(&mut msg, 3);
+    assert_eq!(t.handle(&msg).unwrap().len(), 0);
+}
+
+#[test]
+fn test_unicast_signal() {
+    use crate::blocking::{Connection, LocalConnection};
+    use std::time::Duration;
+
+    let f = super::Factory::new_fn::<()>();
+    let done = std::sync::Arc::new(f.signal("Done", ()).sarg::<u32,_>("job"));
+    let done2 = done.clone();
+    let iface = std::sync::Arc::new(IfaceName::from("com.example.dbusrs.Jobs"));
+    let iface2 = iface;
+    let t = f.tree(()).add(f.object_path("/jobs", ()).add(f.interface("com.example.dbusrs.Jobs", ())
+        .add_m(f.method("Start", (), move |m| {
+            let mut r = m.reply(())?;
+            r.push(m.signal_to_sender("com.example.dbusrs.Jobs", "Done", (1u32,)));
+            r.push(done2.emit_to(m.path.get_name(), &iface2, &m.sender().unwrap(), &[2u32]));
+            Ok(r)
+        }))
+        .add_s(done)
+    ));
+    let mut server = LocalConnection::new_session().unwrap();
+    let name = server.unique_name().into_static();
+    t.start_receive(&server);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let th = std::thread::spawn(move || {
+        let mut client = Connection::new_session().unwrap();
+        let mut other = Connection::new_session().unwrap();
+        let mut mr = crate::message::MatchRule::new_signal("com.example.dbusrs.Jobs", "Done");
+        mr.sender = Some(name.clone());
+        other.add_match_no_cb(&mr.match_str()).unwrap();
+        client.add_match_no_cb(&mr.match_str()).unwrap();
+
+        let p = client.with_proxy(name, "/jobs", Duration::from_secs(5));
+        let () = p.method_call("com.example.dbusrs.Jobs", "Start", ()).unwrap();
+        let m1 = client.wait_for_signal(mr.clone(), Duration::from_secs(5)).unwrap();
+        let m2 = client.wait_for_signal(mr.clone(), Duration::from_secs(5)).unwrap();
+        let r = (m1.read1::<u32>().unwrap(), m2.read1::<u32>().unwrap());
+        // The signals were not broadcast, so the other connection gets nothing.
+        let e = other.wait_for_signal(mr, Duration::from_millis(200)).unwrap_err();
+        tx.send((r, e.name().map(|x| x.to_string()))).unwrap();
+    });
+
+    let r = loop {
+        if let Ok(r) = rx.try_recv() { break r }
+        server.process(Duration::from_millis(100)).unwrap();
+        if th.is_finished() { break rx.recv().unwrap() }
+    };
+    th.join().unwrap();
+    assert_eq!(r, ((1, 2), Some("org.freedesktop.DBus.Error.Timeout".into())));
+}
+
+#[test]
+fn test_error_args() {
+    use crate::blocking::{Connection, LocalConnection};
+    use std::time::Duration;
+
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/cheese", ()).add(f.interface("com.example.dbusrs.Cheese", ())
+        .add_m(f.method("Get", (), |_| {
+            Err(MethodErr::from(("com.example.dbusrs.Error.OutOfCheese", "No cheese left")).with_arg(42u32).with_arg("brie"))
+        }))
+    ));
+    let mut m = Message::new_method_call("com.example.dbusrs", "/cheese", "com.example.dbusrs.Cheese", "Get").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    let r = t.handle(&m).unwrap();
+    assert_eq!(r[0].get2::<&str, u32>(), (Some("No cheese left"), Some(42)));
+
+    let mut server = LocalConnection::new_session().unwrap();
+    let name = server.unique_name().into_static();
+    t.start_receive(&server);
+    let th = std::thread::spawn(move || {
+        let client = Connection::new_session().unwrap();
+        let p = client.with_proxy(name, "/cheese", Duration::from_secs(5));
+        let e = p.method_call::<(), _, _, _>("com.example.dbusrs.Cheese", "Get", ()).unwrap_err();
+        (e.name().map(|x| x.to_string()), e.message().map(|x| x.to_string()), e.read_args::<(u32, String)>().unwrap())
+    });
+    while !th.is_finished() { server.process(Duration::from_millis(100)).unwrap(); }
+    let (name, msg, args) = th.join().unwrap();
+    assert_eq!(name.as_deref(), Some("com.example.dbusrs.Error.OutOfCheese"));
+    assert_eq!(msg.as_deref(), Some("No cheese left"));
+    assert_eq!(args, (42, "brie".into()));
+
+    // Errors with float arguments are ordered consistently with equality, even for NaN.
+    let e = |v: f64| MethodErr::failed("Float").with_arg(MessageItem::Struct(vec!(MessageItem::Double(v))));
+    assert_eq!(e(f64::NAN), e(f64::NAN));
+    assert_eq!(e(f64::NAN).cmp(&e(f64::NAN)), Ordering::Equal);
+    assert_ne!(e(f64::NAN), e(1.0));
+    assert_eq!(e(1.0).cmp(&e(2.0)), Ordering::Less);
+    assert_eq!(e(1.0).cmp(&e(f64::NAN)), e(f64::NAN).cmp(&e(1.0)).reverse());
+}
+
+#[test]
+fn test_iface_data() {
+    use std::cell::Cell;
+    #[derive(Debug, Default)]
+    struct Counters;
+    impl DataType for Counters {
+        type Tree = ();
+        type ObjectPath = ();
+        type Interface = Cell<u32>;
+        type Property = ();
+        type Method = ();
+        type Signal = ();
+    }
+    let f = super::Factory::new_fn::<Counters>();
+    let counter = |start| f.interface("com.example.Counter", Cell::new(start))
+        .add_m(f.method("Increment", (), |m| { let c = m.iface_data(); c.set(c.get() + 1); m.reply((c.get(),)) }))
+        .add_p(f.property::<u32, _>("Value", ()).on_get(|i, p| { i.append(p.iface_data().get()); Ok(()) }));
+    let t = f.tree(())
+        .add(f.object_path("/counter/a", ()).add(counter(0)))
+        .add(f.object_path("/counter/b", ()).add(counter(100)));
+
+    let call = |path: &str, iface: &str, member: &str| {
+        let mut m = Message::new_method_call("com.example.counter", path, iface, member).unwrap();
+        if member == "Get" { m = m.append2("com.example.Counter", "Value"); }
+        crate::message::message_set_serial(&mut m, 1);
+        t.handle(&m).unwrap().into_iter().next().unwrap()
+    };
+    assert_eq!(call("/counter/a", "com.example.Counter", "Increment").read1::<u32>().unwrap(), 1);
+    assert_eq!(call("/counter/a", "com.example.Counter", "Increment").read1::<u32>().unwrap(), 2);
+    assert_eq!(call("/counter/b", "com.example.Counter", "Increment").read1::<u32>().unwrap(), 101);
+    let get = |path| call(path, "org.freedesktop.DBus.Properties", "Get").read1::<crate::arg::Variant<u32>>().unwrap().0;
+    assert_eq!((get("/counter/a"), get("/counter/b")), (2, 101));
 }
@@ -9,7 +9,7 @@
 //! let t = f.tree(()).add(f.object_path("/example", ()).introspectable()
 //!     .add(f.interface("com.example.dbus.rs", ())
 //!         .add_m(f.method("CallMe", (), |m| {
-//!             Ok(vec!(m.msg.method_return().append1("Thanks!"))) }
+//!             Ok(m.msg.method_return().append1("Thanks!").into()) }
 //!         ).out_arg("s"))
 //! ));
 //!
@@ -27,9 +27,29 @@ mod methodtype;
 mod leaves;
 mod objectpath;
 mod factory;
+mod parallel;
+mod concurrent;
+mod simple;
+mod propchanged;
+mod policy;
+mod polkit;
+mod idle;
+mod destination;
+mod service;
+mod statictree;
 
-pub use self::utils::{Argument, Iter};
-pub use self::methodtype::{MethodErr, MethodInfo, PropInfo, MethodResult, MethodType, DataType, MTFn, MTFnMut, MTSync};
+pub use self::utils::{Argument, Annotation, Iter};
+pub use self::methodtype::{MethodErr, MethodInfo, PropInfo, MethodResult, MethodReplies, MethodRepliesIter, MethodType, DataType, MTFn, MTFnMut, MTSync, MTFuture, MethodFuture};
 pub use self::leaves::{Method, Signal, Property, Access, EmitsChangedSignal};
-pub use self::objectpath::{Interface, ObjectPath, Tree, TreeServer};
+pub use self::objectpath::{Interface, Mixin, ObjectPath, Tree, TreeServer};
 pub use self::factory::Factory;
+pub use self::parallel::{ThreadPoolDispatcher, DispatchOrder};
+pub use self::concurrent::AsyncDispatcher;
+pub use self::simple::{SimpleServer, SimpleHandler};
+pub use self::propchanged::{FlushPolicy, coalesce_properties_changed};
+pub use self::policy::{Policy, Principal, Credentials, CredentialsSource};
+pub use self::polkit::Authorization;
+pub use self::idle::{IdleExit, Activity};
+pub use self::destination::Destinations;
+pub use self::service::{Service, StopHandle};
+pub use self::statictree::{StaticTree, StaticPath, StaticInterface, StaticMethod, StaticHandler};
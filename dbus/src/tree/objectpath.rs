@@ -1,20 +1,32 @@
-use super::utils::{ArcMap, Iter, IterE, Annotations, Introspect};
-use super::{Factory, MethodType, MethodInfo, MethodResult, MethodErr, DataType, Property, Method, Signal, methodtype};
+use super::utils::{ArcMap, Iter, IterE, Annotation, Annotations, Introspect, Names, xml_escape};
+use super::{Factory, MethodType, MethodInfo, MethodResult, MethodReplies, MethodErr, DataType, Property, Method, Signal, MTFuture, methodtype};
 use std::sync::{Arc, Mutex};
 use crate::{Message, MessageType, Error, arg, message, channel};
 use crate::strings::{Member, Path, Signature, Interface as IfaceName};
 use crate::ffidisp::{ConnectionItem, MsgHandler, Connection, MsgHandlerType, MsgHandlerResult};
 use std::fmt;
 use std::ffi::CStr;
-use super::leaves::prop_append_dict;
-
+use std::future::Future;
+use std::pin::Pin;
+use super::leaves::{prop_append_dict, prop_append_dict_lossy};
+use super::propchanged::{ChangedQueue, FlushPolicy};
+use super::policy::Policy;
+use super::idle::{IdleExit, Activity};
+use super::destination::Destinations;
+use super::service::StopHandle;
+use crate::metrics::{Metrics, SharedMetrics};
+use std::time::Instant;
+
+const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+
+// The map is sorted by name, so the output does not depend on the order things were added in.
 fn introspect_map<I: fmt::Display, T: Introspect>
     (h: &ArcMap<I, T>, indent: &str) -> String {
 
     h.iter().fold("".into(), |a, (k, v)| {
         let (name, params, contents) = (v.xml_name(), v.xml_params(), v.xml_contents());
         format!("{}{}<{} name=\"{}\"{}{}>\n",
-            a, indent, name, &*k, params, if !contents.is_empty() {
+            a, indent, name, xml_escape(&k.to_string()), params, if !contents.is_empty() {
                 format!(">\n{}{}</{}", contents, indent, name)
             }
             else { "/".to_string() }
@@ -26,8 +38,8 @@ fn introspect_map<I: fmt::Display, T: Introspect>
 /// Represents a D-Bus interface.
 pub struct Interface<M: MethodType<D>, D: DataType> {
     name: Arc<IfaceName<'static>>,
-    methods: ArcMap<Member<'static>, Method<M, D>>,
-    signals: ArcMap<Member<'static>, Signal<D>>,
+    methods: ArcMap<Arc<Member<'static>>, Method<M, D>>,
+    signals: ArcMap<Arc<Member<'static>>, Signal<D>>,
     properties: ArcMap<String, Property<M, D>>,
     anns: Annotations,
     data: D::Interface,
@@ -37,14 +49,14 @@ impl<M: MethodType<D>, D: DataType> Interface<M, D> {
     /// Builder function that adds a method to the interface.
     pub fn add_m<I: Into<Arc<Method<M, D>>>>(mut self, m: I) -> Self {
         let m = m.into();
-        self.methods.insert(m.get_name().clone(), m);
+        self.methods.insert(m.name_arc().clone(), m);
         self
     }
 
     /// Builder function that adds a signal to the interface.
     pub fn add_s<I: Into<Arc<Signal<D>>>>(mut self, s: I) -> Self {
         let m = s.into();
-        self.signals.insert(m.get_name().clone(), m);
+        self.signals.insert(m.name_arc().clone(), m);
         self
     }
 
@@ -55,13 +67,24 @@ impl<M: MethodType<D>, D: DataType> Interface<M, D> {
         self
     }
 
+    /// Builder function that adds all methods, signals and properties of a mixin to the interface.
+    ///
+    /// Members already in the interface with the same name are replaced.
+    pub fn mixin(self, m: &Mixin<M, D>) -> Self {
+        let s = m.methods.iter().fold(self, |s, x| s.add_m(x.clone()));
+        let s = m.signals.iter().fold(s, |s, x| s.add_s(x.clone()));
+        m.properties.iter().fold(s, |s, x| s.add_p(x.clone()))
+    }
+
     /// Builder function that adds an annotation to this interface.
     pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
         self.anns.insert(name, value); self
     }
+    /// Builder function that adds a well-known annotation to the interface.
+    pub fn annotation(self, a: Annotation) -> Self { let (n, v) = a.into_pair(); self.annotate(n, v) }
 
     /// Builder function that adds an annotation that this entity is deprecated.
-    pub fn deprecated(self) -> Self { self.annotate("org.freedesktop.DBus.Deprecated", "true") }
+    pub fn deprecated(self) -> Self { self.annotation(Annotation::Deprecated) }
 
     /// Get interface name
     pub fn get_name(&self) -> &IfaceName<'static> { &self.name }
@@ -75,6 +98,20 @@ impl<M: MethodType<D>, D: DataType> Interface<M, D> {
     /// Iterates over signals implemented by this interface.
     pub fn iter_s<'a>(&'a self) -> Iter<'a, Signal<D>> { IterE::Member(self.signals.values()).into() }
 
+    // Makes the interface use the tree's copies of its names, unless the interface is shared,
+    // in which case its names are only added to the tree's.
+    fn intern_names(i: &mut Arc<Self>, names: &mut Names) {
+        let name = names.iface(&i.name);
+        match Arc::get_mut(i) {
+            Some(i) => {
+                i.name = name;
+                i.methods = std::mem::take(&mut i.methods).into_values().map(|mut m| (Method::intern_name(&mut m, names), m)).collect();
+                i.signals = std::mem::take(&mut i.signals).into_values().map(|mut s| (Signal::intern_name(&mut s, names), s)).collect();
+            },
+            None => for n in i.methods.keys().chain(i.signals.keys()) { names.member(n); },
+        }
+    }
+
     /// Iterates over properties implemented by this interface.
     pub fn iter_p<'a>(&'a self) -> Iter<'a, Property<M, D>> { IterE::String(self.properties.values()).into() }
 }
@@ -92,6 +129,48 @@ impl<M: MethodType<D>, D: DataType> Introspect for Interface<M, D> {
 }
 
 
+/// A reusable set of methods, properties and signals, which can be added to several interfaces.
+///
+/// This way a crate can ship standard building blocks, which users then add to interfaces
+/// in their own trees, with `Interface::mixin`. The members are reference counted,
+/// so adding a mixin to many interfaces does not copy them.
+pub struct Mixin<M: MethodType<D>, D: DataType> {
+    methods: Vec<Arc<Method<M, D>>>,
+    signals: Vec<Arc<Signal<D>>>,
+    properties: Vec<Arc<Property<M, D>>>,
+}
+
+impl<M: MethodType<D>, D: DataType> Mixin<M, D> {
+    /// Builder function that adds a method to the mixin.
+    pub fn add_m<I: Into<Arc<Method<M, D>>>>(mut self, m: I) -> Self { self.methods.push(m.into()); self }
+
+    /// Builder function that adds a signal to the mixin.
+    pub fn add_s<I: Into<Arc<Signal<D>>>>(mut self, s: I) -> Self { self.signals.push(s.into()); self }
+
+    /// Builder function that adds a property to the mixin.
+    pub fn add_p<I: Into<Arc<Property<M, D>>>>(mut self, p: I) -> Self { self.properties.push(p.into()); self }
+}
+
+impl<M: MethodType<D>, D: DataType> Clone for Mixin<M, D> {
+    fn clone(&self) -> Self {
+        Mixin { methods: self.methods.clone(), signals: self.signals.clone(), properties: self.properties.clone() }
+    }
+}
+
+impl<M: MethodType<D>, D: DataType> fmt::Debug for Mixin<M, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mixin")
+            .field("methods", &self.methods.iter().map(|m| m.get_name()).collect::<Vec<_>>())
+            .field("signals", &self.signals.iter().map(|s| s.get_name()).collect::<Vec<_>>())
+            .field("properties", &self.properties.iter().map(|p| p.get_name()).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+pub fn new_mixin<M: MethodType<D>, D: DataType>() -> Mixin<M, D> {
+    Mixin { methods: vec!(), signals: vec!(), properties: vec!() }
+}
+
 pub fn new_interface<M: MethodType<D>, D: DataType>(t: IfaceName<'static>, d: D::Interface) -> Interface<M, D> {
     Interface { name: Arc::new(t), methods: ArcMap::new(), signals: ArcMap::new(),
         properties: ArcMap::new(), anns: Annotations::new(), data: d
@@ -138,6 +217,8 @@ pub struct ObjectPath<M: MethodType<D>, D: DataType> {
     ifaces: ArcMap<Arc<IfaceName<'static>>, Interface<M, D>>,
     ifacecache: Arc<IfaceCache<M, D>>,
     data: D::ObjectPath,
+    hidden: Vec<IfaceName<'static>>,
+    shown: Option<Vec<IfaceName<'static>>>,
 }
 
 impl<M: MethodType<D>, D: DataType> ObjectPath<M, D> {
@@ -151,16 +232,22 @@ impl<M: MethodType<D>, D: DataType> ObjectPath<M, D> {
     /// Iterates over interfaces implemented by this object path.
     pub fn iter<'a>(&'a self) -> Iter<'a, Interface<M, D>> { IterE::Iface(self.ifaces.values()).into() }
 
+    // Whether the interface is listed when introspecting; it is handled either way.
+    fn introspected(&self, i: &IfaceName) -> bool {
+        !self.hidden.iter().any(|h| h == i) && self.shown.as_ref().map(|s| s.iter().any(|x| x == i)).unwrap_or(true)
+    }
+
     pub(super) fn introspect(&self, tree: &Tree<M, D>) -> String {
-        let ifacestr = introspect_map(&self.ifaces, "  ");
+        let ifaces: ArcMap<_, _> = self.ifaces.iter().filter(|(k, _)| self.introspected(k)).map(|(k, v)| (k.clone(), v.clone())).collect();
+        let ifacestr = introspect_map(&ifaces, "  ");
         let olen = if &**self.name == "/" { 1 } else { self.name.len()+1 };
         let childstr = tree.children(self, true).iter().fold("".to_string(), |na, n|
-            format!("{}  <node name=\"{}\"/>\n", na, &n.name[olen..])
+            format!("{}  <node name=\"{}\"/>\n", na, xml_escape(&n.name[olen..]))
         );
 
         let nodestr = format!(r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
 <node name="{}">
-{}{}</node>"##, self.name, ifacestr, childstr);
+{}{}</node>"##, xml_escape(&self.name), ifacestr, childstr);
         nodestr
     }
 
@@ -172,8 +259,10 @@ impl<M: MethodType<D>, D: DataType> ObjectPath<M, D> {
     fn prop_get(&self, m: &MethodInfo<M, D>) -> MethodResult {
         let (iname, prop_name): (&CStr, &str) = m.msg.read2()?;
         let iface = self.get_iface(iname)?;
-        let prop: &Property<M, D> = iface.properties.get(&String::from(prop_name))
+        let prop: &Property<M, D> = iface.properties.get(prop_name)
             .ok_or_else(|| MethodErr::no_property(&prop_name))?;
+        trace_event!(interface = ?iface.get_name(), property = prop_name, "Getting property");
+        m.tree.check_property(m.msg, iface.get_name(), prop_name, false)?;
         prop.can_get()?;
         let mut mret = m.msg.method_return();
         {
@@ -181,23 +270,26 @@ impl<M: MethodType<D>, D: DataType> ObjectPath<M, D> {
             let pinfo = m.to_prop_info(iface, prop);
             prop.get_as_variant(&mut iter, &pinfo)?;
         }
-        Ok(vec!(mret))
+        Ok(mret.into())
     }
 
     fn prop_get_all(&self, m: &MethodInfo<M, D>) -> MethodResult {
         let iface = self.get_iface(m.msg.read1()?)?;
+        trace_event!(interface = ?iface.get_name(), "Getting all properties");
         let mut mret = m.msg.method_return(); 
-        prop_append_dict(&mut arg::IterAppend::new(&mut mret), 
-            iface.properties.values().map(|v| &**v), m)?;
-        Ok(vec!(mret))
+        prop_append_dict(&mut arg::IterAppend::new(&mut mret),
+            iface.properties.values().map(|v| &**v).filter(|p| m.tree.check_property(m.msg, iface.get_name(), p.get_name(), false).is_ok()), m)?;
+        Ok(mret.into())
     }
 
 
     fn prop_set(&self, m: &MethodInfo<M, D>) -> MethodResult {
         let (iname, prop_name): (&CStr, &str) = m.msg.read2()?;
         let iface = self.get_iface(iname)?;
-        let prop: &Property<M, D> = iface.properties.get(&String::from(prop_name))
+        let prop: &Property<M, D> = iface.properties.get(prop_name)
             .ok_or_else(|| MethodErr::no_property(&prop_name))?;
+        trace_event!(interface = ?iface.get_name(), property = prop_name, "Setting property");
+        m.tree.check_property(m.msg, iface.get_name(), prop_name, true)?;
 
         let mut iter = arg::Iter::new(m.msg);
         iter.next(); iter.next();
@@ -205,49 +297,73 @@ impl<M: MethodType<D>, D: DataType> ObjectPath<M, D> {
         prop.can_set(Some(iter))?;
 
         let pinfo = m.to_prop_info(iface, prop);
-        let mut r: Vec<Message> = prop.set_as_variant(&mut iter2, &pinfo)?.into_iter().collect();
+        let mut r: MethodReplies = prop.set_as_variant(&mut iter2, &pinfo)?.into_iter().collect();
         r.push(m.msg.method_return());
         Ok(r)
 
     }
 
     fn get_managed_objects(&self, m: &MethodInfo<M, D>) -> MethodResult {
+        let mut r = m.msg.method_return();
+        m.tree.append_managed_objects(&mut arg::IterAppend::new(&mut r), &self.name, m)?;
+        Ok(r.into())
+    }
+
+    /// Appends all interfaces of this object path, and their properties, as an `a{sa{sv}}`.
+    ///
+    /// This is the format used by the InterfacesAdded signal. The property getters are called with
+    /// the message, method and tree in "minfo".
+    pub fn append_interfaces(&self, i: &mut arg::IterAppend, minfo: &MethodInfo<M, D>) -> Result<(), MethodErr> {
+        self.append_ifaces(i, minfo, false)
+    }
+
+    // With "snapshot", for signals sent by the tree itself, the policy is not checked (there is no caller)
+    // and the properties whose getter fails are left out, so that this does not fail.
+    fn append_ifaces(&self, i: &mut arg::IterAppend, minfo: &MethodInfo<M, D>, snapshot: bool) -> Result<(), MethodErr> {
         use crate::arg::{Dict, Variant};
-        let mut paths = m.tree.children(&self, false);
-        paths.push(&self);
         let mut result = Ok(());
-        let mut r = m.msg.method_return();
-        {
-            let mut i = arg::IterAppend::new(&mut r);
-            i.append_dict(&Signature::make::<Path>(), &Signature::make::<Dict<&str,Dict<&str,Variant<()>,()>,()>>(), |ii| {
-                for p in paths {
-                    ii.append_dict_entry(|pi| {
-                        pi.append(&*p.name);
-                        pi.append_dict(&Signature::make::<&str>(), &Signature::make::<Dict<&str,Variant<()>,()>>(), |pii| {
-                            for ifaces in p.ifaces.values() {
-                                let m2 = MethodInfo { msg: m.msg, path: p, iface: ifaces, tree: m.tree, method: m.method };
-                                pii.append_dict_entry(|ppii| {
-                                    ppii.append(&**ifaces.name);
-                                    result = prop_append_dict(ppii, ifaces.properties.values().map(|v| &**v), &m2);
-                                });
-                                if result.is_err() { break; }
-                            }
-                        });
-                    });
-                    if result.is_err() { break; }
-                }
-            });
+        i.append_dict(&Signature::make::<&str>(), &Signature::make::<Dict<&str,Variant<()>,()>>(), |ii| {
+            for iface in self.ifaces.values() {
+                let m2 = MethodInfo { msg: minfo.msg, path: self, iface, tree: minfo.tree, method: minfo.method };
+                ii.append_dict_entry(|iii| {
+                    iii.append(&**iface.name);
+                    let props = iface.properties.values().map(|v| &**v)
+                        .filter(|p| snapshot || minfo.tree.check_property(minfo.msg, iface.get_name(), p.get_name(), false).is_ok());
+                    if snapshot { prop_append_dict_lossy(iii, props, &m2) }
+                    else { result = prop_append_dict(iii, props, &m2) }
+                });
+                if result.is_err() { break; }
+            }
+        });
+        result
+    }
+
+    // See Interface::intern_names.
+    fn intern_names(p: &mut Arc<Self>, names: &mut Names) {
+        match Arc::get_mut(p) {
+            Some(p) => p.ifaces = std::mem::take(&mut p.ifaces).into_values().map(|mut i| {
+                Interface::intern_names(&mut i, names);
+                (i.name.clone(), i)
+            }).collect(),
+            None => for i in p.ifaces.values() { Interface::intern_names(&mut i.clone(), names) },
         }
-        result?;
-        Ok(vec!(r))
     }
 
-    fn handle(&self, m: &Message, t: &Tree<M, D>) -> MethodResult {
-        let iname = m.interface().or_else(|| { self.default_iface.clone() });
-        let i = iname.and_then(|i| self.ifaces.get(&i)).ok_or_else(|| MethodErr::no_interface(&""))?;
-        let me = m.member().and_then(|me| i.methods.get(&me)).ok_or_else(|| MethodErr::no_method(&""))?;
+    fn handle(&self, m: &Message, t: &Tree<M, D>, call: &mut dyn FnMut(&MethodInfo<M, D>) -> MethodResult) -> MethodResult {
+        // Look up the default interface by reference, so that no name is copied.
+        let iname = m.interface();
+        let i = match iname {
+            Some(ref i) => t.names.get_iface(i).and_then(|i| self.ifaces.get(i)),
+            None => self.default_iface.as_ref().and_then(|i| self.ifaces.get(i)),
+        };
+        let i = i.ok_or_else(|| MethodErr::no_interface(&""))?;
+        let me = m.member().and_then(|me| t.names.get_member(&me)).and_then(|me| i.methods.get(me)).ok_or_else(|| MethodErr::no_method(&""))?;
+        // Properties are checked one by one, when they are accessed.
+        if let Some(p) = t.policy.as_ref().filter(|_| &**i.name != "org.freedesktop.DBus.Properties") {
+            p.check_method(m, &i.name, me.get_name())?;
+        }
         let minfo = MethodInfo { msg: m, tree: t, path: self, iface: i, method: me };
-        me.call(&minfo)
+        call(&minfo)
     }
 
 }
@@ -279,13 +395,37 @@ where <D as DataType>::Interface: Default, <D as DataType>::Method: Default
         self
     }
 
+    /// Builder function that leaves the interface out of the introspection data. It is still handled.
+    ///
+    /// Together with `hide_standard_interfaces` and `introspect_only`, this helps when the introspection data has to
+    /// match an existing document exactly.
+    pub fn hide_interface<I: Into<IfaceName<'static>>>(mut self, i: I) -> Self {
+        self.hidden.push(i.into());
+        self
+    }
+
+    /// Builder function that leaves the Introspectable, Properties, Peer and ObjectManager
+    /// interfaces out of the introspection data. They are still handled.
+    pub fn hide_standard_interfaces(self) -> Self {
+        ["org.freedesktop.DBus.Introspectable", "org.freedesktop.DBus.Properties", "org.freedesktop.DBus.Peer", OBJECT_MANAGER]
+            .iter().fold(self, |s, &i| s.hide_interface(i))
+    }
+
+    /// Builder function that only lists the given interfaces in the introspection data. All interfaces are still handled.
+    pub fn introspect_only<I: IntoIterator<Item=N>, N: Into<IfaceName<'static>>>(mut self, ifaces: I) -> Self {
+        self.shown = Some(ifaces.into_iter().map(|i| i.into()).collect());
+        self
+    }
+
     /// Adds ObjectManager support for this object path.
     ///
+    /// When object paths below this one are added to or removed from the tree with `Tree::insert`
+    /// and `Tree::remove`, the tree sends InterfacesAdded and InterfacesRemoved signals from this path.
     /// It is not possible to add/remove interfaces while the object path belongs to a tree,
-    /// hence no InterfacesAdded / InterfacesRemoved signals are sent.
+    /// so those signals always list all interfaces of the object path.
     pub fn object_manager(mut self) -> Self {
         use crate::arg::{Variant, Dict};
-        let ifname = IfaceName::from("org.freedesktop.DBus.ObjectManager");
+        let ifname = IfaceName::from(OBJECT_MANAGER);
         if self.ifaces.contains_key(&ifname) { return self };
         let z = self.ifacecache.get(ifname, |i| {
             i.add_m(super::leaves::new_method("GetManagedObjects".into(), Default::default(),
@@ -322,7 +462,7 @@ where <D as DataType>::Interface: Default, <D as DataType>::Method: Default
 
 pub fn new_objectpath<M: MethodType<D>, D: DataType>(n: Path<'static>, d: D::ObjectPath, cache: Arc<IfaceCache<M, D>>)
     -> ObjectPath<M, D> {
-    ObjectPath { name: Arc::new(n), data: d, ifaces: ArcMap::new(), ifacecache: cache, default_iface: None }
+    ObjectPath { name: Arc::new(n), data: d, ifaces: ArcMap::new(), ifacecache: cache, default_iface: None, hidden: vec!(), shown: None }
 }
 
 
@@ -331,6 +471,13 @@ pub fn new_objectpath<M: MethodType<D>, D: DataType>(n: Path<'static>, d: D::Obj
 pub struct Tree<M: MethodType<D>, D: DataType> {
     paths: ArcMap<Arc<Path<'static>>, ObjectPath<M, D>>,
     data: D::Tree,
+    changed: ChangedQueue,
+    policy: Option<Policy>,
+    idle: Option<IdleExit>,
+    metrics: Option<SharedMetrics>,
+    destinations: Option<Destinations>,
+    namespaces: Vec<Path<'static>>,
+    names: Names,
 }
 
 impl<M: MethodType<D>, D: DataType> Tree<M, D> {
@@ -338,11 +485,98 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
     ///
     /// Note: This does not register a path with the connection, so if the tree is currently registered,
     /// you might want to call Connection::register_object_path to add the path manually.
+    ///
+    /// Unlike `insert`, this does not send an InterfacesAdded signal.
     pub fn add<I: Into<Arc<ObjectPath<M, D>>>>(mut self, s: I) -> Self {
-        self.insert(s);
+        let mut m = s.into();
+        ObjectPath::intern_names(&mut m, &mut self.names);
+        self.paths.insert(m.name.clone(), m);
+        self
+    }
+
+    /// Builder function that sets when PropertiesChanged signals are sent.
+    ///
+    /// See `FlushPolicy` for the options. Only signals that end up in the replies of a method
+    /// call are affected, e g the ones generated when a property is set.
+    pub fn flush_policy(mut self, p: FlushPolicy) -> Self {
+        self.changed.policy = p;
+        self
+    }
+
+    /// Builder function that sets where signals are sent that are not the result of a method call.
+    ///
+    /// This is PropertiesChanged signals held back by `FlushPolicy::Interval`, when the interval has passed,
+    /// and the InterfacesAdded and InterfacesRemoved signals from `insert` and `remove`, right away.
+    /// Without a sender, they wait for the next method call or `send_changed`.
+    pub fn signal_sender(mut self, s: channel::MsgSender) -> Self {
+        self.changed.sender = Some(s);
+        self
+    }
+
+    /// Builder function that sets which callers may call which methods, and get and set which properties.
+    ///
+    /// See `Policy` for details.
+    pub fn policy(mut self, p: Policy) -> Self {
+        self.policy = Some(p);
+        self
+    }
+
+    /// Builder function that counts every method call to an object in this tree as activity of "i".
+    ///
+    /// See `IdleExit` for details.
+    pub fn idle_exit(mut self, i: IdleExit) -> Self {
+        self.idle = Some(i);
+        self
+    }
+
+    /// Builder function that reports how long method calls take, and which errors they return.
+    ///
+    /// See the `metrics` module for details.
+    pub fn metrics(mut self, m: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(SharedMetrics(m));
+        self
+    }
+
+    /// Builder function that makes the tree only handle method calls meant for one of the names in "d".
+    ///
+    /// Other method calls are passed through, see `Destinations` for details.
+    pub fn destinations(mut self, d: Destinations) -> Self {
+        self.destinations = Some(d);
+        self
+    }
+
+    /// Builder function that makes the tree reply with an UnknownObject error to method calls for "p",
+    /// or any path below it, that is not in the tree.
+    ///
+    /// Without this, such method calls are not handled (i e they are passed through), and
+    /// unless something else replies, the caller has to wait for a timeout.
+    pub fn unknown_object_namespace<P: Into<Path<'static>>>(mut self, p: P) -> Self {
+        self.namespaces.push(p.into());
         self
     }
 
+    fn in_namespace(&self, p: &Path) -> bool {
+        self.namespaces.iter().any(|n| p.strip_prefix(&**n).map(|r| r.is_empty() || r.starts_with('/') || &**n == "/").unwrap_or(false))
+    }
+
+    fn check_property(&self, msg: &Message, iface: &IfaceName, prop: &str, set: bool) -> Result<(), MethodErr> {
+        match &self.policy { Some(p) => p.check_property(msg, iface, prop, set), None => Ok(()) }
+    }
+
+    /// Returns all PropertiesChanged signals currently held back, merged into as few signals as possible.
+    ///
+    /// Only useful with `FlushPolicy::Interval`, or after calling `insert` or `remove` without a `signal_sender`
+    /// (the InterfacesAdded and InterfacesRemoved signals come first). Unless the tree has a `signal_sender`,
+    /// call this when the connection is idle to make sure that no signal is held back forever.
+    pub fn flush_changed(&self) -> Vec<Message> { self.changed.flush() }
+
+    /// Sends the PropertiesChanged signals held back (see `flush_changed`) through "s".
+    #[allow(clippy::result_unit_err)] // Same error type as channel::Sender
+    pub fn send_changed<S: channel::Sender + ?Sized>(&self, s: &S) -> Result<(), ()> {
+        for m in self.flush_changed() { s.send(m)?; }
+        Ok(())
+    }
+
     /// Get a reference to an object path from the tree.
     pub fn get(&self, p: &Path<'static>) -> Option<&Arc<ObjectPath<M, D>>> {
         self.paths.get(p)
@@ -355,8 +589,15 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
     ///
     /// Note: This does not register a path with the connection, so if the tree is currently registered,
     /// you might want to call Connection::register_object_path to add the path manually.
+    ///
+    /// If an ancestor of the path has ObjectManager support, an InterfacesAdded signal, with the
+    /// current values of all properties, is sent right away through the tree's `signal_sender`.
+    /// Properties whose getter fails (e g because it needs to know the caller) are left out of it.
+    /// Without one, it is sent with the replies of the next method call, or by `send_changed`.
     pub fn insert<I: Into<Arc<ObjectPath<M, D>>>>(&mut self, s: I) {
-        let m = s.into();
+        let mut m = s.into();
+        ObjectPath::intern_names(&mut m, &mut self.names);
+        if let Some(sig) = self.interfaces_added(&m) { self.changed.push(sig) }
         self.paths.insert(m.name.clone(), m);
     }
 
@@ -365,10 +606,46 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
     ///
     /// Note: This does not unregister a path with the connection, so if the tree is currently registered,
     /// you might want to call Connection::unregister_object_path to remove the path manually.
+    ///
+    /// If an ancestor of the path has ObjectManager support, an InterfacesRemoved signal is sent right away
+    /// through the tree's `signal_sender`. Without one, it is sent with the replies of the next method call,
+    /// or by `send_changed`.
     pub fn remove(&mut self, p: &Path<'static>) -> Option<Arc<ObjectPath<M, D>>> {
         // There is no real reason p needs to have a static lifetime; but
         // the borrow checker doesn't agree. :-(
-        self.paths.remove(p)
+        let r = self.paths.remove(p)?;
+        if let Some(manager) = self.object_manager_of(p) {
+            let ifaces: Vec<&str> = r.ifaces.keys().map(|i| &***i).collect();
+            self.changed.push(Message::signal(&manager.name, &OBJECT_MANAGER.into(), &"InterfacesRemoved".into()).append2(p, ifaces));
+        }
+        Some(r)
+    }
+
+    // The closest ancestor of "p" with ObjectManager support.
+    fn object_manager_of(&self, p: &Path) -> Option<&Arc<ObjectPath<M, D>>> {
+        let ifname = IfaceName::from(OBJECT_MANAGER);
+        let mut parent = p.parent();
+        while let Some(pp) = parent {
+            if let Some(o) = self.paths.get(&pp).filter(|o| o.ifaces.contains_key(&ifname)) { return Some(o) }
+            parent = pp.parent();
+        }
+        None
+    }
+
+    fn interfaces_added(&self, o: &ObjectPath<M, D>) -> Option<Message> {
+        let manager = self.object_manager_of(&o.name)?;
+        let iface = manager.ifaces.get(&IfaceName::from(OBJECT_MANAGER))?;
+        let method = iface.methods.get(&Member::from("GetManagedObjects"))?;
+        // The property getters see this as a GetManagedObjects call from ourselves.
+        let mut call = Message::method_call(&"org.freedesktop.DBus".into(), &manager.name, &iface.name, method.get_name());
+        call.set_destination(None);
+        let minfo = MethodInfo { msg: &call, method, iface, path: manager, tree: self };
+        let mut s = Message::signal(&manager.name, &iface.name, &"InterfacesAdded".into());
+        let mut i = arg::IterAppend::new(&mut s);
+        i.append(&*o.name);
+        // A getter failing is not a reason for the insert to fail, so such properties are left out.
+        o.append_ifaces(&mut i, &minfo, true).ok()?;
+        Some(s)
     }
 
     /// Registers or unregisters all object paths in the tree to a ffidisp::Connection.
@@ -394,36 +671,87 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
 
     /// This method takes an `ConnectionItem` iterator (you get it from `Connection::iter()`)
     /// and handles all matching items. Non-matching items (e g signals) are passed through.
-    pub fn run<'a, I: Iterator<Item=ConnectionItem>>(&'a self, c: &'a Connection, i: I) -> TreeServer<'a, I, M, D> {
-        TreeServer { iter: i, tree: &self, conn: c }
+    ///
+    /// Replies are sent through "c", which is typically the `Connection` the items came from,
+    /// but can be anything that implements `channel::Sender`, e g a mock that records the replies.
+    pub fn run<'a, C: channel::Sender + ?Sized, I: Iterator<Item=ConnectionItem>>(&'a self, c: &'a C, i: I) -> TreeServer<'a, I, M, D, C> {
+        TreeServer { iter: i, tree: &self, conn: c, other: None, stop: Default::default() }
+    }
+
+    /// Handles all method calls arriving on "c", calling "f" for all other incoming messages.
+    ///
+    /// This is `run` and `TreeServer::on_other` combined, for servers that have nothing else to do.
+    /// It only returns when the connection is closed, with the reason as the error.
+    pub fn serve<F: FnMut(ConnectionItem)>(&self, c: &Connection, f: F) -> Result<(), Error> {
+        for i in self.run(c, c.iter(1000)).on_other(f) {
+            if let ConnectionItem::Disconnected(e) = i { return Err(e) }
+        }
+        Ok(())
     }
 
     /// Handles a message.
     ///
     /// Will return None in case the object path was not
     /// found in this tree, or otherwise a list of messages to be sent back.
-    pub fn handle(&self, m: &Message) -> Option<Vec<Message>> {
-        if m.msg_type() != MessageType::MethodCall { None }
-        else { m.path().and_then(|p| self.paths.get(&p).map(|s| s.handle(m, &self)
-            .unwrap_or_else(|e| vec!(e.to_message(m))))) }
+    /// Method calls meant for someone else (see `destinations`) also return None, and method calls
+    /// for unknown paths inside a namespace (see `unknown_object_namespace`) return an error reply.
+    pub fn handle(&self, m: &Message) -> Option<MethodReplies> {
+        let (r, start, _activity) = self.dispatch(m, &mut |minfo| minfo.method.call(minfo))?;
+        Some(finish_call(&self.changed, self.metrics.as_ref(), m, r, start))
+    }
+
+    // Finds the method for "handle" and calls it through "call", returning its result, when it was called,
+    // and the idle activity to hold until the replies are ready. The result still needs to go through finish_call.
+    fn dispatch(&self, m: &Message, call: &mut dyn FnMut(&MethodInfo<M, D>) -> MethodResult) -> Option<(MethodResult, Instant, Option<Activity>)> {
+        trace_span!("handle", serial = ?m.get_serial(), path = ?m.path(), interface = ?m.interface(), member = ?m.member());
+        if let Some(d) = &self.destinations {
+            if d.update(m) || !d.accepts(m) { return None }
+        }
+        if m.msg_type() != MessageType::MethodCall { return None }
+        let p = m.path()?;
+        let start = Instant::now();
+        let (r, activity) = match self.paths.get(&p) {
+            Some(s) => {
+                let activity = self.idle.as_ref().map(|i| i.activity());
+                (s.handle(m, &self, call), activity)
+            }
+            None if self.in_namespace(&p) => (Err(MethodErr::no_path(&p)), None),
+            None => return None,
+        };
+        Some((r, start, activity))
     }
 
 
-    fn children(&self, o: &ObjectPath<M, D>, direct_only: bool) -> Vec<&ObjectPath<M, D>> {
-        let parent: &str = &o.name;
-        let plen = if parent == "/" { 1 } else { parent.len()+1 };
-        let mut r: Vec<&ObjectPath<M, D>> = self.paths.values().filter_map(|v| {
-            let k: &str = &v.name;
-            if !k.starts_with(parent) || k.len() <= plen || &k[plen-1..plen] != "/" {None} else {
-                Some(&**v)
+    /// Appends all object paths starting with "prefix" (including "prefix" itself), their interfaces
+    /// and properties, as an `a{oa{sa{sv}}}`.
+    ///
+    /// This is the format used by the GetManagedObjects method. The property getters are called with
+    /// the message, method and tree in "minfo".
+    pub fn append_managed_objects(&self, i: &mut arg::IterAppend, prefix: &Path, minfo: &MethodInfo<M, D>) -> Result<(), MethodErr> {
+        use crate::arg::{Dict, Variant};
+        let mut result = Ok(());
+        let paths = self.paths.values().filter(|p| p.name.is_descendant_of(prefix));
+        i.append_dict(&Signature::make::<Path>(), &Signature::make::<Dict<&str,Dict<&str,Variant<()>,()>,()>>(), |ii| {
+            for p in paths {
+                ii.append_dict_entry(|pi| {
+                    pi.append(&*p.name);
+                    result = p.append_interfaces(pi, minfo);
+                });
+                if result.is_err() { break; }
             }
-        }).collect();
+        });
+        result
+    }
+
+    fn children(&self, o: &ObjectPath<M, D>, direct_only: bool) -> Vec<&ObjectPath<M, D>> {
+        let mut r: Vec<&ObjectPath<M, D>> = self.paths.values()
+            .filter(|v| v.name != o.name && v.name.is_descendant_of(&o.name)).map(|v| &**v).collect();
         if direct_only {
             r.sort_by_key(|v| &**v.name);
             let mut prev: Option<&ObjectPath<M, D>> = None;
             r.retain(|v| {
-                let a = prev.map(|prev| !v.name.starts_with(&**prev.name)).unwrap_or(true);
-                prev = Some(v);
+                let a = prev.map(|prev| !v.name.is_descendant_of(&prev.name)).unwrap_or(true);
+                if a { prev = Some(v) };
                 a
             });
         }
@@ -436,10 +764,20 @@ impl<M: MethodType<D>, D: DataType> Tree<M, D> {
 
 impl<M: MethodType<D> + 'static, D: DataType + 'static> Tree<M, D> {
     /// Connects a Connection with a Tree so that incoming method calls are handled.
+    ///
+    /// If the tree has `destinations`, NameAcquired and NameLost signals from the bus are received too,
+    /// to keep them up to date.
     pub fn start_receive<C>(self, connection: &C)
     where
         C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool>> + channel::Sender
     {
+        if let Some(d) = self.destinations.clone() {
+            let mut rule = message::MatchRule::new();
+            rule.msg_type = Some(MessageType::Signal);
+            rule.sender = Some("org.freedesktop.DBus".into());
+            rule.interface = Some("org.freedesktop.DBus".into());
+            connection.start_receive(rule, Box::new(move |msg, _| { d.update(&msg); true }));
+        }
         let mut rule = message::MatchRule::new();
         rule.msg_type = Some(MessageType::MethodCall);
         connection.start_receive(rule, Box::new(move |msg, c| {
@@ -452,20 +790,48 @@ impl<M: MethodType<D> + 'static, D: DataType + 'static> Tree<M, D> {
 
 }
 
+// Records the metrics of a method call, and turns its result into the replies to send,
+// with the signals generated by it (or held back earlier) according to the flush policy.
+fn finish_call(changed: &ChangedQueue, metrics: Option<&SharedMetrics>, m: &Message, r: MethodResult, start: Instant) -> MethodReplies {
+    if let Some(metrics) = metrics { metrics.dispatched(m, r.as_ref().err(), start.elapsed()) }
+    changed.process(r.unwrap_or_else(|e| e.to_message(m).into()))
+}
+
+impl<D: DataType> Tree<MTFuture<D>, D> {
+    /// Handles a message, where the method handler might need to wait before it can reply.
+    ///
+    /// Will return None in case the object path was not found in this tree, or otherwise
+    /// a future which resolves into the messages to be sent back.
+    /// The future needs to be spawned on an executor, see e g the dbus-tokio crate.
+    pub fn handle_async(&self, m: Message) -> Option<Pin<Box<dyn Future<Output = MethodReplies>>>> {
+        let mut f = None;
+        let (r, start, activity) = self.dispatch(&m, &mut |minfo| { f = Some(minfo.method.call_future(minfo)); Ok(MethodReplies::new()) })?;
+        let (changed, metrics) = (self.changed.clone(), self.metrics.clone());
+        Some(Box::pin(async move {
+            let _activity = activity;
+            let r = match (r, f) {
+                (Ok(_), Some(f)) => f.await,
+                (r, _) => r,
+            };
+            finish_call(&changed, metrics.as_ref(), &m, r, start)
+        }))
+    }
+}
+
 pub fn new_tree<M: MethodType<D>, D: DataType>(d: D::Tree) -> Tree<M, D> {
-    Tree { paths: ArcMap::new(), data: d }
+    Tree { paths: ArcMap::new(), data: d, changed: Default::default(), policy: None, idle: None, metrics: None, destinations: None, namespaces: vec!(), names: Default::default() }
 }
 
 impl<M: MethodType<D>, D: DataType> MsgHandler for Tree<M, D> {
     fn handle_msg(&mut self, msg: &Message) -> Option<MsgHandlerResult> {
-        self.handle(msg).map(|v| MsgHandlerResult { handled: true, done: false, reply: v })
+        self.handle(msg).map(|v| MsgHandlerResult { handled: true, done: false, reply: v.into() })
     }
     fn handler_type(&self) -> MsgHandlerType { MsgHandlerType::MsgType(MessageType::MethodCall) }
 }
 
 impl<M: MethodType<D>, D: DataType> MsgHandler for Arc<Tree<M, D>> {
     fn handle_msg(&mut self, msg: &Message) -> Option<MsgHandlerResult> {
-        self.handle(msg).map(|v| MsgHandlerResult { handled: true, done: false, reply: v })
+        self.handle(msg).map(|v| MsgHandlerResult { handled: true, done: false, reply: v.into() })
     }
     fn handler_type(&self) -> MsgHandlerType { MsgHandlerType::MsgType(MessageType::MethodCall) }
 }
@@ -474,18 +840,45 @@ impl<M: MethodType<D>, D: DataType> MsgHandler for Arc<Tree<M, D>> {
 ///
 /// Method calls that match an object path in the tree are handled and consumed by this
 /// iterator. Other messages are passed through.
-pub struct TreeServer<'a, I, M: MethodType<D> + 'a, D: DataType + 'a> {
+pub struct TreeServer<'a, I, M: MethodType<D> + 'a, D: DataType + 'a, C: ?Sized + 'a = Connection> {
     iter: I,
-    conn: &'a Connection,
+    conn: &'a C,
     tree: &'a Tree<M, D>,
+    other: Option<Box<dyn FnMut(ConnectionItem) + 'a>>,
+    stop: StopHandle,
 }
 
-impl<'a, I: Iterator<Item=ConnectionItem>, M: 'a + MethodType<D>, D: DataType + 'a> Iterator for TreeServer<'a, I, M, D> {
+impl<'a, I, M: MethodType<D> + 'a, D: DataType + 'a, C: ?Sized + 'a> TreeServer<'a, I, M, D, C> {
+    /// Builder function that makes the iterator call "f" for the messages it does not handle
+    /// (signals, method returns and method calls for paths not in the tree), instead of returning them.
+    ///
+    /// `ConnectionItem::Nothing` and `ConnectionItem::Disconnected` are still returned, so that the caller
+    /// can do something else when the connection is idle, and notice when it is closed.
+    pub fn on_other<F: FnMut(ConnectionItem) + 'a>(mut self, f: F) -> Self {
+        self.other = Some(Box::new(f));
+        self
+    }
+
+    /// Returns a handle that makes the iterator finish.
+    ///
+    /// After `StopHandle::stop` is called, e g from another thread or a signal handler, the iterator
+    /// returns None instead of waiting for the next message. A message already being handled is
+    /// handled to the end, and its replies are sent.
+    pub fn stop_handle(&self) -> StopHandle { self.stop.clone() }
+
+    /// Builder function that makes the iterator finish when "stop" is stopped, instead of
+    /// when its own handle is. Useful for stopping several servers, or a `Service`, at once.
+    pub fn stop_on(mut self, stop: StopHandle) -> Self { self.stop = stop; self }
+}
+
+impl<'a, I: Iterator<Item=ConnectionItem>, M: 'a + MethodType<D>, D: DataType + 'a, C: channel::Sender + ?Sized + 'a> Iterator for TreeServer<'a, I, M, D, C> {
     type Item = ConnectionItem;
 
     fn next(&mut self) -> Option<ConnectionItem> {
         loop {
+            if self.stop.is_stopped() { return None }
             let n = self.iter.next();
+            if let (Some(ConnectionItem::Signal(ref msg)), Some(d)) = (&n, &self.tree.destinations) { d.update(msg); }
             if let Some(ConnectionItem::MethodCall(ref msg)) = n {
                 if let Some(v) = self.tree.handle(&msg) {
                     // Probably the wisest is to ignore any send errors here -
@@ -494,7 +887,11 @@ impl<'a, I: Iterator<Item=ConnectionItem>, M: 'a + MethodType<D>, D: DataType +
                     continue;
                 }
             }
-            return n;
+            match (&mut self.other, n) {
+                (Some(f), Some(i @ ConnectionItem::Signal(_))) | (Some(f), Some(i @ ConnectionItem::MethodCall(_))) |
+                    (Some(f), Some(i @ ConnectionItem::MethodReturn(_))) => f(i),
+                (_, n) => return n,
+            }
         }
     }
 }
@@ -516,6 +913,132 @@ fn test_iter() {
     assert_eq!(paths.len(), 2);
 }
 
+#[test]
+fn test_interned_names() {
+    let f = super::Factory::new_fn::<()>();
+    let t = (0..3).fold(f.tree(()), |t, n| t.add(f.object_path(format!("/interned/{}", n), ())
+        .add(f.interface(String::from("com.example.dbusrs.Interned"), ())
+            .add_m(f.method(String::from("Hello"), (), |m| Ok(m.msg.method_return().into())))
+            .add_s(f.signal(String::from("Hello"), ())))));
+    let ifaces: Vec<_> = t.iter().flat_map(|p| p.iter().cloned().collect::<Vec<_>>()).collect();
+    assert_eq!(ifaces.len(), 3);
+    let ptr = ifaces[0].get_name().as_cstr().as_ptr();
+    let mptr = ifaces[0].iter_m().next().unwrap().get_name().as_cstr().as_ptr();
+    for i in &ifaces {
+        assert_eq!(i.get_name().as_cstr().as_ptr(), ptr);
+        assert_eq!(i.iter_m().next().unwrap().get_name().as_cstr().as_ptr(), mptr);
+        assert_eq!(i.iter_s().next().unwrap().get_name().as_cstr().as_ptr(), mptr);
+    }
+
+    // Names are looked up through the tree's names
+    let mut m = Message::new_method_call("com.example.dbusrs", "/interned/2", "com.example.dbusrs.Interned", "Hello").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    assert_eq!(t.handle(&m).unwrap()[0].msg_type(), MessageType::MethodReturn);
+    let mut m = Message::new_method_call("com.example.dbusrs", "/interned/2", "com.example.dbusrs.Interned", "Goodbye").unwrap();
+    crate::message::message_set_serial(&mut m, 2);
+    assert_eq!(t.handle(&m).unwrap()[0].msg_type(), MessageType::Error);
+
+    // Each tree has its own names
+    let t2 = f.tree(()).add(f.object_path("/interned/0", ()).add(f.interface(String::from("com.example.dbusrs.Interned"), ())));
+    assert_ne!(t2.iter().next().unwrap().iter().next().unwrap().get_name().as_cstr().as_ptr(), ptr);
+}
+
+#[test]
+fn test_unknown_object_namespace() {
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(()).unknown_object_namespace("/com/example/ns").add(f.object_path("/com/example/ns/known", ()).introspectable());
+    let call = |p: &str| {
+        let mut m = Message::new_method_call("com.example.ns", p, "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+        message::message_set_serial(&mut m, 1);
+        t.handle(&m).map(|mut r| r[0].as_result().err().and_then(|e| e.name().map(String::from)))
+    };
+    assert_eq!(call("/com/example/ns/known"), Some(None));
+    assert_eq!(call("/com/example/ns/unknown"), Some(Some("org.freedesktop.DBus.Error.UnknownObject".into())));
+    assert_eq!(call("/com/example/ns"), Some(Some("org.freedesktop.DBus.Error.UnknownObject".into())));
+    assert_eq!(call("/com/example/nsx"), None);
+    assert_eq!(call("/com/example"), None);
+}
+
+#[test]
+fn test_run_with_sender() {
+    use std::cell::RefCell;
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/echo", ()).add(f.interface("com.example.echo", ())
+        .add_m(f.method("Echo", (), |m| {
+            let s: &str = m.msg.read1()?;
+            Ok(m.msg.method_return().append1(s).into())
+        }))
+    ));
+    let mut m = Message::new_method_call("com.example.echo", "/echo", "com.example.echo", "Echo").unwrap().append1("Hi");
+    message::message_set_serial(&mut m, 1);
+    let s = Message::new_signal("/echo", "com.example.echo", "Echoed").unwrap();
+    let items = vec!(ConnectionItem::MethodCall(m), ConnectionItem::Signal(s));
+
+    let sent = RefCell::new(vec!());
+    let rest: Vec<_> = t.run(&sent, items.into_iter()).collect();
+    assert_eq!(rest.len(), 1);
+    assert!(matches!(rest[0], ConnectionItem::Signal(_)));
+    let sent = sent.into_inner();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].read1::<&str>().unwrap(), "Hi");
+
+    let recorder = RefCell::new(vec!());
+    assert!(t.send_changed(&recorder as &dyn channel::Sender).is_ok());
+    f.signal("Echoed", ()).send(&recorder, &"/echo".into(), &"com.example.echo".into(), &["Hi"]).unwrap();
+    let sent = recorder.into_inner();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(&*sent[0].member().unwrap(), "Echoed");
+}
+
+#[test]
+fn test_run_on_other() {
+    use std::cell::RefCell;
+    let f = super::Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/echo", ()).introspectable());
+    let call = |p| {
+        let mut m = Message::new_method_call("com.example.echo", p, "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+        message::message_set_serial(&mut m, 1);
+        ConnectionItem::MethodCall(m)
+    };
+    let s = Message::new_signal("/echo", "com.example.echo", "Echoed").unwrap();
+    let items = vec!(call("/echo"), ConnectionItem::Signal(s), ConnectionItem::Nothing, call("/other"));
+
+    let (sent, other) = (RefCell::new(vec!()), RefCell::new(vec!()));
+    let rest: Vec<_> = t.run(&sent, items.into_iter()).on_other(|i| other.borrow_mut().push(i)).collect();
+    assert_eq!(rest.len(), 1);
+    assert!(matches!(rest[0], ConnectionItem::Nothing));
+    assert_eq!(sent.into_inner().len(), 1);
+    let other = other.into_inner();
+    assert_eq!(other.len(), 2);
+    assert!(matches!(other[0], ConnectionItem::Signal(_)));
+    assert!(matches!(other[1], ConnectionItem::MethodCall(_)));
+}
+
+#[test]
+fn test_run_stop() {
+    use std::cell::RefCell;
+    let f = super::Factory::new_fn::<()>();
+    let stop = super::StopHandle::default();
+    let stop2 = stop.clone();
+    let t = f.tree(()).add(f.object_path("/stop", ()).add(f.interface("com.example.stop", ())
+        .add_m(f.method("Stop", (), move |m| { stop2.stop(); m.reply(()) }))
+    ));
+    let call = |member, serial| {
+        let mut m = Message::new_method_call("com.example.stop", "/stop", "com.example.stop", member).unwrap();
+        message::message_set_serial(&mut m, serial);
+        ConnectionItem::MethodCall(m)
+    };
+    let items = vec!(ConnectionItem::Nothing, call("Stop", 1), call("Stop", 2), ConnectionItem::Nothing);
+    let sent = RefCell::new(vec!());
+    let mut server = t.run(&sent, items.into_iter()).stop_on(stop.clone());
+    assert!(!server.stop_handle().is_stopped());
+    assert!(matches!(server.next(), Some(ConnectionItem::Nothing)));
+    // The call that stops the server is still replied to, but the next one is not handled.
+    assert!(server.next().is_none());
+    assert!(server.stop_handle().is_stopped());
+    assert_eq!(sent.borrow().len(), 1);
+}
+
 #[test]
 fn test_set_default_interface() {
     let iface_name: IfaceName<'_> = "com.example.echo".into();
@@ -578,3 +1101,364 @@ fn test_introspection() {
     assert_eq!(expected_result, actual_result);   
 }
 
+#[test]
+fn test_typed_annotations() {
+    use super::{Annotation, Argument};
+    let f = super::Factory::new_fn::<()>();
+    let i = f.interface("com.example.ann", ()).annotation(Annotation::CSymbol("ann".into()))
+        .add_m(f.method("Fire", (), |_| Ok(vec!().into())).no_reply()
+            .in_arg(Argument::from(("points", "a(ii)")).annotation(Annotation::QtTypeName("QList<QPoint>".into())))
+            .in_arg(Argument::from(("old", "s")).deprecated()))
+        .add_s(f.signal("Fired", ()).annotation(Annotation::Other("com.example.Note".into(), "x".into())))
+        .add_p(f.property::<i32,_>("Count", ()).annotation(Annotation::Deprecated));
+    let xml = f.object_path("/ann", ()).add(i).introspect(&f.tree(()));
+    assert!(xml.contains(r##"
+  <interface name="com.example.ann">
+    <method name="Fire">
+      <arg name="points" type="a(ii)" direction="in">
+        <annotation name="org.qtproject.QtDBus.QtTypeName" value="QList&lt;QPoint&gt;"/>
+      </arg>
+      <arg name="old" type="s" direction="in">
+        <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+      </arg>
+      <annotation name="org.freedesktop.DBus.Method.NoReply" value="true"/>
+    </method>
+    <property name="Count" type="i" access="read">
+      <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+    </property>
+    <signal name="Fired">
+      <annotation name="com.example.Note" value="x"/>
+    </signal>
+    <annotation name="org.freedesktop.DBus.GLib.CSymbol" value="ann"/>
+  </interface>
+"##), "{}", xml);
+}
+
+#[test]
+fn test_introspection_hidden() {
+    let f = super::Factory::new_fn::<()>();
+    let o = || f.object_path("/hide", ()).introspectable()
+        .add(f.interface("com.example.a", ()).add_p(f.property::<i32,_>("X", ()).on_get(|i, _| { i.append(3i32); Ok(()) })))
+        .add(f.interface("com.example.b", ()));
+    let t = f.tree(()).add(o().hide_standard_interfaces());
+    let call = |t: &Tree<_, _>, iface: &str, member: &str| {
+        let mut m = Message::new_method_call("com.example", "/hide", iface, member).unwrap();
+        if member == "Get" { m = m.append2("com.example.a", "X"); }
+        message::message_set_serial(&mut m, 1);
+        t.handle(&m).unwrap().into_iter().next().unwrap()
+    };
+    let xml = call(&t, "org.freedesktop.DBus.Introspectable", "Introspect").read1::<String>().unwrap();
+    assert!(xml.contains("com.example.a") && xml.contains("com.example.b"));
+    assert!(!xml.contains("org.freedesktop.DBus"));
+    // Hidden interfaces are still handled.
+    assert_eq!(call(&t, "org.freedesktop.DBus.Properties", "Get").read1::<crate::arg::Variant<i32>>().unwrap().0, 3);
+
+    let t = f.tree(()).add(o().introspect_only(vec!("com.example.b", "org.freedesktop.DBus.Introspectable")));
+    let xml = call(&t, "org.freedesktop.DBus.Introspectable", "Introspect").read1::<String>().unwrap();
+    assert!(!xml.contains("com.example.a") && xml.contains("com.example.b"));
+    assert!(xml.contains("org.freedesktop.DBus.Introspectable") && !xml.contains("org.freedesktop.DBus.Properties"));
+
+    let t = f.tree(()).add(o().hide_interface("com.example.b"));
+    let xml = call(&t, "org.freedesktop.DBus.Introspectable", "Introspect").read1::<String>().unwrap();
+    assert!(xml.contains("com.example.a") && !xml.contains("com.example.b"));
+    assert!(xml.contains("org.freedesktop.DBus.Properties"));
+}
+
+#[test]
+fn test_introspection_escaped_and_sorted() {
+    let f = super::Factory::new_fn::<()>();
+    let m = |n: &'static str| f.method(n, (), |_| Err(MethodErr::failed("Not called")));
+    let a = f.interface("com.example.a", ()).add_m(m("Z")).add_m(m("A").annotate("com.example.Note", "<a & \"b\">"));
+    let b = f.interface("com.example.b", ()).add_p(f.property::<i32,_>("Y", ())).add_p(f.property::<i32,_>("X", ()));
+    let t1 = f.object_path("/o", ()).add(a).add(b);
+    let a = f.interface("com.example.a", ()).add_m(m("A").annotate("com.example.Note", "<a & \"b\">")).add_m(m("Z"));
+    let b = f.interface("com.example.b", ()).add_p(f.property::<i32,_>("X", ())).add_p(f.property::<i32,_>("Y", ()));
+    let t2 = f.object_path("/o", ()).add(b).add(a);
+
+    let s = t1.introspect(&f.tree(()));
+    assert_eq!(s, t2.introspect(&f.tree(())));
+    assert!(s.contains("<annotation name=\"com.example.Note\" value=\"&lt;a &amp; &quot;b&quot;&gt;\"/>"));
+    let order: Vec<_> = ["com.example.a", "\"A\"", "\"Z\"", "com.example.b", "\"X\"", "\"Y\""].iter().map(|n| s.find(n).unwrap()).collect();
+    assert!(order.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(super::utils::xml_escape("plain"), "plain");
+}
+
+
+#[test]
+fn test_handle_async() {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    // Returns Pending once before it is ready.
+    struct YieldOnce(bool);
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+            if self.0 { return Poll::Ready(()) }
+            self.0 = true;
+            ctx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    fn block_on<F: Future + ?Sized>(mut f: Pin<Box<F>>) -> F::Output {
+        fn raw() -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut ctx = Context::from_waker(&waker);
+        loop { if let Poll::Ready(r) = f.as_mut().poll(&mut ctx) { return r } }
+    }
+
+    let f = super::Factory::new_future::<()>();
+    let t = f.tree(()).add(f.object_path("/echo", ()).introspectable()
+        .add(f.interface("com.example.echo", ())
+            .add_m(f.method("Echo", (), |m| {
+                let s: Result<String, _> = m.msg.read1();
+                let mret = m.msg.method_return();
+                async move {
+                    YieldOnce(false).await;
+                    Ok(mret.append1(s?).into())
+                }
+            }).inarg::<&str,_>("request").outarg::<&str,_>("reply"))
+    ));
+
+    let call = |member, serial| {
+        let mut m = Message::new_method_call("com.example.echo", "/echo", "com.example.echo", member).unwrap();
+        message::message_set_serial(&mut m, serial);
+        m
+    };
+
+    let r = block_on(t.handle_async(call("Echo", 1).append1("Hello")).unwrap());
+    assert_eq!(r.len(), 1);
+    assert_eq!(r[0].read1::<&str>().unwrap(), "Hello");
+
+    let mut r = block_on(t.handle_async(call("Echo", 2)).unwrap());
+    assert!(r[0].as_result().is_err());
+
+    let mut m = Message::new_method_call("com.example.echo", "/echo", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    message::message_set_serial(&mut m, 3);
+    let r = block_on(t.handle_async(m).unwrap());
+    assert!(r[0].read1::<&str>().unwrap().contains("<method name=\"Echo\">"));
+
+    let m = Message::new_method_call("com.example.echo", "/nothing", "com.example.echo", "Echo").unwrap();
+    assert!(t.handle_async(m).is_none());
+
+    // Handled without handle_async, the caller still gets a reply, and nothing is left behind for the next call.
+    let mut r = t.handle(&call("Echo", 4).append1("Lost")).unwrap();
+    assert_eq!(r[0].as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.Failed"));
+    let r = block_on(t.handle_async(call("Echo", 5).append1("Found")).unwrap());
+    assert_eq!(r.len(), 1);
+    assert_eq!(r[0].read1::<&str>().unwrap(), "Found");
+
+    // Replies from a future go through the flush policy and the metrics, like those of other trees.
+    use crate::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+    use crate::message::SignalArgs;
+    use crate::arg::{RefArg, Variant};
+    struct Errors(Mutex<Vec<String>>);
+    impl Metrics for Errors {
+        fn counter(&self, name: &'static str, labels: &[(&'static str, &str)], _: u64) {
+            if name == crate::metrics::ERRORS_SENT { self.0.lock().unwrap().push(labels[1].1.into()) }
+        }
+    }
+    let errors = Arc::new(Errors(Mutex::new(vec!())));
+    let t = f.tree(()).flush_policy(FlushPolicy::PerMethodCall).metrics(errors.clone()).add(f.object_path("/echo", ())
+        .add(f.interface("com.example.echo", ())
+            .add_m(f.method("Change", (), |m| {
+                let s = |p: &str| {
+                    let mut pc = PropertiesPropertiesChanged { interface_name: "com.example.echo".into(),
+                        changed_properties: Default::default(), invalidated_properties: vec!() };
+                    pc.changed_properties.insert(p.into(), Variant(Box::new(5u32) as Box<dyn RefArg>));
+                    pc.to_emit_message(m.path.get_name())
+                };
+                let r: MethodReplies = vec!(s("A"), m.msg.method_return(), s("B")).into_iter().collect();
+                async move { YieldOnce(false).await; Ok(r) }
+            }))
+            .add_m(f.method("Fail", (), |_| async move {
+                YieldOnce(false).await;
+                Err(MethodErr::failed(&"Async failure"))
+            }))
+    ));
+    let r = block_on(t.handle_async(call("Change", 6)).unwrap());
+    assert_eq!(r.len(), 2);
+    assert_eq!(PropertiesPropertiesChanged::from_message(&r[0]).unwrap().changed_properties.len(), 2);
+    assert!(errors.0.lock().unwrap().is_empty());
+    let mut r = block_on(t.handle_async(call("Fail", 7)).unwrap());
+    assert!(r[0].as_result().is_err());
+    assert_eq!(*errors.0.lock().unwrap(), vec!("Fail".to_string()));
+}
+
+#[test]
+fn test_mixin() {
+    let f = super::Factory::new_fn::<()>();
+    let player = f.mixin()
+        .add_m(f.method("Play", (), |m| Ok(m.msg.method_return().append1(&**m.iface.get_name()).into())))
+        .add_p(f.property::<bool,_>("Playing", ()).on_get(|i, _| { i.append(false); Ok(()) }))
+        .add_s(f.signal("Seeked", ()).sarg::<i64,_>("position"));
+    let t = f.tree(())
+        .add(f.object_path("/a", ()).add(f.interface("com.example.a", ()).mixin(&player)))
+        .add(f.object_path("/b", ()).introspectable().add(f.interface("com.example.b", ())
+            .add_m(f.method("Stop", (), |_| Err(MethodErr::failed("Not called"))))
+            .mixin(&player)));
+
+    for (i, (path, iface)) in [("/a", "com.example.a"), ("/b", "com.example.b")].iter().enumerate() {
+        let mut m = Message::new_method_call("com.example", *path, *iface, "Play").unwrap();
+        message::message_set_serial(&mut m, i as u32 + 1);
+        let r = t.handle(&m).unwrap();
+        assert_eq!(r[0].read1::<&str>().unwrap(), *iface);
+    }
+
+    let mut m = Message::new_method_call("com.example", "/b", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    message::message_set_serial(&mut m, 3);
+    let r = t.handle(&m).unwrap();
+    let s: &str = r[0].read1().unwrap();
+    assert!(s.contains(r#"<method name="Play"/>"#));
+    assert!(s.contains(r#"<method name="Stop"/>"#));
+    assert!(s.contains(r#"<property name="Playing" type="b" access="read"/>"#));
+    assert!(s.contains(r#"<signal name="Seeked">"#));
+    assert!(s.contains(r#"<interface name="org.freedesktop.DBus.Properties">"#));
+}
+
+#[test]
+fn test_managed_objects() {
+    use crate::arg::{Dict, Variant, RefArg};
+    use std::collections::HashMap;
+    let f = super::Factory::new_fn::<()>();
+    let iface = Arc::new(f.interface("com.example.thing", ())
+        .add_p(f.property::<i32,_>("Value", ()).on_get(|i, p| { i.append(p.path.get_name().len() as i32); Ok(()) })));
+    let t = f.tree(())
+        .add(f.object_path("/a", ()).object_manager().add(iface.clone())
+            .add(f.interface("com.example.manager", ()).add_m(f.method("Announce", (), |m| {
+                // The same data, for InterfacesAdded
+                let p = m.tree.iter().find(|p| &**p.get_name() == "/c").unwrap();
+                let mut s = Message::new_signal("/a", "org.freedesktop.DBus.ObjectManager", "InterfacesAdded").unwrap();
+                {
+                    let mut i = arg::IterAppend::new(&mut s);
+                    i.append(p.get_name());
+                    p.append_interfaces(&mut i, m)?;
+                }
+                Ok(s.into())
+            }))))
+        .add(f.object_path("/a/b", ()).add(iface.clone()))
+        .add(f.object_path("/ab", ()).add(iface.clone()))
+        .add(f.object_path("/c", ()).add(iface));
+
+    let mut m = Message::new_method_call("com.example", "/a", "org.freedesktop.DBus.ObjectManager", "GetManagedObjects").unwrap();
+    message::message_set_serial(&mut m, 1);
+    let r = t.handle(&m).unwrap();
+    let objs: HashMap<Path, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>> = r[0].read1().unwrap();
+    let mut paths: Vec<_> = objs.keys().map(|p| p.to_string()).collect();
+    paths.sort();
+    assert_eq!(paths, vec!("/a", "/a/b"));
+    assert_eq!(objs[&Path::from("/a/b")]["com.example.thing"]["Value"].0.as_i64(), Some(4));
+
+    let mut m = Message::new_method_call("com.example", "/a", "com.example.manager", "Announce").unwrap();
+    message::message_set_serial(&mut m, 2);
+    let r = t.handle(&m).unwrap();
+    let (path, ifaces): (Path, Dict<&str, Dict<&str, Variant<i32>, _>, _>) = r[0].read2().unwrap();
+    assert_eq!(&*path, "/c");
+    let ifaces: Vec<_> = ifaces.map(|(k, v)| (k, v.collect::<Vec<_>>())).collect();
+    assert_eq!(ifaces, vec!(("com.example.thing", vec!(("Value", Variant(2)))), ("org.freedesktop.DBus.Properties", vec!())));
+}
+
+#[test]
+fn test_object_manager_signals() {
+    use crate::arg::{Dict, Variant};
+    let f = super::Factory::new_fn::<()>();
+    let iface = Arc::new(f.interface("com.example.thing", ())
+        .add_p(f.property::<i32,_>("Value", ()).on_get(|i, p| { i.append(p.path.get_name().len() as i32); Ok(()) })));
+    let mut t = f.tree(())
+        .add(f.object_path("/a", ()).object_manager().introspectable())
+        .add(f.object_path("/a/b", ()).add(iface.clone()));
+    assert!(t.flush_changed().is_empty());
+
+    // Not below an object manager
+    t.insert(f.object_path("/c", ()).add(iface.clone()));
+    assert!(t.remove(&"/c".into()).is_some());
+    assert!(t.flush_changed().is_empty());
+
+    t.insert(f.object_path("/a/b/c", ()).add(iface.clone()));
+    let mut m = Message::new_method_call("com.example", "/a", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    message::message_set_serial(&mut m, 1);
+    let r = t.handle(&m).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(r[0].msg_type(), MessageType::MethodReturn);
+    assert_eq!(&*r[1].path().unwrap(), "/a");
+    assert_eq!(&*r[1].member().unwrap(), "InterfacesAdded");
+    let (path, ifaces): (Path, Dict<&str, Dict<&str, Variant<i32>, _>, _>) = r[1].read2().unwrap();
+    assert_eq!(&*path, "/a/b/c");
+    let ifaces: Vec<_> = ifaces.map(|(k, v)| (k, v.collect::<Vec<_>>())).collect();
+    assert_eq!(ifaces, vec!(("com.example.thing", vec!(("Value", Variant(6)))), ("org.freedesktop.DBus.Properties", vec!())));
+
+    // Nothing is sent twice
+    assert!(t.flush_changed().is_empty());
+    assert!(t.remove(&"/a/b/c".into()).is_some());
+    assert!(t.remove(&"/a/b/c".into()).is_none());
+    let r = t.flush_changed();
+    assert_eq!(r.len(), 1);
+    assert_eq!(&*r[0].member().unwrap(), "InterfacesRemoved");
+    let (path, ifaces): (Path, Vec<String>) = r[0].read2().unwrap();
+    assert_eq!(&*path, "/a/b/c");
+    assert_eq!(ifaces, vec!("com.example.thing", "org.freedesktop.DBus.Properties"));
+
+    // With a sender, the signals are sent right away.
+    let c = crate::blocking::Connection::new_session().unwrap();
+    c.add_match_no_cb(&format!("type='signal',path='/a',sender='{}'", c.unique_name())).unwrap();
+    let mut t = t.signal_sender(c.msg_sender());
+    t.insert(f.object_path("/a/d", ()).add(iface.clone()));
+    assert!(t.remove(&"/a/d".into()).is_some());
+    assert!(t.flush_changed().is_empty());
+    let ch: &crate::channel::Channel = c.as_ref();
+    let (mut members, start) = (vec!(), std::time::Instant::now());
+    while members.len() < 2 {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        // Short timeouts, so that the signals are written soon after they have been queued.
+        if let Some(m) = ch.blocking_pop_message(std::time::Duration::from_millis(50)).unwrap() {
+            if m.path().as_deref() == Some("/a") { members.push(m.member().unwrap().to_string()) }
+        }
+    }
+    assert_eq!(members, vec!("InterfacesAdded", "InterfacesRemoved"));
+}
+
+#[test]
+fn test_object_manager_failing_getter() {
+    use crate::arg::{Dict, Variant};
+    use super::Policy;
+    let f = super::Factory::new_fn::<()>();
+    // A getter that needs a caller fails for the signal, and a policy has nobody to check.
+    let iface = f.interface("com.example.thing", ())
+        .add_p(f.property::<i32,_>("Value", ()).on_get(|i, _| { i.append(5); Ok(()) }))
+        .add_p(f.property::<&str,_>("Caller", ()).on_get(|i, p| {
+            i.append(&*p.msg.sender().ok_or_else(|| MethodErr::failed("No caller"))?); Ok(())
+        }));
+    let mut t = f.tree(()).policy(Policy::new().deny_by_default())
+        .add(f.object_path("/a", ()).object_manager());
+    t.insert(f.object_path("/a/b", ()).add(iface));
+    let r = t.flush_changed();
+    assert_eq!(r.len(), 1);
+    assert_eq!(&*r[0].member().unwrap(), "InterfacesAdded");
+    let (path, ifaces): (Path, Dict<&str, Dict<&str, Variant<i32>, _>, _>) = r[0].read2().unwrap();
+    assert_eq!(&*path, "/a/b");
+    let ifaces: Vec<_> = ifaces.map(|(k, v)| (k, v.collect::<Vec<_>>())).collect();
+    assert_eq!(ifaces, vec!(("com.example.thing", vec!(("Value", Variant(5)))), ("org.freedesktop.DBus.Properties", vec!())));
+}
+
+#[test]
+#[ignore]
+fn bench_dispatch() {
+    // Run with "cargo test --release bench_dispatch -- --ignored --nocapture" to see how long dispatching takes.
+    use super::{Policy, Principal};
+    let f = Factory::new_fn::<()>();
+    let iface = Arc::new(f.interface("com.example.bench", ()).add_m(f.method("Ping", (), |m| Ok(m.msg.method_return().into()))));
+    let path = || f.object_path("/bench", ()).introspectable().add(iface.clone());
+    let plain = f.tree(()).add(path());
+    let policed = f.tree(()).add(path()).policy(Policy::new().allow_method("com.example.bench", "Ping", Principal::Anyone));
+
+    let mut m = Message::new_method_call("com.example", "/bench", "com.example.bench", "Ping").unwrap();
+    message::message_set_serial(&mut m, 1);
+
+    const N: u32 = 100_000;
+    for (name, t) in &[("plain", &plain), ("policy", &policed)] {
+        let start = std::time::Instant::now();
+        for _ in 0..N { assert!(t.handle(&m).is_some()); }
+        println!("{}: {:?} per call", name, start.elapsed() / N);
+    }
+}
@@ -0,0 +1,266 @@
+// Dispatching of incoming method calls to a pool of threads.
+
+use super::{Tree, MTSync, DataType};
+use crate::{Message, MessageType, message, channel};
+use std::sync::{Arc, Weak, Mutex, mpsc};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::thread;
+
+/// Decides which incoming method calls must be handled in the order they arrived.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DispatchOrder {
+    /// Method calls to the same object path are handled in order.
+    PerPath,
+    /// Method calls from the same sender are handled in order.
+    PerSender,
+}
+
+/// Runs the method handlers of a Tree on a pool of threads.
+///
+/// This is useful for services where handlers do blocking work. Method calls are spread
+/// out over the threads, but calls to the same object path (or from the same sender,
+/// depending on the DispatchOrder) always go to the same thread, so these are handled
+/// in the order they arrived.
+///
+/// Dropping the dispatcher waits for the threads to finish the calls already dispatched.
+/// If it is dropped on one of its own threads (e g, because that thread held the last
+/// reference to the connection owning the dispatcher), that thread is not waited for.
+#[derive(Debug)]
+pub struct ThreadPoolDispatcher {
+    senders: Mutex<Vec<mpsc::Sender<Message>>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    order: DispatchOrder,
+}
+
+impl ThreadPoolDispatcher {
+    /// Starts the threads.
+    ///
+    /// Replies are sent through "replies", which is typically a connection. It is weak to avoid
+    /// reference cycles in case the dispatcher is owned by the connection itself.
+    pub fn new<D, S>(tree: Arc<Tree<MTSync<D>, D>>, replies: Weak<S>, threads: usize, order: DispatchOrder) -> Self
+    where D: DataType + 'static, Tree<MTSync<D>, D>: Send + Sync, S: channel::Sender + Send + Sync + 'static {
+        assert!(threads > 0, "ThreadPoolDispatcher needs at least one thread");
+        let (senders, threads) = (0..threads).map(|_| {
+            let (tx, rx) = mpsc::channel::<Message>();
+            let (tree, replies) = (tree.clone(), replies.clone());
+            (tx, thread::spawn(move || for msg in rx {
+                if let Some(r) = tree.handle(&msg) {
+                    let s = match replies.upgrade() { Some(s) => s, None => return };
+                    // Ignore send errors, the remote might have disconnected during our processing.
+                    for m in r { let _ = s.send(m); }
+                }
+            }))
+        }).unzip();
+        ThreadPoolDispatcher { senders: Mutex::new(senders), threads, order }
+    }
+
+    /// Hands over a method call to one of the threads.
+    ///
+    /// Returns false if the message is not a method call, or the thread has stopped.
+    pub fn dispatch(&self, msg: Message) -> bool {
+        if msg.msg_type() != MessageType::MethodCall { return false }
+        let mut h = DefaultHasher::new();
+        match self.order {
+            DispatchOrder::PerPath => msg.path().as_deref().hash(&mut h),
+            DispatchOrder::PerSender => msg.sender().as_deref().hash(&mut h),
+        }
+        let senders = self.senders.lock().unwrap();
+        let idx = (h.finish() % senders.len() as u64) as usize;
+        senders[idx].send(msg).is_ok()
+    }
+}
+
+impl Drop for ThreadPoolDispatcher {
+    fn drop(&mut self) {
+        self.senders.lock().unwrap().clear();
+        // Joining the current thread would never return.
+        let current = thread::current().id();
+        for t in self.threads.drain(..) {
+            if t.thread().id() != current { let _ = t.join(); }
+        }
+    }
+}
+
+impl<D: DataType + 'static> Tree<MTSync<D>, D> where Tree<MTSync<D>, D>: Send + Sync {
+    /// Connects a Connection with a Tree so that incoming method calls are handled by a pool of threads.
+    ///
+    /// See ThreadPoolDispatcher for details.
+    pub fn start_receive_parallel<C>(self, connection: &Arc<C>, threads: usize, order: DispatchOrder)
+    where
+        C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool + Send + Sync>> + channel::Sender + Send + Sync + 'static
+    {
+        let d = ThreadPoolDispatcher::new(Arc::new(self), Arc::downgrade(connection), threads, order);
+        let mut rule = message::MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        connection.start_receive(rule, Box::new(move |msg, _| { d.dispatch(msg); true }));
+    }
+
+    /// Connects a Connection with a shared Tree so that incoming method calls are handled.
+    ///
+    /// Unlike `start_receive`, the tree is not consumed, so the same tree can serve several
+    /// connections, each processed by its own thread. Method handlers are then called from
+    /// these threads at the same time, which is why this needs an MTSync tree.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use dbus::tree::Factory;
+    /// use dbus::blocking::SyncConnection;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let f = Factory::new_sync::<()>();
+    /// let tree = Arc::new(f.tree(()).add(f.object_path("/hello", ()).add(f.interface("com.example.hello", ())
+    ///     .add_m(f.method("Hello", (), |m| m.reply(("Hello!",)))))));
+    /// let threads: Vec<_> = (0..4).map(|_| {
+    ///     let tree = tree.clone();
+    ///     std::thread::spawn(move || {
+    ///         let mut c = SyncConnection::new_session().unwrap();
+    ///         tree.start_receive_shared(&c);
+    ///         loop { c.process(Duration::from_millis(1000)).unwrap(); }
+    ///     })
+    /// }).collect();
+    /// ```
+    pub fn start_receive_shared<C>(self: Arc<Self>, connection: &C)
+    where
+        C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool + Send + Sync>> + channel::Sender
+    {
+        let mut rule = message::MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        connection.start_receive(rule, Box::new(move |msg, c| {
+            if let Some(replies) = self.handle(&msg) {
+                for r in replies { let _ = c.send(r); }
+            }
+            true
+        }));
+    }
+}
+
+#[test]
+fn test_ordering() {
+    use super::Factory;
+    use std::time::Duration;
+
+    struct Replies(Mutex<Vec<Message>>);
+    impl channel::Sender for Replies {
+        fn send(&self, msg: Message) -> Result<u32, ()> { self.0.lock().unwrap().push(msg); Ok(0) }
+    }
+
+    let f = Factory::new_sync::<()>();
+    let t = Arc::new(f.tree(()).add(f.object_path("/slow", ()).add(f.interface("com.example.test", ())
+        .add_m(f.method("Sleep", (), |m| {
+            let ms: u32 = m.msg.read1()?;
+            thread::sleep(Duration::from_millis(ms as u64));
+            Ok(m.msg.method_return().append1(ms).into())
+        }))
+    )));
+
+    let replies = Arc::new(Replies(Mutex::new(vec!())));
+    let d = ThreadPoolDispatcher::new(t, Arc::downgrade(&replies), 4, DispatchOrder::PerPath);
+    for (i, ms) in [50u32, 1, 2].iter().enumerate() {
+        let mut msg = Message::new_method_call("com.example.test", "/slow", "com.example.test", "Sleep").unwrap().append1(ms);
+        crate::message::message_set_serial(&mut msg, i as u32 + 1);
+        assert!(d.dispatch(msg));
+    }
+    drop(d);
+
+    let r: Vec<u32> = replies.0.lock().unwrap().iter().map(|m| m.read1().unwrap()).collect();
+    assert_eq!(r, vec!(50, 1, 2));
+}
+
+#[test]
+fn test_drop_on_worker() {
+    use super::Factory;
+    use std::time::Duration;
+
+    // Owns the dispatcher, like a connection does after start_receive_parallel.
+    struct Owner {
+        d: Mutex<Option<ThreadPoolDispatcher>>,
+        sending: Mutex<mpsc::Sender<()>>,
+        released: Mutex<mpsc::Receiver<()>>,
+        done: Mutex<mpsc::Sender<()>>,
+    }
+    impl channel::Sender for Owner {
+        fn send(&self, _: Message) -> Result<u32, ()> {
+            self.sending.lock().unwrap().send(()).unwrap();
+            self.released.lock().unwrap().recv().unwrap();
+            Ok(0)
+        }
+    }
+    impl Drop for Owner {
+        fn drop(&mut self) {
+            drop(self.d.lock().unwrap().take());
+            let _ = self.done.lock().unwrap().send(());
+        }
+    }
+
+    let f = Factory::new_sync::<()>();
+    let t = Arc::new(f.tree(()).add(f.object_path("/", ()).add(f.interface("com.example.test", ())
+        .add_m(f.method("Ping", (), |m| m.reply(())))
+    )));
+    let (sending_tx, sending_rx) = mpsc::channel();
+    let (released_tx, released_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    let owner = Arc::new(Owner { d: Mutex::new(None), sending: Mutex::new(sending_tx),
+        released: Mutex::new(released_rx), done: Mutex::new(done_tx) });
+    *owner.d.lock().unwrap() = Some(ThreadPoolDispatcher::new(t, Arc::downgrade(&owner), 2, DispatchOrder::PerPath));
+
+    let mut msg = Message::new_method_call("com.example.test", "/", "com.example.test", "Ping").unwrap();
+    crate::message::message_set_serial(&mut msg, 1);
+    assert!(owner.d.lock().unwrap().as_ref().unwrap().dispatch(msg));
+
+    // Once the worker is sending the reply, it holds the last reference.
+    sending_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    drop(owner);
+    released_tx.send(()).unwrap();
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn test_shared_tree() {
+    use super::Factory;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let f = Factory::new_sync::<()>();
+    let count = Arc::new(AtomicU32::new(0));
+    let count2 = count.clone();
+    let t = Arc::new(f.tree(()).add(f.object_path("/count", ()).add(f.interface("com.example.test", ())
+        .add_m(f.method("Count", (), move |m| m.reply((count2.fetch_add(1, Ordering::SeqCst) + 1,))))
+    )));
+    let threads: Vec<_> = (0..4).map(|_| {
+        let t = t.clone();
+        thread::spawn(move || for i in 0..100 {
+            let mut msg = Message::new_method_call("com.example.test", "/count", "com.example.test", "Count").unwrap();
+            crate::message::message_set_serial(&mut msg, i + 1);
+            assert!(t.handle(&msg).unwrap().into_iter().next().unwrap().read1::<u32>().unwrap() > 0);
+        })
+    }).collect();
+    for th in threads { th.join().unwrap(); }
+    assert_eq!(count.load(Ordering::SeqCst), 400);
+
+    // The same tree, served over two connections at once.
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let servers: Vec<_> = (0..2).map(|_| {
+        let (t, stop, tx) = (t.clone(), stop.clone(), tx.clone());
+        thread::spawn(move || {
+            let mut c = crate::blocking::SyncConnection::new_session().unwrap();
+            t.start_receive_shared(&c);
+            tx.send(c.unique_name().to_string()).unwrap();
+            while !stop.load(Ordering::SeqCst) { c.process(Duration::from_millis(50)).unwrap(); }
+        })
+    }).collect();
+    let c = crate::blocking::Connection::new_session().unwrap();
+    for name in rx.iter().take(2) {
+        let p = c.with_proxy(name, "/count", Duration::from_secs(5));
+        for _ in 0..10 {
+            let (n,): (u32,) = p.method_call("com.example.test", "Count", ()).unwrap();
+            assert!(n > 400);
+        }
+    }
+    stop.store(true, Ordering::SeqCst);
+    for th in servers { th.join().unwrap(); }
+    assert_eq!(count.load(Ordering::SeqCst), 420);
+}
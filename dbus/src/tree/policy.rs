@@ -0,0 +1,330 @@
+// Access control for the methods and properties of a Tree.
+
+use super::{MethodErr, MethodInfo, PropInfo, MethodType, DataType};
+use crate::{Message, Error};
+use crate::arg::{PropMap, PropMapExt};
+use crate::strings::{BusName, UniqueName, Interface as IfaceName, Member};
+use std::fmt;
+use std::borrow::Cow;
+
+/// What the Policy knows about the caller, besides its unique name.
+///
+/// This information is not part of the incoming message, so it needs to be looked up,
+/// see `Policy::credentials`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    /// The Unix user id of the calling process.
+    pub uid: Option<u32>,
+    /// The process id of the calling process.
+    pub pid: Option<u32>,
+    /// The security label of the calling process, e g its SELinux context.
+    pub label: Option<String>,
+}
+
+impl Credentials {
+    /// Reads the reply of the bus daemon's `GetConnectionCredentials` method.
+    pub fn from_map(m: &PropMap) -> Credentials {
+        let label = m.get_typed::<Vec<u8>>("LinuxSecurityLabel").ok().map(|mut v| {
+            // The label might include a trailing nul byte.
+            while v.last() == Some(&0) { v.pop(); }
+            String::from_utf8_lossy(&v).into_owned()
+        });
+        Credentials { uid: m.get_typed("UnixUserID").ok(), pid: m.get_typed("ProcessID").ok(), label }
+    }
+}
+
+/// Something that can look up the credentials of other connections to the bus.
+///
+/// The blocking connections implement this by calling the bus daemon's `GetConnectionCredentials` method,
+/// caching the result per unique name.
+pub trait CredentialsSource {
+    /// Returns the credentials of the connection with this name.
+    fn credentials(&self, name: &BusName) -> Result<Credentials, Error>;
+}
+
+impl<'a, M: MethodType<D>, D: DataType> MethodInfo<'a, M, D> {
+    fn sender_credentials<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<Credentials, MethodErr> {
+        let sender = self.msg.sender().ok_or_else(|| MethodErr::failed(&"Message has no sender"))?;
+        Ok(conn.credentials(&sender)?)
+    }
+
+    /// Returns the Unix user id of the caller of this method.
+    ///
+    /// The credentials are looked up through "conn", typically the connection the call came in on,
+    /// which caches them so that only the first lookup per caller involves a roundtrip to the bus daemon.
+    pub fn sender_uid<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<u32, MethodErr> {
+        self.sender_credentials(conn)?.uid.ok_or_else(|| MethodErr::failed(&"The uid of the caller is not known"))
+    }
+
+    /// Returns the process id of the caller of this method.
+    ///
+    /// See `sender_uid` for how it is looked up.
+    pub fn sender_pid<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<u32, MethodErr> {
+        self.sender_credentials(conn)?.pid.ok_or_else(|| MethodErr::failed(&"The pid of the caller is not known"))
+    }
+
+    /// Returns the security label (e g the SELinux context) of the caller of this method.
+    ///
+    /// See `sender_uid` for how it is looked up.
+    pub fn sender_label<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<String, MethodErr> {
+        self.sender_credentials(conn)?.label.ok_or_else(|| MethodErr::failed(&"The security label of the caller is not known"))
+    }
+}
+
+impl<'a, M: MethodType<D>, D: DataType> PropInfo<'a, M, D> {
+    /// Returns the Unix user id of the caller that gets or sets the property.
+    ///
+    /// Like `MethodInfo::sender_uid`, the credentials are only looked up when this is called.
+    pub fn sender_uid<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<u32, MethodErr> { self.to_method_info().sender_uid(conn) }
+
+    /// Returns the process id of the caller that gets or sets the property.
+    pub fn sender_pid<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<u32, MethodErr> { self.to_method_info().sender_pid(conn) }
+
+    /// Returns the security label of the caller that gets or sets the property.
+    pub fn sender_label<C: CredentialsSource + ?Sized>(&self, conn: &C) -> Result<String, MethodErr> { self.to_method_info().sender_label(conn) }
+}
+
+/// Who a rule of a Policy allows access.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principal {
+    /// Any caller.
+    Anyone,
+    /// The connection with this unique name.
+    Sender(UniqueName<'static>),
+    /// Processes running as this Unix user.
+    Uid(u32),
+    /// Processes with this security label.
+    Label(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Target<'a> {
+    Interface(IfaceName<'a>),
+    Method(IfaceName<'a>, Member<'a>),
+    Get(IfaceName<'a>, Cow<'a, str>),
+    Set(IfaceName<'a>, Cow<'a, str>),
+}
+
+// A copy of "i" that borrows instead of allocating, since it is already known to be valid.
+fn borrowed_iface<'a>(i: &'a IfaceName) -> IfaceName<'a> { unsafe { IfaceName::from_slice_unchecked(i.as_cstr().to_bytes_with_nul()) } }
+
+type CredentialsFn = Box<dyn Fn(&BusName) -> Option<Credentials> + Send + Sync + 'static>;
+
+/// Decides which callers may call methods, and get and set properties, of a Tree.
+///
+/// Rules are added per interface, method or property, and allow one or more principals.
+/// The most specific rule wins: a method call is checked against the rules for that method if there are any,
+/// otherwise against the rules for its interface. The same goes for properties, which are checked
+/// when they are accessed through the `org.freedesktop.DBus.Properties` interface. Everything not covered
+/// by a rule is allowed, unless `deny_by_default` is set. Denied calls get an AccessDenied error reply.
+///
+/// Attach to a Tree with `Tree::policy`.
+///
+/// # Example
+/// ```rust
+/// use dbus::tree::{Policy, Principal};
+/// let p = Policy::new()
+///     .allow_interface("com.example.Admin", Principal::Uid(0))
+///     .allow_method("com.example.Admin", "Status", Principal::Anyone)
+///     .allow_set("com.example.Player", "Volume", Principal::Label("system_u:system_r:mixer_t:s0".into()));
+/// ```
+#[derive(Default)]
+pub struct Policy {
+    rules: Vec<(Target<'static>, Principal)>,
+    deny: bool,
+    credentials: Option<CredentialsFn>,
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Policy").field("rules", &self.rules).field("deny_by_default", &self.deny).finish()
+    }
+}
+
+impl Policy {
+    /// Creates a new policy, without any rules.
+    pub fn new() -> Self { Default::default() }
+
+    /// Builder function that denies access to everything not covered by a rule.
+    ///
+    /// Note that this includes standard interfaces such as `org.freedesktop.DBus.Introspectable`.
+    pub fn deny_by_default(mut self) -> Self { self.deny = true; self }
+
+    /// Builder function that allows the principal to call all methods, and get and set all properties, of the interface.
+    pub fn allow_interface<I: Into<IfaceName<'static>>>(mut self, iface: I, p: Principal) -> Self {
+        self.rules.push((Target::Interface(iface.into()), p)); self
+    }
+
+    /// Builder function that allows the principal to call the method.
+    pub fn allow_method<I: Into<IfaceName<'static>>, M: Into<Member<'static>>>(mut self, iface: I, method: M, p: Principal) -> Self {
+        self.rules.push((Target::Method(iface.into(), method.into()), p)); self
+    }
+
+    /// Builder function that allows the principal to get the property.
+    pub fn allow_get<I: Into<IfaceName<'static>>>(mut self, iface: I, prop: &str, p: Principal) -> Self {
+        self.rules.push((Target::Get(iface.into(), prop.to_string().into()), p)); self
+    }
+
+    /// Builder function that allows the principal to set the property.
+    pub fn allow_set<I: Into<IfaceName<'static>>>(mut self, iface: I, prop: &str, p: Principal) -> Self {
+        self.rules.push((Target::Set(iface.into(), prop.to_string().into()), p)); self
+    }
+
+    /// Builder function that sets how to look up the credentials of a caller, given its unique name.
+    ///
+    /// This is needed for `Principal::Uid` and `Principal::Label` rules, which never match otherwise.
+    /// It is only called when such a rule needs to be checked, typically by calling the bus daemon's
+    /// `GetConnectionCredentials` method and passing the reply to `Credentials::from_map`,
+    /// or through a `CredentialsSource`.
+    pub fn credentials<F: Fn(&BusName) -> Option<Credentials> + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.credentials = Some(Box::new(f)); self
+    }
+
+    fn check(&self, msg: &Message, specific: &Target<'_>, iface: &IfaceName, what: &dyn fmt::Display) -> Result<(), MethodErr> {
+        // This runs for every method call, so look at the rules in place rather than collecting them.
+        let has_specific = self.rules.iter().any(|(t, _)| t == specific);
+        let mut rules = self.rules.iter().filter(|(t, _)| match t {
+            Target::Interface(i) if !has_specific => i == iface,
+            _ => has_specific && t == specific,
+        }).map(|(_, p)| p).peekable();
+        if rules.peek().is_none() && !self.deny { return Ok(()) }
+
+        let sender = msg.sender();
+        let mut creds = None;
+        let allowed = rules.any(|p| {
+            let uid_or_label = match p {
+                Principal::Anyone => return true,
+                Principal::Sender(s) => return sender.as_deref() == Some(&**s),
+                Principal::Uid(_) | Principal::Label(_) => p,
+            };
+            let c = creds.get_or_insert_with(|| match (&self.credentials, &sender) {
+                (Some(f), Some(s)) => f(s),
+                _ => None,
+            });
+            match (uid_or_label, c) {
+                (Principal::Uid(u), Some(c)) => c.uid == Some(*u),
+                (Principal::Label(l), Some(c)) => c.label.as_ref() == Some(l),
+                _ => false,
+            }
+        });
+        if allowed { return Ok(()) }
+        trace_event!(sender = ?sender, target = %what, "Access denied by policy");
+        Err(MethodErr::access_denied(what))
+    }
+
+    /// Checks whether the sender of msg may call the method.
+    pub fn check_method(&self, msg: &Message, iface: &IfaceName, method: &Member) -> Result<(), MethodErr> {
+        let t = Target::Method(borrowed_iface(iface), unsafe { Member::from_slice_unchecked(method.as_cstr().to_bytes_with_nul()) });
+        self.check(msg, &t, iface, &format_args!("{}.{}", &**iface, &**method))
+    }
+
+    /// Checks whether the sender of msg may get (or, if "set" is true, set) the property.
+    pub fn check_property(&self, msg: &Message, iface: &IfaceName, prop: &str, set: bool) -> Result<(), MethodErr> {
+        let (i, p) = (borrowed_iface(iface), Cow::Borrowed(prop));
+        let t = if set { Target::Set(i, p) } else { Target::Get(i, p) };
+        self.check(msg, &t, iface, &format_args!("property {}.{}", &**iface, prop))
+    }
+}
+
+#[test]
+fn test_policy() {
+    use super::Factory;
+    use std::sync::{Arc, Mutex};
+
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/policy", ()).introspectable()
+        .add(f.interface("com.example.Admin", ())
+            .add_m(f.method("Reboot", (), |m| Ok(m.msg.method_return().into())))
+            .add_m(f.method("Status", (), |m| Ok(m.msg.method_return().into())))
+            .add_p(f.property::<u32, _>("Volume", ()).access(super::Access::ReadWrite)
+                .on_get(|i, _| { i.append(5u32); Ok(()) })
+                .on_set(|_, _| Ok(())))
+            .add_p(f.property::<u32, _>("Secret", ()).on_get(|i, _| { i.append(42u32); Ok(()) }))
+        ));
+
+    let lookups = Arc::new(Mutex::new(0));
+    let lookups2 = lookups.clone();
+    let t = t.policy(Policy::new()
+        .allow_interface("com.example.Admin", Principal::Uid(0))
+        .allow_method("com.example.Admin", "Status", Principal::Anyone)
+        .allow_get("com.example.Admin", "Volume", Principal::Anyone)
+        .allow_set("com.example.Admin", "Volume", Principal::Sender(":1.2".into()))
+        .credentials(move |s| {
+            *lookups2.lock().unwrap() += 1;
+            Some(Credentials { uid: Some(if &**s == ":1.1" { 0 } else { 1000 }), pid: None, label: None })
+        }));
+
+    let call = |sender: &str, iface: &str, member: &str, args: &[&str]| -> Option<String> {
+        let mut m = Message::new_method_call("com.example.dbusrs", "/policy", iface, member).unwrap();
+        for a in args { m = m.append1(*a); }
+        if member == "Set" { m = m.append1(crate::arg::Variant(7u32)); }
+        m.set_sender(Some(sender.into()));
+        crate::message::message_set_serial(&mut m, 1);
+        let r = t.handle(&m).unwrap();
+        r.into_iter().next().unwrap().as_result().err().map(|e| e.name().unwrap().to_string())
+    };
+    let denied = Some("org.freedesktop.DBus.Error.AccessDenied".to_string());
+
+    assert_eq!(call(":1.1", "com.example.Admin", "Reboot", &[]), None);
+    assert_eq!(call(":1.2", "com.example.Admin", "Reboot", &[]), denied);
+    assert_eq!(call(":1.2", "com.example.Admin", "Status", &[]), None);
+    assert_eq!(*lookups.lock().unwrap(), 2);
+
+    let props = "org.freedesktop.DBus.Properties";
+    assert_eq!(call(":1.2", props, "Get", &["com.example.Admin", "Volume"]), None);
+    assert_eq!(call(":1.2", props, "Get", &["com.example.Admin", "Secret"]), denied);
+    assert_eq!(call(":1.1", props, "Get", &["com.example.Admin", "Secret"]), None);
+    assert_eq!(call(":1.2", props, "Set", &["com.example.Admin", "Volume"]), None);
+    assert_eq!(call(":1.3", props, "Set", &["com.example.Admin", "Volume"]), denied);
+
+    // GetAll leaves out what the caller may not read.
+    let mut m = Message::new_method_call("com.example.dbusrs", "/policy", props, "GetAll").unwrap().append1("com.example.Admin");
+    m.set_sender(Some(":1.2".into()));
+    crate::message::message_set_serial(&mut m, 1);
+    let r = t.handle(&m).unwrap().into_iter().next().unwrap();
+    let all: PropMap = r.read1().unwrap();
+    assert_eq!(all.keys().collect::<Vec<_>>(), vec!("Volume"));
+
+    // Not covered by any rule
+    assert_eq!(call(":1.2", "org.freedesktop.DBus.Introspectable", "Introspect", &[]), None);
+
+    let mut m = Message::new_method_call("com.example.dbusrs", "/policy", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    m.set_sender(Some(":1.2".into()));
+    let p = Policy::new().deny_by_default();
+    assert!(p.check_method(&m, &"org.freedesktop.DBus.Introspectable".into(), &"Introspect".into()).is_err());
+    let p = p.allow_interface("org.freedesktop.DBus.Introspectable", Principal::Anyone);
+    assert!(p.check_method(&m, &"org.freedesktop.DBus.Introspectable".into(), &"Introspect".into()).is_ok());
+}
+
+#[test]
+fn test_prop_sender() {
+    use super::{Factory, Access};
+    use std::sync::Arc;
+    struct Lookup;
+    impl CredentialsSource for Lookup {
+        fn credentials(&self, name: &BusName) -> Result<Credentials, Error> {
+            Ok(Credentials { uid: Some(if &**name == ":1.1" { 0 } else { 1000 }), pid: Some(7), label: None })
+        }
+    }
+    let conn = Arc::new(Lookup);
+    let f = Factory::new_fn::<()>();
+    let t = f.tree(()).add(f.object_path("/props", ()).add(f.interface("com.example.Props", ())
+        .add_p(f.property::<&str, _>("Caller", ()).on_get(|i, p| { i.append(&*p.sender().unwrap()); Ok(()) }))
+        .add_p(f.property::<u32, _>("Limit", ()).access(Access::ReadWrite)
+            .on_get(move |i, p| { i.append(p.sender_pid(&*conn)?); Ok(()) })
+            .on_set(|_, p| if p.sender_uid(&Lookup)? == 0 { Ok(()) } else { Err(MethodErr::access_denied(&"Limit")) }))
+    ));
+    let call = |sender: &str, member: &str, prop: &str| {
+        let mut m = Message::new_method_call("com.example.dbusrs", "/props", "org.freedesktop.DBus.Properties", member).unwrap()
+            .append2("com.example.Props", prop);
+        if member == "Set" { m = m.append1(crate::arg::Variant(7u32)); }
+        m.set_sender(Some(sender.into()));
+        crate::message::message_set_serial(&mut m, 1);
+        t.handle(&m).unwrap().into_iter().next().unwrap()
+    };
+    assert_eq!(call(":1.1", "Get", "Caller").read1::<crate::arg::Variant<&str>>().unwrap().0, ":1.1");
+    assert_eq!(call(":1.2", "Get", "Caller").read1::<crate::arg::Variant<&str>>().unwrap().0, ":1.2");
+    assert_eq!(call(":1.2", "Get", "Limit").read1::<crate::arg::Variant<u32>>().unwrap().0, 7);
+    assert!(call(":1.1", "Set", "Limit").as_result().is_ok());
+    assert_eq!(call(":1.2", "Set", "Limit").as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+}
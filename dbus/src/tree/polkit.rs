@@ -0,0 +1,119 @@
+// Authorizing method calls through polkit.
+
+use super::{MethodType, DataType, MethodInfo, MethodErr};
+use crate::{Message, Error};
+use crate::arg::Variant;
+use crate::blocking::BlockingSender;
+use crate::nonblock::{self, NonblockReply, MethodReply};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::time::Duration;
+
+const POLKIT_NAME: &str = "org.freedesktop.PolicyKit1";
+const POLKIT_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const POLKIT_IFACE: &str = "org.freedesktop.PolicyKit1.Authority";
+
+/// The result of asking polkit whether the caller of a method is authorized to perform an action.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Authorization {
+    /// The caller is authorized.
+    Authorized,
+    /// The caller is not authorized.
+    Denied,
+    /// The caller could be authorized, but only after authenticating (e g entering a password),
+    /// which is not possible because the caller did not allow interactive authorization.
+    Challenge,
+}
+
+impl Authorization {
+    /// Turns anything but Authorized into an error reply for the action.
+    ///
+    /// A Challenge gives an InteractiveAuthorizationRequired error, so that the caller knows it
+    /// can retry with interactive authorization allowed.
+    pub fn into_result(self, action_id: &str) -> Result<(), MethodErr> {
+        match self {
+            Authorization::Authorized => Ok(()),
+            Authorization::Denied => Err(MethodErr::access_denied(action_id)),
+            Authorization::Challenge => Err(("org.freedesktop.DBus.Error.InteractiveAuthorizationRequired",
+                format!("Interactive authorization required for {}", action_id)).into()),
+        }
+    }
+}
+
+// The (subject, action_id, details, flags, cancellation_id) arguments of CheckAuthorization.
+type CheckArgs<'a> = ((&'static str, HashMap<&'static str, Variant<String>>), &'a str, HashMap<&'static str, &'static str>, u32, &'static str);
+
+// Without a sender there is no subject to ask about, so the call is denied without asking polkit.
+fn check_args<'a>(msg: &Message, action_id: &'a str) -> Result<CheckArgs<'a>, MethodErr> {
+    let sender = msg.sender().ok_or_else(|| MethodErr::access_denied(action_id))?;
+    let mut subject = HashMap::new();
+    subject.insert("name", Variant(sender.to_string()));
+    // AllowUserInteraction
+    let flags = if msg.get_allow_interactive_authorization() { 1 } else { 0 };
+    Ok((("system-bus-name", subject), action_id, HashMap::new(), flags, ""))
+}
+
+fn parse_reply(r: ((bool, bool, HashMap<String, String>),)) -> Result<Authorization, Error> {
+    let (is_authorized, is_challenge, _) = r.0;
+    Ok(if is_authorized { Authorization::Authorized } else if is_challenge { Authorization::Challenge } else { Authorization::Denied })
+}
+
+impl<'a, M: MethodType<D>, D: DataType> MethodInfo<'a, M, D> {
+    /// Asks polkit whether the caller of this method is authorized to perform the action,
+    /// and blocks until it has answered.
+    ///
+    /// "conn" needs to be a connection to the system bus. If the caller allowed interactive authorization
+    /// (see `allow_interactive_authorization`), polkit might ask the user to authenticate before answering,
+    /// so the timeout should be generous.
+    ///
+    /// If the method call has no sender (e g on a peer-to-peer connection), this fails with AccessDenied.
+    pub fn check_authorization<S: BlockingSender>(&self, conn: &S, action_id: &str, timeout: Duration) -> Result<Authorization, MethodErr> {
+        let mut m = Message::new_method_call(POLKIT_NAME, POLKIT_PATH, POLKIT_IFACE, "CheckAuthorization").map_err(|e| MethodErr::failed(&e))?;
+        crate::arg::AppendAll::append(&check_args(self.msg, action_id)?, &mut crate::arg::IterAppend::new(&mut m));
+        let r = conn.send_with_reply_and_block(m, timeout)?;
+        Ok(parse_reply(r.read_all()?)?)
+    }
+
+    /// Asks polkit whether the caller of this method is authorized to perform the action.
+    ///
+    /// Returns a future that resolves to the answer, for use with `MTFuture` methods.
+    /// "conn" needs to be (a reference to) a nonblocking connection to the system bus.
+    /// As with `check_authorization`, a method call without a sender fails with AccessDenied.
+    pub fn check_authorization_async<T: NonblockReply, C: Deref<Target=T>>(&self, conn: C, action_id: &str) -> MethodReply<Authorization> {
+        let args = match check_args(self.msg, action_id) { Ok(a) => a, Err(e) => return MethodReply::from_err(e.into()) };
+        let p = nonblock::Proxy::new(POLKIT_NAME, POLKIT_PATH, conn);
+        p.method_call(POLKIT_IFACE, "CheckAuthorization", args).and_then(parse_reply)
+    }
+}
+
+#[test]
+fn test_polkit_args() {
+    use crate::arg::{AppendAll, IterAppend, RefArg};
+    let mut call = Message::new_method_call("com.example.dbusrs", "/", "com.example.dbusrs", "Reboot").unwrap();
+    call.set_sender(Some(":1.42".into()));
+    call.set_allow_interactive_authorization(true);
+
+    let mut m = Message::new_method_call(POLKIT_NAME, POLKIT_PATH, POLKIT_IFACE, "CheckAuthorization").unwrap();
+    check_args(&call, "com.example.reboot").unwrap().append(&mut IterAppend::new(&mut m));
+    crate::message::message_set_serial(&mut m, 1);
+    let mut i = m.iter_init();
+    assert_eq!(&*i.signature(), "(sa{sv})");
+    let subject: (String, HashMap<String, Variant<Box<dyn RefArg>>>) = i.read().unwrap();
+    assert_eq!(subject.0, "system-bus-name");
+    assert_eq!(subject.1["name"].as_str(), Some(":1.42"));
+    let (action, details, flags, cancel): (&str, HashMap<String, String>, u32, &str) = (i.read().unwrap(), i.read().unwrap(), i.read().unwrap(), i.read().unwrap());
+    assert_eq!((action, details.len(), flags, cancel), ("com.example.reboot", 0, 1, ""));
+
+    let reply = |a, c| m.method_return().append1((a, c, HashMap::<String, String>::new()));
+    assert_eq!(parse_reply(reply(true, false).read_all().unwrap()).ok(), Some(Authorization::Authorized));
+    assert_eq!(parse_reply(reply(false, true).read_all().unwrap()).ok(), Some(Authorization::Challenge));
+    assert_eq!(parse_reply(reply(false, false).read_all().unwrap()).ok(), Some(Authorization::Denied));
+
+    assert_eq!(Authorization::Authorized.into_result("com.example.reboot"), Ok(()));
+    assert_eq!(Authorization::Denied.into_result("com.example.reboot").unwrap_err().errorname(), &"org.freedesktop.DBus.Error.AccessDenied".into());
+    assert_eq!(Authorization::Challenge.into_result("com.example.reboot").unwrap_err().errorname(),
+        &"org.freedesktop.DBus.Error.InteractiveAuthorizationRequired".into());
+
+    call.set_sender(None);
+    assert_eq!(check_args(&call, "com.example.reboot").unwrap_err().errorname(), &"org.freedesktop.DBus.Error.AccessDenied".into());
+}
@@ -0,0 +1,231 @@
+// Coalescing of PropertiesChanged signals.
+
+use super::MethodReplies;
+use crate::Message;
+use crate::channel::{Sender, MsgSender};
+use crate::message::SignalArgs;
+use crate::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Decides when the PropertiesChanged signals generated by a Tree are sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Signals are sent as soon as they are generated, one per changed property. This is the default.
+    Immediate,
+    /// Signals generated while handling one method call are merged, so that at most one
+    /// signal per object path and interface is sent with the reply.
+    PerMethodCall,
+    /// Signals are held back and merged until the given time has passed since the first one
+    /// was held back. They are then sent together with the reply of the next method call,
+    /// or when `Tree::flush_changed` is called.
+    ///
+    /// Nothing else sends them, unless the tree has a sender (see `Tree::signal_sender`):
+    /// then they are sent when the time has passed, even if no method call comes in.
+    Interval(Duration),
+}
+
+// Clones share the signals held back, so a clone can finish a method call handled asynchronously.
+#[derive(Debug, Clone)]
+pub struct ChangedQueue {
+    pub policy: FlushPolicy,
+    pub sender: Option<MsgSender>,
+    // When the first signal was held back, and the signals held back.
+    pending: Arc<Mutex<(Option<Instant>, Vec<Message>)>>,
+    // InterfacesAdded / InterfacesRemoved signals, sent regardless of the policy, if there is no sender.
+    objects: Arc<Mutex<Vec<Message>>>,
+}
+
+impl Default for ChangedQueue {
+    fn default() -> Self { ChangedQueue { policy: FlushPolicy::Immediate, sender: None, pending: Default::default(), objects: Default::default() } }
+}
+
+fn is_properties_changed(m: &Message) -> bool {
+    PropertiesPropertiesChanged::match_rule(None, None).matches(m)
+}
+
+impl ChangedQueue {
+    /// Sends a signal through the sender, or if there is none, queues it to be sent with the replies
+    /// of the next method call, or when flushed.
+    pub fn push(&self, m: Message) {
+        match &self.sender {
+            Some(s) => { let _ = s.send(m); },
+            None => self.objects.lock().unwrap().push(m),
+        }
+    }
+
+    /// Applies the flush policy to the replies of a method call, and adds the queued signals.
+    pub fn process(&self, r: MethodReplies) -> MethodReplies {
+        let mut r = self.process_changed(r);
+        r.extend(self.objects.lock().unwrap().drain(..));
+        r
+    }
+
+    fn process_changed(&self, r: MethodReplies) -> MethodReplies {
+        let d = match self.policy {
+            FlushPolicy::Immediate => return r,
+            FlushPolicy::PerMethodCall => return coalesce_properties_changed(r).into_iter().collect(),
+            FlushPolicy::Interval(d) => d,
+        };
+        let (changed, r): (Vec<_>, Vec<_>) = r.into_iter().partition(is_properties_changed);
+        let mut r: MethodReplies = r.into_iter().collect();
+        let mut pending = self.pending.lock().unwrap();
+        if !changed.is_empty() {
+            if pending.0.is_none() {
+                let start = Instant::now();
+                pending.0 = Some(start);
+                if let Some(s) = &self.sender { flush_later(self.pending.clone(), s.clone(), start, d) }
+            }
+            pending.1.extend(changed);
+        }
+        if pending.0.map(|t| t.elapsed() >= d).unwrap_or(false) {
+            pending.0 = None;
+            r.extend(coalesce_properties_changed(std::mem::take(&mut pending.1)));
+        }
+        r
+    }
+
+    /// Returns all signals held back, merged, after the queued signals.
+    pub fn flush(&self) -> Vec<Message> {
+        let mut r = std::mem::take(&mut *self.objects.lock().unwrap());
+        let mut pending = self.pending.lock().unwrap();
+        pending.0 = None;
+        r.extend(coalesce_properties_changed(std::mem::take(&mut pending.1)));
+        r
+    }
+}
+
+// Sends the signals held back since "start", once "d" has passed, unless they have been sent already.
+fn flush_later(pending: Arc<Mutex<(Option<Instant>, Vec<Message>)>>, s: MsgSender, start: Instant, d: Duration) {
+    std::thread::spawn(move || {
+        std::thread::sleep(d);
+        let msgs = {
+            let mut pending = pending.lock().unwrap();
+            if pending.0 != Some(start) { return }
+            pending.0 = None;
+            std::mem::take(&mut pending.1)
+        };
+        for m in coalesce_properties_changed(msgs) { let _ = s.send(m); }
+    });
+}
+
+/// Merges PropertiesChanged signals, so that there is only one signal per object path,
+/// destination and interface.
+///
+/// A merged signal takes the place of the first signal it was merged from. If a property is
+/// both changed and invalidated, the latest one wins. Other messages are passed through unchanged.
+pub fn coalesce_properties_changed<I: IntoIterator<Item=Message>>(msgs: I) -> Vec<Message> {
+    let mut r: Vec<Result<Message, PropertiesPropertiesChanged>> = vec!();
+    let mut keys = vec!();
+    for m in msgs {
+        let pc = if is_properties_changed(&m) { PropertiesPropertiesChanged::from_message(&m) } else { None };
+        let pc = match pc { Some(pc) => pc, None => { r.push(Ok(m)); continue } };
+        let key = (m.path().map(|p| p.into_static()), m.destination().map(|d| d.into_static()), pc.interface_name.clone());
+        let idx = match keys.iter().position(|(k, _)| *k == key) {
+            Some(i) => keys[i].1,
+            None => {
+                keys.push((key, r.len()));
+                r.push(Err(PropertiesPropertiesChanged { interface_name: pc.interface_name, changed_properties: Default::default(), invalidated_properties: vec!() }));
+                r.len() - 1
+            }
+        };
+        let merged = match r[idx] { Err(ref mut x) => x, Ok(_) => unreachable!() };
+        for (name, v) in pc.changed_properties {
+            merged.invalidated_properties.retain(|x| *x != name);
+            merged.changed_properties.insert(name, v);
+        }
+        for name in pc.invalidated_properties {
+            merged.changed_properties.remove(&name);
+            if !merged.invalidated_properties.contains(&name) { merged.invalidated_properties.push(name) }
+        }
+    }
+    let mut keys = keys.into_iter();
+    r.into_iter().map(|x| x.unwrap_or_else(|pc| {
+        let ((path, dest, _), _) = keys.next().unwrap();
+        let mut m = pc.to_emit_message(&path.unwrap());
+        m.set_destination(dest);
+        m
+    })).collect()
+}
+
+#[test]
+fn test_coalesce() {
+    use crate::tree::{Factory, Access, EmitsChangedSignal};
+    use crate::arg::{RefArg, Variant};
+    use std::collections::HashMap;
+
+    let f = Factory::new_fn::<()>();
+    let set = |p: &str, v: u32, serial| {
+        let mut m = Message::new_method_call("com.example.dbusrs", "/coalesce", "org.freedesktop.DBus.Properties", "Set").unwrap()
+            .append3("com.example.dbusrs.Coalesce", p, Variant(v));
+        crate::message::message_set_serial(&mut m, serial);
+        m
+    };
+    let prop = |name| f.property::<u32,_>(name, ()).access(Access::ReadWrite)
+        .on_get(|i, _| { i.append(0u32); Ok(()) }).on_set(|_, _| Ok(()));
+    let tree = |p| f.tree(()).flush_policy(p).add(f.object_path("/coalesce", ())
+        .add(f.interface("com.example.dbusrs.Coalesce", ())
+            .add_p(prop("A")).add_p(prop("B")).add_p(prop("C").emits_changed(EmitsChangedSignal::Invalidates))
+            .add_m(f.method("SetAll", (), |m| {
+                let s = |p: &str| {
+                    let mut pc = PropertiesPropertiesChanged { interface_name: "com.example.dbusrs.Coalesce".into(),
+                        changed_properties: HashMap::new(), invalidated_properties: vec!() };
+                    pc.changed_properties.insert(p.into(), Variant(Box::new(5u32) as Box<dyn RefArg>));
+                    pc.to_emit_message(m.path.get_name())
+                };
+                Ok(vec!(s("A"), m.msg.method_return(), s("B")).into_iter().collect())
+            }))
+    ));
+    let read = |m: &Message| {
+        let pc = PropertiesPropertiesChanged::from_message(m).unwrap();
+        let mut c: Vec<_> = pc.changed_properties.keys().cloned().collect();
+        c.sort();
+        (c, pc.invalidated_properties)
+    };
+
+    // Immediate
+    let t = tree(FlushPolicy::Immediate);
+    let mut m = Message::new_method_call("com.example.dbusrs", "/coalesce", "com.example.dbusrs.Coalesce", "SetAll").unwrap();
+    crate::message::message_set_serial(&mut m, 1);
+    assert_eq!(t.handle(&m).unwrap().len(), 3);
+
+    // Per method call
+    let t = tree(FlushPolicy::PerMethodCall);
+    let r: Vec<_> = t.handle(&m).unwrap().into_iter().collect();
+    assert_eq!(r.len(), 2);
+    assert_eq!(read(&r[0]), (vec!("A".to_string(), "B".to_string()), vec!()));
+    assert_eq!(r[1].msg_type(), crate::MessageType::MethodReturn);
+
+    // Interval
+    let t = tree(FlushPolicy::Interval(Duration::from_millis(100)));
+    assert_eq!(t.handle(&set("A", 1, 2)).unwrap().len(), 1);
+    assert_eq!(t.handle(&set("C", 1, 3)).unwrap().len(), 1);
+    assert_eq!(t.handle(&set("B", 1, 4)).unwrap().len(), 1);
+    std::thread::sleep(Duration::from_millis(150));
+    let r: Vec<_> = t.handle(&set("A", 2, 5)).unwrap().into_iter().collect();
+    assert_eq!(r.len(), 2);
+    assert_eq!(read(&r[1]), (vec!("A".to_string(), "B".to_string()), vec!("C".to_string())));
+    assert!(t.flush_changed().is_empty());
+    assert_eq!(t.handle(&set("C", 2, 6)).unwrap().len(), 1);
+    let r = t.flush_changed();
+    assert_eq!(r.len(), 1);
+    assert_eq!(read(&r[0]), (vec!(), vec!("C".to_string())));
+
+    // With a sender, held back signals are sent once the interval has passed, without another method call.
+    let c = crate::blocking::Connection::new_session().unwrap();
+    c.add_match_no_cb(&format!("type='signal',path='/coalesce',sender='{}'", c.unique_name())).unwrap();
+    let t = tree(FlushPolicy::Interval(Duration::from_millis(100))).signal_sender(c.msg_sender());
+    assert_eq!(t.handle(&set("A", 3, 7)).unwrap().len(), 1);
+    assert_eq!(t.handle(&set("B", 3, 8)).unwrap().len(), 1);
+    let ch: &crate::channel::Channel = c.as_ref();
+    // Short timeouts, so that the signal is written soon after it has been queued.
+    let start = Instant::now();
+    let m = loop {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        if let Some(m) = ch.blocking_pop_message(Duration::from_millis(50)).unwrap() {
+            if is_properties_changed(&m) { break m }
+        }
+    };
+    assert_eq!(read(&m), (vec!("A".to_string(), "B".to_string()), vec!()));
+    assert!(t.flush_changed().is_empty());
+}
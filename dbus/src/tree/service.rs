@@ -0,0 +1,137 @@
+// Connection, name ownership, tree and main loop in one, for small services.
+
+use super::{Factory, Tree, ObjectPath, MethodType, DataType, MTFn};
+use crate::Error;
+use crate::blocking::LocalConnection;
+use crate::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use crate::channel::{Channel, BusType};
+use crate::strings::WellKnownName;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// How often "run" checks whether it has been asked to stop.
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Asks a running `Service` or `TreeServer` to stop. Returned from `Service::stop_handle`
+/// and `TreeServer::stop_handle`; can be sent to other threads.
+///
+/// Stopping only sets an atomic flag, so it is also safe to do from a signal handler, e g on SIGTERM.
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Asks the service to stop. A Service does so within a tenth of a second, a TreeServer
+    /// when it is done with the current message, or when the underlying iterator yields next.
+    pub fn stop(&self) { self.0.store(true, Ordering::SeqCst) }
+
+    /// Returns true if `stop` has been called.
+    pub fn is_stopped(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
+
+/// A complete, small D-Bus service: a connection, a well-known name, a tree and the loop that serves it.
+///
+/// `run` connects to the bus, takes the name (failing if someone else already has it),
+/// and handles method calls until `StopHandle::stop` is called. Then it releases the name,
+/// and answers the method calls that arrived before that.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::tree::{Factory, Service};
+/// let f = Factory::new_fn::<()>();
+/// let service = Service::new("com.example.hello").add_path(f.object_path("/hello", ()).introspectable()
+///     .add(f.interface("com.example.hello", ()).add_m(f.method("Hello", (), |m| {
+///         Ok(m.msg.method_return().append1("Hello!").into())
+///     }))));
+/// let stop = service.stop_handle();
+/// ctrlc_like_handler(move || stop.stop());
+/// service.run().unwrap();
+/// # fn ctrlc_like_handler<F: FnOnce()>(_: F) {}
+/// ```
+#[derive(Debug)]
+pub struct Service<M: MethodType<D>, D: DataType> {
+    name: WellKnownName<'static>,
+    bus: BusType,
+    tree: Tree<M, D>,
+    stop: StopHandle,
+}
+
+impl Service<MTFn<()>, ()> {
+    /// Creates a new service with an empty tree, that will own "name" on the session bus.
+    pub fn new<N: Into<WellKnownName<'static>>>(name: N) -> Self {
+        Self::with_tree(name, Factory::new_fn::<()>().tree(()))
+    }
+}
+
+impl<M: MethodType<D> + 'static, D: DataType + 'static> Service<M, D> {
+    /// Creates a new service from an existing tree, that will own "name" on the session bus.
+    pub fn with_tree<N: Into<WellKnownName<'static>>>(name: N, tree: Tree<M, D>) -> Self {
+        Service { name: name.into(), bus: BusType::Session, tree, stop: Default::default() }
+    }
+
+    /// Builder function that sets the bus to connect to.
+    pub fn bus(mut self, bus: BusType) -> Self { self.bus = bus; self }
+
+    /// Builder function that adds an object path to the tree.
+    pub fn add_path<I: Into<Arc<ObjectPath<M, D>>>>(mut self, p: I) -> Self { self.tree = self.tree.add(p); self }
+
+    /// Builder function that changes the tree in some other way, e g sets a policy.
+    pub fn with<F: FnOnce(Tree<M, D>) -> Tree<M, D>>(mut self, f: F) -> Self { self.tree = f(self.tree); self }
+
+    /// The well-known name this service owns while running.
+    pub fn name(&self) -> &WellKnownName<'static> { &self.name }
+
+    /// Returns a handle that makes `run` return.
+    pub fn stop_handle(&self) -> StopHandle { self.stop.clone() }
+
+    /// Connects to the bus, and serves method calls until asked to stop.
+    pub fn run(self) -> Result<(), Error> {
+        let mut c: LocalConnection = Channel::get_private(self.bus)?.into();
+        let r = c.request_name(self.name.clone(), false, false, true)?;
+        if r != RequestNameReply::PrimaryOwner && r != RequestNameReply::AlreadyOwner {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.AddressInUse", &format!("The name {} is already taken", self.name)))
+        }
+        let (name, stop) = (self.name, self.stop);
+        self.tree.start_receive(&c);
+        while !stop.is_stopped() { c.process(STOP_INTERVAL)?; }
+        c.release_name(name)?;
+        while c.process(Duration::from_millis(0))? {}
+        Ok(())
+    }
+}
+
+#[test]
+fn test_service() {
+    use crate::blocking::Connection;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let t = std::thread::spawn(move || {
+        let f = Factory::new_fn::<()>();
+        let s = Service::new("com.example.dbusrs.service").add_path(f.object_path("/service", ()).introspectable()
+            .add(f.interface("com.example.dbusrs.Service", ()).add_m(f.method("Hello", (), |m| {
+                Ok(m.msg.method_return().append1("Hello!").into())
+            }))));
+        assert_eq!(&**s.name(), "com.example.dbusrs.service");
+        tx.send(s.stop_handle()).unwrap();
+        s.run()
+    });
+    let stop = rx.recv().unwrap();
+
+    let c = Connection::new_session().unwrap();
+    let p = c.with_proxy("com.example.dbusrs.service", "/service", Duration::from_secs(5));
+    // The service might not have taken its name yet.
+    let mut r = p.method_call("com.example.dbusrs.Service", "Hello", ());
+    for _ in 0..50 {
+        if r.is_ok() { break }
+        std::thread::sleep(Duration::from_millis(20));
+        r = p.method_call("com.example.dbusrs.Service", "Hello", ());
+    }
+    let (s,): (String,) = r.unwrap();
+    assert_eq!(s, "Hello!");
+
+    // A second instance cannot take the name.
+    assert!(Service::new("com.example.dbusrs.service").run().is_err());
+
+    stop.stop();
+    t.join().unwrap().unwrap();
+    assert!(p.method_call::<(String,), _, _, _>("com.example.dbusrs.Service", "Hello", ()).is_err());
+}
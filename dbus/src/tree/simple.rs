@@ -0,0 +1,135 @@
+// A less generic facade over the tree, for simple services.
+
+use super::{Factory, Tree, MTFnMut, MethodResult};
+use crate::{Message, channel};
+use crate::strings::{Path, Interface, Member};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The method handler type of a SimpleServer.
+pub type SimpleHandler = Box<dyn FnMut(&Message) -> MethodResult>;
+
+/// A simpler way to set up a server, for when you don't need the flexibility of the tree.
+///
+/// Object paths, interfaces and methods are just strings, and all method handlers are of
+/// the same type, `FnMut(&Message) -> MethodResult`. This can keep its state in captured
+/// variables. All object paths are introspectable.
+///
+/// Under the hood, this creates an ordinary Tree, so you can use `into_tree` in case you need more.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::tree::SimpleServer;
+/// use dbus::blocking::LocalConnection;
+/// use std::time::Duration;
+///
+/// let mut count = 0u32;
+/// let mut server = SimpleServer::new();
+/// server.add_method("/counter", "com.example.Counter", "Increment", move |m| {
+///     count += 1;
+///     Ok(m.method_return().append1(count).into())
+/// });
+///
+/// let mut c = LocalConnection::new_session().unwrap();
+/// c.request_name("com.example.counter", false, true, false).unwrap();
+/// server.start_receive(&c);
+/// loop { c.process(Duration::from_millis(1000)).unwrap(); }
+/// ```
+#[derive(Default)]
+pub struct SimpleServer {
+    paths: BTreeMap<String, BTreeMap<String, BTreeMap<String, SimpleHandler>>>,
+}
+
+impl fmt::Debug for SimpleServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let paths: BTreeMap<_, BTreeMap<_, Vec<_>>> = self.paths.iter().map(|(p, ifaces)|
+            (p, ifaces.iter().map(|(i, methods)| (i, methods.keys().collect())).collect())).collect();
+        f.debug_struct("SimpleServer").field("paths", &paths).finish()
+    }
+}
+
+impl SimpleServer {
+    /// Creates a new, empty server.
+    pub fn new() -> Self { Default::default() }
+
+    /// Adds a method handler. Object paths and interfaces are created as needed.
+    ///
+    /// A handler added earlier for the same method is replaced.
+    /// Panics if the path, interface or method name is not valid.
+    pub fn add_method<F>(&mut self, path: &str, iface: &str, method: &str, handler: F) -> &mut Self
+    where F: FnMut(&Message) -> MethodResult + 'static {
+        // Check the names now rather than later, so that the panic points to the culprit.
+        Path::new(path).unwrap();
+        Interface::new(iface).unwrap();
+        Member::new(method).unwrap();
+        self.paths.entry(path.into()).or_default()
+            .entry(iface.into()).or_default()
+            .insert(method.into(), Box::new(handler));
+        self
+    }
+
+    /// Removes a method handler, and returns it.
+    pub fn remove_method(&mut self, path: &str, iface: &str, method: &str) -> Option<SimpleHandler> {
+        let ifaces = self.paths.get_mut(path)?;
+        let methods = ifaces.get_mut(iface)?;
+        let r = methods.remove(method);
+        if methods.is_empty() { ifaces.remove(iface); }
+        if ifaces.is_empty() { self.paths.remove(path); }
+        r
+    }
+
+    /// Turns this into an ordinary Tree.
+    pub fn into_tree(self) -> Tree<MTFnMut<()>, ()> {
+        let f = Factory::new_fnmut::<()>();
+        self.paths.into_iter().fold(f.tree(()), |t, (path, ifaces)| {
+            t.add(ifaces.into_iter().fold(f.object_path(path, ()).introspectable(), |o, (iface, methods)| {
+                o.add(methods.into_iter().fold(f.interface(iface, ()), |i, (method, mut h)| {
+                    i.add_m(f.method(method, (), move |m| h(m.msg)))
+                }))
+            }))
+        })
+    }
+
+    /// Connects a Connection with this server so that incoming method calls are handled.
+    pub fn start_receive<C>(self, connection: &C)
+    where
+        C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool>> + channel::Sender
+    {
+        self.into_tree().start_receive(connection)
+    }
+}
+
+#[test]
+fn test_simple_server() {
+    let mut count = 0u32;
+    let mut server = SimpleServer::new();
+    server.add_method("/counter", "com.example.Counter", "Increment", move |m| {
+        count += 1;
+        Ok(m.method_return().append1(count).into())
+    }).add_method("/counter", "com.example.Counter", "Fail", |_| Err(super::MethodErr::failed("Oops")))
+    .add_method("/other", "com.example.Other", "Foo", |_| Err(super::MethodErr::failed("Not called")));
+    assert!(server.remove_method("/other", "com.example.Other", "Foo").is_some());
+    assert!(server.remove_method("/other", "com.example.Other", "Foo").is_none());
+    assert_eq!(format!("{:?}", server), r#"SimpleServer { paths: {"/counter": {"com.example.Counter": ["Fail", "Increment"]}} }"#);
+
+    let t = server.into_tree();
+    for i in 1..3u32 {
+        let mut m = Message::new_method_call("com.example.counter", "/counter", "com.example.Counter", "Increment").unwrap();
+        crate::message::message_set_serial(&mut m, i);
+        let r = t.handle(&m).unwrap();
+        assert_eq!(r[0].read1::<u32>().unwrap(), i);
+    }
+
+    let mut m = Message::new_method_call("com.example.counter", "/counter", "com.example.Counter", "Fail").unwrap();
+    crate::message::message_set_serial(&mut m, 3);
+    let mut r = t.handle(&m).unwrap();
+    assert_eq!(r[0].as_result().unwrap_err().message(), Some("Oops"));
+
+    let mut m = Message::new_method_call("com.example.counter", "/counter", "org.freedesktop.DBus.Introspectable", "Introspect").unwrap();
+    crate::message::message_set_serial(&mut m, 4);
+    let r = t.handle(&m).unwrap();
+    assert!(r[0].read1::<&str>().unwrap().contains(r#"<method name="Increment"/>"#));
+
+    let m = Message::new_method_call("com.example.counter", "/other", "com.example.Other", "Foo").unwrap();
+    assert!(t.handle(&m).is_none());
+}
@@ -0,0 +1,244 @@
+// A tree without Arc, Mutex or boxed closures, for single-threaded and constrained servers.
+
+use super::MethodErr;
+use super::utils::xml_escape;
+use crate::{Message, MessageType, channel, message};
+use std::fmt;
+use std::fmt::Write;
+
+/// The method handler type of a StaticTree: a plain function, which gets the data of the tree.
+pub type StaticHandler<D> = fn(&Message, &mut D) -> Result<Message, MethodErr>;
+
+/// A method of a StaticInterface.
+pub struct StaticMethod<D: 'static> {
+    /// The name of the method.
+    pub name: &'static str,
+    /// Names and signatures of the input arguments. Only used for introspection.
+    pub in_args: &'static [(&'static str, &'static str)],
+    /// Names and signatures of the output arguments. Only used for introspection.
+    pub out_args: &'static [(&'static str, &'static str)],
+    /// Called to handle the method call. Returns the reply, typically made with `Message::method_return`.
+    pub handler: StaticHandler<D>,
+}
+
+/// An interface of a StaticPath.
+pub struct StaticInterface<D: 'static> {
+    /// The name of the interface.
+    pub name: &'static str,
+    /// The methods of the interface.
+    pub methods: &'static [StaticMethod<D>],
+}
+
+/// An object path of a StaticTree.
+pub struct StaticPath<D: 'static> {
+    /// The object path.
+    pub path: &'static str,
+    /// The interfaces of the object path. `org.freedesktop.DBus.Introspectable` is always added.
+    pub interfaces: &'static [StaticInterface<D>],
+}
+
+impl<D> fmt::Debug for StaticMethod<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticMethod").field("name", &self.name).field("in_args", &self.in_args)
+            .field("out_args", &self.out_args).finish()
+    }
+}
+
+impl<D> fmt::Debug for StaticInterface<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticInterface").field("name", &self.name).field("methods", &self.methods).finish()
+    }
+}
+
+impl<D> fmt::Debug for StaticPath<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticPath").field("path", &self.path).field("interfaces", &self.interfaces).finish()
+    }
+}
+
+/// A tree where object paths, interfaces and methods are fixed at compile time.
+///
+/// Unlike `Tree`, this has no reference counting, locking or boxed closures: the layout
+/// lives in `static` (or `const`) slices, handlers are plain functions, and the data is owned by
+/// the tree and handed to the handlers as `&mut D`. This suits single-threaded services and
+/// those where dispatching speed matters more than flexibility. Properties, signals
+/// and the other standard interfaces are not supported; only introspection is.
+///
+/// Names are not validated until a method call arrives, so make sure they are valid.
+///
+/// # Example
+/// ```rust,no_run
+/// use dbus::tree::{StaticTree, StaticPath, StaticInterface, StaticMethod, MethodErr};
+/// use dbus::{Message, blocking::LocalConnection};
+/// use std::time::Duration;
+///
+/// fn increment(m: &Message, count: &mut u32) -> Result<Message, MethodErr> {
+///     *count += 1;
+///     Ok(m.method_return().append1(*count))
+/// }
+///
+/// static PATHS: &[StaticPath<u32>] = &[StaticPath { path: "/counter", interfaces: &[
+///     StaticInterface { name: "com.example.Counter", methods: &[
+///         StaticMethod { name: "Increment", in_args: &[], out_args: &[("count", "u")], handler: increment },
+///     ]},
+/// ]}];
+///
+/// let mut c = LocalConnection::new_session().unwrap();
+/// StaticTree::new(PATHS, 0u32).start_receive(&c);
+/// loop { c.process(Duration::from_millis(1000)).unwrap(); }
+/// ```
+pub struct StaticTree<D: 'static> {
+    paths: &'static [StaticPath<D>],
+    data: D,
+}
+
+impl<D: fmt::Debug> fmt::Debug for StaticTree<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticTree").field("paths", &self.paths).field("data", &self.data).finish()
+    }
+}
+
+impl<D: 'static> StaticTree<D> {
+    /// Creates a new tree with the given object paths and data.
+    pub fn new(paths: &'static [StaticPath<D>], data: D) -> Self { StaticTree { paths, data } }
+
+    /// The data of this tree.
+    pub fn data(&self) -> &D { &self.data }
+
+    /// The data of this tree, for changing it between method calls.
+    pub fn data_mut(&mut self) -> &mut D { &mut self.data }
+
+    /// Consumes the tree and returns its data.
+    pub fn into_data(self) -> D { self.data }
+
+    /// Handles a message.
+    ///
+    /// Will return None in case the object path was not found in this tree, or otherwise
+    /// the reply to send back.
+    pub fn handle(&mut self, m: &Message) -> Option<Message> {
+        if m.msg_type() != MessageType::MethodCall { return None }
+        let path = m.path()?;
+        let p = self.paths.iter().find(|p| p.path == &*path)?;
+        Some(self.dispatch(p, m).unwrap_or_else(|e| e.to_message(m)))
+    }
+
+    fn dispatch(&mut self, p: &StaticPath<D>, m: &Message) -> Result<Message, MethodErr> {
+        let member = m.member().ok_or_else(|| MethodErr::no_method(&""))?;
+        let iname = m.interface();
+        if iname.as_deref().unwrap_or("org.freedesktop.DBus.Introspectable") == "org.freedesktop.DBus.Introspectable" && &*member == "Introspect" {
+            return Ok(m.method_return().append1(self.introspect(p)));
+        }
+        // Without an interface, the first method with the right name is called.
+        let me = match iname {
+            Some(ref i) => p.interfaces.iter().find(|x| x.name == &**i).ok_or_else(|| MethodErr::no_interface(i))?
+                .methods.iter().find(|x| x.name == &*member),
+            None => p.interfaces.iter().flat_map(|x| x.methods).find(|x| x.name == &*member),
+        };
+        let me = me.ok_or_else(|| MethodErr::no_method(&member))?;
+        (me.handler)(m, &mut self.data)
+    }
+
+    fn introspect(&self, p: &StaticPath<D>) -> String {
+        let mut s = format!(r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="{}">
+"##, xml_escape(p.path));
+        let args = |s: &mut String, args: &[(&str, &str)], dir| for (n, t) in args {
+            let _ = writeln!(s, "      <arg name=\"{}\" type=\"{}\" direction=\"{}\"/>", xml_escape(n), xml_escape(t), dir);
+        };
+        for i in p.interfaces {
+            let _ = writeln!(s, "  <interface name=\"{}\">", xml_escape(i.name));
+            for me in i.methods {
+                let _ = writeln!(s, "    <method name=\"{}\">", xml_escape(me.name));
+                args(&mut s, me.in_args, "in");
+                args(&mut s, me.out_args, "out");
+                s.push_str("    </method>\n");
+            }
+            s.push_str("  </interface>\n");
+        }
+        s.push_str(r#"  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+"#);
+        let prefix = if p.path == "/" { "/".to_string() } else { format!("{}/", p.path) };
+        for c in self.paths.iter().filter_map(|c| c.path.strip_prefix(&*prefix)).filter(|c| !c.is_empty() && !c.contains('/')) {
+            let _ = writeln!(s, "  <node name=\"{}\"/>", xml_escape(c));
+        }
+        s.push_str("</node>");
+        s
+    }
+
+    /// Connects a Connection with this tree so that incoming method calls are handled.
+    pub fn start_receive<C>(mut self, connection: &C)
+    where
+        C: channel::MatchingReceiver<F=Box<dyn FnMut(Message, &C) -> bool>> + channel::Sender
+    {
+        let mut rule = message::MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        connection.start_receive(rule, Box::new(move |msg, c| {
+            if let Some(reply) = self.handle(&msg) { let _ = c.send(reply); }
+            true
+        }));
+    }
+}
+
+#[test]
+fn test_static_tree() {
+    fn add(m: &Message, sum: &mut i32) -> Result<Message, MethodErr> {
+        *sum += m.read1::<i32>()?;
+        Ok(m.method_return().append1(*sum))
+    }
+    fn fail(_: &Message, _: &mut i32) -> Result<Message, MethodErr> { Err(MethodErr::failed("Oops")) }
+    static PATHS: &[StaticPath<i32>] = &[
+        StaticPath { path: "/calc", interfaces: &[StaticInterface { name: "com.example.Calc", methods: &[
+            StaticMethod { name: "Add", in_args: &[("term", "i")], out_args: &[("sum", "i")], handler: add },
+            StaticMethod { name: "Fail", in_args: &[], out_args: &[], handler: fail },
+        ]}]},
+        StaticPath { path: "/calc/sub", interfaces: &[] },
+        StaticPath { path: "/calc/sub/deeper", interfaces: &[] },
+    ];
+
+    let mut t = StaticTree::new(PATHS, 5);
+    let call = |t: &mut StaticTree<i32>, path: &str, iface: &str, member: &str, arg: Option<i32>| {
+        let mut m = Message::new_method_call("com.example.calc", path, iface, member).unwrap();
+        if let Some(a) = arg { m = m.append1(a); }
+        message::message_set_serial(&mut m, 1);
+        t.handle(&m)
+    };
+    assert_eq!(call(&mut t, "/calc", "com.example.Calc", "Add", Some(3)).unwrap().read1::<i32>().unwrap(), 8);
+    assert_eq!(call(&mut t, "/calc", "com.example.Calc", "Add", Some(4)).unwrap().read1::<i32>().unwrap(), 12);
+    assert_eq!(*t.data(), 12);
+    *t.data_mut() = 0;
+
+    let mut r = call(&mut t, "/calc", "com.example.Calc", "Add", None).unwrap();
+    assert_eq!(r.as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.Failed"));
+    let mut r = call(&mut t, "/calc", "com.example.Calc", "Fail", None).unwrap();
+    assert_eq!(r.as_result().unwrap_err().message(), Some("Oops"));
+    let mut r = call(&mut t, "/calc", "com.example.Calc", "Sub", None).unwrap();
+    assert_eq!(r.as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.UnknownMethod"));
+    let mut r = call(&mut t, "/calc", "com.example.Other", "Add", None).unwrap();
+    assert_eq!(r.as_result().unwrap_err().name(), Some("org.freedesktop.DBus.Error.UnknownInterface"));
+    assert!(call(&mut t, "/other", "com.example.Calc", "Add", Some(1)).is_none());
+    assert_eq!(t.into_data(), 0);
+
+    let mut t = StaticTree::new(PATHS, 0);
+    let r = call(&mut t, "/calc", "org.freedesktop.DBus.Introspectable", "Introspect", None).unwrap();
+    assert_eq!(r.read1::<&str>().unwrap(), r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="/calc">
+  <interface name="com.example.Calc">
+    <method name="Add">
+      <arg name="term" type="i" direction="in"/>
+      <arg name="sum" type="i" direction="out"/>
+    </method>
+    <method name="Fail">
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <node name="sub"/>
+</node>"##);
+}
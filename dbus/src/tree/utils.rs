@@ -1,16 +1,47 @@
 // Small structs that don't have their own unit.
 
 use crate::strings::{Signature, Member, Path, Interface as IfaceName};
-use std::collections::{BTreeMap, btree_map};
+use std::collections::{BTreeMap, BTreeSet, btree_map};
+use std::borrow::Borrow;
+use std::ffi::CStr;
 use std::sync::Arc;
 
 pub type ArcMap<K, V> = BTreeMap<K, Arc<V>>;
 
+// The interface and member names used in a tree. Paths added to the tree share these where they
+// can, so that trees with thousands of paths allocate each name once. The names of incoming method
+// calls are looked up here first, so calls to names the tree does not have fail before the path is searched.
+#[derive(Debug, Default)]
+pub struct Names {
+    ifaces: BTreeSet<Name<IfaceName<'static>>>,
+    members: BTreeSet<Name<Member<'static>>>,
+}
+
+// Can be looked up by the string, whatever its lifetime.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Name<T>(Arc<T>);
+
+impl Borrow<CStr> for Name<IfaceName<'static>> { fn borrow(&self) -> &CStr { self.0.as_cstr() } }
+impl Borrow<CStr> for Name<Member<'static>> { fn borrow(&self) -> &CStr { self.0.as_cstr() } }
+
+fn intern<T: Ord>(set: &mut BTreeSet<Name<T>>, n: &Arc<T>, c: &CStr) -> Arc<T> where Name<T>: Borrow<CStr> {
+    if let Some(x) = set.get(c) { return x.0.clone() }
+    set.insert(Name(n.clone()));
+    n.clone()
+}
+
+impl Names {
+    pub fn iface(&mut self, n: &Arc<IfaceName<'static>>) -> Arc<IfaceName<'static>> { intern(&mut self.ifaces, n, n.as_cstr()) }
+    pub fn member(&mut self, n: &Arc<Member<'static>>) -> Arc<Member<'static>> { intern(&mut self.members, n, n.as_cstr()) }
+    pub fn get_iface(&self, n: &IfaceName) -> Option<&Arc<IfaceName<'static>>> { self.ifaces.get(n.as_cstr()).map(|x| &x.0) }
+    pub fn get_member(&self, n: &Member) -> Option<&Arc<Member<'static>>> { self.members.get(n.as_cstr()).map(|x| &x.0) }
+}
+
 #[derive(Clone, Debug)]
 pub enum IterE<'a, V: 'a> {
     Path(btree_map::Values<'a, Arc<Path<'static>>, Arc<V>>),
     Iface(btree_map::Values<'a, Arc<IfaceName<'static>>, Arc<V>>),
-    Member(btree_map::Values<'a, Member<'static>, Arc<V>>),
+    Member(btree_map::Values<'a, Arc<Member<'static>>, Arc<V>>),
     String(btree_map::Values<'a, String, Arc<V>>),
 }
 
@@ -34,11 +65,11 @@ impl<'a, V: 'a> Iterator for Iter<'a, V> {
 
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
 /// A D-Bus Argument.
-pub struct Argument(Option<String>, Signature<'static>);
+pub struct Argument(Option<String>, Signature<'static>, Annotations);
 
 impl Argument {
     /// Create a new Argument.
-    pub fn new(name: Option<String>, sig: Signature<'static>) -> Argument { Argument(name, sig) }
+    pub fn new(name: Option<String>, sig: Signature<'static>) -> Argument { Argument(name, sig, Annotations::new()) }
 
     /// Descriptive name (if any).
     pub fn name(&self) -> Option<&str> { self.0.as_ref().map(|s| &**s) }
@@ -46,19 +77,77 @@ impl Argument {
     /// Type signature of argument.
     pub fn signature(&self) -> &Signature<'static> { &self.1 }
 
+    /// Builder method that adds an annotation to the argument.
+    pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.2.insert(name, value); self
+    }
+    /// Builder method that adds a well-known annotation to the argument.
+    pub fn annotation(self, a: Annotation) -> Self { let (n, v) = a.into_pair(); self.annotate(n, v) }
+    /// Builder method that adds an annotation that this entity is deprecated.
+    pub fn deprecated(self) -> Self { self.annotation(Annotation::Deprecated) }
+
     fn introspect(&self, indent: &str, dir: &str) -> String { 
-        let n = self.0.as_ref().map(|n| format!("name=\"{}\" ", n)).unwrap_or_default();
-        format!("{}<arg {}type=\"{}\"{}/>\n", indent, n, self.1, dir)
+        let n = self.0.as_ref().map(|n| format!("name=\"{}\" ", xml_escape(n))).unwrap_or_default();
+        let anns = self.2.introspect(&format!("{}  ", indent));
+        if anns.is_empty() { return format!("{}<arg {}type=\"{}\"{}/>\n", indent, n, self.1, dir) }
+        format!("{}<arg {}type=\"{}\"{}>\n{}{}</arg>\n", indent, n, self.1, dir, anns, indent)
     }
 
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Well-known annotations, for the `annotation` builder methods of methods, signals, properties, interfaces and arguments.
+///
+/// The `EmitsChangedSignal` annotation of properties is set through `Property::emits_changed` instead.
+pub enum Annotation {
+    /// The entity is deprecated (`org.freedesktop.DBus.Deprecated`).
+    Deprecated,
+    /// The method does not send a reply (`org.freedesktop.DBus.Method.NoReply`). This only informs callers;
+    /// it is up to the method to return no messages.
+    NoReply,
+    /// The name of the C symbol for this entity in GLib bindings (`org.freedesktop.DBus.GLib.CSymbol`).
+    CSymbol(String),
+    /// The Qt type of an argument or property with a complex type (`org.qtproject.QtDBus.QtTypeName`).
+    QtTypeName(String),
+    /// Any other annotation, as name and value.
+    Other(String, String),
+}
+
+impl Annotation {
+    /// The name and value of the annotation.
+    pub fn into_pair(self) -> (String, String) {
+        match self {
+            Annotation::Deprecated => ("org.freedesktop.DBus.Deprecated".into(), "true".into()),
+            Annotation::NoReply => ("org.freedesktop.DBus.Method.NoReply".into(), "true".into()),
+            Annotation::CSymbol(s) => ("org.freedesktop.DBus.GLib.CSymbol".into(), s),
+            Annotation::QtTypeName(s) => ("org.qtproject.QtDBus.QtTypeName".into(), s),
+            Annotation::Other(n, v) => (n, v),
+        }
+    }
+}
+
+/// Escapes a string for use inside an XML attribute value.
+pub fn xml_escape(s: &str) -> std::borrow::Cow<'_, str> {
+    if !s.contains(['&', '<', '>', '"', '\'']) { return s.into() }
+    s.chars().fold(String::with_capacity(s.len() + 8), |mut r, c| {
+        match c {
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            '"' => r.push_str("&quot;"),
+            '\'' => r.push_str("&apos;"),
+            c => r.push(c),
+        }
+        r
+    }).into()
+}
+
 pub fn introspect_args(args: &[Argument], indent: &str, dir: &str) -> String {
     args.iter().fold("".to_string(), |aa, az| format!("{}{}", aa, az.introspect(indent, dir)))
 }
 
 // Small helper struct to reduce memory somewhat for objects without annotations
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialOrd, Ord, PartialEq, Eq)]
 pub struct Annotations(Option<BTreeMap<String, String>>);
 
 impl Annotations {
@@ -71,7 +160,7 @@ impl Annotations {
 
     pub fn introspect(&self, indent: &str) -> String {
         self.0.as_ref().map(|s| s.iter().fold("".into(), |aa, (ak, av)| {
-            format!("{}{}<annotation name=\"{}\" value=\"{}\"/>\n", aa, indent, ak, av)
+            format!("{}{}<annotation name=\"{}\" value=\"{}\"/>\n", aa, indent, xml_escape(ak), xml_escape(av))
         })).unwrap_or_default()
     }
 }
@@ -80,15 +169,15 @@ impl Annotations {
 // impl<S: Into<Signature>> From<S> for Argument
 
 impl From<Signature<'static>> for Argument {
-    fn from(t: Signature<'static>) -> Argument { Argument(None, t) }
+    fn from(t: Signature<'static>) -> Argument { Argument::new(None, t) }
 }
 
 impl<'a> From<&'a str> for Argument {
-    fn from(t: &'a str) -> Argument { Argument(None, String::from(t).into()) }
+    fn from(t: &'a str) -> Argument { Argument::new(None, String::from(t).into()) }
 }
 
 impl<N: Into<String>, S: Into<Signature<'static>>> From<(N, S)> for Argument {
-    fn from((n, s): (N, S)) -> Argument { Argument(Some(n.into()), s.into()) }
+    fn from((n, s): (N, S)) -> Argument { Argument::new(Some(n.into()), s.into()) }
 }
 
 pub trait Introspect {
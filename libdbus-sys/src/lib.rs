@@ -1,10 +1,12 @@
-use std::os::raw::{c_void, c_char, c_uint, c_int, c_long};
+use std::os::raw::{c_void, c_char, c_uint, c_int, c_long, c_ulong};
 
 pub type DBusConnection = c_void;
 pub type DBusMessage = c_void;
 pub type DBusWatch = c_void;
 pub type DBusPendingCall = c_void;
 pub type DBusTimeout = c_void;
+pub type DBusAddressEntry = c_void;
+pub type DBusServer = c_void;
 
 #[repr(C)]
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -129,6 +131,8 @@ pub struct DBusMessageIter {
 
 pub type DBusHandleMessageFunction = Option<extern fn(conn: *mut DBusConnection, msg: *mut DBusMessage, user_data: *mut c_void) -> DBusHandlerResult>;
 
+pub type DBusNewConnectionFunction = Option<extern fn(server: *mut DBusServer, conn: *mut DBusConnection, user_data: *mut c_void)>;
+pub type DBusAllowUnixUserFunction = Option<extern fn(conn: *mut DBusConnection, uid: c_ulong, user_data: *mut c_void) -> u32>;
 pub type DBusAddWatchFunction = Option<extern fn(watch: *mut DBusWatch, user_data: *mut c_void) -> u32>;
 pub type DBusRemoveWatchFunction = Option<extern fn(watch: *mut DBusWatch, user_data: *mut c_void)>;
 pub type DBusWatchToggledFunction = Option<extern fn(watch: *mut DBusWatch, user_data: *mut c_void)>;
@@ -172,8 +176,10 @@ extern "C" {
     pub fn dbus_connection_dispatch(conn: *mut DBusConnection) -> DBusDispatchStatus;
     pub fn dbus_connection_flush(conn: *mut DBusConnection);
     pub fn dbus_connection_open_private(address: *const c_char, error: *mut DBusError) -> *mut DBusConnection;
+    pub fn dbus_connection_ref(conn: *mut DBusConnection) -> *mut DBusConnection;
     pub fn dbus_connection_unref(conn: *mut DBusConnection);
     pub fn dbus_connection_get_is_connected(conn: *mut DBusConnection) -> u32;
+    pub fn dbus_connection_get_unix_fd(conn: *mut DBusConnection, fd: *mut c_int) -> u32;
     pub fn dbus_connection_set_exit_on_disconnect(conn: *mut DBusConnection, enable: u32);
     pub fn dbus_connection_send_with_reply_and_block(conn: *mut DBusConnection,
         message: *mut DBusMessage, timeout_milliseconds: c_int, error: *mut DBusError) -> *mut DBusMessage;
@@ -220,6 +226,7 @@ extern "C" {
         error_name: *const c_char, error_message: *const c_char) -> *mut DBusMessage;
     pub fn dbus_message_new_signal(path: *const c_char,
         iface: *const c_char, name: *const c_char) -> *mut DBusMessage;
+    pub fn dbus_message_copy(message: *const DBusMessage) -> *mut DBusMessage;
     pub fn dbus_message_ref(message: *mut DBusMessage) -> *mut DBusMessage;
     pub fn dbus_message_unref(message: *mut DBusMessage);
     pub fn dbus_message_get_type(message: *mut DBusMessage) -> c_int;
@@ -231,13 +238,18 @@ extern "C" {
     pub fn dbus_message_get_interface(message: *mut DBusMessage) -> *const c_char;
     pub fn dbus_message_get_destination(message: *mut DBusMessage) -> *const c_char;
     pub fn dbus_message_get_member(message: *mut DBusMessage) -> *const c_char;
+    pub fn dbus_message_get_error_name(message: *mut DBusMessage) -> *const c_char;
     pub fn dbus_message_get_sender(message: *mut DBusMessage) -> *const c_char;
+    pub fn dbus_message_set_sender(message: *mut DBusMessage, sender: *const c_char) -> u32;
     pub fn dbus_message_set_serial(message: *mut DBusMessage, serial: u32);
     pub fn dbus_message_set_destination(message: *mut DBusMessage, destination: *const c_char) -> u32;
+    pub fn dbus_message_set_interface(message: *mut DBusMessage, iface: *const c_char) -> u32;
     pub fn dbus_message_get_no_reply(message: *mut DBusMessage) -> u32;
     pub fn dbus_message_set_no_reply(message: *mut DBusMessage, no_reply: u32);
     pub fn dbus_message_get_auto_start(message: *mut DBusMessage) -> u32;
     pub fn dbus_message_set_auto_start(message: *mut DBusMessage, no_reply: u32);
+    pub fn dbus_message_get_allow_interactive_authorization(message: *mut DBusMessage) -> u32;
+    pub fn dbus_message_set_allow_interactive_authorization(message: *mut DBusMessage, allow: u32);
 
     pub fn dbus_message_iter_append_basic(iter: *mut DBusMessageIter, t: c_int, value: *const c_void) -> u32;
     pub fn dbus_message_iter_append_fixed_array(iter: *mut DBusMessageIter, element_type: c_int,
@@ -289,11 +301,18 @@ extern "C" {
     pub fn dbus_pending_call_set_notify(pending: *mut DBusPendingCall, n: DBusPendingCallNotifyFunction,
         user_data: *mut c_void, free_user_data: DBusFreeFunction) -> u32;
     pub fn dbus_pending_call_steal_reply(pending: *mut DBusPendingCall) -> *mut DBusMessage;
+    pub fn dbus_pending_call_block(pending: *mut DBusPendingCall);
 
     pub fn dbus_message_marshal(msg: *mut DBusMessage, marshalled_data_p: *mut *mut c_char, len_p: *mut c_int) -> u32;
     pub fn dbus_message_demarshal(s: *const c_char, len: c_int, error: *mut DBusError) -> *mut DBusMessage;
     pub fn dbus_message_demarshal_bytes_needed(buf: *const c_char, len: c_int) -> c_int;
 
+    pub fn dbus_parse_address(address: *const c_char, entry_result: *mut *mut *mut DBusAddressEntry,
+        array_len: *mut c_int, error: *mut DBusError) -> u32;
+    pub fn dbus_address_entries_free(entries: *mut *mut DBusAddressEntry);
+    pub fn dbus_address_entry_get_method(entry: *mut DBusAddressEntry) -> *const c_char;
+    pub fn dbus_address_entry_get_value(entry: *mut DBusAddressEntry, key: *const c_char) -> *const c_char;
+
     pub fn dbus_connection_set_max_message_size(conn: *mut DBusConnection, size: c_long);
     pub fn dbus_connection_get_max_message_size(conn: *mut DBusConnection) -> c_long;
     pub fn dbus_connection_set_max_message_unix_fds(conn: *mut DBusConnection, n: c_long);
@@ -307,6 +326,26 @@ extern "C" {
     pub fn dbus_connection_get_outgoing_size(conn: *mut DBusConnection) -> c_long;
     pub fn dbus_connection_get_outgoing_unix_fds(conn: *mut DBusConnection) -> c_long;
     pub fn dbus_connection_has_messages_to_send(conn: *mut DBusConnection) -> u32;
+    pub fn dbus_connection_can_send_type(conn: *mut DBusConnection, type_: c_int) -> u32;
+    pub fn dbus_message_contains_unix_fds(message: *mut DBusMessage) -> u32;
+
+    pub fn dbus_connection_get_unix_user(conn: *mut DBusConnection, uid: *mut c_ulong) -> u32;
+    pub fn dbus_connection_get_unix_process_id(conn: *mut DBusConnection, pid: *mut c_ulong) -> u32;
+    pub fn dbus_connection_set_unix_user_function(conn: *mut DBusConnection, function: DBusAllowUnixUserFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction);
+
+    pub fn dbus_server_listen(address: *const c_char, error: *mut DBusError) -> *mut DBusServer;
+    pub fn dbus_server_ref(server: *mut DBusServer) -> *mut DBusServer;
+    pub fn dbus_server_unref(server: *mut DBusServer);
+    pub fn dbus_server_disconnect(server: *mut DBusServer);
+    pub fn dbus_server_get_is_connected(server: *mut DBusServer) -> u32;
+    pub fn dbus_server_get_address(server: *mut DBusServer) -> *mut c_char;
+    pub fn dbus_server_get_id(server: *mut DBusServer) -> *mut c_char;
+    pub fn dbus_server_set_new_connection_function(server: *mut DBusServer, function: DBusNewConnectionFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction);
+    pub fn dbus_server_set_watch_functions(server: *mut DBusServer, add_function: DBusAddWatchFunction,
+        remove_function: DBusRemoveWatchFunction, toggled_function: DBusWatchToggledFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction) -> u32;
 
     pub fn dbus_try_get_local_machine_id(error: *mut DBusError) -> *mut c_char;
     pub fn dbus_get_local_machine_id() -> *mut c_char;